@@ -1,5 +1,45 @@
 // Copyright 2023-, Semiotic AI, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+use alloy::sol_types::SolStruct;
+use tap_core::{
+    rav_request::RavRequest,
+    receipt::{ReceiptWithState, WithValueAndTimestamp},
+    signed_message::Eip712SignedMessage,
+};
+
 pub mod v1;
 pub mod v2;
+
+/// Checks with [`RavRequest::validate_aggregate`] that the value of `rav` is
+/// the sum of the values of `receipts` and of `previous_rav`, before `rav`
+/// gets signed. `receipts` must have been checked already.
+pub(crate) fn validate_aggregate<Rcpt, Rav>(
+    receipts: &[Rcpt],
+    previous_rav: Option<Eip712SignedMessage<Rav>>,
+    rav: &Rav,
+) -> Result<(), tap_core::Error>
+where
+    Rcpt: Clone + WithValueAndTimestamp,
+    Rav: Clone + SolStruct + WithValueAndTimestamp,
+{
+    RavRequest {
+        valid_receipts: receipts
+            .iter()
+            .cloned()
+            // completing successful checks can't fail
+            .filter_map(|receipt| {
+                ReceiptWithState::new(receipt)
+                    .complete_checks(Ok(()))
+                    .ok()?
+                    .ok()
+            })
+            .collect(),
+        previous_rav,
+        invalid_receipts: Vec::new(),
+        expected_rav: Ok(rav.clone()),
+        omitted_receipts: 0,
+        omitted_value: 0,
+    }
+    .validate_aggregate()
+}
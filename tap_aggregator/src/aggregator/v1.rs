@@ -12,6 +12,11 @@ use rayon::prelude::*;
 use tap_core::signed_message::{Eip712SignedMessage, SignatureBytes, SignatureBytesExt};
 use tap_graph::{Receipt, ReceiptAggregateVoucher};
 
+/// Checks `receipts` and `previous_rav`, and returns the RAV aggregating them
+/// signed with `wallet`.
+///
+/// Signing is deterministic, see [`Eip712SignedMessage::new`]: the same
+/// inputs always produce a byte-identical signed RAV.
 pub fn check_and_aggregate_receipts(
     domain_separator: &Eip712Domain,
     receipts: &[Eip712SignedMessage<Receipt>],
@@ -60,7 +65,9 @@ pub fn check_and_aggregate_receipts(
     }
 
     // Aggregate the receipts
-    let rav = ReceiptAggregateVoucher::aggregate_receipts(allocation_id, receipts, previous_rav)?;
+    let rav =
+        ReceiptAggregateVoucher::aggregate_receipts(allocation_id, receipts, previous_rav.clone())?;
+    super::validate_aggregate(receipts, previous_rav, &rav)?;
 
     // Sign the rav and return
     Ok(Eip712SignedMessage::new(domain_separator, rav, wallet)?)
@@ -71,7 +78,8 @@ fn check_signature_is_from_one_of_addresses<M: SolStruct>(
     domain_separator: &Eip712Domain,
     accepted_addresses: &HashSet<Address>,
 ) -> Result<()> {
-    let recovered_address = message.recover_signer(domain_separator)?;
+    // a high-S signature is a second signature of the same message
+    let recovered_address = message.recover_signer_strict(domain_separator)?;
     if !accepted_addresses.contains(&recovered_address) {
         bail!(tap_core::Error::InvalidRecoveredSigner {
             address: recovered_address,
@@ -96,7 +104,12 @@ fn check_allocation_id(
 fn check_signatures_unique(receipts: &[Eip712SignedMessage<Receipt>]) -> Result<()> {
     let mut receipt_signatures: hash_set::HashSet<SignatureBytes> = hash_set::HashSet::new();
     for receipt in receipts.iter() {
-        let signature = receipt.signature.get_signature_bytes();
+        // both forms of a signature count as the same signature
+        let signature = receipt
+            .signature
+            .normalize_s()
+            .unwrap_or(receipt.signature)
+            .get_signature_bytes();
         if !receipt_signatures.insert(signature) {
             return Err(tap_core::Error::DuplicateReceiptSignature(format!(
                 "{:?}",
@@ -132,7 +145,11 @@ fn check_receipt_timestamps(
 mod tests {
     use std::str::FromStr;
 
-    use alloy::{dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner};
+    use alloy::{
+        dyn_abi::Eip712Domain,
+        primitives::{Address, PrimitiveSignature as Signature, U256},
+        signers::local::PrivateKeySigner,
+    };
     use rstest::*;
     use tap_core::{signed_message::Eip712SignedMessage, tap_eip712_domain};
     use tap_graph::{Receipt, ReceiptAggregateVoucher};
@@ -183,6 +200,51 @@ mod tests {
         assert!(res.is_err());
     }
 
+    #[rstest]
+    #[test]
+    /// Test that a receipt submitted again with its signature in its high-S
+    /// form isn't aggregated twice
+    fn check_signatures_unique_high_s_fail(
+        keys: (PrivateKeySigner, Address),
+        allocation_ids: Vec<Address>,
+        domain_separator: Eip712Domain,
+    ) {
+        let curve_order = U256::from_str_radix(
+            "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141",
+            16,
+        )
+        .unwrap();
+        let receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 42).unwrap(),
+            &keys.0,
+        )
+        .unwrap();
+        let mut high_s_receipt = receipt.clone();
+        let signature = receipt.signature;
+        high_s_receipt.signature =
+            Signature::new(signature.r(), curve_order - signature.s(), !signature.v());
+        let accepted_addresses = std::collections::HashSet::from([keys.1]);
+
+        let res = check_and_aggregate_receipts(
+            &domain_separator,
+            &[receipt.clone(), high_s_receipt.clone()],
+            None,
+            &keys.0,
+            &accepted_addresses,
+        );
+        assert!(res.is_err());
+        // nor alone
+        let res = check_and_aggregate_receipts(
+            &domain_separator,
+            &[high_s_receipt],
+            None,
+            &keys.0,
+            &accepted_addresses,
+        );
+        assert!(res.is_err());
+    }
+
     #[rstest]
     #[test]
     fn check_signatures_unique_ok(
@@ -347,4 +409,39 @@ mod tests {
 
         assert!(res.is_ok());
     }
+
+    #[rstest]
+    #[test]
+    /// Test that aggregating the same receipts twice gives the same signature
+    fn rav_signature_is_deterministic(
+        keys: (PrivateKeySigner, Address),
+        allocation_ids: Vec<Address>,
+        domain_separator: Eip712Domain,
+    ) {
+        let receipts: Vec<_> = (42..45)
+            .map(|value| {
+                Eip712SignedMessage::new(
+                    &domain_separator,
+                    Receipt::new(allocation_ids[0], value).unwrap(),
+                    &keys.0,
+                )
+                .unwrap()
+            })
+            .collect();
+        let accepted_addresses = HashSet::from([keys.1]);
+        let aggregate = || {
+            check_and_aggregate_receipts(
+                &domain_separator,
+                &receipts,
+                None,
+                &keys.0,
+                &accepted_addresses,
+            )
+            .unwrap()
+        };
+
+        let rav = aggregate();
+        assert_eq!(rav.signature, aggregate().signature);
+        assert_eq!(rav, aggregate());
+    }
 }
@@ -102,8 +102,9 @@ pub fn check_and_aggregate_receipts(
         data_service,
         service_provider,
         receipts,
-        previous_rav,
+        previous_rav.clone(),
     )?;
+    super::validate_aggregate(receipts, previous_rav, &rav)?;
 
     // Sign the rav and return
     Ok(Eip712SignedMessage::new(domain_separator, rav, wallet)?)
@@ -114,7 +115,8 @@ fn check_signature_is_from_one_of_addresses<M: SolStruct>(
     domain_separator: &Eip712Domain,
     accepted_addresses: &HashSet<Address>,
 ) -> Result<()> {
-    let recovered_address = message.recover_signer(domain_separator)?;
+    // a high-S signature is a second signature of the same message
+    let recovered_address = message.recover_signer_strict(domain_separator)?;
     if !accepted_addresses.contains(&recovered_address) {
         bail!(tap_core::Error::InvalidRecoveredSigner {
             address: recovered_address,
@@ -151,7 +153,12 @@ fn check_allocation_id(
 fn check_signatures_unique(receipts: &[Eip712SignedMessage<Receipt>]) -> Result<()> {
     let mut receipt_signatures: hash_set::HashSet<SignatureBytes> = hash_set::HashSet::new();
     for receipt in receipts.iter() {
-        let signature = receipt.signature.get_signature_bytes();
+        // both forms of a signature count as the same signature
+        let signature = receipt
+            .signature
+            .normalize_s()
+            .unwrap_or(receipt.signature)
+            .get_signature_bytes();
         if !receipt_signatures.insert(signature) {
             return Err(tap_core::Error::DuplicateReceiptSignature(format!(
                 "{:?}",
@@ -187,7 +194,7 @@ fn check_receipt_timestamps(
 mod tests {
     use alloy::{
         dyn_abi::Eip712Domain,
-        primitives::{address, Address, Bytes},
+        primitives::{address, Address, Bytes, PrimitiveSignature as Signature, U256},
         signers::local::PrivateKeySigner,
     };
     use rstest::*;
@@ -255,6 +262,54 @@ mod tests {
         assert!(res.is_err());
     }
 
+    #[rstest]
+    #[test]
+    /// Test that a receipt submitted again with its signature in its high-S
+    /// form isn't aggregated twice
+    fn check_signatures_unique_high_s_fail(
+        keys: (PrivateKeySigner, Address),
+        allocation_id: Address,
+        payer: Address,
+        data_service: Address,
+        service_provider: Address,
+        domain_separator: Eip712Domain,
+    ) {
+        let curve_order = U256::from_str_radix(
+            "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141",
+            16,
+        )
+        .unwrap();
+        let receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_id, payer, data_service, service_provider, 42).unwrap(),
+            &keys.0,
+        )
+        .unwrap();
+        let mut high_s_receipt = receipt.clone();
+        let signature = receipt.signature;
+        high_s_receipt.signature =
+            Signature::new(signature.r(), curve_order - signature.s(), !signature.v());
+        let accepted_addresses = std::collections::HashSet::from([keys.1]);
+
+        let res = super::check_and_aggregate_receipts(
+            &domain_separator,
+            &[receipt.clone(), high_s_receipt.clone()],
+            None,
+            &keys.0,
+            &accepted_addresses,
+        );
+        assert!(res.is_err());
+        // nor alone
+        let res = super::check_and_aggregate_receipts(
+            &domain_separator,
+            &[high_s_receipt],
+            None,
+            &keys.0,
+            &accepted_addresses,
+        );
+        assert!(res.is_err());
+    }
+
     #[rstest]
     #[test]
     fn check_signatures_unique_ok(
@@ -459,4 +514,47 @@ mod tests {
 
         assert!(res.is_ok());
     }
+
+    #[rstest]
+    #[test]
+    fn validate_aggregate_detects_corrupted_value(
+        keys: (PrivateKeySigner, Address),
+        allocation_id: Address,
+        payer: Address,
+        data_service: Address,
+        service_provider: Address,
+        domain_separator: Eip712Domain,
+    ) {
+        let receipts: Vec<_> = [42, 43]
+            .into_iter()
+            .map(|value| {
+                Eip712SignedMessage::new(
+                    &domain_separator,
+                    Receipt::new(allocation_id, payer, data_service, service_provider, value)
+                        .unwrap(),
+                    &keys.0,
+                )
+                .unwrap()
+            })
+            .collect();
+        let mut rav = ReceiptAggregateVoucher::aggregate_receipts(
+            allocation_id,
+            payer,
+            data_service,
+            service_provider,
+            &receipts,
+            None,
+        )
+        .unwrap();
+        crate::aggregator::validate_aggregate(&receipts, None, &rav).unwrap();
+
+        rav.valueAggregate += 1;
+        assert!(matches!(
+            crate::aggregator::validate_aggregate(&receipts, None, &rav),
+            Err(tap_core::Error::AggregateMismatch {
+                expected_rav_value: 86,
+                recomputed_value: 85,
+            })
+        ));
+    }
 }
@@ -0,0 +1,277 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Configuration of the aggregator server, see [`crate::server::Server::from_config`].
+
+use std::{collections::HashSet, net::IpAddr, str::FromStr, time::Duration};
+
+use alloy::{dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner};
+use serde::{Deserialize, Deserializer};
+use tap_core::tap_eip712_domain;
+
+use crate::wire_format::WireFormats;
+
+const DEFAULT_PORT: u16 = 8080;
+const DEFAULT_MAX_REQUEST_BODY_SIZE: u32 = 10 * 1024 * 1024;
+const DEFAULT_MAX_RESPONSE_BODY_SIZE: u32 = 100 * 1024;
+const DEFAULT_MAX_CONCURRENT_CONNECTIONS: u32 = 32;
+const DEFAULT_DOMAIN_CHAIN_ID: u64 = 1;
+pub(crate) const DEFAULT_RECEIPT_SUBMISSION_TIMEOUT: Duration = Duration::from_secs(10);
+pub(crate) const DEFAULT_RAV_SIGNING_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Settings of the aggregator server.
+///
+/// Only the signing key is required when deserializing, every other field
+/// falls back to the same defaults as the command line.
+#[derive(Clone, Deserialize)]
+pub struct AggregatorConfig {
+    /// Wallet signing the RAVs, deserialized from a hex private key
+    #[serde(rename = "private_key", deserialize_with = "deserialize_wallet")]
+    pub wallet: PrivateKeySigner,
+
+    /// Port to listen on, `0` picks any available port
+    #[serde(default = "default_port")]
+    pub port: u16,
+
+    /// Signers accepted for incoming receipts and RAVs, on top of the wallet address
+    #[serde(default)]
+    pub accepted_addresses: HashSet<Address>,
+
+    /// Chain id of the EIP-712 domain separator
+    #[serde(default = "default_domain_chain_id")]
+    pub domain_chain_id: u64,
+
+    /// Verifying contract of the EIP-712 domain separator
+    #[serde(default)]
+    pub domain_verifying_contract: Address,
+
+    /// Maximum request body size in bytes
+    #[serde(default = "default_max_request_body_size")]
+    pub max_request_body_size: u32,
+
+    /// Maximum response body size in bytes
+    #[serde(default = "default_max_response_body_size")]
+    pub max_response_body_size: u32,
+
+    /// Maximum number of concurrent connections
+    #[serde(default = "default_max_concurrent_connections")]
+    pub max_concurrent_connections: u32,
+
+    /// Time allowed to `tap_submit_receipts` and `tap_check_receipt` calls,
+    /// deserialized from milliseconds
+    #[serde(
+        rename = "receipt_submission_timeout_ms",
+        default = "default_receipt_submission_timeout",
+        deserialize_with = "deserialize_millis"
+    )]
+    pub receipt_submission_timeout: Duration,
+
+    /// Time allowed to aggregate and sign a RAV, deserialized from milliseconds
+    #[serde(
+        rename = "rav_signing_timeout_ms",
+        default = "default_rav_signing_timeout",
+        deserialize_with = "deserialize_millis"
+    )]
+    pub rav_signing_timeout: Duration,
+
+    /// Names of the JSON-RPC methods served, all of them when `None`. Other
+    /// methods are answered with a method-not-found error.
+    #[serde(default)]
+    pub enabled_methods: Option<HashSet<String>>,
+
+    /// Token required in the `Authorization: Bearer` header of the `admin_*`
+    /// JSON-RPC methods. The admin methods are refused when `None`.
+    #[serde(default)]
+    pub admin_token: Option<String>,
+
+    /// Tokens accepted in the `Authorization: Bearer` header of every request,
+    /// other requests are rejected with a 401. Authentication is off when
+    /// empty. The admin token is accepted as well.
+    #[serde(default)]
+    pub auth_tokens: HashSet<String>,
+
+    /// Per-client rate limiting of the requests, off when `None`
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+
+    /// Formats the JSON-RPC API speaks on top of JSON, JSON and CBOR by
+    /// default. Not deserialized, custom formats are set in code.
+    #[serde(skip)]
+    pub wire_formats: WireFormats,
+}
+
+/// Token-bucket rate limiting of the requests of each client IP. Requests
+/// over the limit are rejected with a 429 and a `Retry-After` header.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RateLimitConfig {
+    /// Rate at which each client regains requests, must be above 0
+    pub requests_per_second: u32,
+
+    /// Number of requests a client can burst, defaults to `requests_per_second`
+    #[serde(default)]
+    pub burst: u32,
+
+    /// Header holding the client IP, e.g. `X-Forwarded-For` behind a proxy.
+    /// The first address of the header is used, or the peer address when the
+    /// header is missing or invalid. Only set it behind a proxy overwriting
+    /// the header, clients could pick their IP otherwise.
+    #[serde(default)]
+    pub client_ip_header: Option<String>,
+
+    /// Client IPs exempt from the rate limiting
+    #[serde(default)]
+    pub exempt_ips: HashSet<IpAddr>,
+}
+
+impl AggregatorConfig {
+    /// Creates a configuration signing with `wallet`, with default values for
+    /// everything else
+    pub fn new(wallet: PrivateKeySigner) -> Self {
+        Self {
+            wallet,
+            port: DEFAULT_PORT,
+            accepted_addresses: HashSet::new(),
+            domain_chain_id: DEFAULT_DOMAIN_CHAIN_ID,
+            domain_verifying_contract: Address::ZERO,
+            max_request_body_size: DEFAULT_MAX_REQUEST_BODY_SIZE,
+            max_response_body_size: DEFAULT_MAX_RESPONSE_BODY_SIZE,
+            max_concurrent_connections: DEFAULT_MAX_CONCURRENT_CONNECTIONS,
+            receipt_submission_timeout: DEFAULT_RECEIPT_SUBMISSION_TIMEOUT,
+            rav_signing_timeout: DEFAULT_RAV_SIGNING_TIMEOUT,
+            enabled_methods: None,
+            admin_token: None,
+            auth_tokens: HashSet::new(),
+            rate_limit: None,
+            wire_formats: WireFormats::default(),
+        }
+    }
+
+    /// Returns the EIP-712 domain separator built from the domain settings
+    pub fn domain_separator(&self) -> Eip712Domain {
+        tap_eip712_domain(self.domain_chain_id, self.domain_verifying_contract)
+    }
+
+    /// Returns the accepted signers, including the wallet address
+    pub fn all_accepted_addresses(&self) -> HashSet<Address> {
+        let mut accepted_addresses = self.accepted_addresses.clone();
+        accepted_addresses.insert(self.wallet.address());
+        accepted_addresses
+    }
+}
+
+// Not derived, to keep the private key out of the logs
+impl std::fmt::Debug for AggregatorConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AggregatorConfig")
+            .field("wallet", &self.wallet.address())
+            .field("port", &self.port)
+            .field("accepted_addresses", &self.accepted_addresses)
+            .field("domain_chain_id", &self.domain_chain_id)
+            .field("domain_verifying_contract", &self.domain_verifying_contract)
+            .field("max_request_body_size", &self.max_request_body_size)
+            .field("max_response_body_size", &self.max_response_body_size)
+            .field(
+                "max_concurrent_connections",
+                &self.max_concurrent_connections,
+            )
+            .field(
+                "receipt_submission_timeout",
+                &self.receipt_submission_timeout,
+            )
+            .field("rav_signing_timeout", &self.rav_signing_timeout)
+            .field("enabled_methods", &self.enabled_methods)
+            .field(
+                "admin_token",
+                &self.admin_token.as_ref().map(|_| "<redacted>"),
+            )
+            .field(
+                "auth_tokens",
+                &format!("<{} redacted>", self.auth_tokens.len()),
+            )
+            .field("rate_limit", &self.rate_limit)
+            .field("wire_formats", &self.wire_formats)
+            .finish()
+    }
+}
+
+fn deserialize_wallet<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<PrivateKeySigner, D::Error> {
+    let private_key = String::deserialize(deserializer)?;
+    PrivateKeySigner::from_str(&private_key).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_millis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_millis)
+}
+
+fn default_port() -> u16 {
+    DEFAULT_PORT
+}
+
+fn default_domain_chain_id() -> u64 {
+    DEFAULT_DOMAIN_CHAIN_ID
+}
+
+fn default_max_request_body_size() -> u32 {
+    DEFAULT_MAX_REQUEST_BODY_SIZE
+}
+
+fn default_max_response_body_size() -> u32 {
+    DEFAULT_MAX_RESPONSE_BODY_SIZE
+}
+
+fn default_max_concurrent_connections() -> u32 {
+    DEFAULT_MAX_CONCURRENT_CONNECTIONS
+}
+
+fn default_receipt_submission_timeout() -> Duration {
+    DEFAULT_RECEIPT_SUBMISSION_TIMEOUT
+}
+
+fn default_rav_signing_timeout() -> Duration {
+    DEFAULT_RAV_SIGNING_TIMEOUT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialize_with_defaults() {
+        let wallet = PrivateKeySigner::random();
+        let config: AggregatorConfig = serde_json::from_value(serde_json::json!({
+            "private_key": wallet.to_bytes().to_string(),
+            "port": 9000,
+            "rav_signing_timeout_ms": 1500,
+        }))
+        .unwrap();
+
+        assert_eq!(config.wallet.address(), wallet.address());
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.max_request_body_size, DEFAULT_MAX_REQUEST_BODY_SIZE);
+        assert_eq!(
+            config.receipt_submission_timeout,
+            DEFAULT_RECEIPT_SUBMISSION_TIMEOUT
+        );
+        assert_eq!(config.rav_signing_timeout, Duration::from_millis(1500));
+        assert_eq!(config.enabled_methods, None);
+        assert_eq!(config.admin_token, None);
+        assert!(config.auth_tokens.is_empty());
+        assert!(config.rate_limit.is_none());
+        assert_eq!(
+            config.all_accepted_addresses(),
+            HashSet::from([wallet.address()])
+        );
+        assert_eq!(
+            config.domain_separator(),
+            tap_eip712_domain(DEFAULT_DOMAIN_CHAIN_ID, Address::ZERO)
+        );
+        assert!(!format!("{config:?}").contains(&wallet.to_bytes().to_string()[2..]));
+    }
+
+    #[test]
+    fn private_key_is_required() {
+        assert!(serde_json::from_value::<AggregatorConfig>(serde_json::json!({})).is_err());
+    }
+}
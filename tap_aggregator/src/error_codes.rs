@@ -13,12 +13,19 @@
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum JsonRpcErrorCode {
     /// -32000 -- Generic error.
-    #[allow(dead_code)]
     Generic = -32000,
     /// -32001 -- Invalid API version.
     InvalidVersion = -32001,
     /// -32002 -- Error during receipt aggregation.
     Aggregation = -32002,
+    /// -32003 -- The method needs receipt storage, which this server doesn't have.
+    StorageNotConfigured = -32003,
+    /// -32004 -- The request didn't complete within the configured timeout.
+    Timeout = -32004,
+    /// -32005 -- The admin method was called without the configured admin token.
+    Unauthorized = -32005,
+    /// -32006 -- The receipt storage is full, receipts are accepted again once space frees up.
+    StorageQuotaExceeded = -32006,
 }
 
 /// JSON-RPC warning codes
@@ -3,8 +3,11 @@
 
 pub mod aggregator;
 pub mod api_versioning;
+pub mod config;
 pub mod error_codes;
 pub mod grpc;
 pub mod jsonrpsee_helpers;
 pub mod metrics;
+pub mod rate_limit;
 pub mod server;
+pub mod wire_format;
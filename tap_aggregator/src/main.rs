@@ -3,16 +3,19 @@
 
 #![doc = include_str!("../README.md")]
 
-use std::{collections::HashSet, str::FromStr};
+use std::{net::IpAddr, str::FromStr, time::Duration};
 
-use alloy::{dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner};
+use alloy::{primitives::Address, signers::local::PrivateKeySigner};
 use anyhow::Result;
 use clap::Parser;
 use log::{debug, info};
-use tap_aggregator::{metrics, server};
-use tap_core::tap_eip712_domain;
+use tap_aggregator::{
+    config::{AggregatorConfig, RateLimitConfig},
+    metrics,
+    server::Server,
+};
 
-#[derive(Parser, Debug)]
+#[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// Port to listen on for JSON-RPC requests.
@@ -47,6 +50,56 @@ struct Args {
     #[arg(long, default_value_t = 32, env = "TAP_MAX_CONNECTIONS")]
     max_connections: u32,
 
+    /// Time allowed to receipt submission and check requests, in milliseconds.
+    /// Defaults to 10s.
+    #[arg(
+        long,
+        default_value_t = 10_000,
+        env = "TAP_RECEIPT_SUBMISSION_TIMEOUT_MS"
+    )]
+    receipt_submission_timeout_ms: u64,
+
+    /// Time allowed to aggregate and sign a RAV, in milliseconds.
+    /// Defaults to 30s.
+    #[arg(long, default_value_t = 30_000, env = "TAP_RAV_SIGNING_TIMEOUT_MS")]
+    rav_signing_timeout_ms: u64,
+
+    /// JSON-RPC methods to serve, the others are answered with a method-not-found error.
+    /// Expects a comma-separated list of method names. Defaults to all the methods.
+    #[arg(long, value_delimiter = ',', env = "TAP_ENABLED_METHODS")]
+    enabled_methods: Option<Vec<String>>,
+
+    /// Token required in the `Authorization: Bearer` header of the admin JSON-RPC methods.
+    /// The admin methods are disabled when not set.
+    #[arg(long, env = "TAP_ADMIN_TOKEN")]
+    admin_token: Option<String>,
+
+    /// Tokens accepted in the `Authorization: Bearer` header of every request, the others are
+    /// rejected with a 401. Expects a comma-separated list of tokens. Authentication is off when
+    /// not set.
+    #[arg(long, value_delimiter = ',', env = "TAP_AUTH_TOKENS")]
+    auth_tokens: Option<Vec<String>>,
+
+    /// Requests per second allowed to each client IP, the excess is rejected with a 429.
+    /// Rate limiting is off when not set.
+    #[arg(long, env = "TAP_RATE_LIMIT_PER_SECOND")]
+    rate_limit_per_second: Option<u32>,
+
+    /// Number of requests a client IP can burst over the rate limit.
+    /// Defaults to the requests per second.
+    #[arg(long, env = "TAP_RATE_LIMIT_BURST")]
+    rate_limit_burst: Option<u32>,
+
+    /// Header holding the client IP for the rate limiting, e.g. `X-Forwarded-For` behind a proxy.
+    /// Defaults to the peer address of the connection.
+    #[arg(long, env = "TAP_RATE_LIMIT_CLIENT_IP_HEADER")]
+    rate_limit_client_ip_header: Option<String>,
+
+    /// Client IPs exempt from the rate limiting.
+    /// Expects a comma-separated list of IP addresses.
+    #[arg(long, value_delimiter = ',', env = "TAP_RATE_LIMIT_EXEMPT_IPS")]
+    rate_limit_exempt_ips: Option<Vec<IpAddr>>,
+
     /// Metrics server port.
     /// Defaults to 5000.
     #[arg(long, default_value_t = 5000, env = "TAP_METRICS_PORT")]
@@ -73,6 +126,50 @@ struct Args {
     domain_salt: Option<String>,
 }
 
+// Not derived, to keep the private key and the tokens out of the logs
+impl std::fmt::Debug for Args {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Args")
+            .field("port", &self.port)
+            .field("private_key", &"<redacted>")
+            .field("public_keys", &self.public_keys)
+            .field("max_request_body_size", &self.max_request_body_size)
+            .field("max_response_body_size", &self.max_response_body_size)
+            .field("max_connections", &self.max_connections)
+            .field(
+                "receipt_submission_timeout_ms",
+                &self.receipt_submission_timeout_ms,
+            )
+            .field("rav_signing_timeout_ms", &self.rav_signing_timeout_ms)
+            .field("enabled_methods", &self.enabled_methods)
+            .field(
+                "admin_token",
+                &self.admin_token.as_ref().map(|_| "<redacted>"),
+            )
+            .field(
+                "auth_tokens",
+                &self
+                    .auth_tokens
+                    .as_ref()
+                    .map(|tokens| format!("<{} redacted>", tokens.len())),
+            )
+            .field("rate_limit_per_second", &self.rate_limit_per_second)
+            .field("rate_limit_burst", &self.rate_limit_burst)
+            .field(
+                "rate_limit_client_ip_header",
+                &self.rate_limit_client_ip_header,
+            )
+            .field("rate_limit_exempt_ips", &self.rate_limit_exempt_ips)
+            .field("metrics_port", &self.metrics_port)
+            .field("domain_name", &self.domain_name)
+            .field("domain_version", &self.domain_version)
+            .field("domain_chain_id", &self.domain_chain_id)
+            .field("domain_verifying_contract", &self.domain_verifying_contract)
+            .field("domain_salt", &self.domain_salt)
+            .finish()
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // Initialize the logger.
@@ -94,28 +191,43 @@ async fn main() -> Result<()> {
 
     info!("Wallet address: {:#40x}", wallet.address());
 
-    // Create the EIP-712 domain separator.
-    let domain_separator = create_eip712_domain(&args)?;
+    let mut config = AggregatorConfig::new(wallet);
+    config.port = args.port;
+    // The wallet address is always accepted, on top of these
+    config.accepted_addresses = args.public_keys.iter().flatten().cloned().collect();
 
-    // Create HashSet of *all* allowed signers
-    let mut accepted_addresses: HashSet<Address> = std::collections::HashSet::new();
-    accepted_addresses.insert(wallet.address().0.into());
-    if let Some(public_keys) = &args.public_keys {
-        accepted_addresses.extend(public_keys.iter().cloned());
+    // Settings of the EIP-712 domain separator.
+    if let Some(domain_chain_id) = &args.domain_chain_id {
+        debug!("Parsing domain chain ID...");
+        config.domain_chain_id = domain_chain_id.parse()?;
     }
+    if args.domain_salt.is_some() {
+        debug!("Parsing domain salt...");
+    }
+    config.domain_verifying_contract = args.domain_verifying_contract.unwrap_or_default();
+
+    config.max_request_body_size = args.max_request_body_size;
+    config.max_response_body_size = args.max_response_body_size;
+    config.max_concurrent_connections = args.max_connections;
+    config.receipt_submission_timeout = Duration::from_millis(args.receipt_submission_timeout_ms);
+    config.rav_signing_timeout = Duration::from_millis(args.rav_signing_timeout_ms);
+    config.enabled_methods = args
+        .enabled_methods
+        .map(|methods| methods.into_iter().collect());
+    config.admin_token = args.admin_token;
+    config.auth_tokens = args.auth_tokens.into_iter().flatten().collect();
+    config.rate_limit = args
+        .rate_limit_per_second
+        .map(|requests_per_second| RateLimitConfig {
+            requests_per_second,
+            burst: args.rate_limit_burst.unwrap_or(requests_per_second),
+            client_ip_header: args.rate_limit_client_ip_header,
+            exempt_ips: args.rate_limit_exempt_ips.into_iter().flatten().collect(),
+        });
 
     // Start the JSON-RPC server.
     // This await is non-blocking
-    let (handle, _) = server::run_server(
-        args.port,
-        wallet,
-        accepted_addresses,
-        domain_separator,
-        args.max_request_body_size,
-        args.max_response_body_size,
-        args.max_connections,
-    )
-    .await?;
+    let Server { handle, .. } = Server::from_config(config).await?;
     info!("Server started. Listening on port {}.", args.port);
 
     let _ = handle.await;
@@ -124,31 +236,3 @@ async fn main() -> Result<()> {
     info!("Shutting down...");
     Ok(())
 }
-
-fn create_eip712_domain(args: &Args) -> Result<Eip712Domain> {
-    // Transfrom the args into the types expected by Eip712Domain::new().
-
-    // Transform optional strings into optional Cow<str>.
-    // Transform optional strings into optional U256.
-    if args.domain_chain_id.is_some() {
-        debug!("Parsing domain chain ID...");
-    }
-    let chain_id: Option<u64> = args
-        .domain_chain_id
-        .as_ref()
-        .map(|s| s.parse())
-        .transpose()?;
-
-    if args.domain_salt.is_some() {
-        debug!("Parsing domain salt...");
-    }
-
-    // Transform optional strings into optional Address.
-    let verifying_contract: Option<Address> = args.domain_verifying_contract;
-
-    // Create the EIP-712 domain separator.
-    Ok(tap_eip712_domain(
-        chain_id.unwrap_or(1),
-        verifying_contract.unwrap_or_default(),
-    ))
-}
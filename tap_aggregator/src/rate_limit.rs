@@ -0,0 +1,168 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Per-client rate limiting of the aggregator server, see
+//! [`crate::config::RateLimitConfig`].
+
+use std::{collections::HashMap, net::IpAddr, sync::Mutex, time::Duration};
+
+use hyper::HeaderMap;
+// follows the tokio clock, so that tests can pause and advance it
+use tokio::time::Instant;
+
+use crate::config::RateLimitConfig;
+
+/// Number of tracked clients above which the full buckets are dropped, to
+/// bound the memory used by one-off clients
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Token bucket of a single client
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token-bucket rate limiter keyed by client IP
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Creates a rate limiter, `config.requests_per_second` must not be 0
+    pub fn new(mut config: RateLimitConfig) -> Self {
+        if config.burst == 0 {
+            config.burst = config.requests_per_second;
+        }
+        Self {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the IP a request is accounted to: the first address of the
+    /// configured header when it is set and valid, the peer address otherwise
+    pub fn client_ip(&self, headers: &HeaderMap, peer_ip: IpAddr) -> IpAddr {
+        self.config
+            .client_ip_header
+            .as_ref()
+            .and_then(|header| headers.get(header))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .and_then(|ip| ip.trim().parse().ok())
+            .unwrap_or(peer_ip)
+    }
+
+    /// Takes a token from the bucket of `ip`. Returns the time to wait for the
+    /// next token when the bucket is empty.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        if self.config.exempt_ips.contains(&ip) {
+            return Ok(());
+        }
+        let rate = self.config.requests_per_second as f64;
+        let burst = self.config.burst as f64;
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.last_refill).as_secs_f64() * rate < burst
+            });
+        }
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            last_refill: now,
+        });
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.last_refill).as_secs_f64() * rate)
+            .min(burst);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use hyper::header::HeaderValue;
+
+    use super::*;
+
+    fn rate_limiter(exempt_ips: HashSet<IpAddr>) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            requests_per_second: 1,
+            burst: 2,
+            client_ip_header: Some("x-forwarded-for".to_string()),
+            exempt_ips,
+        })
+    }
+
+    #[test]
+    fn client_ip_from_header() {
+        let rate_limiter = rate_limiter(HashSet::new());
+        let peer_ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(rate_limiter.client_ip(&headers, peer_ip), peer_ip);
+
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("203.0.113.7, 10.0.0.2"),
+        );
+        assert_eq!(
+            rate_limiter.client_ip(&headers, peer_ip),
+            "203.0.113.7".parse::<IpAddr>().unwrap()
+        );
+
+        headers.insert("x-forwarded-for", HeaderValue::from_static("garbage"));
+        assert_eq!(rate_limiter.client_ip(&headers, peer_ip), peer_ip);
+    }
+
+    #[test]
+    fn buckets_per_ip() {
+        let exempt_ip: IpAddr = "10.0.0.3".parse().unwrap();
+        let rate_limiter = rate_limiter(HashSet::from([exempt_ip]));
+        let ip_a: IpAddr = "10.0.0.1".parse().unwrap();
+        let ip_b: IpAddr = "10.0.0.2".parse().unwrap();
+
+        assert!(rate_limiter.check(ip_a).is_ok());
+        assert!(rate_limiter.check(ip_a).is_ok());
+        let retry_after = rate_limiter.check(ip_a).unwrap_err();
+        assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_secs(1));
+
+        // other clients have their own bucket
+        assert!(rate_limiter.check(ip_b).is_ok());
+        for _ in 0..10 {
+            assert!(rate_limiter.check(exempt_ip).is_ok());
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tokens_refill_over_time() {
+        let rate_limiter = rate_limiter(HashSet::new());
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        assert!(rate_limiter.check(ip).is_ok());
+        assert!(rate_limiter.check(ip).is_ok());
+        assert_eq!(rate_limiter.check(ip), Err(Duration::from_secs(1)));
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert_eq!(rate_limiter.check(ip), Err(Duration::from_millis(500)));
+
+        // a token is back after a second, the burst after two
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert!(rate_limiter.check(ip).is_ok());
+        assert!(rate_limiter.check(ip).is_err());
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(rate_limiter.check(ip).is_ok());
+        assert!(rate_limiter.check(ip).is_ok());
+        assert!(rate_limiter.check(ip).is_err());
+    }
+}
@@ -1,24 +1,42 @@
 // Copyright 2023-, Semiotic AI, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::{collections::HashSet, str::FromStr};
+use std::{collections::HashSet, str::FromStr, sync::Arc, time::Duration};
 
 use alloy::{dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner};
 use anyhow::Result;
-use axum::{error_handling::HandleError, routing::post_service, BoxError, Router};
+use axum::{
+    error_handling::HandleError,
+    extract::{ConnectInfo, Request as HttpRequest},
+    middleware::Next,
+    response::IntoResponse,
+    routing::post_service,
+    BoxError, Router,
+};
+use futures_util::{future, stream, StreamExt};
 use hyper::StatusCode;
 use jsonrpsee::{
     proc_macros::rpc,
-    server::{ServerBuilder, ServerHandle, TowerService},
+    server::{Extensions, ServerBuilder, ServerHandle, TowerService},
 };
 use lazy_static::lazy_static;
 use log::info;
 use prometheus::{register_counter, register_int_counter, Counter, IntCounter};
-use tap_core::signed_message::Eip712SignedMessage;
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use tap_core::{
+    manager::{
+        adapters::{RavRead, ReceiptStore},
+        context::memory::InMemoryContext,
+        CheckWarning, Manager,
+    },
+    receipt::{Context, ReceiptError},
+    signed_message::Eip712SignedMessage,
+};
 use tap_graph::{Receipt, ReceiptAggregateVoucher, SignedReceipt};
 use tokio::{net::TcpListener, signal, task::JoinHandle};
 use tonic::{codec::CompressionEncoding, service::Routes, Request, Response, Status};
-use tower::{layer::util::Identity, make::Shared};
+use tower::layer::util::Identity;
 
 use crate::{
     aggregator,
@@ -26,9 +44,12 @@ use crate::{
         tap_rpc_api_versions_info, TapRpcApiVersion, TapRpcApiVersionsInfo,
         TAP_RPC_API_VERSIONS_DEPRECATED,
     },
+    config::AggregatorConfig,
     error_codes::{JsonRpcErrorCode, JsonRpcWarningCode},
     grpc::{v1, v2},
     jsonrpsee_helpers::{JsonRpcError, JsonRpcResponse, JsonRpcResult, JsonRpcWarning},
+    rate_limit::RateLimiter,
+    wire_format::{self, WireFormats},
 };
 
 // Register the metrics into the global metrics registry.
@@ -53,6 +74,11 @@ lazy_static! {
         "Number of API version errors sent to clients."
     )
     .unwrap();
+    static ref RATE_LIMITED_REQUESTS: IntCounter = register_int_counter!(
+        "rate_limited_requests_count",
+        "Number of requests rejected by the rate limiting."
+    )
+    .unwrap();
     static ref TOTAL_AGGREGATED_RECEIPTS: IntCounter = register_int_counter!(
         "total_aggregated_receipts",
         "Total number of receipts successfully aggregated."
@@ -64,8 +90,100 @@ lazy_static! {
         "Total successfully aggregated GRT value (wei)."
     )
     .unwrap();
+    static ref TOTAL_STORED_RECEIPTS: IntCounter = register_int_counter!(
+        "total_stored_receipts",
+        "Total number of submitted receipts that passed the checks and were stored."
+    )
+    .unwrap();
+    static ref REQUEST_TIMEOUT_COUNT: IntCounter = register_int_counter!(
+        "request_timeout_count",
+        "Number of requests that did not complete within their timeout."
+    )
+    .unwrap();
+}
+
+/// Manager backing the JSON-RPC methods that need receipt storage
+pub type ReceiptManager<E = InMemoryContext> = Manager<E, SignedReceipt>;
+
+/// Storage a [`ReceiptManager`] needs to back the JSON-RPC methods: storing
+/// receipts and reading the latest RAVs. Implemented for every context
+/// providing both, e.g. [`InMemoryContext`] or a database-backed context.
+pub trait ManagerContext:
+    ReceiptStore<SignedReceipt> + RavRead<ReceiptAggregateVoucher> + Send + Sync + 'static
+{
+}
+
+impl<E> ManagerContext for E where
+    E: ReceiptStore<SignedReceipt> + RavRead<ReceiptAggregateVoucher> + Send + Sync + 'static
+{
+}
+
+/// Outcome of a single receipt submitted through `tap_submit_receipts`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ReceiptSubmissionResult {
+    /// Whether the receipt passed all checks and was stored
+    pub accepted: bool,
+    /// Reason the receipt was rejected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Whether the rejected receipt may be accepted if submitted again later.
+    /// Permanent failures, e.g. an invalid signature, need the receipt fixed.
+    #[serde(default)]
+    pub retryable: bool,
+    /// Advisory checks the accepted receipt failed, see
+    /// [`CheckSeverity::Warn`](tap_core::receipt::checks::CheckSeverity::Warn)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<ReceiptWarning>,
 }
 
+/// Advisory check failed by a receipt that was still stored
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ReceiptWarning {
+    /// Name of the failed check
+    pub check: String,
+    /// Why the check failed
+    pub message: String,
+}
+
+/// Outcome of a receipt probed through `tap_check_receipt`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReceiptCheckResult {
+    /// Whether the receipt passed all checks
+    pub valid: bool,
+    /// First check the receipt failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<ReceiptError>,
+}
+
+/// Manager check listed by `admin_list_checks`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CheckStatus {
+    /// Name of the check
+    pub name: String,
+    /// Whether the check runs, see `admin_set_check_enabled`
+    pub enabled: bool,
+}
+
+/// State of the receipt manager returned by `admin_stats`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AdminStats {
+    /// Whether receipt ingestion is paused
+    pub paused: bool,
+    /// Number of stored receipts not yet aggregated into a RAV
+    pub pending_receipts: u64,
+    /// Number of shadow check failures
+    pub shadow_check_failures: u64,
+    /// Number of receipts accepted below the minimum timestamp
+    pub below_min_timestamp_receipts: u64,
+    /// Number of receipts rejected for lack of escrow
+    pub escrow_insufficient_rejections: u64,
+}
+
+/// Bearer token of the `Authorization` header of an HTTP request, handed to
+/// the JSON-RPC methods through the request extensions
+#[derive(Clone)]
+struct BearerToken(String);
+
 /// Generates the `RpcServer` trait that is used to define the JSON-RPC API.
 ///
 /// Note that because of the way the `rpc` macro works, we cannot document the RpcServer trait here.
@@ -81,19 +199,149 @@ pub trait Rpc {
     /// Aggregates the given receipts into a receipt aggregate voucher.
     /// Returns an error if the user expected API version is not supported.
     #[method(name = "aggregate_receipts")]
-    fn aggregate_receipts(
+    async fn aggregate_receipts(
         &self,
         api_version: String,
         receipts: Vec<Eip712SignedMessage<Receipt>>,
         previous_rav: Option<Eip712SignedMessage<ReceiptAggregateVoucher>>,
     ) -> JsonRpcResult<Eip712SignedMessage<ReceiptAggregateVoucher>>;
+
+    /// Checks and stores a batch of receipts, returning one result per receipt.
+    /// Returns an error if the server was started without receipt storage, or
+    /// if the receipt storage is full, with the results as error data.
+    #[method(name = "tap_submit_receipts")]
+    async fn submit_receipts(
+        &self,
+        receipts: Vec<Eip712SignedMessage<Receipt>>,
+    ) -> JsonRpcResult<Vec<ReceiptSubmissionResult>>;
+
+    /// Returns the latest RAV stored for `sender` and `allocation_id`, if any.
+    /// Returns an error if the server was started without receipt storage.
+    #[method(name = "tap_latest_rav")]
+    async fn latest_rav(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+    ) -> JsonRpcResult<Option<Eip712SignedMessage<ReceiptAggregateVoucher>>>;
+
+    /// Checks the given receipt without storing it.
+    #[method(name = "tap_check_receipt")]
+    async fn check_receipt(
+        &self,
+        receipt: Eip712SignedMessage<Receipt>,
+    ) -> JsonRpcResult<ReceiptCheckResult>;
+
+    /// Lists the checks of the receipt manager and whether they are enabled.
+    /// Requires the admin token.
+    #[method(name = "admin_list_checks", with_extensions)]
+    async fn admin_list_checks(&self) -> JsonRpcResult<Vec<CheckStatus>>;
+
+    /// Enables or disables a check of the receipt manager.
+    /// Requires the admin token.
+    #[method(name = "admin_set_check_enabled", with_extensions)]
+    async fn admin_set_check_enabled(&self, check_name: String, enabled: bool)
+        -> JsonRpcResult<()>;
+
+    /// Pauses (`true`) or resumes (`false`) receipt ingestion.
+    /// Requires the admin token.
+    #[method(name = "admin_pause", with_extensions)]
+    async fn admin_pause(&self, paused: bool) -> JsonRpcResult<()>;
+
+    /// Returns the state of the receipt manager.
+    /// Requires the admin token.
+    #[method(name = "admin_stats", with_extensions)]
+    async fn admin_stats(&self) -> JsonRpcResult<AdminStats>;
 }
 
-#[derive(Clone)]
-struct RpcImpl {
+struct RpcImpl<E> {
     wallet: PrivateKeySigner,
     accepted_addresses: HashSet<Address>,
     domain_separator: Eip712Domain,
+    manager: Option<Arc<ReceiptManager<E>>>,
+    /// `None` leaves the requests unbounded
+    receipt_submission_timeout: Option<Duration>,
+    /// `None` leaves the aggregations unbounded
+    rav_signing_timeout: Option<Duration>,
+    admin_token: Option<String>,
+}
+
+// derived `Clone` would require `E: Clone`, the manager is shared instead
+impl<E> Clone for RpcImpl<E> {
+    fn clone(&self) -> Self {
+        Self {
+            wallet: self.wallet.clone(),
+            accepted_addresses: self.accepted_addresses.clone(),
+            domain_separator: self.domain_separator.clone(),
+            manager: self.manager.clone(),
+            receipt_submission_timeout: self.receipt_submission_timeout,
+            rav_signing_timeout: self.rav_signing_timeout,
+            admin_token: self.admin_token.clone(),
+        }
+    }
+}
+
+impl<E: ManagerContext> RpcImpl<E> {
+    /// Returns the receipt manager, or an error if the server doesn't store receipts.
+    fn manager(&self) -> Result<&Arc<ReceiptManager<E>>, JsonRpcError> {
+        self.manager.as_ref().ok_or_else(|| {
+            jsonrpsee::types::ErrorObject::owned(
+                JsonRpcErrorCode::StorageNotConfigured as i32,
+                "This aggregator is not configured to store receipts.",
+                None::<()>,
+            )
+        })
+    }
+
+    /// Returns the receipt manager if the request carries the admin token, or
+    /// an error if it doesn't or if no admin token is configured.
+    fn admin_manager(&self, ext: &Extensions) -> Result<&Arc<ReceiptManager<E>>, JsonRpcError> {
+        let unauthorized = |message: &str| {
+            jsonrpsee::types::ErrorObject::owned(
+                JsonRpcErrorCode::Unauthorized as i32,
+                message,
+                None::<()>,
+            )
+        };
+        let admin_token = self
+            .admin_token
+            .as_deref()
+            .ok_or_else(|| unauthorized("The admin methods are disabled on this aggregator."))?;
+        match ext.get::<BearerToken>() {
+            Some(BearerToken(token)) if tokens_match(token, admin_token) => self.manager(),
+            _ => Err(unauthorized("Missing or invalid admin token.")),
+        }
+    }
+
+    /// Runs the CPU bound `aggregate` on the blocking thread pool, giving up
+    /// after the RAV signing timeout. The aggregation itself can't be
+    /// interrupted and finishes in the background, but the request returns.
+    /// Returns the timeout that elapsed as an error.
+    async fn aggregate_with_timeout<T: Send + 'static>(
+        &self,
+        aggregate: impl FnOnce(&RpcImpl<E>) -> T + Send + 'static,
+    ) -> Result<T, Duration> {
+        let rpc_impl = self.clone();
+        let aggregation = tokio::task::spawn_blocking(move || aggregate(&rpc_impl));
+        let res = match self.rav_signing_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, aggregation)
+                .await
+                .map_err(|_| {
+                    REQUEST_TIMEOUT_COUNT.inc();
+                    timeout
+                })?,
+            None => aggregation.await,
+        };
+        Ok(res.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic())))
+    }
+}
+
+/// Error returned when `method` didn't complete within `timeout`.
+fn timeout_error(method: &str, timeout: Duration) -> JsonRpcError {
+    jsonrpsee::types::ErrorObject::owned(
+        JsonRpcErrorCode::Timeout as i32,
+        format!("{method} did not complete within {timeout:?}"),
+        None::<()>,
+    )
 }
 
 /// Helper method that checks if the given API version is supported.
@@ -172,7 +420,7 @@ fn aggregate_receipts_(
 }
 
 #[tonic::async_trait]
-impl v1::tap_aggregator_server::TapAggregator for RpcImpl {
+impl<E: ManagerContext> v1::tap_aggregator_server::TapAggregator for RpcImpl<E> {
     async fn aggregate_receipts(
         &self,
         request: Request<v1::RavRequest>,
@@ -194,13 +442,25 @@ impl v1::tap_aggregator_server::TapAggregator for RpcImpl {
         let receipts_grt: u128 = receipts.iter().map(|r| r.message.value).sum();
         let receipts_count: u64 = receipts.len() as u64;
 
-        match aggregator::v1::check_and_aggregate_receipts(
-            &self.domain_separator,
-            receipts.as_slice(),
-            previous_rav,
-            &self.wallet,
-            &self.accepted_addresses,
-        ) {
+        let res = self
+            .aggregate_with_timeout(move |rpc_impl| {
+                aggregator::v1::check_and_aggregate_receipts(
+                    &rpc_impl.domain_separator,
+                    receipts.as_slice(),
+                    previous_rav,
+                    &rpc_impl.wallet,
+                    &rpc_impl.accepted_addresses,
+                )
+            })
+            .await
+            .map_err(|timeout| {
+                AGGREGATION_FAILURE_COUNTER.inc();
+                Status::deadline_exceeded(format!(
+                    "RAV signing did not complete within {timeout:?}"
+                ))
+            })?;
+
+        match res {
             Ok(res) => {
                 TOTAL_GRT_AGGREGATED.inc_by(receipts_grt as f64);
                 TOTAL_AGGREGATED_RECEIPTS.inc_by(receipts_count);
@@ -220,7 +480,7 @@ impl v1::tap_aggregator_server::TapAggregator for RpcImpl {
 }
 
 #[tonic::async_trait]
-impl v2::tap_aggregator_server::TapAggregator for RpcImpl {
+impl<E: ManagerContext> v2::tap_aggregator_server::TapAggregator for RpcImpl<E> {
     async fn aggregate_receipts(
         &self,
         request: Request<v2::RavRequest>,
@@ -242,13 +502,25 @@ impl v2::tap_aggregator_server::TapAggregator for RpcImpl {
         let receipts_grt: u128 = receipts.iter().map(|r| r.message.value).sum();
         let receipts_count: u64 = receipts.len() as u64;
 
-        match aggregator::v2::check_and_aggregate_receipts(
-            &self.domain_separator,
-            receipts.as_slice(),
-            previous_rav,
-            &self.wallet,
-            &self.accepted_addresses,
-        ) {
+        let res = self
+            .aggregate_with_timeout(move |rpc_impl| {
+                aggregator::v2::check_and_aggregate_receipts(
+                    &rpc_impl.domain_separator,
+                    receipts.as_slice(),
+                    previous_rav,
+                    &rpc_impl.wallet,
+                    &rpc_impl.accepted_addresses,
+                )
+            })
+            .await
+            .map_err(|timeout| {
+                AGGREGATION_FAILURE_COUNTER.inc();
+                Status::deadline_exceeded(format!(
+                    "RAV signing did not complete within {timeout:?}"
+                ))
+            })?;
+
+        match res {
             Ok(res) => {
                 TOTAL_GRT_AGGREGATED.inc_by(receipts_grt as f64);
                 TOTAL_AGGREGATED_RECEIPTS.inc_by(receipts_count);
@@ -267,12 +539,13 @@ impl v2::tap_aggregator_server::TapAggregator for RpcImpl {
     }
 }
 
-impl RpcServer for RpcImpl {
+#[jsonrpsee::core::async_trait]
+impl<E: ManagerContext> RpcServer for RpcImpl<E> {
     fn api_versions(&self) -> JsonRpcResult<TapRpcApiVersionsInfo> {
         Ok(JsonRpcResponse::ok(tap_rpc_api_versions_info()))
     }
 
-    fn aggregate_receipts(
+    async fn aggregate_receipts(
         &self,
         api_version: String,
         receipts: Vec<Eip712SignedMessage<Receipt>>,
@@ -282,14 +555,21 @@ impl RpcServer for RpcImpl {
         let receipts_grt: u128 = receipts.iter().map(|r| r.message.value).sum();
         let receipts_count: u64 = receipts.len() as u64;
 
-        match aggregate_receipts_(
-            api_version,
-            &self.wallet,
-            &self.accepted_addresses,
-            &self.domain_separator,
-            receipts,
-            previous_rav,
-        ) {
+        let res = self
+            .aggregate_with_timeout(move |rpc_impl| {
+                aggregate_receipts_(
+                    api_version,
+                    &rpc_impl.wallet,
+                    &rpc_impl.accepted_addresses,
+                    &rpc_impl.domain_separator,
+                    receipts,
+                    previous_rav,
+                )
+            })
+            .await
+            .unwrap_or_else(|timeout| Err(timeout_error("aggregate_receipts", timeout)));
+
+        match res {
             Ok(res) => {
                 TOTAL_GRT_AGGREGATED.inc_by(receipts_grt as f64);
                 TOTAL_AGGREGATED_RECEIPTS.inc_by(receipts_count);
@@ -302,8 +582,362 @@ impl RpcServer for RpcImpl {
             }
         }
     }
+
+    async fn submit_receipts(
+        &self,
+        receipts: Vec<Eip712SignedMessage<Receipt>>,
+    ) -> JsonRpcResult<Vec<ReceiptSubmissionResult>> {
+        let manager = self.manager()?.clone();
+        let receipt_count = receipts.len();
+        // The receipts are stored by a task of their own, which the request
+        // doesn't cancel when it gives up at the deadline: a receipt dropped
+        // halfway through its checks could leave behind what the checks
+        // already claimed for it, such as its query id or escrow.
+        let (results_tx, mut results_rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let ctx = Context::new();
+            stream::iter(receipts.into_iter().enumerate())
+                .map(|(index, receipt)| {
+                    let (manager, ctx) = (&manager, &ctx);
+                    async move {
+                        let result = manager
+                            .verify_and_store_receipt_with_warnings(ctx, receipt)
+                            .await;
+                        (index, result)
+                    }
+                })
+                .buffer_unordered(manager.receipt_concurrency())
+                .for_each(|result| {
+                    // the request may have returned already
+                    let _ = results_tx.send(result);
+                    future::ready(())
+                })
+                .await;
+        });
+
+        let mut results: Vec<Option<Result<Vec<CheckWarning>, tap_core::Error>>> =
+            std::iter::repeat_with(|| None)
+                .take(receipt_count)
+                .collect();
+        let collect = async {
+            while let Some((index, result)) = results_rx.recv().await {
+                results[index] = Some(result);
+            }
+        };
+        match self.receipt_submission_timeout {
+            Some(timeout) => {
+                if tokio::time::timeout(timeout, collect).await.is_err() {
+                    REQUEST_TIMEOUT_COUNT.inc();
+                }
+            }
+            None => collect.await,
+        }
+
+        let quota_exceeded = results.iter().any(|result| {
+            matches!(
+                result,
+                Some(Err(tap_core::Error::StorageQuotaExceeded { .. }))
+            )
+        });
+        let results: Vec<_> = results
+            .into_iter()
+            .map(|result| match result {
+                // still being checked at the deadline
+                None => ReceiptSubmissionResult {
+                    accepted: false,
+                    error: Some(
+                        "The receipt was not stored before the deadline, it may still be stored"
+                            .to_string(),
+                    ),
+                    retryable: true,
+                    warnings: Vec::new(),
+                },
+                Some(Ok(warnings)) => {
+                    TOTAL_STORED_RECEIPTS.inc();
+                    ReceiptSubmissionResult {
+                        accepted: true,
+                        error: None,
+                        retryable: false,
+                        warnings: warnings
+                            .into_iter()
+                            .map(|warning| ReceiptWarning {
+                                check: warning.check_name.to_string(),
+                                message: warning.message,
+                            })
+                            .collect(),
+                    }
+                }
+                Some(Err(e)) => ReceiptSubmissionResult {
+                    accepted: false,
+                    error: Some(e.to_string()),
+                    retryable: e.is_retryable(),
+                    warnings: Vec::new(),
+                },
+            })
+            .collect();
+        if quota_exceeded {
+            // the per receipt results tell which receipts were stored before
+            // the storage filled up
+            return Err(jsonrpsee::types::ErrorObject::owned(
+                JsonRpcErrorCode::StorageQuotaExceeded as i32,
+                "The receipt storage is full, retry later.",
+                Some(results),
+            ));
+        }
+        Ok(JsonRpcResponse::ok(results))
+    }
+
+    async fn latest_rav(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+    ) -> JsonRpcResult<Option<Eip712SignedMessage<ReceiptAggregateVoucher>>> {
+        let rav = self
+            .manager()?
+            .latest_rav(sender, allocation_id)
+            .await
+            .map_err(|e| {
+                jsonrpsee::types::ErrorObject::owned(
+                    JsonRpcErrorCode::Generic as i32,
+                    e.to_string(),
+                    None::<()>,
+                )
+            })?;
+        Ok(JsonRpcResponse::ok(rav))
+    }
+
+    async fn check_receipt(
+        &self,
+        receipt: Eip712SignedMessage<Receipt>,
+    ) -> JsonRpcResult<ReceiptCheckResult> {
+        let result = match &self.manager {
+            Some(manager) => {
+                let ctx = Context::new();
+                let check = manager.check_receipt(&ctx, receipt);
+                let result = match self.receipt_submission_timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, check).await.map_err(|_| {
+                        REQUEST_TIMEOUT_COUNT.inc();
+                        timeout_error("tap_check_receipt", timeout)
+                    })?,
+                    None => check.await,
+                };
+                result.map_err(|e| match e {
+                    tap_core::Error::ReceiptError(e) => e,
+                    e => ReceiptError::CheckFailure(e.to_string()),
+                })
+            }
+            // without a manager, only the signer is checked, like when aggregating
+            None => match receipt.recover_signer(&self.domain_separator) {
+                Ok(signer) if self.accepted_addresses.contains(&signer) => Ok(()),
+                Ok(signer) => Err(ReceiptError::InvalidSignature {
+                    source_error_message: format!("Signer {signer} is not accepted"),
+                }),
+                Err(e) => Err(ReceiptError::InvalidSignature {
+                    source_error_message: e.to_string(),
+                }),
+            },
+        };
+        Ok(JsonRpcResponse::ok(ReceiptCheckResult {
+            valid: result.is_ok(),
+            reason: result.err(),
+        }))
+    }
+
+    async fn admin_list_checks(&self, ext: &Extensions) -> JsonRpcResult<Vec<CheckStatus>> {
+        let manager = self.admin_manager(ext)?;
+        let checks = manager
+            .check_names()
+            .into_iter()
+            .map(|name| CheckStatus {
+                name: name.to_string(),
+                enabled: manager.is_check_enabled(name),
+            })
+            .collect();
+        Ok(JsonRpcResponse::ok(checks))
+    }
+
+    async fn admin_set_check_enabled(
+        &self,
+        ext: &Extensions,
+        check_name: String,
+        enabled: bool,
+    ) -> JsonRpcResult<()> {
+        self.admin_manager(ext)?
+            .set_check_enabled(&check_name, enabled)
+            .map_err(|e| {
+                jsonrpsee::types::ErrorObject::owned(
+                    JsonRpcErrorCode::Generic as i32,
+                    e.to_string(),
+                    None::<()>,
+                )
+            })?;
+        info!("Check {check_name} enabled: {enabled}");
+        Ok(JsonRpcResponse::ok(()))
+    }
+
+    async fn admin_pause(&self, ext: &Extensions, paused: bool) -> JsonRpcResult<()> {
+        self.admin_manager(ext)?.set_paused(paused);
+        info!("Receipt ingestion paused: {paused}");
+        Ok(JsonRpcResponse::ok(()))
+    }
+
+    async fn admin_stats(&self, ext: &Extensions) -> JsonRpcResult<AdminStats> {
+        let manager = self.admin_manager(ext)?;
+        Ok(JsonRpcResponse::ok(AdminStats {
+            paused: manager.is_paused(),
+            pending_receipts: manager.pending_receipts(),
+            shadow_check_failures: manager.shadow_check_failures(),
+            below_min_timestamp_receipts: manager.below_min_timestamp_receipts(),
+            escrow_insufficient_rejections: manager.escrow_insufficient_rejections(),
+        }))
+    }
+}
+
+/// Returns the bearer token of the `Authorization` header, if any
+fn bearer_token(request: &HttpRequest) -> Option<&str> {
+    request
+        .headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Compares two tokens in constant time, so that the time taken doesn't leak
+/// how much of a guessed token is right
+fn tokens_match(token: &str, expected: &str) -> bool {
+    token.as_bytes().ct_eq(expected.as_bytes()).into()
+}
+
+/// Hands the bearer token of the `Authorization` header, if any, to the
+/// JSON-RPC methods
+async fn extract_bearer_token(mut request: HttpRequest, next: Next) -> axum::response::Response {
+    if let Some(token) = bearer_token(&request).map(|token| BearerToken(token.to_string())) {
+        request.extensions_mut().insert(token);
+    }
+    next.run(request).await
+}
+
+/// Rejects with a 401 the requests without one of `auth_tokens` as bearer
+/// token. gRPC clients see it as an `Unauthenticated` status.
+async fn require_auth_token(
+    auth_tokens: Arc<HashSet<String>>,
+    request: HttpRequest,
+    next: Next,
+) -> axum::response::Response {
+    match bearer_token(&request) {
+        // compare against every token rather than look the token up, so that
+        // the time taken doesn't depend on which token matched
+        Some(token)
+            if auth_tokens.iter().fold(false, |matched, expected| {
+                matched | tokens_match(token, expected)
+            }) =>
+        {
+            next.run(request).await
+        }
+        _ => (StatusCode::UNAUTHORIZED, "Missing or invalid bearer token").into_response(),
+    }
+}
+
+/// Rejects with a 429 the requests over the rate limit of their client
+async fn enforce_rate_limit(
+    rate_limiter: Arc<RateLimiter>,
+    request: HttpRequest,
+    next: Next,
+) -> axum::response::Response {
+    let Some(ConnectInfo(peer_addr)) = request
+        .extensions()
+        .get::<ConnectInfo<std::net::SocketAddr>>()
+        .copied()
+    else {
+        return next.run(request).await;
+    };
+    let client_ip = rate_limiter.client_ip(request.headers(), peer_addr.ip());
+    match rate_limiter.check(client_ip) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            RATE_LIMITED_REQUESTS.inc();
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(
+                    hyper::header::RETRY_AFTER,
+                    retry_after.as_secs_f64().ceil().max(1.0).to_string(),
+                )],
+                "Rate limit exceeded",
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Running aggregator server
+pub struct Server {
+    /// Task serving the requests, it completes on shutdown
+    pub handle: JoinHandle<()>,
+    /// Address the server listens on
+    pub local_addr: std::net::SocketAddr,
+}
+
+impl Server {
+    /// Starts a server with the settings of `config`
+    pub async fn from_config(config: AggregatorConfig) -> Result<Self> {
+        Self::start::<InMemoryContext>(config, None).await
+    }
+
+    /// Same as [`Server::from_config`], but also serves the JSON-RPC methods
+    /// that store receipts (such as `tap_submit_receipts`), backed by
+    /// `manager`. `tap_check_receipt` then runs the full check list of
+    /// `manager`.
+    pub async fn from_config_with_manager<E: ManagerContext>(
+        config: AggregatorConfig,
+        manager: Arc<ReceiptManager<E>>,
+    ) -> Result<Self> {
+        Self::start(config, Some(manager)).await
+    }
+
+    async fn start<E: ManagerContext>(
+        config: AggregatorConfig,
+        manager: Option<Arc<ReceiptManager<E>>>,
+    ) -> Result<Self> {
+        let mut auth_tokens = config.auth_tokens.clone();
+        // a single Authorization header carries the admin token, so it must
+        // get past the authentication too
+        if let (false, Some(admin_token)) = (auth_tokens.is_empty(), &config.admin_token) {
+            auth_tokens.insert(admin_token.clone());
+        }
+        if let Some(rate_limit) = &config.rate_limit {
+            anyhow::ensure!(
+                rate_limit.requests_per_second > 0,
+                "The rate limit must allow at least one request per second"
+            );
+        }
+        let rpc_impl = RpcImpl {
+            accepted_addresses: config.all_accepted_addresses(),
+            domain_separator: config.domain_separator(),
+            wallet: config.wallet,
+            manager,
+            receipt_submission_timeout: Some(config.receipt_submission_timeout),
+            rav_signing_timeout: Some(config.rav_signing_timeout),
+            admin_token: config.admin_token,
+        };
+        let (handle, local_addr) = serve(
+            rpc_impl,
+            config.port,
+            config.max_request_body_size,
+            config.max_response_body_size,
+            config.max_concurrent_connections,
+            config.enabled_methods.as_ref(),
+            auth_tokens,
+            config.rate_limit.map(RateLimiter::new),
+            config.wire_formats,
+        )
+        .await?;
+        Ok(Self { handle, local_addr })
+    }
 }
 
+/// Serves the aggregation methods only, without timeouts: see
+/// [`Server::from_config`] to set them, along with the other settings of an
+/// [`AggregatorConfig`].
 pub async fn run_server(
     port: u16,
     wallet: PrivateKeySigner,
@@ -313,17 +947,48 @@ pub async fn run_server(
     max_response_body_size: u32,
     max_concurrent_connections: u32,
 ) -> Result<(JoinHandle<()>, std::net::SocketAddr)> {
-    // Setting up the JSON RPC server
-    let rpc_impl = RpcImpl {
+    let rpc_impl = RpcImpl::<InMemoryContext> {
         wallet,
         accepted_addresses,
         domain_separator,
+        manager: None,
+        receipt_submission_timeout: None,
+        rav_signing_timeout: None,
+        admin_token: None,
     };
+    serve(
+        rpc_impl,
+        port,
+        max_request_body_size,
+        max_response_body_size,
+        max_concurrent_connections,
+        None,
+        HashSet::new(),
+        None,
+        WireFormats::default(),
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn serve<E: ManagerContext>(
+    rpc_impl: RpcImpl<E>,
+    port: u16,
+    max_request_body_size: u32,
+    max_response_body_size: u32,
+    max_concurrent_connections: u32,
+    enabled_methods: Option<&HashSet<String>>,
+    auth_tokens: HashSet<String>,
+    rate_limiter: Option<RateLimiter>,
+    wire_formats: WireFormats,
+) -> Result<(JoinHandle<()>, std::net::SocketAddr)> {
+    // Setting up the JSON RPC server
     let (json_rpc_service, _) = create_json_rpc_service(
         rpc_impl.clone(),
         max_request_body_size,
         max_response_body_size,
         max_concurrent_connections,
+        enabled_methods,
     )?;
 
     async fn handle_anyhow_error(err: BoxError) -> (StatusCode, String) {
@@ -332,32 +997,68 @@ pub async fn run_server(
             format!("Something went wrong: {err}"),
         )
     }
-    let json_rpc_router = Router::new().route_service(
-        "/",
-        HandleError::new(post_service(json_rpc_service), handle_anyhow_error),
-    );
+    let wire_formats = Arc::new(wire_formats);
+    let json_rpc_router = Router::new()
+        .route_service(
+            "/",
+            HandleError::new(post_service(json_rpc_service), handle_anyhow_error),
+        )
+        .layer(axum::middleware::from_fn(
+            move |request: HttpRequest, next: Next| {
+                wire_format::negotiate(
+                    wire_formats.clone(),
+                    max_request_body_size as usize,
+                    request,
+                    next,
+                )
+            },
+        ))
+        .layer(axum::middleware::from_fn(extract_bearer_token));
 
     let grpc_service = create_grpc_service(rpc_impl)?;
 
-    let service = tower::steer::Steer::new(
-        [json_rpc_router, grpc_service.into_axum_router()],
-        |req: &hyper::Request<_>, _services: &[_]| {
-            if req
-                .headers()
-                .get(hyper::header::CONTENT_TYPE)
-                .map(|content_type| content_type.as_bytes())
-                .filter(|content_type| content_type.starts_with(b"application/grpc"))
-                .is_some()
-            {
-                // route to the gRPC service (second service element) when the
-                // header is set
-                1
-            } else {
-                // otherwise route to the REST service
-                0
-            }
-        },
-    );
+    let mut routers = [json_rpc_router, grpc_service.into_axum_router()];
+    // authentication is off without any token
+    if !auth_tokens.is_empty() {
+        let auth_tokens = Arc::new(auth_tokens);
+        routers = routers.map(|router| {
+            let auth_tokens = auth_tokens.clone();
+            router.layer(axum::middleware::from_fn(
+                move |request: HttpRequest, next: Next| {
+                    require_auth_token(auth_tokens.clone(), request, next)
+                },
+            ))
+        });
+    }
+
+    let service = tower::steer::Steer::new(routers, |req: &hyper::Request<_>, _services: &[_]| {
+        if req
+            .headers()
+            .get(hyper::header::CONTENT_TYPE)
+            .map(|content_type| content_type.as_bytes())
+            .filter(|content_type| content_type.starts_with(b"application/grpc"))
+            .is_some()
+        {
+            // route to the gRPC service (second service element) when the
+            // header is set
+            1
+        } else {
+            // otherwise route to the REST service
+            0
+        }
+    });
+
+    // the peer address of the connections is handed to the rate limiting,
+    // which runs first to turn away excess requests as cheaply as possible
+    let mut app = Router::new().fallback_service(service);
+    if let Some(rate_limiter) = rate_limiter {
+        let rate_limiter = Arc::new(rate_limiter);
+        app = app.layer(axum::middleware::from_fn(
+            move |request: HttpRequest, next: Next| {
+                enforce_rate_limit(rate_limiter.clone(), request, next)
+            },
+        ));
+    }
 
     // Create a `TcpListener` using tokio.
     let listener = TcpListener::bind(&format!("0.0.0.0:{}", port))
@@ -366,9 +1067,12 @@ pub async fn run_server(
 
     let addr = listener.local_addr()?;
     let handle = tokio::spawn(async move {
-        if let Err(e) = axum::serve(listener, Shared::new(service))
-            .with_graceful_shutdown(shutdown_handler())
-            .await
+        if let Err(e) = axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+        )
+        .with_graceful_shutdown(shutdown_handler())
+        .await
         {
             log::error!("Tap Aggregator error: {e}");
         }
@@ -400,7 +1104,7 @@ async fn shutdown_handler() {
     info!("Signal received, starting graceful shutdown");
 }
 
-fn create_grpc_service(rpc_impl: RpcImpl) -> Result<Routes> {
+fn create_grpc_service<E: ManagerContext>(rpc_impl: RpcImpl<E>) -> Result<Routes> {
     let grpc_service = Routes::new(
         v1::tap_aggregator_server::TapAggregatorServer::new(rpc_impl.clone())
             .accept_compressed(CompressionEncoding::Zstd),
@@ -414,12 +1118,30 @@ fn create_grpc_service(rpc_impl: RpcImpl) -> Result<Routes> {
     Ok(grpc_service)
 }
 
-fn create_json_rpc_service(
-    rpc_impl: RpcImpl,
+fn create_json_rpc_service<E: ManagerContext>(
+    rpc_impl: RpcImpl<E>,
     max_request_body_size: u32,
     max_response_body_size: u32,
     max_concurrent_connections: u32,
+    enabled_methods: Option<&HashSet<String>>,
 ) -> Result<(TowerService<Identity, Identity>, ServerHandle)> {
+    let mut rpc_module = rpc_impl.into_rpc();
+    if let Some(enabled_methods) = enabled_methods {
+        let method_names = rpc_module.method_names().collect::<HashSet<_>>();
+        if let Some(unknown) = enabled_methods
+            .iter()
+            .find(|name| !method_names.contains(name.as_str()))
+        {
+            anyhow::bail!("Unknown JSON-RPC method in the enabled methods: {unknown}");
+        }
+        // removed methods are answered with a method-not-found error
+        for name in method_names {
+            if !enabled_methods.contains(name) {
+                rpc_module.remove_method(name);
+            }
+        }
+    }
+
     let service_builder = ServerBuilder::new()
         .max_request_body_size(max_request_body_size)
         .max_response_body_size(max_response_body_size)
@@ -428,23 +1150,49 @@ fn create_json_rpc_service(
         .to_service_builder();
     use jsonrpsee::server::stop_channel;
     let (stop_handle, server_handle) = stop_channel();
-    let handle = service_builder.build(rpc_impl.into_rpc(), stop_handle);
+    let handle = service_builder.build(rpc_module, stop_handle);
     Ok((handle, server_handle))
 }
 
 #[cfg(test)]
 #[allow(clippy::too_many_arguments)]
 mod tests {
-    use std::{collections::HashSet, str::FromStr};
+    use std::{
+        collections::{HashMap, HashSet},
+        str::FromStr,
+        sync::{Arc, RwLock},
+    };
 
     use alloy::{dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner};
-    use jsonrpsee::{core::client::ClientT, http_client::HttpClientBuilder, rpc_params};
+    use jsonrpsee::{
+        core::client::ClientT,
+        http_client::{HeaderMap, HeaderValue, HttpClientBuilder},
+        rpc_params,
+    };
     use rand::{prelude::*, seq::SliceRandom};
     use rstest::*;
-    use tap_core::{signed_message::Eip712SignedMessage, tap_eip712_domain};
-    use tap_graph::{Receipt, ReceiptAggregateVoucher};
+    use tap_core::{
+        manager::{
+            context::memory::{checks::get_full_list_of_checks, InMemoryContext},
+            Manager,
+        },
+        receipt::{
+            checks::{
+                Check, CheckError, CheckList, CheckResult, CheckSeverity, StatefulTimestampCheck,
+            },
+            state::Checking,
+            Context, ReceiptWithState,
+        },
+        signed_message::Eip712SignedMessage,
+        tap_eip712_domain,
+    };
+    use tap_graph::{Receipt, ReceiptAggregateVoucher, SignedReceipt};
 
-    use crate::server;
+    use crate::{
+        config::{AggregatorConfig, RateLimitConfig},
+        server,
+        wire_format::{decode_message, encode_message, Cbor, WireFormat, WireFormats},
+    };
 
     #[derive(Clone)]
     struct Keys {
@@ -544,7 +1292,7 @@ mod tests {
         let keys_0 = keys();
         let keys_1 = keys();
         // Vector of all wallets to make it easier to select one randomly
-        let all_wallets = vec![keys_main.clone(), keys_0.clone(), keys_1.clone()];
+        let all_wallets = [keys_main.clone(), keys_0.clone(), keys_1.clone()];
         // PRNG for selecting a random wallet
         let mut rng = StdRng::seed_from_u64(random_seed);
 
@@ -624,7 +1372,7 @@ mod tests {
         let keys_0 = keys();
         let keys_1 = keys();
         // Vector of all wallets to make it easier to select one randomly
-        let all_wallets = vec![keys_main.clone(), keys_0.clone(), keys_1.clone()];
+        let all_wallets = [keys_main.clone(), keys_0.clone(), keys_1.clone()];
         // PRNG for selecting a random wallet
         let mut rng = StdRng::seed_from_u64(random_seed);
 
@@ -863,4 +1611,979 @@ mod tests {
 
         handle.abort();
     }
+
+    #[rstest]
+    #[tokio::test]
+    async fn cbor_round_trip(
+        domain_separator: Eip712Domain,
+        http_request_size_limit: u32,
+        http_response_size_limit: u32,
+        http_max_concurrent_connections: u32,
+        allocation_ids: Vec<Address>,
+    ) {
+        let keys_main = keys();
+
+        let (handle, local_addr) = server::run_server(
+            0,
+            keys_main.wallet.clone(),
+            HashSet::from([keys_main.address]),
+            domain_separator.clone(),
+            http_request_size_limit,
+            http_response_size_limit,
+            http_max_concurrent_connections,
+        )
+        .await
+        .unwrap();
+
+        let mut receipts = Vec::new();
+        for value in [45, 56, 34, 23] {
+            receipts.push(
+                Eip712SignedMessage::new(
+                    &domain_separator,
+                    Receipt::new(allocation_ids[0], value).unwrap(),
+                    &keys_main.wallet,
+                )
+                .unwrap(),
+            );
+        }
+
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 0,
+            "method": "aggregate_receipts",
+            "params": ["0.0", &receipts, null],
+        });
+        let response = reqwest::Client::new()
+            .post(format!("http://127.0.0.1:{}", local_addr.port()))
+            .header(reqwest::header::CONTENT_TYPE, Cbor.mime_type())
+            .header(reqwest::header::ACCEPT, Cbor.mime_type())
+            .body(encode_message(&Cbor, &request).unwrap())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[reqwest::header::CONTENT_TYPE],
+            Cbor.mime_type()
+        );
+
+        let response: serde_json::Value =
+            decode_message(&Cbor, response.bytes().await.unwrap().as_ref()).unwrap();
+        let res: server::JsonRpcResponse<Eip712SignedMessage<ReceiptAggregateVoucher>> =
+            serde_json::from_value(response["result"].clone()).unwrap();
+
+        let local_rav =
+            ReceiptAggregateVoucher::aggregate_receipts(allocation_ids[0], &receipts, None)
+                .unwrap();
+        assert_eq!(res.data.message, local_rav);
+        assert_eq!(
+            res.data.recover_signer(&domain_separator).unwrap(),
+            keys_main.address
+        );
+
+        handle.abort();
+    }
+
+    /// JSON with its bytes reversed, a format the server only speaks once
+    /// configured with it
+    struct ReversedJson;
+
+    impl WireFormat for ReversedJson {
+        fn mime_type(&self) -> &'static str {
+            "application/x-reversed-json"
+        }
+
+        fn encode(&self, value: &serde_json::Value) -> anyhow::Result<Vec<u8>> {
+            let mut bytes = serde_json::to_vec(value)?;
+            bytes.reverse();
+            Ok(bytes)
+        }
+
+        fn decode(&self, bytes: &[u8]) -> anyhow::Result<serde_json::Value> {
+            let mut bytes = bytes.to_vec();
+            bytes.reverse();
+            Ok(serde_json::from_slice(&bytes)?)
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn custom_wire_format_from_config(allocation_ids: Vec<Address>) {
+        let keys_main = keys();
+        let mut config = AggregatorConfig::new(keys_main.wallet.clone());
+        config.port = 0;
+        config.wire_formats = WireFormats::default().with_format(Arc::new(ReversedJson));
+        let domain_separator = config.domain_separator();
+
+        let server = server::Server::from_config(config).await.unwrap();
+
+        let receipts = [45, 56]
+            .into_iter()
+            .map(|value| {
+                Eip712SignedMessage::new(
+                    &domain_separator,
+                    Receipt::new(allocation_ids[0], value).unwrap(),
+                    &keys_main.wallet,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 0,
+            "method": "aggregate_receipts",
+            "params": ["0.0", &receipts, null],
+        });
+        let response = reqwest::Client::new()
+            .post(format!("http://127.0.0.1:{}", server.local_addr.port()))
+            .header(reqwest::header::CONTENT_TYPE, ReversedJson.mime_type())
+            .header(reqwest::header::ACCEPT, ReversedJson.mime_type())
+            .body(encode_message(&ReversedJson, &request).unwrap())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[reqwest::header::CONTENT_TYPE],
+            ReversedJson.mime_type()
+        );
+
+        let response: serde_json::Value =
+            decode_message(&ReversedJson, response.bytes().await.unwrap().as_ref()).unwrap();
+        let res: server::JsonRpcResponse<Eip712SignedMessage<ReceiptAggregateVoucher>> =
+            serde_json::from_value(response["result"].clone()).unwrap();
+        let local_rav =
+            ReceiptAggregateVoucher::aggregate_receipts(allocation_ids[0], &receipts, None)
+                .unwrap();
+        assert_eq!(res.data.message, local_rav);
+
+        server.handle.abort();
+    }
+
+    /// Manager storing receipts in memory, accepting receipts for `allocation_ids`
+    /// signed by any of `signers`.
+    fn receipt_manager(
+        domain_separator: &Eip712Domain,
+        signers: HashSet<Address>,
+        allocation_ids: &[Address],
+    ) -> Arc<server::ReceiptManager> {
+        let timestamp_check = Arc::new(StatefulTimestampCheck::new(0));
+        let context = InMemoryContext::new(
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            timestamp_check.clone(),
+        );
+        let mut checks = get_full_list_of_checks(
+            domain_separator.clone(),
+            signers,
+            Arc::new(RwLock::new(allocation_ids.iter().cloned().collect())),
+            Arc::new(RwLock::new(HashMap::new())),
+        );
+        checks.push(timestamp_check);
+        Arc::new(Manager::new(
+            domain_separator.clone(),
+            context,
+            CheckList::new(checks),
+        ))
+    }
+
+    /// Config of a server signing with `keys` in the domain of the
+    /// `domain_separator` fixture
+    fn server_config(
+        keys: &Keys,
+        http_request_size_limit: u32,
+        http_response_size_limit: u32,
+        http_max_concurrent_connections: u32,
+    ) -> AggregatorConfig {
+        let mut config = AggregatorConfig::new(keys.wallet.clone());
+        config.port = 0;
+        config.domain_chain_id = 1;
+        config.domain_verifying_contract = Address::from([0x11u8; 20]);
+        config.max_request_body_size = http_request_size_limit;
+        config.max_response_body_size = http_response_size_limit;
+        config.max_concurrent_connections = http_max_concurrent_connections;
+        config
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn submit_receipts(
+        domain_separator: Eip712Domain,
+        http_request_size_limit: u32,
+        http_response_size_limit: u32,
+        http_max_concurrent_connections: u32,
+        allocation_ids: Vec<Address>,
+    ) {
+        let keys_main = keys();
+        let keys_unknown = keys();
+        let manager = receipt_manager(
+            &domain_separator,
+            HashSet::from([keys_main.address]),
+            &allocation_ids,
+        );
+
+        let config = server_config(
+            &keys_main,
+            http_request_size_limit,
+            http_response_size_limit,
+            http_max_concurrent_connections,
+        );
+        let server::Server { handle, local_addr } =
+            server::Server::from_config_with_manager(config, manager)
+                .await
+                .unwrap();
+
+        let client = HttpClientBuilder::default()
+            .build(format!("http://127.0.0.1:{}", local_addr.port()))
+            .unwrap();
+
+        // Two valid receipts around one signed by an unknown signer
+        let receipts = [&keys_main, &keys_unknown, &keys_main]
+            .iter()
+            .map(|keys| {
+                Eip712SignedMessage::new(
+                    &domain_separator,
+                    Receipt::new(allocation_ids[0], 42).unwrap(),
+                    &keys.wallet,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let res: server::JsonRpcResponse<Vec<server::ReceiptSubmissionResult>> = client
+            .request("tap_submit_receipts", rpc_params!(&receipts))
+            .await
+            .unwrap();
+
+        let accepted: Vec<bool> = res.data.iter().map(|result| result.accepted).collect();
+        assert_eq!(accepted, vec![true, false, true]);
+        assert!(res.data[1].error.is_some());
+        // an unknown signer won't be accepted on retry
+        assert!(!res.data[1].retryable);
+
+        handle.abort();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn submit_receipts_without_storage(
+        domain_separator: Eip712Domain,
+        http_request_size_limit: u32,
+        http_response_size_limit: u32,
+        http_max_concurrent_connections: u32,
+        allocation_ids: Vec<Address>,
+    ) {
+        let keys_main = keys();
+
+        let (handle, local_addr) = server::run_server(
+            0,
+            keys_main.wallet.clone(),
+            HashSet::from([keys_main.address]),
+            domain_separator.clone(),
+            http_request_size_limit,
+            http_response_size_limit,
+            http_max_concurrent_connections,
+        )
+        .await
+        .unwrap();
+
+        let client = HttpClientBuilder::default()
+            .build(format!("http://127.0.0.1:{}", local_addr.port()))
+            .unwrap();
+
+        let receipts = vec![Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 42).unwrap(),
+            &keys_main.wallet,
+        )
+        .unwrap()];
+
+        let res: Result<
+            server::JsonRpcResponse<Vec<server::ReceiptSubmissionResult>>,
+            jsonrpsee::core::ClientError,
+        > = client
+            .request("tap_submit_receipts", rpc_params!(&receipts))
+            .await;
+
+        match res.expect_err("Expected an error") {
+            jsonrpsee::core::ClientError::Call(err) => {
+                assert_eq!(
+                    err.code(),
+                    crate::error_codes::JsonRpcErrorCode::StorageNotConfigured as i32
+                );
+            }
+            e => panic!("Unexpected error: {e}"),
+        }
+
+        handle.abort();
+    }
+
+    #[rstest]
+    #[case::with_manager(true)]
+    #[case::without_manager(false)]
+    #[tokio::test]
+    async fn check_receipt(
+        domain_separator: Eip712Domain,
+        http_request_size_limit: u32,
+        http_response_size_limit: u32,
+        http_max_concurrent_connections: u32,
+        allocation_ids: Vec<Address>,
+        #[case] with_manager: bool,
+    ) {
+        let keys_main = keys();
+        let keys_unknown = keys();
+        let manager = receipt_manager(
+            &domain_separator,
+            HashSet::from([keys_main.address]),
+            &allocation_ids,
+        );
+
+        let config = server_config(
+            &keys_main,
+            http_request_size_limit,
+            http_response_size_limit,
+            http_max_concurrent_connections,
+        );
+        let server::Server { handle, local_addr } = if with_manager {
+            server::Server::from_config_with_manager(config, manager.clone()).await
+        } else {
+            server::Server::from_config(config).await
+        }
+        .unwrap();
+
+        let client = HttpClientBuilder::default()
+            .build(format!("http://127.0.0.1:{}", local_addr.port()))
+            .unwrap();
+
+        for (keys, valid) in [(&keys_main, true), (&keys_unknown, false)] {
+            let receipt = Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(allocation_ids[0], 42).unwrap(),
+                &keys.wallet,
+            )
+            .unwrap();
+            let res: server::JsonRpcResponse<server::ReceiptCheckResult> = client
+                .request("tap_check_receipt", rpc_params!(&receipt))
+                .await
+                .unwrap();
+            assert_eq!(res.data.valid, valid);
+            assert_eq!(res.data.reason.is_none(), valid);
+        }
+
+        // nothing was stored
+        assert!(manager
+            .allocations_with_pending_receipts()
+            .await
+            .unwrap()
+            .is_empty());
+
+        handle.abort();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn server_from_config(allocation_ids: Vec<Address>) {
+        let keys_main = keys();
+        let keys_other = keys();
+        let mut config = AggregatorConfig::new(keys_main.wallet.clone());
+        config.port = 0;
+        config.accepted_addresses = HashSet::from([keys_other.address]);
+        config.domain_chain_id = 42;
+        let domain_separator = config.domain_separator();
+
+        let server = server::Server::from_config(config).await.unwrap();
+        let client = HttpClientBuilder::default()
+            .build(format!("http://127.0.0.1:{}", server.local_addr.port()))
+            .unwrap();
+
+        // receipts from both the wallet and the extra accepted signer
+        let receipts = [&keys_main, &keys_other]
+            .iter()
+            .map(|keys| {
+                Eip712SignedMessage::new(
+                    &domain_separator,
+                    Receipt::new(allocation_ids[0], 42).unwrap(),
+                    &keys.wallet,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let res: server::JsonRpcResponse<Eip712SignedMessage<ReceiptAggregateVoucher>> = client
+            .request(
+                "aggregate_receipts",
+                rpc_params!("0.0", &receipts, None::<()>),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(res.data.message.valueAggregate, 84);
+        assert_eq!(
+            res.data.recover_signer(&domain_separator).unwrap(),
+            keys_main.address
+        );
+
+        server.handle.abort();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn submit_receipts_retryable(
+        domain_separator: Eip712Domain,
+        allocation_ids: Vec<Address>,
+    ) {
+        // Looks up the escrow of the sender, whose backend is down for the
+        // second allocation. The third allocation has no escrow at all.
+        struct EscrowLookupCheck(Vec<Address>);
+
+        #[async_trait::async_trait]
+        impl Check<SignedReceipt> for EscrowLookupCheck {
+            async fn check(
+                &self,
+                _: &Context,
+                receipt: &ReceiptWithState<Checking, SignedReceipt>,
+            ) -> CheckResult {
+                let allocation_id = receipt.signed_receipt().message.allocation_id;
+                if allocation_id == self.0[1] {
+                    Err(CheckError::Retryable(anyhow::anyhow!(
+                        "Escrow lookup failed"
+                    )))
+                } else if allocation_id == self.0[2] {
+                    Err(CheckError::Failed(anyhow::anyhow!("No escrow")))
+                } else {
+                    Ok(())
+                }
+            }
+        }
+
+        let keys_main = keys();
+        let manager = Arc::new(Manager::new(
+            domain_separator.clone(),
+            InMemoryContext::new(
+                Arc::new(RwLock::new(HashMap::new())),
+                Arc::new(RwLock::new(HashMap::new())),
+                Arc::new(RwLock::new(HashMap::new())),
+                Arc::new(StatefulTimestampCheck::new(0)),
+            ),
+            CheckList::new(vec![Arc::new(EscrowLookupCheck(allocation_ids.clone()))]),
+        ));
+        let mut config = AggregatorConfig::new(keys_main.wallet.clone());
+        config.port = 0;
+
+        let server = server::Server::from_config_with_manager(config, manager)
+            .await
+            .unwrap();
+        let client = HttpClientBuilder::default()
+            .build(format!("http://127.0.0.1:{}", server.local_addr.port()))
+            .unwrap();
+
+        let receipts = allocation_ids[..3]
+            .iter()
+            .map(|allocation_id| {
+                Eip712SignedMessage::new(
+                    &domain_separator,
+                    Receipt::new(*allocation_id, 42).unwrap(),
+                    &keys_main.wallet,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let res: server::JsonRpcResponse<Vec<server::ReceiptSubmissionResult>> = client
+            .request("tap_submit_receipts", rpc_params!(&receipts))
+            .await
+            .unwrap();
+
+        let outcomes: Vec<(bool, bool)> = res
+            .data
+            .iter()
+            .map(|result| (result.accepted, result.retryable))
+            .collect();
+        assert_eq!(outcomes, vec![(true, false), (false, true), (false, false)]);
+
+        server.handle.abort();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn submit_receipts_returns_warnings(
+        domain_separator: Eip712Domain,
+        allocation_ids: Vec<Address>,
+    ) {
+        // Advisory check flagging receipts of the second allocation
+        struct LargeValueCheck(Address);
+
+        #[async_trait::async_trait]
+        impl Check<SignedReceipt> for LargeValueCheck {
+            async fn check(
+                &self,
+                _: &Context,
+                receipt: &ReceiptWithState<Checking, SignedReceipt>,
+            ) -> CheckResult {
+                if receipt.signed_receipt().message.allocation_id == self.0 {
+                    Err(CheckError::Failed(anyhow::anyhow!("Unusually large value")))
+                } else {
+                    Ok(())
+                }
+            }
+
+            fn name(&self) -> &'static str {
+                "large_value"
+            }
+
+            fn severity(&self) -> CheckSeverity {
+                CheckSeverity::Warn
+            }
+        }
+
+        let keys_main = keys();
+        let manager = Arc::new(Manager::new(
+            domain_separator.clone(),
+            InMemoryContext::new(
+                Arc::new(RwLock::new(HashMap::new())),
+                Arc::new(RwLock::new(HashMap::new())),
+                Arc::new(RwLock::new(HashMap::new())),
+                Arc::new(StatefulTimestampCheck::new(0)),
+            ),
+            CheckList::new(vec![Arc::new(LargeValueCheck(allocation_ids[1]))]),
+        ));
+        let mut config = AggregatorConfig::new(keys_main.wallet.clone());
+        config.port = 0;
+
+        let server = server::Server::from_config_with_manager(config, manager)
+            .await
+            .unwrap();
+        let client = HttpClientBuilder::default()
+            .build(format!("http://127.0.0.1:{}", server.local_addr.port()))
+            .unwrap();
+
+        let receipts = allocation_ids[..2]
+            .iter()
+            .map(|allocation_id| {
+                Eip712SignedMessage::new(
+                    &domain_separator,
+                    Receipt::new(*allocation_id, 42).unwrap(),
+                    &keys_main.wallet,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let res: server::JsonRpcResponse<Vec<server::ReceiptSubmissionResult>> = client
+            .request("tap_submit_receipts", rpc_params!(&receipts))
+            .await
+            .unwrap();
+
+        // the flagged receipt is still stored
+        assert!(res.data.iter().all(|result| result.accepted));
+        assert!(res.data[0].warnings.is_empty());
+        assert_eq!(
+            res.data[1].warnings,
+            vec![server::ReceiptWarning {
+                check: "large_value".to_string(),
+                message: "Unusually large value".to_string(),
+            }]
+        );
+
+        server.handle.abort();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn receipt_submission_timeout(
+        domain_separator: Eip712Domain,
+        allocation_ids: Vec<Address>,
+    ) {
+        // Stands in for a signer check backed by a remote signer that stalls
+        struct SlowSignerCheck;
+
+        #[async_trait::async_trait]
+        impl Check<SignedReceipt> for SlowSignerCheck {
+            async fn check(
+                &self,
+                _: &Context,
+                _: &ReceiptWithState<Checking, SignedReceipt>,
+            ) -> CheckResult {
+                tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+                Ok(())
+            }
+        }
+
+        let keys_main = keys();
+        let manager = Arc::new(Manager::new(
+            domain_separator.clone(),
+            InMemoryContext::new(
+                Arc::new(RwLock::new(HashMap::new())),
+                Arc::new(RwLock::new(HashMap::new())),
+                Arc::new(RwLock::new(HashMap::new())),
+                Arc::new(StatefulTimestampCheck::new(0)),
+            ),
+            CheckList::new(vec![Arc::new(SlowSignerCheck)]),
+        ));
+        let mut config = AggregatorConfig::new(keys_main.wallet.clone());
+        config.port = 0;
+        config.receipt_submission_timeout = std::time::Duration::from_millis(50);
+
+        let server = server::Server::from_config_with_manager(config, manager.clone())
+            .await
+            .unwrap();
+        let client = HttpClientBuilder::default()
+            .build(format!("http://127.0.0.1:{}", server.local_addr.port()))
+            .unwrap();
+
+        let receipts = vec![Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 42).unwrap(),
+            &keys_main.wallet,
+        )
+        .unwrap()];
+        let res: Result<
+            server::JsonRpcResponse<Vec<server::ReceiptSubmissionResult>>,
+            jsonrpsee::core::ClientError,
+        > = client
+            .request("tap_submit_receipts", rpc_params!(&receipts))
+            .await;
+
+        // the request returns at the deadline, while the receipt is still
+        // being checked
+        let results = res.unwrap().data;
+        assert_eq!(results.len(), 1);
+        assert!(!results[0].accepted);
+        assert!(results[0].retryable);
+        assert!(manager
+            .allocations_with_pending_receipts()
+            .await
+            .unwrap()
+            .is_empty());
+        let _: server::JsonRpcResponse<crate::api_versioning::TapRpcApiVersionsInfo> = client
+            .request("api_versions", rpc_params!(None::<()>))
+            .await
+            .unwrap();
+
+        server.handle.abort();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn latest_rav(domain_separator: Eip712Domain, allocation_ids: Vec<Address>) {
+        let keys_main = keys();
+        let keys_sender = keys();
+        let signed_rav = Eip712SignedMessage::new(
+            &domain_separator,
+            ReceiptAggregateVoucher {
+                allocationId: allocation_ids[0],
+                timestampNs: 42,
+                valueAggregate: 100,
+            },
+            &keys_sender.wallet,
+        )
+        .unwrap();
+        let context = InMemoryContext::new(
+            Arc::new(RwLock::new(HashMap::from([(
+                (keys_sender.address, allocation_ids[0]),
+                signed_rav.clone(),
+            )]))),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(StatefulTimestampCheck::new(0)),
+        );
+        let manager = Arc::new(Manager::new(
+            domain_separator.clone(),
+            context,
+            CheckList::empty(),
+        ));
+        let mut config = AggregatorConfig::new(keys_main.wallet.clone());
+        config.port = 0;
+
+        let server = server::Server::from_config_with_manager(config, manager)
+            .await
+            .unwrap();
+        let client = HttpClientBuilder::default()
+            .build(format!("http://127.0.0.1:{}", server.local_addr.port()))
+            .unwrap();
+
+        let res: server::JsonRpcResponse<Option<Eip712SignedMessage<ReceiptAggregateVoucher>>> =
+            client
+                .request(
+                    "tap_latest_rav",
+                    rpc_params!(keys_sender.address, allocation_ids[0]),
+                )
+                .await
+                .unwrap();
+        assert_eq!(res.data, Some(signed_rav));
+
+        // nothing stored for this allocation
+        let res: server::JsonRpcResponse<Option<Eip712SignedMessage<ReceiptAggregateVoucher>>> =
+            client
+                .request(
+                    "tap_latest_rav",
+                    rpc_params!(keys_sender.address, allocation_ids[1]),
+                )
+                .await
+                .unwrap();
+        assert_eq!(res.data, None);
+
+        server.handle.abort();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn admin_methods(domain_separator: Eip712Domain, allocation_ids: Vec<Address>) {
+        let keys_main = keys();
+        let manager = receipt_manager(
+            &domain_separator,
+            HashSet::from([keys_main.address]),
+            &allocation_ids,
+        );
+        let mut config = AggregatorConfig::new(keys_main.wallet.clone());
+        config.port = 0;
+        config.admin_token = Some("admin-secret".to_string());
+
+        let server = server::Server::from_config_with_manager(config, manager.clone())
+            .await
+            .unwrap();
+        let client_with_token = |token: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(token) = token {
+                headers.insert(
+                    hyper::header::AUTHORIZATION,
+                    HeaderValue::from_str(&format!("Bearer {token}")).unwrap(),
+                );
+            }
+            HttpClientBuilder::default()
+                .set_headers(headers)
+                .build(format!("http://127.0.0.1:{}", server.local_addr.port()))
+                .unwrap()
+        };
+
+        // missing and wrong tokens are rejected
+        for token in [None, Some("wrong")] {
+            let res: Result<server::JsonRpcResponse<()>, jsonrpsee::core::ClientError> =
+                client_with_token(token)
+                    .request("admin_pause", rpc_params!(true))
+                    .await;
+            match res.expect_err("Expected an unauthorized error") {
+                jsonrpsee::core::ClientError::Call(err) => {
+                    assert_eq!(
+                        err.code(),
+                        crate::error_codes::JsonRpcErrorCode::Unauthorized as i32
+                    );
+                }
+                e => panic!("Unexpected error: {e}"),
+            }
+        }
+        assert!(!manager.is_paused());
+
+        let client = client_with_token(Some("admin-secret"));
+        let res: server::JsonRpcResponse<Vec<server::CheckStatus>> = client
+            .request("admin_list_checks", rpc_params!())
+            .await
+            .unwrap();
+        assert_eq!(res.data.len(), manager.check_names().len());
+        assert!(res.data.iter().all(|check| check.enabled));
+
+        let check_name = res.data[0].name.clone();
+        let _: server::JsonRpcResponse<()> = client
+            .request("admin_set_check_enabled", rpc_params!(&check_name, false))
+            .await
+            .unwrap();
+        assert!(!manager.is_check_enabled(&check_name));
+        let res: server::JsonRpcResponse<Vec<server::CheckStatus>> = client
+            .request("admin_list_checks", rpc_params!())
+            .await
+            .unwrap();
+        assert!(!res.data[0].enabled);
+
+        let _: server::JsonRpcResponse<()> = client
+            .request("admin_pause", rpc_params!(true))
+            .await
+            .unwrap();
+        let res: server::JsonRpcResponse<server::AdminStats> =
+            client.request("admin_stats", rpc_params!()).await.unwrap();
+        assert!(res.data.paused);
+        assert_eq!(res.data.pending_receipts, 0);
+
+        server.handle.abort();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn auth_tokens(domain_separator: Eip712Domain, allocation_ids: Vec<Address>) {
+        let keys_main = keys();
+        let manager = receipt_manager(
+            &domain_separator,
+            HashSet::from([keys_main.address]),
+            &allocation_ids,
+        );
+        let mut config = AggregatorConfig::new(keys_main.wallet.clone());
+        config.port = 0;
+        config.admin_token = Some("admin-secret".to_string());
+        config.auth_tokens = HashSet::from(["client-secret".to_string()]);
+
+        let server = server::Server::from_config_with_manager(config, manager)
+            .await
+            .unwrap();
+        let client_with_token = |token: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(token) = token {
+                headers.insert(
+                    hyper::header::AUTHORIZATION,
+                    HeaderValue::from_str(&format!("Bearer {token}")).unwrap(),
+                );
+            }
+            HttpClientBuilder::default()
+                .set_headers(headers)
+                .build(format!("http://127.0.0.1:{}", server.local_addr.port()))
+                .unwrap()
+        };
+
+        // missing and wrong tokens are rejected before reaching the JSON-RPC layer
+        for token in [None, Some("wrong")] {
+            let res: Result<
+                server::JsonRpcResponse<server::TapRpcApiVersionsInfo>,
+                jsonrpsee::core::ClientError,
+            > = client_with_token(token)
+                .request("api_versions", rpc_params!(None::<()>))
+                .await;
+            match res.expect_err("Expected an unauthorized error") {
+                jsonrpsee::core::ClientError::Transport(err) => {
+                    assert!(err.to_string().contains("401"), "{err}");
+                }
+                e => panic!("Unexpected error: {e}"),
+            }
+        }
+
+        // both the client and the admin tokens are accepted
+        for token in ["client-secret", "admin-secret"] {
+            let _: server::JsonRpcResponse<server::TapRpcApiVersionsInfo> =
+                client_with_token(Some(token))
+                    .request("api_versions", rpc_params!(None::<()>))
+                    .await
+                    .unwrap();
+        }
+
+        server.handle.abort();
+    }
+
+    // the clock is paused so that the buckets only refill when the test
+    // advances it, however slow the requests are
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit() {
+        let keys_main = keys();
+        let mut config = AggregatorConfig::new(keys_main.wallet.clone());
+        config.port = 0;
+        config.rate_limit = Some(RateLimitConfig {
+            requests_per_second: 1,
+            burst: 3,
+            client_ip_header: None,
+            exempt_ips: HashSet::new(),
+        });
+
+        let server = server::Server::from_config(config).await.unwrap();
+        let api_versions = || async {
+            reqwest::Client::new()
+                .post(format!("http://127.0.0.1:{}", server.local_addr.port()))
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(
+                    serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "id": 0,
+                        "method": "api_versions",
+                        "params": [null],
+                    })
+                    .to_string(),
+                )
+                .send()
+                .await
+                .unwrap()
+        };
+
+        // the burst goes through, then the excess is rejected
+        for _ in 0..3 {
+            assert_eq!(api_versions().await.status(), reqwest::StatusCode::OK);
+        }
+        for _ in 0..3 {
+            let response = api_versions().await;
+            assert_eq!(response.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+            assert_eq!(response.headers()[reqwest::header::RETRY_AFTER], "1");
+        }
+
+        // a token is back after a second
+        tokio::time::advance(std::time::Duration::from_secs(1)).await;
+        assert_eq!(api_versions().await.status(), reqwest::StatusCode::OK);
+        assert_eq!(
+            api_versions().await.status(),
+            reqwest::StatusCode::TOO_MANY_REQUESTS
+        );
+
+        server.handle.abort();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn enabled_methods(domain_separator: Eip712Domain, allocation_ids: Vec<Address>) {
+        let keys_main = keys();
+        let manager = Arc::new(Manager::new(
+            domain_separator.clone(),
+            InMemoryContext::new(
+                Arc::new(RwLock::new(HashMap::new())),
+                Arc::new(RwLock::new(HashMap::new())),
+                Arc::new(RwLock::new(HashMap::new())),
+                Arc::new(StatefulTimestampCheck::new(0)),
+            ),
+            CheckList::empty(),
+        ));
+        // read-only replica
+        let mut config = AggregatorConfig::new(keys_main.wallet.clone());
+        config.port = 0;
+        config.enabled_methods = Some(HashSet::from(["tap_latest_rav".to_string()]));
+
+        let server = server::Server::from_config_with_manager(config, manager.clone())
+            .await
+            .unwrap();
+        let client = HttpClientBuilder::default()
+            .build(format!("http://127.0.0.1:{}", server.local_addr.port()))
+            .unwrap();
+
+        let res: server::JsonRpcResponse<Option<Eip712SignedMessage<ReceiptAggregateVoucher>>> =
+            client
+                .request(
+                    "tap_latest_rav",
+                    rpc_params!(keys_main.address, allocation_ids[0]),
+                )
+                .await
+                .unwrap();
+        assert_eq!(res.data, None);
+
+        let receipts = vec![Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 42).unwrap(),
+            &keys_main.wallet,
+        )
+        .unwrap()];
+        let res: Result<
+            server::JsonRpcResponse<Vec<server::ReceiptSubmissionResult>>,
+            jsonrpsee::core::ClientError,
+        > = client
+            .request("tap_submit_receipts", rpc_params!(&receipts))
+            .await;
+        match res.expect_err("Expected the method to be disabled") {
+            jsonrpsee::core::ClientError::Call(err) => {
+                assert_eq!(
+                    err.code(),
+                    jsonrpsee::types::error::ErrorCode::MethodNotFound.code()
+                );
+            }
+            e => panic!("Unexpected error: {e}"),
+        }
+        assert!(manager
+            .allocations_with_pending_receipts()
+            .await
+            .unwrap()
+            .is_empty());
+
+        server.handle.abort();
+
+        // a typo in the allowlist doesn't silently disable a method
+        let mut config = AggregatorConfig::new(keys_main.wallet.clone());
+        config.port = 0;
+        config.enabled_methods = Some(HashSet::from(["tap_latest_ravs".to_string()]));
+        assert!(server::Server::from_config(config).await.is_err());
+    }
 }
@@ -0,0 +1,253 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Pluggable wire formats for the JSON-RPC API.
+//!
+//! The JSON-RPC service itself only speaks JSON. A [`WireFormat`] translates between JSON and
+//! another encoding, and the [`negotiate`] middleware picks one per request: the request body is
+//! decoded according to its `Content-Type`, and the response is encoded according to `Accept`.
+//! Both default to JSON. The messages keep the same structure in every format, since they are
+//! all produced from the same serde derives.
+
+use std::sync::Arc;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hyper::{
+    header::{HeaderName, ACCEPT, CONTENT_LENGTH, CONTENT_TYPE},
+    HeaderMap, StatusCode,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+/// Encoding of receipts, RAVs and JSON-RPC messages on the wire
+pub trait WireFormat: Send + Sync {
+    /// MIME type identifying the format in the `Content-Type` and `Accept` headers
+    fn mime_type(&self) -> &'static str;
+
+    /// Encodes a JSON document in this format
+    fn encode(&self, value: &Value) -> anyhow::Result<Vec<u8>>;
+
+    /// Decodes a document in this format into JSON
+    fn decode(&self, bytes: &[u8]) -> anyhow::Result<Value>;
+}
+
+/// Encodes any serializable message, such as a signed receipt or RAV, with `format`
+pub fn encode_message<T: Serialize>(
+    format: &dyn WireFormat,
+    message: &T,
+) -> anyhow::Result<Vec<u8>> {
+    format.encode(&serde_json::to_value(message)?)
+}
+
+/// Decodes a message, such as a signed receipt or RAV, encoded with `format`
+pub fn decode_message<T: DeserializeOwned>(
+    format: &dyn WireFormat,
+    bytes: &[u8],
+) -> anyhow::Result<T> {
+    Ok(serde_json::from_value(format.decode(bytes)?)?)
+}
+
+/// Default format
+pub struct Json;
+
+impl WireFormat for Json {
+    fn mime_type(&self) -> &'static str {
+        "application/json"
+    }
+
+    fn encode(&self, value: &Value) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> anyhow::Result<Value> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// [CBOR](https://cbor.io) format, for bandwidth-sensitive clients
+pub struct Cbor;
+
+impl WireFormat for Cbor {
+    fn mime_type(&self) -> &'static str {
+        "application/cbor"
+    }
+
+    fn encode(&self, value: &Value) -> anyhow::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> anyhow::Result<Value> {
+        Ok(ciborium::from_reader(bytes)?)
+    }
+}
+
+/// Set of formats the server accepts. JSON is always supported.
+#[derive(Clone)]
+pub struct WireFormats {
+    formats: Vec<Arc<dyn WireFormat>>,
+}
+
+impl Default for WireFormats {
+    fn default() -> Self {
+        Self {
+            formats: vec![Arc::new(Json), Arc::new(Cbor)],
+        }
+    }
+}
+
+impl std::fmt::Debug for WireFormats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.formats.iter().map(|format| format.mime_type()))
+            .finish()
+    }
+}
+
+impl WireFormats {
+    /// Adds a format, replacing any format with the same MIME type
+    pub fn with_format(mut self, format: Arc<dyn WireFormat>) -> Self {
+        self.formats
+            .retain(|existing| existing.mime_type() != format.mime_type());
+        self.formats.push(format);
+        self
+    }
+
+    /// Returns the first format listed in `header`, if any
+    fn select(&self, headers: &HeaderMap, header: HeaderName) -> Option<&dyn WireFormat> {
+        let value = headers.get(header)?.to_str().ok()?;
+        value.split(',').find_map(|mime| {
+            let mime = mime.split(';').next().unwrap_or_default().trim();
+            self.formats
+                .iter()
+                .find(|format| format.mime_type() == mime)
+                .map(AsRef::as_ref)
+        })
+    }
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with(Json.mime_type()))
+        .unwrap_or(false)
+}
+
+/// Axum middleware translating request and response bodies between the negotiated format
+/// and JSON. `max_body_size` bounds the size of the request bodies buffered for translation.
+pub async fn negotiate(
+    formats: Arc<WireFormats>,
+    max_body_size: usize,
+    request: Request,
+    next: Next,
+) -> Response {
+    let response_format = formats
+        .select(request.headers(), ACCEPT)
+        .filter(|format| format.mime_type() != Json.mime_type());
+
+    let request = match formats
+        .select(request.headers(), CONTENT_TYPE)
+        .filter(|format| format.mime_type() != Json.mime_type())
+    {
+        Some(format) => {
+            let (mut parts, body) = request.into_parts();
+            let json = match to_bytes(body, max_body_size).await {
+                Ok(bytes) => format.decode(&bytes).and_then(|value| Json.encode(&value)),
+                Err(e) => Err(anyhow::anyhow!(e)),
+            };
+            match json {
+                Ok(json) => {
+                    parts.headers.insert(
+                        CONTENT_TYPE,
+                        Json.mime_type().parse().expect("valid header value"),
+                    );
+                    parts.headers.remove(CONTENT_LENGTH);
+                    Request::from_parts(parts, Body::from(json))
+                }
+                Err(e) => {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        format!("Invalid {} body: {e}", format.mime_type()),
+                    )
+                        .into_response()
+                }
+            }
+        }
+        None => request,
+    };
+
+    let response = next.run(request).await;
+    let format = match response_format {
+        Some(format) if is_json(response.headers()) => format,
+        _ => return response,
+    };
+
+    let (mut parts, body) = response.into_parts();
+    let encoded = match to_bytes(body, usize::MAX).await {
+        Ok(json) => Json.decode(&json).and_then(|value| format.encode(&value)),
+        Err(e) => Err(anyhow::anyhow!(e)),
+    };
+    match encoded {
+        Ok(encoded) => {
+            parts.headers.insert(
+                CONTENT_TYPE,
+                format.mime_type().parse().expect("valid header value"),
+            );
+            parts.headers.remove(CONTENT_LENGTH);
+            Response::from_parts(parts, Body::from(encoded))
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to encode response as {}: {e}", format.mime_type()),
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{primitives::Address, signers::local::PrivateKeySigner};
+    use rstest::*;
+    use tap_core::{signed_message::Eip712SignedMessage, tap_eip712_domain};
+    use tap_graph::{Receipt, SignedReceipt};
+
+    use super::*;
+
+    #[rstest]
+    #[case::json(Arc::new(Json))]
+    #[case::cbor(Arc::new(Cbor))]
+    fn receipt_round_trip(#[case] format: Arc<dyn WireFormat>) {
+        let domain_separator = tap_eip712_domain(1, Address::from([0x11u8; 20]));
+        let receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(Address::from([0xabu8; 20]), 42).unwrap(),
+            &PrivateKeySigner::random(),
+        )
+        .unwrap();
+
+        let bytes = encode_message(format.as_ref(), &receipt).unwrap();
+        let decoded: SignedReceipt = decode_message(format.as_ref(), &bytes).unwrap();
+
+        assert_eq!(decoded, receipt);
+    }
+
+    #[rstest]
+    fn select_format_from_header() {
+        let formats = WireFormats::default();
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, "text/html, application/cbor;q=0.9".parse().unwrap());
+
+        assert_eq!(
+            formats.select(&headers, ACCEPT).map(|f| f.mime_type()),
+            Some(Cbor.mime_type())
+        );
+        assert!(formats.select(&headers, CONTENT_TYPE).is_none());
+    }
+}
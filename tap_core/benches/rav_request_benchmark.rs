@@ -0,0 +1,92 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Benchmarks [`Manager::create_rav_request`] on a batch of stored receipts
+//! with varying receipt verification concurrency.
+
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use alloy::{primitives::Address, signers::local::PrivateKeySigner};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use tap_core::{
+    manager::{
+        context::memory::{checks::get_full_list_of_checks, InMemoryContext},
+        Manager, RavKey,
+    },
+    receipt::{
+        checks::{CheckList, StatefulTimestampCheck},
+        Context,
+    },
+    signed_message::Eip712SignedMessage,
+    tap_eip712_domain,
+};
+use tap_graph::{Receipt, ReceiptAggregateVoucher};
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let domain_separator = tap_eip712_domain(1, Address::from([0x11u8; 20]));
+    let wallet = PrivateKeySigner::random();
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+
+    let mut group = c.benchmark_group("Create RAV request with varying concurrency");
+
+    for concurrency in [1, 4, 16] {
+        let context = InMemoryContext::new(
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(StatefulTimestampCheck::new(0)),
+        );
+        let checks = get_full_list_of_checks(
+            domain_separator.clone(),
+            [wallet.address()].into_iter().collect(),
+            Arc::new(RwLock::new([allocation_id].into_iter().collect())),
+            Arc::new(RwLock::new(HashMap::new())),
+        );
+        let manager = Manager::new(domain_separator.clone(), context, CheckList::new(checks))
+            .with_verification_concurrency(concurrency);
+
+        runtime.block_on(async {
+            for _ in 0..1024 {
+                let receipt = Eip712SignedMessage::new(
+                    &domain_separator,
+                    Receipt::new(allocation_id, 100).unwrap(),
+                    &wallet,
+                )
+                .unwrap();
+                manager
+                    .verify_and_store_receipt(&Context::new(), receipt)
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let mut ctx = Context::new();
+        ctx.insert(RavKey {
+            sender: wallet.address(),
+            allocation_id,
+        });
+        group.bench_with_input(
+            BenchmarkId::from_parameter(concurrency),
+            &manager,
+            |b, manager| {
+                b.iter(|| {
+                    runtime
+                        .block_on(
+                            manager.create_rav_request::<ReceiptAggregateVoucher>(&ctx, 0, None),
+                        )
+                        .unwrap()
+                })
+            },
+        );
+    }
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,68 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Benchmarks verifying the same receipts several times, as happens when they
+//! are checked on receipt and again when aggregated, with and without a
+//! [`SignatureCache`].
+
+use std::{hint::black_box, num::NonZeroUsize, str::FromStr};
+
+use alloy::{primitives::Address, signers::local::PrivateKeySigner};
+use criterion::{criterion_group, criterion_main, Criterion};
+use tap_core::{
+    signature_cache::SignatureCache, signed_message::Eip712SignedMessage, tap_eip712_domain,
+};
+use tap_graph::Receipt;
+
+const RECEIPTS: usize = 64;
+const ROUNDS: usize = 4;
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let domain_separator = tap_eip712_domain(1, Address::from([0x11u8; 20]));
+    let wallet = PrivateKeySigner::random();
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+    let receipts = (0..RECEIPTS)
+        .map(|_| {
+            Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(allocation_id, 100).unwrap(),
+                &wallet,
+            )
+            .unwrap()
+        })
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("Recover recurring receipt signers");
+
+    group.bench_function("uncached", |b| {
+        b.iter(|| {
+            for _ in 0..ROUNDS {
+                for receipt in &receipts {
+                    black_box(receipt.recover_signer(&domain_separator).unwrap());
+                }
+            }
+        })
+    });
+
+    group.bench_function("cached", |b| {
+        b.iter(|| {
+            let signature_cache = SignatureCache::new(NonZeroUsize::new(RECEIPTS).unwrap());
+            for _ in 0..ROUNDS {
+                for receipt in &receipts {
+                    black_box(
+                        signature_cache
+                            .recover_signer(receipt, &domain_separator)
+                            .unwrap(),
+                    );
+                }
+            }
+            // only the first round recovers the signers
+            assert_eq!(signature_cache.recoveries(), RECEIPTS as u64);
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
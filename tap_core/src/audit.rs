@@ -0,0 +1,358 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! # RAV auditing
+//!
+//! Independent recomputation of a RAV from the receipts it claims to
+//! aggregate. This is meant for auditors and disputing parties that hold
+//! a set of receipts and a signed RAV and want to check that both agree,
+//! without going through a [`crate::manager::Manager`].
+
+use std::{collections::HashSet, sync::Arc};
+
+use alloy::{
+    dyn_abi::Eip712Domain,
+    primitives::{Address, B256},
+    sol_types::SolStruct,
+};
+use serde::{Deserialize, Serialize};
+use tap_receipt::rav::{Aggregate, AggregationError};
+
+use crate::{
+    merkle::merkle_root,
+    receipt::{
+        checks::{Check, CheckError, CheckResult, ReceiptCheck},
+        state::{Checking, Failed},
+        Context, ReceiptError, ReceiptWithState, WithReceiptCommitment, WithReceiptHash,
+        WithValueAndTimestamp,
+    },
+    signed_message::{Eip712SignedMessage, MessageId},
+    Error,
+};
+
+/// Result of [`verify_rav_against_receipts`]
+#[derive(Debug)]
+pub struct RavAuditReport<Rcpt> {
+    /// Signer recovered from the claimed RAV
+    pub rav_signer: Address,
+    /// `valueAggregate` found in the claimed RAV
+    pub claimed_value_aggregate: u128,
+    /// Aggregate value recomputed from the valid receipts
+    pub recomputed_value_aggregate: u128,
+    /// Receipts that were excluded from the recomputation, along with the reason
+    pub invalid_receipts: Vec<ReceiptWithState<Failed, Rcpt>>,
+}
+
+impl<Rcpt> RavAuditReport<Rcpt> {
+    /// Returns `true` if every receipt is valid and the recomputed aggregate
+    /// matches the claimed one
+    pub fn is_consistent(&self) -> bool {
+        self.invalid_receipts.is_empty()
+            && self.claimed_value_aggregate == self.recomputed_value_aggregate
+    }
+}
+
+/// Result of [`audit_rav_receipts`]
+#[derive(Debug)]
+pub struct RavDisputeReport<Rcpt> {
+    /// Signer recovered from the disputed RAV
+    pub rav_signer: Address,
+    /// `valueAggregate` found in the disputed RAV
+    pub claimed_value_aggregate: u128,
+    /// Aggregate value of the presented receipts that the sender signed
+    pub legitimate_value_aggregate: u128,
+    /// Presented receipts that are not part of the sender's signed set or
+    /// whose signature doesn't match the RAV signer
+    pub flagged_receipts: Vec<ReceiptWithState<Failed, Rcpt>>,
+}
+
+impl<Rcpt> RavDisputeReport<Rcpt> {
+    /// Returns `true` if the RAV claims more than the legitimate receipts add up to
+    pub fn is_inflated(&self) -> bool {
+        self.claimed_value_aggregate > self.legitimate_value_aggregate
+    }
+}
+
+/// Verifies that every receipt was signed by the same signer as the RAV.
+struct SignerCheck {
+    domain_separator: Eip712Domain,
+    expected_signer: Address,
+}
+
+#[async_trait::async_trait]
+impl<T> Check<Eip712SignedMessage<T>> for SignerCheck
+where
+    T: SolStruct + Send + Sync,
+{
+    async fn check(
+        &self,
+        _: &Context,
+        receipt: &ReceiptWithState<Checking, Eip712SignedMessage<T>>,
+    ) -> CheckResult {
+        let signer = receipt
+            .signed_receipt()
+            .recover_signer(&self.domain_separator)
+            .map_err(|e| CheckError::Failed(e.into()))?;
+        if signer != self.expected_signer {
+            return Err(CheckError::Failed(anyhow::anyhow!(
+                "Receipt signed by {signer}, expected the RAV signer {}",
+                self.expected_signer
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Verifies that every receipt is part of the set of receipts signed by the sender.
+struct SignedSetCheck {
+    sender_signed_set: HashSet<MessageId>,
+}
+
+#[async_trait::async_trait]
+impl<T> Check<Eip712SignedMessage<T>> for SignedSetCheck
+where
+    T: SolStruct + Send + Sync,
+{
+    async fn check(
+        &self,
+        _: &Context,
+        receipt: &ReceiptWithState<Checking, Eip712SignedMessage<T>>,
+    ) -> CheckResult {
+        if !self
+            .sender_signed_set
+            .contains(&receipt.signed_receipt().unique_hash())
+        {
+            return Err(CheckError::Failed(anyhow::anyhow!(
+                "Receipt is not part of the sender's signed receipts"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Runs `checks` on every receipt, then aggregates the ones that passed on
+/// top of `previous_rav`, if any. Returns the aggregate value along with the
+/// receipts that failed.
+async fn recompute_value_aggregate<T, Rav>(
+    checks: &[ReceiptCheck<Eip712SignedMessage<T>>],
+    receipts: Vec<Eip712SignedMessage<T>>,
+    previous_rav: Option<Eip712SignedMessage<Rav>>,
+) -> Result<(u128, Vec<ReceiptWithState<Failed, Eip712SignedMessage<T>>>), Error>
+where
+    T: SolStruct + Send + Sync + 'static,
+    Rav: SolStruct + WithValueAndTimestamp + Aggregate<Eip712SignedMessage<T>>,
+{
+    let ctx = Context::new();
+    let mut valid_receipts = vec![];
+    let mut invalid_receipts = vec![];
+    for receipt in receipts {
+        match ReceiptWithState::new(receipt)
+            .finalize_receipt_checks(&ctx, checks)
+            .await
+            .map_err(|e| Error::ReceiptError(ReceiptError::RetryableCheck(e)))?
+        {
+            Ok(checked) => valid_receipts.push(checked),
+            Err(failed) => invalid_receipts.push(failed),
+        }
+    }
+
+    let previous_value = previous_rav.as_ref().map_or(0, |rav| rav.message.value());
+    let value_aggregate = match Rav::aggregate_receipts(&valid_receipts, previous_rav) {
+        Ok(rav) => rav.value(),
+        Err(AggregationError::NoValidReceiptsForRavRequest) => previous_value,
+        Err(e) => return Err(e.into()),
+    };
+    Ok((value_aggregate, invalid_receipts))
+}
+
+/// Recomputes the aggregate of `receipts` and compares it to `claimed_rav`.
+///
+/// Every receipt signature is recovered and must match the RAV signer.
+/// Receipts failing that are reported as invalid and are left out of the
+/// recomputed aggregate. The recomputation itself reuses the [`Aggregate`]
+/// implementation of the RAV type, so it behaves exactly like a RAV request.
+///
+/// # Errors
+///
+/// Returns [`Error::SignatureError`] if the signer of `claimed_rav` can't be
+/// recovered
+///
+/// Returns [`Error::AggregationError`] if the valid receipts can't be
+/// aggregated, e.g. if their values overflow
+///
+pub async fn verify_rav_against_receipts<T, Rav>(
+    domain_separator: &Eip712Domain,
+    receipts: Vec<Eip712SignedMessage<T>>,
+    claimed_rav: &Eip712SignedMessage<Rav>,
+) -> Result<RavAuditReport<Eip712SignedMessage<T>>, Error>
+where
+    T: SolStruct + Send + Sync + 'static,
+    Rav: SolStruct + WithValueAndTimestamp + Aggregate<Eip712SignedMessage<T>>,
+{
+    let rav_signer = claimed_rav.recover_signer(domain_separator)?;
+    let checks: [ReceiptCheck<Eip712SignedMessage<T>>; 1] = [Arc::new(SignerCheck {
+        domain_separator: domain_separator.clone(),
+        expected_signer: rav_signer,
+    })];
+
+    let (recomputed_value_aggregate, invalid_receipts) =
+        recompute_value_aggregate::<T, Rav>(&checks, receipts, None).await?;
+
+    Ok(RavAuditReport {
+        rav_signer,
+        claimed_value_aggregate: claimed_rav.message.value(),
+        recomputed_value_aggregate,
+        invalid_receipts,
+    })
+}
+
+/// Checks the receipts presented to back `rav` against the receipts the
+/// sender actually signed, identified by their [`MessageId`].
+///
+/// A presented receipt is flagged if it's not in `sender_signed_set`, or if
+/// its signature doesn't match the RAV signer. Only the remaining receipts
+/// count towards the legitimate aggregate, so a sender can contest a RAV
+/// whose [`RavDisputeReport::is_inflated`] returns `true`.
+///
+/// # Errors
+///
+/// Returns [`Error::SignatureError`] if the signer of `rav` can't be recovered
+///
+/// Returns [`Error::AggregationError`] if the legitimate receipts can't be
+/// aggregated, e.g. if their values overflow
+///
+pub async fn audit_rav_receipts<T, Rav>(
+    domain_separator: &Eip712Domain,
+    rav: &Eip712SignedMessage<Rav>,
+    presented_receipts: Vec<Eip712SignedMessage<T>>,
+    sender_signed_set: HashSet<MessageId>,
+) -> Result<RavDisputeReport<Eip712SignedMessage<T>>, Error>
+where
+    T: SolStruct + Send + Sync + 'static,
+    Rav: SolStruct + WithValueAndTimestamp + Aggregate<Eip712SignedMessage<T>>,
+{
+    let rav_signer = rav.recover_signer(domain_separator)?;
+    let checks: [ReceiptCheck<Eip712SignedMessage<T>>; 2] = [
+        Arc::new(SignerCheck {
+            domain_separator: domain_separator.clone(),
+            expected_signer: rav_signer,
+        }),
+        Arc::new(SignedSetCheck { sender_signed_set }),
+    ];
+
+    let (legitimate_value_aggregate, flagged_receipts) =
+        recompute_value_aggregate::<T, Rav>(&checks, presented_receipts, None).await?;
+
+    Ok(RavDisputeReport {
+        rav_signer,
+        claimed_value_aggregate: rav.message.value(),
+        legitimate_value_aggregate,
+        flagged_receipts,
+    })
+}
+
+/// Checks that `rav` commits to `receipts`, i.e. that the Merkle root of the
+/// hashes of `receipts`, in order, is the commitment embedded in the RAV, see
+/// [`crate::rav_request::RavRequest::receipt_merkle_root`]. The signature of
+/// the RAV isn't verified.
+///
+/// # Errors
+///
+/// Returns [`Error::MissingReceiptCommitment`] if the RAV embeds no commitment
+///
+/// Returns [`Error::ReceiptCommitmentMismatch`] if the commitment doesn't
+/// match `receipts`
+///
+pub fn verify_rav_receipt_commitment<Rcpt, Rav>(
+    rav: &Eip712SignedMessage<Rav>,
+    receipts: &[Rcpt],
+) -> Result<(), Error>
+where
+    Rcpt: WithReceiptHash,
+    Rav: SolStruct + WithReceiptCommitment,
+{
+    let committed = rav
+        .message
+        .receipt_commitment()
+        .ok_or(Error::MissingReceiptCommitment)?;
+    let receipt_hashes: Vec<_> = receipts.iter().map(WithReceiptHash::receipt_hash).collect();
+    let computed = merkle_root(&receipt_hashes);
+    if computed != committed {
+        return Err(Error::ReceiptCommitmentMismatch {
+            committed: B256::from(committed),
+            computed: B256::from(computed),
+        });
+    }
+    Ok(())
+}
+
+/// Signed RAV along with the receipts backing it, for an on-chain redemption
+/// service or an auditor to check independently, see
+/// [`crate::manager::Manager::export_rav_bundle`].
+///
+/// The RAV aggregates `receipts` on top of `previous_rav`, if any, which is
+/// why it is part of the bundle.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RavBundle<Rcpt, Rav: SolStruct> {
+    /// RAV to redeem or audit
+    pub rav: Eip712SignedMessage<Rav>,
+    /// RAV that `rav` was chained on, if any
+    pub previous_rav: Option<Eip712SignedMessage<Rav>>,
+    /// Receipts aggregated into `rav` on top of `previous_rav`
+    pub receipts: Vec<Rcpt>,
+}
+
+impl<T, Rav> RavBundle<Eip712SignedMessage<T>, Rav>
+where
+    T: SolStruct + Clone + Send + Sync + 'static,
+    Rav: SolStruct + WithValueAndTimestamp + Aggregate<Eip712SignedMessage<T>> + Clone,
+{
+    /// Recomputes the aggregate of the bundle and compares it to the RAV,
+    /// like [`verify_rav_against_receipts`] but on top of the previous RAV.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::SignatureError`] if the signer of the RAV can't be
+    /// recovered
+    ///
+    /// Returns [`Error::CorruptPreviousRav`] if the previous RAV isn't signed
+    /// by the RAV signer
+    ///
+    /// Returns [`Error::AggregationError`] if the valid receipts can't be
+    /// aggregated, e.g. if their values overflow
+    ///
+    pub async fn verify(
+        &self,
+        domain_separator: &Eip712Domain,
+    ) -> Result<RavAuditReport<Eip712SignedMessage<T>>, Error> {
+        let rav_signer = self.rav.recover_signer(domain_separator)?;
+        if let Some(previous_rav) = &self.previous_rav {
+            let previous_signer = previous_rav.recover_signer(domain_separator)?;
+            if previous_signer != rav_signer {
+                return Err(Error::CorruptPreviousRav {
+                    source_error_message: format!(
+                        "Signed by {previous_signer}, expected the RAV signer {rav_signer}"
+                    ),
+                });
+            }
+        }
+        let checks: [ReceiptCheck<Eip712SignedMessage<T>>; 1] = [Arc::new(SignerCheck {
+            domain_separator: domain_separator.clone(),
+            expected_signer: rav_signer,
+        })];
+
+        let (recomputed_value_aggregate, invalid_receipts) = recompute_value_aggregate::<T, Rav>(
+            &checks,
+            self.receipts.clone(),
+            self.previous_rav.clone(),
+        )
+        .await?;
+
+        Ok(RavAuditReport {
+            rav_signer,
+            claimed_value_aggregate: self.rav.message.value(),
+            recomputed_value_aggregate,
+            invalid_receipts,
+        })
+    }
+}
@@ -6,10 +6,10 @@
 
 use std::result::Result as StdResult;
 
-use alloy::primitives::Address;
+use alloy::primitives::{Address, B256};
 use thiserror::Error as ThisError;
 
-use crate::receipt::ReceiptError;
+use crate::receipt::{rav::AggregationError, ReceiptError};
 
 /// Error type for the TAP protocol
 #[derive(ThisError, Debug)]
@@ -52,6 +52,10 @@ pub enum Error {
     #[error("Previous RAV allocation id ({prev_id}) doesn't match the allocation id from the new receipt ({new_id}).")]
     RavAllocationIdMismatch { prev_id: String, new_id: String },
 
+    /// Error when receipts can't be aggregated into a RAV
+    #[error(transparent)]
+    AggregationError(#[from] AggregationError),
+
     /// Error when all receipts do not have the same allocation id
     ///
     /// Used in tap_aggregator
@@ -80,14 +84,223 @@ pub enum Error {
     ReceiptError(#[from] ReceiptError),
 
     /// Error when the recovered signer address is invalid
-    /// Used by [`crate::manager::adapters::EscrowHandler`]
+    /// Used by [`crate::manager::adapters::EscrowHandler`] and
+    /// [`crate::rav_request::verify_rav_chain()`]
     #[error("Recovered sender address invalid {address}")]
     InvalidRecoveredSigner { address: Address },
 
+    /// Error when the recovered signer of a RAV is not authorized.
+    /// `digest` is the EIP-712 digest that was recovered from, only set when
+    /// signature debugging is enabled on the manager.
+    /// Used by [`crate::manager::Manager::verify_and_store_rav()`]
+    #[error(
+        "Signature mismatch, recovered signer {recovered_address}{}",
+        .digest.map(|digest| format!(" from EIP-712 digest {digest}")).unwrap_or_default()
+    )]
+    SignatureMismatch {
+        recovered_address: Address,
+        digest: Option<B256>,
+    },
+
     /// Indicates a failure while verifying the signer
     /// Used by [`crate::manager::adapters::EscrowHandler`]
     #[error("Failed to check the signer: {0}")]
     FailedToVerifySigner(String),
+
+    /// Error when receipt ingestion is paused by the operator.
+    /// This is retryable, the receipt can be resent once ingestion resumes.
+    /// Used by [`crate::manager::Manager::verify_and_store_receipt()`]
+    #[error("Receipt ingestion is paused, retry later")]
+    IngestionPaused,
+
+    /// Error when the request carries a chain id the manager has no domain separator for
+    #[error("No domain separator configured for chain id {chain_id}")]
+    UnknownChainId { chain_id: u64 },
+
+    /// Error when the request carries no [`crate::manager::RavKey`] to chain
+    /// or store the RAV under
+    #[error("No sender and allocation found in the request context")]
+    MissingRavKey,
+
+    /// Error when too many receipts are waiting to be aggregated into a RAV.
+    /// This is retryable, the receipt can be resent once a RAV is stored.
+    /// Used by [`crate::manager::Manager::verify_and_store_receipt()`]
+    #[error("Too many receipts pending aggregation ({pending}/{limit}), retry later")]
+    BackpressureLimitReached { pending: u64, limit: u64 },
+
+    /// Error when the previous RAV loaded from storage isn't signed by an
+    /// authorized signer, which means the storage can't be trusted.
+    /// Used by [`crate::manager::Manager::create_rav_request()`]
+    #[error("Previous RAV in storage failed verification: {source_error_message}")]
+    CorruptPreviousRav { source_error_message: String },
+
+    /// Error when the expected RAV is worth less than the configured
+    /// profitability threshold.
+    /// Used by [`crate::manager::Manager::create_rav_request()`]
+    #[error(
+        "RAV value {rav_value} is below the profitability threshold of {min_profitable_value}"
+    )]
+    RavBelowProfitabilityThreshold {
+        rav_value: u128,
+        min_profitable_value: u128,
+    },
+
+    /// Error when the value of the expected RAV isn't the sum of the values
+    /// it aggregates, which points to an aggregation bug.
+    /// Used by [`crate::rav_request::RavRequest::validate_aggregate()`]
+    #[error("Expected RAV value {expected_rav_value} doesn't match the aggregated value {recomputed_value}")]
+    AggregateMismatch {
+        expected_rav_value: u128,
+        recomputed_value: u128,
+    },
+
+    /// Error when a RAV is older, or worth less, than the last RAV stored for
+    /// its signer and allocation, so it can't follow it.
+    /// Used by [`crate::manager::Manager::verify_ravs()`]
+    #[error("RAV (timestamp {timestamp_ns}, value {value_aggregate}) can't follow the last stored RAV (timestamp {last_timestamp_ns}, value {last_value_aggregate})")]
+    RavNotChained {
+        timestamp_ns: u64,
+        value_aggregate: u128,
+        last_timestamp_ns: u64,
+        last_value_aggregate: u128,
+    },
+
+    /// Error when a signing payload is signed with another domain separator
+    /// than the one it was built for.
+    /// Used by [`crate::rav_request::SigningPayload::sign()`]
+    #[error("Signing payload is for domain separator {expected}, not {received}")]
+    SigningDomainMismatch { expected: B256, received: B256 },
+
+    /// Error when two signers meant to hold the same key sign for different
+    /// addresses.
+    /// Used by [`crate::rav_request::dual_sign_and_verify()`]
+    #[error("Local signer {local} and remote signer {remote} don't match")]
+    SignerMismatch { local: Address, remote: Address },
+
+    /// Error when a RAV of a history doesn't follow the RAV before it, i.e.
+    /// isn't later or is worth less.
+    /// Used by [`crate::rav_request::verify_rav_chain()`]
+    #[error("RAV #{index} (timestamp {timestamp_ns}, value {value_aggregate}) doesn't follow the previous RAV (timestamp {previous_timestamp_ns}, value {previous_value_aggregate})")]
+    BrokenRavChain {
+        index: usize,
+        timestamp_ns: u64,
+        value_aggregate: u128,
+        previous_timestamp_ns: u64,
+        previous_value_aggregate: u128,
+    },
+
+    /// Error when a RAV embeds no commitment to its receipts.
+    /// Used by [`crate::audit::verify_rav_receipt_commitment()`]
+    #[error("The RAV carries no receipt commitment")]
+    MissingReceiptCommitment,
+
+    /// Error when the receipt commitment of a RAV doesn't match the receipts
+    /// presented for it.
+    /// Used by [`crate::audit::verify_rav_receipt_commitment()`]
+    #[error("RAV commits to receipts root {committed}, but the receipts have root {computed}")]
+    ReceiptCommitmentMismatch { committed: B256, computed: B256 },
+
+    /// Error when the ingestion task of a manager is started more than once.
+    /// Used by [`crate::manager::Manager::start_ingestion()`]
+    #[error("Receipt ingestion task already started")]
+    IngestionAlreadyStarted,
+
+    /// Error when no check of the manager has the given name.
+    /// Used by [`crate::manager::Manager::set_check_enabled()`]
+    #[error("No check named {check_name}")]
+    UnknownCheck { check_name: String },
+
+    /// Error when there is no RAV for a sender and allocation.
+    /// Used by [`crate::manager::Manager::export_rav_bundle()`]
+    #[error("No RAV found for sender {sender} and allocation {allocation_id}")]
+    NoRav {
+        sender: Address,
+        allocation_id: Address,
+    },
+
+    /// Error when the receipts of a batch were signed by different senders.
+    /// Used by [`crate::signed_message::verify_same_sender()`]
+    #[error("Receipts of sender {expected} mixed with receipts of sender {received}")]
+    MixedSenders {
+        expected: Address,
+        received: Address,
+    },
+
+    /// Error when a batch expected to hold receipts is empty
+    #[error("The receipt batch is empty")]
+    EmptyReceiptBatch,
+
+    /// Error when a receipt was sent as another version than the one supported
+    /// by the manager, see
+    /// [`crate::manager::Manager::with_supported_receipt_version()`]
+    #[error("Unsupported receipt version {received} (expected {expected})")]
+    UnsupportedReceiptVersion { received: u8, expected: u8 },
+
+    /// Error when a storage adapter rejects a write because its quota is used
+    /// up. Adapters signal it by returning it as, or as a source of, their
+    /// `AdapterError`. This is retryable, the write can succeed once space
+    /// frees up.
+    /// Used by [`crate::manager::Manager::verify_and_store_receipt()`] and
+    /// [`crate::manager::Manager::verify_and_store_rav()`]
+    #[error("Storage quota exceeded: {message}")]
+    StorageQuotaExceeded { message: String },
+
+    /// Error when the domain separator of a manager doesn't have the expected
+    /// verifying contract, which would fail the verification of every receipt.
+    /// Used by [`crate::manager::Manager::new_validated()`]
+    #[error("Domain separator verifying contract is {configured:?}, expected {expected}")]
+    VerifyingContractMismatch {
+        expected: Address,
+        configured: Option<Address>,
+    },
+
+    /// Error when running aggregates are used without being enabled.
+    /// Used by [`crate::manager::Manager::running_rav()`] and
+    /// [`crate::manager::Manager::rebuild_running_aggregates()`]
+    #[error("Running aggregates are not enabled, see Manager::with_running_aggregates")]
+    RunningAggregatesDisabled,
+
+    /// Error when the query index is used without equivocation detection
+    /// enabled.
+    /// Used by [`crate::manager::Manager::rebuild_query_index()`]
+    #[error("Equivocation detection is not enabled, see Manager::with_equivocation_detection")]
+    EquivocationDetectionDisabled,
+
+    /// Error when a receipt shares its query id with a stored receipt of the
+    /// same sender and allocation but differs in value, i.e. the sender signed two
+    /// conflicting receipts for the same query.
+    /// Used by [`crate::manager::Manager::verify_and_store_receipt()`]
+    #[error("Receipt equivocation: conflicting receipts signed for query id {query_id}")]
+    ReceiptEquivocation { query_id: u64 },
+
+    /// Error when a sink added with
+    /// [`crate::manager::Manager::with_required_rav_sink()`] fails to take a
+    /// stored RAV. The RAV stays stored and the other sinks still get it.
+    /// Used by [`crate::manager::Manager::verify_and_store_rav()`] and
+    /// [`crate::manager::Manager::verify_and_commit_rav()`]
+    #[error("RAV stored, but a required RAV sink failed: {source_error}")]
+    RavSinkFailed { source_error: anyhow::Error },
+
+    /// Error when a RAV request is cancelled through the
+    /// [`CancellationToken`](tokio_util::sync::CancellationToken) of its context
+    #[error("The RAV request was cancelled")]
+    Cancelled,
+}
+
+impl Error {
+    /// Whether the failed operation may succeed if retried later, e.g. once
+    /// ingestion resumes or storage is reachable again. Other errors are
+    /// permanent.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::IngestionPaused
+            | Error::BackpressureLimitReached { .. }
+            | Error::StorageQuotaExceeded { .. }
+            | Error::AdapterError { .. } => true,
+            Error::ReceiptError(error) => error.is_retryable(),
+            _ => false,
+        }
+    }
 }
 
 pub type Result<T> = StdResult<T, Error>;
@@ -11,10 +11,13 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use alloy::{dyn_abi::Eip712Domain, sol_types::eip712_domain};
 use thiserror::Error;
 
+pub mod audit;
 mod error;
 pub mod manager;
+pub mod merkle;
 pub mod rav_request;
 pub mod receipt;
+pub mod signature_cache;
 pub mod signed_message;
 
 pub use error::Error;
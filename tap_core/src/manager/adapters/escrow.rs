@@ -0,0 +1,92 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use alloy::primitives::Address;
+use async_trait::async_trait;
+
+/// Reserves and releases escrow of senders atomically, so that concurrent
+/// receipts can't commit the same escrow twice.
+///
+/// A receipt reserves its value while it is checked, and releases it if it
+/// ends up rejected, instead of reading the balance then deciding.
+///
+/// # Example
+///
+/// For example code see [crate::manager::context::memory::EscrowStorage]
+#[async_trait]
+pub trait EscrowHandler: Send + Sync {
+    /// Defines the user-specified error type.
+    ///
+    /// This error type should implement the `Error` and `Debug` traits from
+    /// the standard library.
+    /// Errors of this type are returned to the user when an operation fails.
+    type AdapterError: std::error::Error + std::fmt::Debug + Send + Sync + 'static;
+
+    /// Reserves `amount` of the escrow of `sender`, if still available.
+    ///
+    /// This method should be implemented so that checking the available
+    /// escrow and reserving it is a single atomic operation, e.g. a
+    /// conditional `UPDATE` in a SQL database. Returns `false` without
+    /// reserving anything if `amount` exceeds the available escrow.
+    async fn try_reserve(&self, sender: Address, amount: u128) -> Result<bool, Self::AdapterError>;
+
+    /// Makes `amount` previously reserved with [`EscrowHandler::try_reserve`]
+    /// available again to `sender`.
+    async fn release(&self, sender: Address, amount: u128) -> Result<(), Self::AdapterError>;
+
+    /// Returns the escrow of `sender` still available, i.e. not reserved.
+    ///
+    /// Reading the escrow doesn't reserve it, use
+    /// [`EscrowHandler::try_reserve`] to commit escrow to a receipt.
+    async fn available_escrow(&self, sender: Address) -> Result<u128, Self::AdapterError>;
+
+    /// Same as [`EscrowHandler::try_reserve`], for the escrow `sender`
+    /// deposited in `token`. Escrow deposited in one token never covers
+    /// receipts denominated in another, see [`crate::receipt::WithToken`].
+    ///
+    /// Defaults to [`EscrowHandler::try_reserve`], for adapters holding
+    /// escrow in a single token.
+    async fn try_reserve_in_token(
+        &self,
+        sender: Address,
+        _token: Address,
+        amount: u128,
+    ) -> Result<bool, Self::AdapterError> {
+        self.try_reserve(sender, amount).await
+    }
+
+    /// Same as [`EscrowHandler::release`], for escrow reserved with
+    /// [`EscrowHandler::try_reserve_in_token`].
+    ///
+    /// Defaults to [`EscrowHandler::release`].
+    async fn release_in_token(
+        &self,
+        sender: Address,
+        _token: Address,
+        amount: u128,
+    ) -> Result<(), Self::AdapterError> {
+        self.release(sender, amount).await
+    }
+
+    /// Same as [`EscrowHandler::available_escrow`], for the escrow `sender`
+    /// deposited in `token`.
+    ///
+    /// Defaults to [`EscrowHandler::available_escrow`].
+    async fn available_escrow_in_token(
+        &self,
+        sender: Address,
+        _token: Address,
+    ) -> Result<u128, Self::AdapterError> {
+        self.available_escrow(sender).await
+    }
+}
+
+/// Notified of receipts rejected because their sender ran out of escrow,
+/// e.g. to alert the sender that it needs to top up.
+///
+/// Set with [`crate::manager::Manager::with_escrow_monitor`].
+pub trait EscrowMonitor: Send + Sync {
+    /// Called when a check rejects a receipt of `sender` worth `needed` with
+    /// only `available` escrow left to cover it
+    fn on_escrow_insufficient(&self, sender: Address, needed: u128, available: u128);
+}
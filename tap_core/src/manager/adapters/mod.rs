@@ -8,10 +8,12 @@
 //! allows for easy integration with various storage solutions and verification
 //! procedures, thereby making the library adaptable to a wide range of use cases.
 
+mod escrow;
 mod rav;
 mod receipt;
 mod signature;
 
+pub use escrow::{EscrowHandler, EscrowMonitor};
 pub use rav::*;
 pub use receipt::*;
 pub use signature::SignatureChecker;
@@ -1,12 +1,18 @@
 // Copyright 2023-, Semiotic AI, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use alloy::sol_types::SolStruct;
+use std::ops::RangeBounds;
+
+use alloy::{
+    primitives::{Address, Bytes, PrimitiveSignature as Signature},
+    sol_types::{SolStruct, SolType, SolValue},
+};
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 
 use crate::signed_message::Eip712SignedMessage;
 
-/// Stores the latest RAV in the storage.
+/// Stores the RAVs of each sender and allocation in the storage
 ///
 /// # Example
 ///
@@ -21,12 +27,84 @@ pub trait RavStore<T: SolStruct> {
     /// Errors of this type are returned to the user when an operation fails.
     type AdapterError: std::error::Error + std::fmt::Debug + Send + Sync + 'static;
 
-    /// Updates the storage with the latest validated `SignedRAV`.
+    /// Updates the storage with the latest validated `SignedRAV` of `sender`
+    /// for `allocation_id`.
     ///
     /// This method should be implemented to store the most recent validated
-    /// `SignedRAV` into your chosen storage system. Any errors that occur
-    /// during this process should be captured and returned as an `AdapterError`.
-    async fn update_last_rav(&self, rav: Eip712SignedMessage<T>) -> Result<(), Self::AdapterError>;
+    /// `SignedRAV` in your chosen storage system, making it the latest RAV of
+    /// the `(sender, allocation_id)` key. Adapters implementing
+    /// [`RavHistoryRead`] keep the RAV it replaces as history. Any errors that
+    /// occur during this process should be captured and returned as an
+    /// `AdapterError`.
+    async fn update_last_rav(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+        rav: Eip712SignedMessage<T>,
+    ) -> Result<(), Self::AdapterError>;
+}
+
+/// Stores a RAV and removes the receipts it aggregates as a single
+/// transaction, so that storage never holds a RAV whose receipts weren't
+/// removed, or removed receipts without their RAV.
+///
+/// Used by [`crate::manager::Manager::verify_and_commit_rav`].
+///
+/// # Example
+///
+/// For example code see [crate::manager::context::memory::InMemoryContext]
+
+#[async_trait]
+pub trait RavTransaction<T: SolStruct> {
+    /// Defines the user-specified error type.
+    ///
+    /// This error type should implement the `Error` and `Debug` traits from
+    /// the standard library.
+    /// Errors of this type are returned to the user when an operation fails.
+    type AdapterError: std::error::Error + std::fmt::Debug + Send + Sync + 'static;
+
+    /// Stores `rav` as the latest RAV of `sender` for `allocation_id`, like
+    /// [`RavStore::update_last_rav`], and removes the receipts of `sender` for
+    /// `allocation_id` within `receipts_timestamp_range_ns`, like
+    /// [`crate::manager::adapters::ReceiptDelete::remove_sender_receipts_in_timestamp_range`],
+    /// returning the number of receipts removed. The receipts of any other
+    /// sender or allocation must be kept.
+    ///
+    /// Both changes must be committed together or not at all: in a SQL
+    /// database, this would run both statements in a single transaction,
+    /// rolled back when any of them fails. Any errors that occur during this
+    /// process should be captured and returned as an `AdapterError`.
+    async fn commit_rav<R: RangeBounds<u64> + std::marker::Send>(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+        rav: Eip712SignedMessage<T>,
+        receipts_timestamp_range_ns: R,
+    ) -> Result<u64, Self::AdapterError>;
+}
+
+/// Deletes past RAVs from the history read by [`RavHistoryRead`].
+///
+/// # Example
+///
+/// For example code see [crate::manager::context::memory::RAVStorage]
+
+#[async_trait]
+pub trait RavDelete {
+    /// Defines the user-specified error type.
+    ///
+    /// This error type should implement the `Error` and `Debug` traits from
+    /// the standard library.
+    /// Errors of this type are returned to the user when an operation fails.
+    type AdapterError: std::error::Error + std::fmt::Debug + Send + Sync + 'static;
+
+    /// Removes all but the `keep_latest` most recent RAVs of every
+    /// `(sender, allocation_id)` key, and returns the number of RAVs removed.
+    ///
+    /// `keep_latest` is at least 1, the latest RAV of a key must never be
+    /// removed. Any errors that occur during this process should be captured
+    /// and returned as an `AdapterError`.
+    async fn prune_rav_history(&self, keep_latest: usize) -> Result<u64, Self::AdapterError>;
 }
 
 /// Reads the RAV from storage
@@ -44,8 +122,134 @@ pub trait RavRead<T: SolStruct> {
     /// Errors of this type are returned to the user when an operation fails.
     type AdapterError: std::error::Error + std::fmt::Debug + Send + Sync + 'static;
 
-    /// Retrieves the latest `SignedRAV` from the storage.
+    /// Retrieves the latest `SignedRAV` of `sender` for `allocation_id` from
+    /// the storage.
     ///
     /// If no `SignedRAV` is available, this method should return `None`.
-    async fn last_rav(&self) -> Result<Option<Eip712SignedMessage<T>>, Self::AdapterError>;
+    async fn get_last_rav(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+    ) -> Result<Option<Eip712SignedMessage<T>>, Self::AdapterError>;
+}
+
+/// Reads the RAVs replaced in storage along with the latest ones
+///
+/// # Example
+///
+/// For example code see [crate::manager::context::memory::InMemoryContext]
+
+#[async_trait]
+pub trait RavHistoryRead<T: SolStruct> {
+    /// Defines the user-specified error type.
+    ///
+    /// This error type should implement the `Error` and `Debug` traits from
+    /// the standard library.
+    /// Errors of this type are returned to the user when an operation fails.
+    type AdapterError: std::error::Error + std::fmt::Debug + Send + Sync + 'static;
+
+    /// Retrieves every `SignedRAV` of `sender` for `allocation_id`, in the
+    /// order they were stored, the last one being the latest.
+    ///
+    /// Any errors that occur during this process should be captured and
+    /// returned as an `AdapterError`.
+    async fn get_rav_history(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+    ) -> Result<Vec<Eip712SignedMessage<T>>, Self::AdapterError>;
+
+    /// Retrieves every `SignedRAV` ever stored, latest or not, with a
+    /// timestamp within `timestamp_range_ns`, ordered by timestamp.
+    ///
+    /// In a SQL database, this would be a range query over an index on the
+    /// RAV timestamp. Any errors that occur during this process should be
+    /// captured and returned as an `AdapterError`.
+    async fn list_ravs_in_range<R: RangeBounds<u64> + std::marker::Send>(
+        &self,
+        timestamp_range_ns: R,
+    ) -> Result<Vec<Eip712SignedMessage<T>>, Self::AdapterError>;
+}
+
+/// Lists the latest RAV of every sender and allocation in storage
+///
+/// # Example
+///
+/// For example code see [crate::manager::context::memory::InMemoryContext]
+
+#[async_trait]
+pub trait RavListRead<T: SolStruct> {
+    /// Defines the user-specified error type.
+    ///
+    /// This error type should implement the `Error` and `Debug` traits from
+    /// the standard library.
+    /// Errors of this type are returned to the user when an operation fails.
+    type AdapterError: std::error::Error + std::fmt::Debug + Send + Sync + 'static;
+
+    /// Retrieves the latest `SignedRAV` of every `(sender, allocation_id)`
+    /// key, along with its sender and allocation id.
+    ///
+    /// In a SQL database, this would be a `SELECT DISTINCT ON (sender,
+    /// allocation_id)` over the RAVs table. Any errors that occur during this
+    /// process should be captured and returned as an `AdapterError`.
+    async fn get_last_ravs(
+        &self,
+    ) -> Result<Vec<(Address, Address, Eip712SignedMessage<T>)>, Self::AdapterError>;
+}
+
+/// RAV handed to a [`RavSink`], with its message ABI-encoded so that a single
+/// sink receives the RAVs of every type, e.g. V1 and V2 RAVs alike.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredRav {
+    /// Sender the RAV was stored for
+    pub sender: Address,
+    /// Allocation the RAV was stored for
+    pub allocation_id: Address,
+    /// ABI encoding of the RAV message, e.g. to redeem it on-chain
+    pub message: Bytes,
+    /// Signature of the RAV
+    pub signature: Signature,
+}
+
+impl StoredRav {
+    pub fn new<T: SolStruct + SolValue>(
+        sender: Address,
+        allocation_id: Address,
+        rav: &Eip712SignedMessage<T>,
+    ) -> Self {
+        Self {
+            sender,
+            allocation_id,
+            message: rav.message.abi_encode().into(),
+            signature: rav.signature,
+        }
+    }
+
+    /// Decodes the RAV message of type `T`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message isn't the ABI encoding of a `T`
+    ///
+    pub fn decode<T: SolStruct>(&self) -> alloy::sol_types::Result<Eip712SignedMessage<T>> {
+        Ok(Eip712SignedMessage {
+            message: <T as SolType>::abi_decode(&self.message, true)?,
+            signature: self.signature,
+        })
+    }
+}
+
+/// Receives every RAV stored by the manager, e.g. to push it to a queue for
+/// on-chain redemption.
+///
+/// Set with [`crate::manager::Manager::with_rav_sink`] or
+/// [`crate::manager::Manager::with_required_rav_sink`].
+#[async_trait]
+pub trait RavSink: Send + Sync {
+    /// Called after `rav` was verified and stored with [`RavStore::update_last_rav`].
+    ///
+    /// Errors are logged by the manager, or returned for a required sink, and
+    /// don't undo the storage of `rav`.
+    async fn on_rav_signed(&self, rav: &StoredRav) -> anyhow::Result<()>;
 }
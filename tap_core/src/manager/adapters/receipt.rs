@@ -3,11 +3,13 @@
 
 use std::ops::RangeBounds;
 
+use alloy::{dyn_abi::Eip712Domain, primitives::Address};
 use async_trait::async_trait;
 
 use crate::receipt::{
+    receipt_order_key,
     state::{Checking, ReceiptState},
-    ReceiptWithState, WithValueAndTimestamp,
+    ReceiptWithState, WithReceiptHash, WithValueAndTimestamp,
 };
 
 /// Stores receipts in the storage.
@@ -26,11 +28,52 @@ pub trait ReceiptStore<Rcpt> {
     /// Stores a new [`ReceiptWithState<Checking>`] into the storage.
     ///
     /// It returns a unique receipt_id associated with the stored receipt. Any errors that occur during
-    /// this process should be captured and returned as an `AdapterError`.
+    /// this process should be captured and returned as an `AdapterError`. A storage full because of
+    /// a quota should return an `AdapterError` whose source chain holds
+    /// [`crate::Error::StorageQuotaExceeded`].
     async fn store_receipt(
         &self,
         receipt: ReceiptWithState<Checking, Rcpt>,
     ) -> Result<u64, Self::AdapterError>;
+
+    /// Same as [`ReceiptStore::store_receipt`], along with the domain
+    /// separator the receipt signature was verified against, e.g. to recover
+    /// and persist its signer so that receipts can be read back by sender,
+    /// see [`ReceiptRead::retrieve_sender_receipts_in_timestamp_range`].
+    ///
+    /// Defaults to [`ReceiptStore::store_receipt`].
+    async fn store_receipt_in_domain(
+        &self,
+        receipt: ReceiptWithState<Checking, Rcpt>,
+        _domain_separator: &Eip712Domain,
+    ) -> Result<u64, Self::AdapterError>
+    where
+        Self: Sync,
+        Rcpt: Send + 'async_trait,
+    {
+        self.store_receipt(receipt).await
+    }
+
+    /// Same as [`ReceiptStore::store_receipt_in_domain`], for a receipt
+    /// accepted below the minimum timestamp during the grace period set with
+    /// [`crate::manager::Manager::with_below_min_timestamp_grace_period_ns`].
+    /// Adapters should flag it in storage, see
+    /// [`StoredReceipt::below_min_timestamp`].
+    ///
+    /// Defaults to [`ReceiptStore::store_receipt_in_domain`], without the
+    /// flag.
+    async fn store_receipt_below_min_timestamp(
+        &self,
+        receipt: ReceiptWithState<Checking, Rcpt>,
+        domain_separator: &Eip712Domain,
+    ) -> Result<u64, Self::AdapterError>
+    where
+        Self: Sync,
+        Rcpt: Send + 'async_trait,
+    {
+        self.store_receipt_in_domain(receipt, domain_separator)
+            .await
+    }
 }
 
 /// Deletes receipts from storage.
@@ -48,12 +91,71 @@ pub trait ReceiptDelete {
     /// Removes all [`ReceiptWithState<Checking>`] within a specific timestamp range from the storage.
     ///
     /// This method should be implemented to remove all `ReceivedReceipts` within a specific timestamp
-    /// range from your storage system. Any errors that occur during this process should be captured and
-    /// returned as an `AdapterError`.
+    /// range from your storage system. Any errors that occur during this process should be captured
+    /// and returned as an `AdapterError`.
     async fn remove_receipts_in_timestamp_range<R: RangeBounds<u64> + std::marker::Send>(
         &self,
         timestamp_ns: R,
     ) -> Result<(), Self::AdapterError>;
+
+    /// Same as [`ReceiptDelete::remove_receipts_in_timestamp_range`], also
+    /// returning the number of receipts removed, e.g. the affected rows of a
+    /// `DELETE` in a SQL database.
+    ///
+    /// Unlike [`ReceiptDelete::remove_sender_receipts_in_timestamp_range`],
+    /// the receipts removed may not be aggregated into a RAV yet. Adapters
+    /// that are also the [`crate::manager::adapters::EscrowHandler`] of the
+    /// receipts must release the escrow these reserved.
+    async fn remove_receipts_in_timestamp_range_counted<R: RangeBounds<u64> + std::marker::Send>(
+        &self,
+        timestamp_ns: R,
+    ) -> Result<u64, Self::AdapterError>;
+
+    /// Same as [`ReceiptDelete::remove_receipts_in_timestamp_range`], for the
+    /// receipts of `sender` for `allocation_id` only, e.g. once they are
+    /// aggregated into a RAV of this key. The receipts of other senders and
+    /// allocations must be left untouched.
+    ///
+    /// Defaults to [`ReceiptDelete::remove_receipts_in_timestamp_range`],
+    /// which suits adapters holding the receipts of a single sender and
+    /// allocation. Adapters holding the receipts of several ones must
+    /// override it.
+    async fn remove_sender_receipts_in_timestamp_range<R: RangeBounds<u64> + std::marker::Send>(
+        &self,
+        _sender: Address,
+        _allocation_id: Address,
+        timestamp_ns: R,
+    ) -> Result<(), Self::AdapterError>
+    where
+        Self: Sync,
+    {
+        self.remove_receipts_in_timestamp_range(timestamp_ns).await
+    }
+}
+
+/// Moves receipts out of storage into a quarantine.
+///
+/// # Example
+///
+/// For example code see [crate::manager::context::memory::InMemoryContext]
+#[async_trait]
+pub trait ReceiptQuarantine {
+    /// Defines the user-specified error type.
+    ///
+    /// This error type should implement the `Error` and `Debug` traits from the standard library.
+    /// Errors of this type are returned to the user when an operation fails.
+    type AdapterError: std::error::Error + std::fmt::Debug + Send + Sync + 'static;
+
+    /// Moves the receipts with the given ids to a quarantine, where they are kept for inspection
+    /// but no longer returned by [`ReceiptRead`] nor aggregated into RAVs. Unknown ids are
+    /// skipped.
+    ///
+    /// Adapters that are also the [`crate::manager::adapters::EscrowHandler`] of the receipts
+    /// must release the escrow reserved by the receipts quarantined that no RAV aggregates yet.
+    ///
+    /// Returns the number of receipts quarantined. Any errors that occur during this process
+    /// should be captured and returned as an `AdapterError`.
+    async fn quarantine_receipts(&self, receipt_ids: &[u64]) -> Result<u64, Self::AdapterError>;
 }
 
 /// Retrieves receipts from storage.
@@ -87,19 +189,199 @@ pub trait ReceiptRead<Rcpt> {
     /// You can use the [`safe_truncate_receipts()`] function to help with this, but feel free to
     /// implement a more efficient solution for your situation if you can.
     ///
+    /// The receipts may be returned in any order, the manager sorts them with
+    /// [`receipt_order_key()`](crate::receipt::receipt_order_key).
+    ///
     /// Any errors that occur during this process should be captured and returned as an `AdapterError`.
     async fn retrieve_receipts_in_timestamp_range<R: RangeBounds<u64> + std::marker::Send>(
         &self,
         timestamp_range_ns: R,
         limit: Option<u64>,
     ) -> Result<Vec<ReceiptWithState<Checking, Rcpt>>, Self::AdapterError>;
+
+    /// Same as [`ReceiptRead::retrieve_receipts_in_timestamp_range`], for the
+    /// receipts of `sender` for `allocation_id` only, i.e. those aggregated
+    /// into the RAVs of this key.
+    ///
+    /// In a SQL database, this would filter on the allocation id and on the
+    /// signers of `sender`. Defaults to
+    /// [`ReceiptRead::retrieve_receipts_in_timestamp_range`], which suits
+    /// adapters holding the receipts of a single sender and allocation.
+    /// Adapters holding the receipts of several ones must override it,
+    /// otherwise their receipts are aggregated together.
+    async fn retrieve_sender_receipts_in_timestamp_range<R: RangeBounds<u64> + std::marker::Send>(
+        &self,
+        _sender: Address,
+        _allocation_id: Address,
+        timestamp_range_ns: R,
+        limit: Option<u64>,
+    ) -> Result<Vec<ReceiptWithState<Checking, Rcpt>>, Self::AdapterError>
+    where
+        Self: Sync,
+    {
+        self.retrieve_receipts_in_timestamp_range(timestamp_range_ns, limit)
+            .await
+    }
+}
+
+/// Lists the allocations that receipts in storage belong to.
+///
+/// # Example
+///
+/// For example code see [crate::manager::context::memory::InMemoryContext]
+#[async_trait]
+pub trait ReceiptAllocationRead {
+    /// Defines the user-specified error type.
+    ///
+    /// This error type should implement the `Error` and `Debug` traits from the standard library.
+    /// Errors of this type are returned to the user when an operation fails.
+    type AdapterError: std::error::Error + std::fmt::Debug + Send + Sync + 'static;
+
+    /// Retrieves the distinct allocation ids of all receipts in the storage.
+    ///
+    /// In a SQL database, this would be a `SELECT DISTINCT allocation_id` over
+    /// the receipts table. Any errors that occur during this process should be
+    /// captured and returned as an `AdapterError`.
+    async fn retrieve_allocation_ids(&self) -> Result<Vec<Address>, Self::AdapterError>;
+}
+
+/// Receipt read back from storage along with its id and the signer recovered
+/// from its signature when it was stored, see [`StoredReceiptRead`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredReceipt<Rcpt> {
+    id: u64,
+    signer: Address,
+    receipt: Rcpt,
+    below_min_timestamp: bool,
+}
+
+impl<Rcpt> StoredReceipt<Rcpt> {
+    pub fn new(id: u64, signer: Address, receipt: Rcpt) -> Self {
+        Self {
+            id,
+            signer,
+            receipt,
+            below_min_timestamp: false,
+        }
+    }
+
+    /// Flags the receipt as stored with
+    /// [`ReceiptStore::store_receipt_below_min_timestamp`]
+    pub fn with_below_min_timestamp(mut self, below_min_timestamp: bool) -> Self {
+        self.below_min_timestamp = below_min_timestamp;
+        self
+    }
+
+    /// Returns the storage id of the receipt
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the signer persisted with the receipt, without running the
+    /// ECDSA recovery again. It is the sender itself unless the sender
+    /// authorized other signers, see
+    /// [`SignatureChecker::sender_of`](super::SignatureChecker::sender_of).
+    pub fn signer(&self) -> Address {
+        self.signer
+    }
+
+    /// Returns whether the receipt was accepted below the minimum timestamp
+    /// during the grace period, see
+    /// [`ReceiptStore::store_receipt_below_min_timestamp`]
+    pub fn below_min_timestamp(&self) -> bool {
+        self.below_min_timestamp
+    }
+
+    /// Returns the signed receipt
+    pub fn signed_receipt(&self) -> &Rcpt {
+        &self.receipt
+    }
+
+    pub fn into_signed_receipt(self) -> Rcpt {
+        self.receipt
+    }
+}
+
+/// Retrieves receipts from storage along with the signer persisted when they
+/// were stored with [`ReceiptStore::store_receipt_in_domain`], so that it
+/// doesn't need to be recovered again.
+///
+/// # Example
+///
+/// For example code see [crate::manager::context::memory::InMemoryContext]
+#[async_trait]
+pub trait StoredReceiptRead<Rcpt> {
+    /// Defines the user-specified error type.
+    ///
+    /// This error type should implement the `Error` and `Debug` traits from the standard library.
+    /// Errors of this type are returned to the user when an operation fails.
+    type AdapterError: std::error::Error + std::fmt::Debug + Send + Sync + 'static;
+
+    /// Retrieves all the receipts within a specific timestamp range as
+    /// [`StoredReceipt`]s.
+    ///
+    /// This method should be implemented to read the signer persisted
+    /// alongside each receipt rather than recovering it from the signature.
+    async fn retrieve_stored_receipts_in_timestamp_range<R: RangeBounds<u64> + std::marker::Send>(
+        &self,
+        timestamp_range_ns: R,
+    ) -> Result<Vec<StoredReceipt<Rcpt>>, Self::AdapterError>;
+
+    /// Same as [`StoredReceiptRead::retrieve_stored_receipts_in_timestamp_range`],
+    /// for the receipts of `sender` for `allocation_id` only, see
+    /// [`ReceiptRead::retrieve_sender_receipts_in_timestamp_range`].
+    async fn retrieve_stored_sender_receipts_in_timestamp_range<
+        R: RangeBounds<u64> + std::marker::Send,
+    >(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+        timestamp_range_ns: R,
+    ) -> Result<Vec<StoredReceipt<Rcpt>>, Self::AdapterError>;
+
+    /// Retrieves the receipts with the given storage ids as [`StoredReceipt`]s,
+    /// in the order of `receipt_ids`, skipping unknown ids.
+    ///
+    /// This is the efficient path when the ids are known already, e.g. to
+    /// fetch the receipts of a RAV again for an audit. In a SQL database, this
+    /// would be a `WHERE id IN (...)` over the receipts table.
+    async fn retrieve_stored_receipts_by_ids(
+        &self,
+        receipt_ids: &[u64],
+    ) -> Result<Vec<StoredReceipt<Rcpt>>, Self::AdapterError>;
+
+    /// Retrieves at most `limit` receipts with a storage id of at least
+    /// `from_id` as [`StoredReceipt`]s, ordered by id, e.g. to page through
+    /// every stored receipt in
+    /// [`crate::manager::Manager::rebuild_running_aggregates`].
+    ///
+    /// In a SQL database, this would be a `WHERE id >= $1 ORDER BY id LIMIT
+    /// $2` over the receipts table. Defaults to filtering every receipt read
+    /// with [`StoredReceiptRead::retrieve_stored_receipts_in_timestamp_range`],
+    /// which holds them all in memory at once.
+    async fn retrieve_stored_receipts_page(
+        &self,
+        from_id: u64,
+        limit: u64,
+    ) -> Result<Vec<StoredReceipt<Rcpt>>, Self::AdapterError>
+    where
+        Self: Sync,
+        Rcpt: Send + 'async_trait,
+    {
+        let mut receipts = self.retrieve_stored_receipts_in_timestamp_range(..).await?;
+        receipts.retain(|receipt| receipt.id() >= from_id);
+        receipts.sort_unstable_by_key(StoredReceipt::id);
+        receipts.truncate(usize::try_from(limit).unwrap_or(usize::MAX));
+        Ok(receipts)
+    }
 }
 
 /// See [`ReceiptRead::retrieve_receipts_in_timestamp_range()`] for details.
 ///
-/// WARNING: Will sort the receipts by timestamp using
-/// [vec::sort_unstable](https://doc.rust-lang.org/std/vec/struct.Vec.html#method.sort_unstable).
-pub fn safe_truncate_receipts<T: ReceiptState, Rcpt: WithValueAndTimestamp>(
+/// WARNING: Will sort the receipts by [`receipt_order_key()`], i.e. by
+/// timestamp then receipt hash, so that the same receipts always truncate to
+/// the same page in the same order.
+pub fn safe_truncate_receipts<T: ReceiptState, Rcpt: WithValueAndTimestamp + WithReceiptHash>(
     receipts: &mut Vec<ReceiptWithState<T, Rcpt>>,
     limit: u64,
 ) {
@@ -110,7 +392,7 @@ pub fn safe_truncate_receipts<T: ReceiptState, Rcpt: WithValueAndTimestamp>(
         return;
     }
 
-    receipts.sort_unstable_by_key(|rx_receipt| rx_receipt.signed_receipt().timestamp_ns());
+    receipts.sort_by_cached_key(|rx_receipt| receipt_order_key(rx_receipt.signed_receipt()));
 
     // This one will be the last timestamp in `receipts` after naive truncation
     let last_timestamp = receipts[limit as usize - 1].signed_receipt().timestamp_ns();
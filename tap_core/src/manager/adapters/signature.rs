@@ -24,6 +24,15 @@ pub trait SignatureChecker: Send + Sync {
     /// Verifies the signer of the receipt
     async fn verify_signer(&self, signer_address: Address) -> Result<bool, Self::AdapterError>;
 
+    /// Returns the sender `signer_address` signs on behalf of, e.g. from the
+    /// signers each sender authorized on-chain, to key its RAVs by sender.
+    ///
+    /// Defaults to `signer_address` itself, for senders signing their own
+    /// receipts and RAVs.
+    async fn sender_of(&self, signer_address: Address) -> Result<Address, Self::AdapterError> {
+        Ok(signer_address)
+    }
+
     /// Checks if the signed message has a sender signature
     async fn check_signature<T: SolStruct + Sync>(
         &self,
@@ -0,0 +1,185 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! # RAV archive
+//!
+//! [`RavArchiveSink`] appends every RAV stored by the manager to an
+//! append-only archive of NDJSON files, one signed RAV per line, e.g. for
+//! compliance. Requires the `archive` feature.
+//!
+//! A RAV that can't be archived must not go unnoticed, so add the sink with
+//! [`Manager::with_required_rav_sink`](crate::manager::Manager::with_required_rav_sink)
+//! to have the failure returned by the manager.
+//!
+//! ```rust,no_run
+//! # use std::sync::Arc;
+//! use tap_core::manager::archive::{ArchiveRotation, RavArchiveSink};
+//! # use tap_core::manager::adapters::RavSink;
+//!
+//! let sink = RavArchiveSink::new("/var/lib/tap/ravs", ArchiveRotation::Daily).with_fsync(true);
+//! let sink: Arc<dyn RavSink> = Arc::new(sink);
+//! ```
+
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+
+use super::adapters::{RavSink, StoredRav};
+
+/// When a [`RavArchiveSink`] moves on to a new file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveRotation {
+    /// A new file every UTC day, named `ravs-day-N.ndjson` with `N` the
+    /// number of days since the Unix epoch
+    Daily,
+    /// A new file once the current one would grow past the given number of
+    /// bytes, named `ravs-N.ndjson` with `N` counting up from 0. A RAV
+    /// larger than the limit still gets a file of its own.
+    MaxBytes(u64),
+}
+
+/// File currently appended to
+struct ArchiveFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+}
+
+/// Appends every RAV it receives to NDJSON files in a directory, rotated as
+/// set by [`ArchiveRotation`]. Existing files are appended to, and only
+/// truncated back to their last complete line when a write fails, so that a
+/// partial write never leaves a corrupt line behind.
+pub struct RavArchiveSink {
+    dir: PathBuf,
+    rotation: ArchiveRotation,
+    fsync: bool,
+    current: Mutex<Option<ArchiveFile>>,
+}
+
+impl RavArchiveSink {
+    /// Creates a sink archiving RAVs in `dir`, created if missing
+    pub fn new(dir: impl Into<PathBuf>, rotation: ArchiveRotation) -> Self {
+        Self {
+            dir: dir.into(),
+            rotation,
+            fsync: false,
+            current: Mutex::new(None),
+        }
+    }
+
+    /// Flushes every RAV to disk before returning, so that an archived RAV
+    /// survives a crash
+    pub fn with_fsync(mut self, fsync: bool) -> Self {
+        self.fsync = fsync;
+        self
+    }
+
+    /// Returns the path of the file a line of `line_len` bytes goes to
+    async fn target_path(
+        &self,
+        current: Option<&ArchiveFile>,
+        line_len: u64,
+    ) -> anyhow::Result<PathBuf> {
+        Ok(match self.rotation {
+            ArchiveRotation::Daily => {
+                let days = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() / 86_400;
+                self.dir.join(format!("ravs-day-{days}.ndjson"))
+            }
+            ArchiveRotation::MaxBytes(max_bytes) => {
+                let index = match current {
+                    Some(current) => file_index(&current.path).unwrap_or(0),
+                    None => last_file_index(&self.dir).await?,
+                };
+                let path = self.dir.join(format!("ravs-{index}.ndjson"));
+                let len = match current {
+                    Some(current) => current.len,
+                    None => fs::metadata(&path)
+                        .await
+                        .map_or(0, |metadata| metadata.len()),
+                };
+                if len > 0 && len + line_len > max_bytes {
+                    self.dir.join(format!("ravs-{}.ndjson", index + 1))
+                } else {
+                    path
+                }
+            }
+        })
+    }
+
+    /// Appends `line` to `archive_file`, flushing it to disk if enabled
+    async fn append(&self, archive_file: &mut ArchiveFile, line: &[u8]) -> std::io::Result<()> {
+        archive_file.file.write_all(line).await?;
+        archive_file.file.flush().await?;
+        if self.fsync {
+            archive_file.file.sync_data().await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RavSink for RavArchiveSink {
+    async fn on_rav_signed(&self, rav: &StoredRav) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(rav)?;
+        line.push(b'\n');
+
+        let mut current = self.current.lock().await;
+        let path = self
+            .target_path(current.as_ref(), line.len() as u64)
+            .await?;
+        if current.as_ref().map(|current| &current.path) != Some(&path) {
+            fs::create_dir_all(&self.dir).await?;
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await?;
+            let len = file.metadata().await?.len();
+            *current = Some(ArchiveFile { path, file, len });
+        }
+        let archive_file = current.as_mut().expect("the archive file was just opened");
+        if let Err(err) = self.append(archive_file, &line).await {
+            // drops the partial line, the file is reopened by the next RAV
+            let truncated = archive_file.file.set_len(archive_file.len).await;
+            *current = None;
+            truncated?;
+            return Err(err.into());
+        }
+        archive_file.len += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Returns `N` for a file named `ravs-N.ndjson`
+fn file_index(path: &Path) -> Option<u64> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix("ravs-")?
+        .strip_suffix(".ndjson")?
+        .parse()
+        .ok()
+}
+
+/// Returns the highest `N` of the `ravs-N.ndjson` files in `dir`, 0 if none
+async fn last_file_index(dir: &Path) -> anyhow::Result<u64> {
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+    let mut last_index = 0;
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(index) = file_index(&entry.path()) {
+            last_index = last_index.max(index);
+        }
+    }
+    Ok(last_index)
+}
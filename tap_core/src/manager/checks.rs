@@ -0,0 +1,617 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Receipt checks backed by the context adapters, see
+//! [`crate::manager::adapters`]
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use alloy::{primitives::Address, sol_types::SolStruct};
+
+use crate::{
+    manager::{
+        adapters::{EscrowHandler, SignatureChecker},
+        DomainSeparators, RavKey,
+    },
+    receipt::{
+        checks::{Check, CheckError, CheckResult, ReceiptCheck},
+        state::Checking,
+        Context, ReceiptError, ReceiptWithState, WithAllocationId, WithToken, WithUniqueId,
+        WithValueAndTimestamp,
+    },
+    signed_message::{Eip712SignedMessage, SignatureBytes},
+};
+
+/// Resolves the sender of `signed_receipt` through `signature_checker`, see
+/// [`SignatureChecker::sender_of`]. The signer is recovered with the domain
+/// selected for `ctx`, or with the first previous domain recovering a signer
+/// `signature_checker` verifies, see [`DomainSeparators::select_all`].
+async fn recover_sender<S, T>(
+    domain_separators: &DomainSeparators,
+    signature_checker: &S,
+    ctx: &Context,
+    signed_receipt: &Eip712SignedMessage<T>,
+) -> Result<Address, CheckError>
+where
+    S: SignatureChecker,
+    T: SolStruct,
+{
+    let invalid_signature = |source_error_message: String| {
+        CheckError::Failed(
+            ReceiptError::InvalidSignature {
+                source_error_message,
+            }
+            .into(),
+        )
+    };
+    let retryable = |e: S::AdapterError| CheckError::Retryable(anyhow::Error::new(e));
+    let domain_separators = domain_separators
+        .select_all(ctx)
+        .map_err(|e| invalid_signature(e.to_string()))?;
+    let mut signers = Vec::with_capacity(domain_separators.len());
+    for domain_separator in &domain_separators {
+        signers.push(
+            signed_receipt
+                .recover_signer(domain_separator)
+                .map_err(|e| invalid_signature(e.to_string()))?,
+        );
+    }
+    let mut signer = signers[0];
+    // only another domain can tell which domain the receipt was signed with
+    if signers.len() > 1 {
+        for &recovered in &signers {
+            if signature_checker
+                .verify_signer(recovered)
+                .await
+                .map_err(retryable)?
+            {
+                signer = recovered;
+                break;
+            }
+        }
+    }
+    signature_checker.sender_of(signer).await.map_err(retryable)
+}
+
+/// Reserves the value of a receipt out of the escrow its sender deposited in
+/// the token the receipt is denominated in, through
+/// [`EscrowHandler::try_reserve_in_token`]. Escrow deposited in one token
+/// never covers receipts in another.
+///
+/// The check reserves escrow, so it is stateful: the escrow it reserved is
+/// released if a later check rejects the receipt, or if it can't be stored.
+pub struct TokenEscrowCheck<E> {
+    domain_separators: DomainSeparators,
+    escrow: Arc<E>,
+}
+
+impl<E> TokenEscrowCheck<E> {
+    /// `domain_separators` is either the default domain separator or the
+    /// [`DomainSeparators`] of the manager running the check.
+    pub fn new(domain_separators: impl Into<DomainSeparators>, escrow: Arc<E>) -> Self {
+        Self {
+            domain_separators: domain_separators.into(),
+            escrow,
+        }
+    }
+
+    /// Same as [`TokenEscrowCheck::new`], as a [`ReceiptCheck`]
+    pub fn boxed<T>(
+        domain_separators: impl Into<DomainSeparators>,
+        escrow: Arc<E>,
+    ) -> ReceiptCheck<Eip712SignedMessage<T>>
+    where
+        E: EscrowHandler + 'static,
+        T: SolStruct + WithValueAndTimestamp + WithToken + Sync,
+    {
+        Arc::new(Self::new(domain_separators, escrow))
+    }
+}
+
+#[async_trait::async_trait]
+impl<E, T> Check<Eip712SignedMessage<T>> for TokenEscrowCheck<E>
+where
+    E: EscrowHandler,
+    T: SolStruct + WithValueAndTimestamp + WithToken + Sync,
+{
+    async fn check(
+        &self,
+        ctx: &Context,
+        receipt: &ReceiptWithState<Checking, Eip712SignedMessage<T>>,
+    ) -> CheckResult {
+        let invalid_signature = |source_error_message: String| {
+            CheckError::Failed(
+                ReceiptError::InvalidSignature {
+                    source_error_message,
+                }
+                .into(),
+            )
+        };
+        let domain_separator = self
+            .domain_separators
+            .select(ctx)
+            .map_err(|e| invalid_signature(e.to_string()))?;
+        let signed_receipt = receipt.signed_receipt();
+        let sender = signed_receipt
+            .recover_signer(&domain_separator)
+            .map_err(|e| invalid_signature(e.to_string()))?;
+        let token = signed_receipt.token();
+        let received_value = signed_receipt.value();
+
+        let retryable = |e: E::AdapterError| CheckError::Retryable(anyhow::Error::new(e));
+        if self
+            .escrow
+            .try_reserve_in_token(sender, token, received_value)
+            .await
+            .map_err(retryable)?
+        {
+            return Ok(());
+        }
+        let available_escrow = self
+            .escrow
+            .available_escrow_in_token(sender, token)
+            .await
+            .map_err(retryable)?;
+        Err(CheckError::Failed(
+            ReceiptError::NotEnoughEscrow {
+                sender,
+                available_escrow,
+                received_value,
+            }
+            .into(),
+        ))
+    }
+
+    fn is_stateful(&self) -> bool {
+        true
+    }
+
+    async fn rollback(
+        &self,
+        ctx: &Context,
+        receipt: &ReceiptWithState<Checking, Eip712SignedMessage<T>>,
+    ) {
+        let signed_receipt = receipt.signed_receipt();
+        // the sender was recovered when reserving, it can't fail now
+        let Ok(sender) = self
+            .domain_separators
+            .select(ctx)
+            .map_err(|e| e.to_string())
+            .and_then(|domain_separator| {
+                signed_receipt
+                    .recover_signer(&domain_separator)
+                    .map_err(|e| e.to_string())
+            })
+        else {
+            return;
+        };
+        if let Err(err) = self
+            .escrow
+            .release_in_token(sender, signed_receipt.token(), signed_receipt.value())
+            .await
+        {
+            log::error!("Failed to release the escrow reserved for a rejected receipt: {err}");
+        }
+    }
+}
+
+/// Key space of the receipts seen by a [`DedupCheck`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DedupScope {
+    /// A single set of receipts, whatever their sender
+    #[default]
+    Global,
+    /// A set of receipts per sender, so that checks of different senders
+    /// don't contend on a single lock
+    PerSender,
+}
+
+/// Receipt remembered by a [`DedupCheck`], to forget it once a RAV covers it
+struct SeenReceipt {
+    sender: Address,
+    allocation_id: Address,
+    timestamp_ns: u64,
+}
+
+/// Receipts seen by a [`DedupCheck`] in one key space, keyed by unique id
+type SeenSet = Arc<Mutex<HashMap<SignatureBytes, SeenReceipt>>>;
+
+/// Rejects receipts already accepted, the same receipts
+/// [`UniqueCheck`](crate::receipt::checks::UniqueCheck) rejects within a RAV
+/// request, i.e. with the same [`WithUniqueId::unique_id`]. The receipts are
+/// kept in one set, or one set per sender as resolved by
+/// [`SignatureChecker::sender_of`], depending on the [`DedupScope`].
+///
+/// A receipt is remembered until a RAV of its sender and allocation covers
+/// it, see [`Check::rav_stored`], so the memory used is bounded by the
+/// receipts pending aggregation. A receipt the manager rejects after this
+/// check is forgotten right away.
+pub struct DedupCheck<S> {
+    domain_separators: DomainSeparators,
+    signature_checker: Arc<S>,
+    scope: DedupScope,
+    seen: RwLock<HashMap<Address, SeenSet>>,
+}
+
+impl<S> DedupCheck<S> {
+    /// `domain_separators` is either the default domain separator or the
+    /// [`DomainSeparators`] of the manager running the check.
+    pub fn new(
+        domain_separators: impl Into<DomainSeparators>,
+        signature_checker: Arc<S>,
+        scope: DedupScope,
+    ) -> Self {
+        Self {
+            domain_separators: domain_separators.into(),
+            signature_checker,
+            scope,
+            seen: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Same as [`DedupCheck::new`], as a [`ReceiptCheck`]
+    pub fn boxed<T>(
+        domain_separators: impl Into<DomainSeparators>,
+        signature_checker: Arc<S>,
+        scope: DedupScope,
+    ) -> ReceiptCheck<Eip712SignedMessage<T>>
+    where
+        S: SignatureChecker + 'static,
+        T: SolStruct + WithAllocationId + WithValueAndTimestamp + Sync,
+    {
+        Arc::new(Self::new(domain_separators, signature_checker, scope))
+    }
+
+    /// Returns the set of receipts seen in the key space of `sender`,
+    /// creating it if needed
+    fn seen_set(&self, sender: Address) -> SeenSet {
+        let key = match self.scope {
+            DedupScope::Global => Address::ZERO,
+            DedupScope::PerSender => sender,
+        };
+        if let Some(seen_set) = self.seen.read().unwrap().get(&key) {
+            return seen_set.clone();
+        }
+        self.seen.write().unwrap().entry(key).or_default().clone()
+    }
+
+    /// Returns the number of receipts remembered
+    pub fn len(&self) -> usize {
+        self.seen
+            .read()
+            .unwrap()
+            .values()
+            .map(|seen_set| seen_set.lock().unwrap().len())
+            .sum()
+    }
+
+    /// Returns whether no receipt is remembered
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<S: SignatureChecker> DedupCheck<S> {
+    /// Resolves the sender of `signed_receipt`, see [`recover_sender`]
+    async fn sender<T: SolStruct>(
+        &self,
+        ctx: &Context,
+        signed_receipt: &Eip712SignedMessage<T>,
+    ) -> Result<Address, CheckError> {
+        recover_sender(
+            &self.domain_separators,
+            self.signature_checker.as_ref(),
+            ctx,
+            signed_receipt,
+        )
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl<S, T> Check<Eip712SignedMessage<T>> for DedupCheck<S>
+where
+    S: SignatureChecker,
+    T: SolStruct + WithAllocationId + WithValueAndTimestamp + Sync,
+{
+    async fn check(
+        &self,
+        ctx: &Context,
+        receipt: &ReceiptWithState<Checking, Eip712SignedMessage<T>>,
+    ) -> CheckResult {
+        let signed_receipt = receipt.signed_receipt();
+        let sender = self.sender(ctx, signed_receipt).await?;
+        let seen_set = self.seen_set(sender);
+        let mut seen_set = seen_set.lock().unwrap();
+        if seen_set.contains_key(&signed_receipt.unique_id()) {
+            return Err(CheckError::Failed(ReceiptError::NonUniqueReceipt.into()));
+        }
+        // recorded right away, so that a concurrent duplicate is rejected
+        seen_set.insert(
+            signed_receipt.unique_id(),
+            SeenReceipt {
+                sender,
+                allocation_id: signed_receipt.allocation_id(),
+                timestamp_ns: signed_receipt.timestamp_ns(),
+            },
+        );
+        Ok(())
+    }
+
+    fn is_stateful(&self) -> bool {
+        true
+    }
+
+    async fn rollback(
+        &self,
+        ctx: &Context,
+        receipt: &ReceiptWithState<Checking, Eip712SignedMessage<T>>,
+    ) {
+        let signed_receipt = receipt.signed_receipt();
+        // the sender was resolved by the check already
+        let Ok(sender) = self.sender(ctx, signed_receipt).await else {
+            return;
+        };
+        self.seen_set(sender)
+            .lock()
+            .unwrap()
+            .remove(&signed_receipt.unique_id());
+    }
+
+    async fn rav_stored(&self, sender: Address, allocation_id: Address, timestamp_ns: u64) {
+        self.seen_set(sender).lock().unwrap().retain(|_, seen| {
+            seen.sender != sender
+                || seen.allocation_id != allocation_id
+                || seen.timestamp_ns > timestamp_ns
+        });
+    }
+}
+
+/// Rejects receipts much older than the newest receipt stored for the same
+/// sender, as resolved by [`SignatureChecker::sender_of`].
+///
+/// Well-behaved senders issue receipts with increasing timestamps, so this
+/// keeps the highest timestamp stored for each sender and rejects receipts
+/// older than it by more than `tolerance_ns`. Receipts arriving slightly out
+/// of order within the tolerance are accepted. This catches replayed receipts
+/// without keeping every receipt seen.
+///
+/// The watermark is raised by [`Check::commit`], once a receipt passed every
+/// check of the manager and was stored, so that a receipt rejected by a
+/// later check or by the storage doesn't move it. It is forgotten once a RAV
+/// of the sender covers it, see [`Check::rav_stored`], so the memory used is
+/// bounded by the senders with receipts pending aggregation. The check should
+/// only see incoming receipts: once newer receipts arrived, re-checking
+/// stored receipts (e.g. when creating a RAV request) would reject them.
+pub struct ReplayWindowCheck<S> {
+    domain_separators: DomainSeparators,
+    signature_checker: Arc<S>,
+    tolerance_ns: u64,
+    watermarks_ns: RwLock<HashMap<Address, u64>>,
+}
+
+impl<S> ReplayWindowCheck<S> {
+    /// `domain_separators` is either the default domain separator or the
+    /// [`DomainSeparators`] of the manager running the check.
+    pub fn new(
+        domain_separators: impl Into<DomainSeparators>,
+        signature_checker: Arc<S>,
+        tolerance_ns: u64,
+    ) -> Self {
+        Self {
+            domain_separators: domain_separators.into(),
+            signature_checker,
+            tolerance_ns,
+            watermarks_ns: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Same as [`ReplayWindowCheck::new`], as a [`ReceiptCheck`]
+    pub fn boxed<T>(
+        domain_separators: impl Into<DomainSeparators>,
+        signature_checker: Arc<S>,
+        tolerance_ns: u64,
+    ) -> ReceiptCheck<Eip712SignedMessage<T>>
+    where
+        S: SignatureChecker + 'static,
+        T: SolStruct + WithValueAndTimestamp + Sync,
+    {
+        Arc::new(Self::new(
+            domain_separators,
+            signature_checker,
+            tolerance_ns,
+        ))
+    }
+
+    /// Returns the number of senders with a watermark
+    pub fn len(&self) -> usize {
+        self.watermarks_ns.read().unwrap().len()
+    }
+
+    /// Returns whether no sender has a watermark
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait::async_trait]
+impl<S, T> Check<Eip712SignedMessage<T>> for ReplayWindowCheck<S>
+where
+    S: SignatureChecker,
+    T: SolStruct + WithValueAndTimestamp + Sync,
+{
+    async fn check(
+        &self,
+        ctx: &Context,
+        receipt: &ReceiptWithState<Checking, Eip712SignedMessage<T>>,
+    ) -> CheckResult {
+        let signed_receipt = receipt.signed_receipt();
+        let sender = recover_sender(
+            &self.domain_separators,
+            self.signature_checker.as_ref(),
+            ctx,
+            signed_receipt,
+        )
+        .await?;
+        let timestamp_ns = signed_receipt.timestamp_ns();
+
+        let Some(watermark_ns) = self.watermarks_ns.read().unwrap().get(&sender).copied() else {
+            return Ok(());
+        };
+        let timestamp_min = watermark_ns.saturating_sub(self.tolerance_ns);
+        if timestamp_ns < timestamp_min {
+            return Err(CheckError::Failed(
+                ReceiptError::InvalidTimestamp {
+                    received_timestamp: timestamp_ns,
+                    timestamp_min,
+                }
+                .into(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn is_stateful(&self) -> bool {
+        true
+    }
+
+    /// Raises the watermark of the sender to the timestamp of `receipt`
+    async fn commit(
+        &self,
+        ctx: &Context,
+        receipt: &ReceiptWithState<Checking, Eip712SignedMessage<T>>,
+    ) {
+        let signed_receipt = receipt.signed_receipt();
+        // the sender was resolved by the check already
+        let Ok(sender) = recover_sender(
+            &self.domain_separators,
+            self.signature_checker.as_ref(),
+            ctx,
+            signed_receipt,
+        )
+        .await
+        else {
+            return;
+        };
+        let timestamp_ns = signed_receipt.timestamp_ns();
+        self.watermarks_ns
+            .write()
+            .unwrap()
+            .entry(sender)
+            .and_modify(|watermark_ns| *watermark_ns = (*watermark_ns).max(timestamp_ns))
+            .or_insert(timestamp_ns);
+    }
+
+    /// Forgets the watermark of `sender` if the RAV covers it
+    async fn rav_stored(&self, sender: Address, _allocation_id: Address, timestamp_ns: u64) {
+        let mut watermarks_ns = self.watermarks_ns.write().unwrap();
+        if watermarks_ns
+            .get(&sender)
+            .is_some_and(|watermark_ns| *watermark_ns <= timestamp_ns)
+        {
+            watermarks_ns.remove(&sender);
+        }
+    }
+}
+
+type AuthorizationFuture = Pin<Box<dyn Future<Output = anyhow::Result<bool>> + Send>>;
+
+/// Verifies that the sender of a receipt is authorized to send receipts for
+/// its allocation, as told by a resolver called with `(allocation_id, sender)`,
+/// e.g. a lookup in the indexer database or on-chain.
+///
+/// The sender is the one of the [`RavKey`] found in the context, if any,
+/// otherwise the one resolved by [`SignatureChecker::sender_of`] for the
+/// signer of the receipt.
+///
+/// A resolver error is a retryable failure, the receipt may be accepted once
+/// the resolver is reachable again.
+pub struct AllocationAuthorizationCheck<S> {
+    domain_separators: DomainSeparators,
+    signature_checker: Arc<S>,
+    resolver: Box<dyn Fn(Address, Address) -> AuthorizationFuture + Send + Sync>,
+}
+
+impl<S> AllocationAuthorizationCheck<S> {
+    /// `domain_separators` is either the default domain separator or the
+    /// [`DomainSeparators`] of the manager running the check.
+    pub fn new<F, Fut>(
+        domain_separators: impl Into<DomainSeparators>,
+        signature_checker: Arc<S>,
+        resolver: F,
+    ) -> Self
+    where
+        F: Fn(Address, Address) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<bool>> + Send + 'static,
+    {
+        Self {
+            domain_separators: domain_separators.into(),
+            signature_checker,
+            resolver: Box::new(move |allocation_id, sender| {
+                Box::pin(resolver(allocation_id, sender))
+            }),
+        }
+    }
+
+    /// Same as [`AllocationAuthorizationCheck::new`], as a [`ReceiptCheck`]
+    pub fn boxed<T, F, Fut>(
+        domain_separators: impl Into<DomainSeparators>,
+        signature_checker: Arc<S>,
+        resolver: F,
+    ) -> ReceiptCheck<Eip712SignedMessage<T>>
+    where
+        S: SignatureChecker + 'static,
+        T: SolStruct + WithAllocationId + Sync,
+        F: Fn(Address, Address) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<bool>> + Send + 'static,
+    {
+        Arc::new(Self::new(domain_separators, signature_checker, resolver))
+    }
+}
+
+#[async_trait::async_trait]
+impl<S, T> Check<Eip712SignedMessage<T>> for AllocationAuthorizationCheck<S>
+where
+    S: SignatureChecker,
+    T: SolStruct + WithAllocationId + Sync,
+{
+    async fn check(
+        &self,
+        ctx: &Context,
+        receipt: &ReceiptWithState<Checking, Eip712SignedMessage<T>>,
+    ) -> CheckResult {
+        let signed_receipt = receipt.signed_receipt();
+        let sender = match ctx.get::<RavKey>() {
+            Some(rav_key) => rav_key.sender,
+            None => {
+                recover_sender(
+                    &self.domain_separators,
+                    self.signature_checker.as_ref(),
+                    ctx,
+                    signed_receipt,
+                )
+                .await?
+            }
+        };
+        let allocation_id = signed_receipt.allocation_id();
+
+        let authorized = (self.resolver)(allocation_id, sender)
+            .await
+            .map_err(CheckError::Retryable)?;
+        if !authorized {
+            return Err(CheckError::Failed(
+                ReceiptError::UnauthorizedSender {
+                    sender,
+                    allocation_id,
+                }
+                .into(),
+            ));
+        }
+        Ok(())
+    }
+}
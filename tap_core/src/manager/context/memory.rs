@@ -6,13 +6,15 @@
 //! This module provides an in-memory implementation of the TAP manager context.
 //! It is useful for testing and development purposes.
 
+#[cfg(test)]
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap, HashSet},
     ops::RangeBounds,
     sync::{Arc, RwLock},
 };
 
-use alloy::primitives::Address;
+use alloy::{dyn_abi::Eip712Domain, primitives::Address};
 use async_trait::async_trait;
 use tap_graph::{ReceiptAggregateVoucher, SignedRav, SignedReceipt};
 
@@ -23,9 +25,11 @@ use crate::{
 };
 
 pub type EscrowStorage = Arc<RwLock<HashMap<Address, u128>>>;
+/// Escrow balances keyed by `(sender, token)`
+pub type TokenEscrowStorage = Arc<RwLock<HashMap<(Address, Address), u128>>>;
 pub type QueryAppraisals = Arc<RwLock<HashMap<MessageId, u128>>>;
 pub type ReceiptStorage = Arc<RwLock<HashMap<u64, ReceiptWithState<Checking, SignedReceipt>>>>;
-pub type RAVStorage = Arc<RwLock<Option<SignedRav>>>;
+pub type RAVStorage = Arc<RwLock<HashMap<(Address, Address), SignedRav>>>;
 
 use thiserror::Error;
 
@@ -33,33 +37,69 @@ use thiserror::Error;
 pub enum InMemoryError {
     #[error("something went wrong: {error}")]
     AdapterError { error: String },
+    #[error("receipt storage is full")]
+    StorageFull {
+        #[source]
+        source: crate::Error,
+    },
 }
 
 #[derive(Clone)]
 pub struct InMemoryContext {
     /// local RAV store with rwlocks to allow sharing with other compenents as needed
     rav_storage: RAVStorage,
+    /// RAVs replaced by a later one, oldest first, along with their sender
+    /// and allocation id
+    rav_history: Arc<RwLock<Vec<(Address, Address, SignedRav)>>>,
     receipt_storage: ReceiptStorage,
     unique_id: Arc<RwLock<u64>>,
     sender_escrow_storage: EscrowStorage,
-    timestamp_check: Arc<StatefulTimestampCheck>,
+    token_escrow_storage: TokenEscrowStorage,
+    /// Escrow reserved by receipts of each sender, apart from the balances
+    reserved_escrow: Arc<RwLock<HashMap<Address, u128>>>,
+    /// Same as `reserved_escrow`, keyed by `(sender, token)`
+    reserved_token_escrow: Arc<RwLock<HashMap<(Address, Address), u128>>>,
     sender_address: Option<Address>,
+    /// Signer of each stored receipt, keyed by receipt id
+    receipt_signers: Arc<RwLock<HashMap<u64, Address>>>,
+    /// Ids of the stored receipts accepted below the minimum timestamp
+    below_min_timestamp_ids: Arc<RwLock<HashSet<u64>>>,
+    /// Fails the next [`RavTransaction`] once the RAV is stored
+    #[cfg(test)]
+    fail_next_transaction: Arc<AtomicBool>,
+    /// Maximum number of receipts stored at once
+    receipt_capacity: Option<usize>,
+    /// Receipts moved out by [`ReceiptQuarantine`], keyed by receipt id
+    quarantine: ReceiptStorage,
 }
 
 impl InMemoryContext {
+    /// The context doesn't update `_timestamp_check` as RAVs are stored
+    /// anymore, a single floor can't cover the RAVs of several senders and
+    /// allocations. The manager keeps a floor per [`crate::manager::RavKey`]
+    /// instead, see [`crate::manager::Manager::verify_and_store_receipt`].
     pub fn new(
         rav_storage: RAVStorage,
         receipt_storage: ReceiptStorage,
         sender_escrow_storage: EscrowStorage,
-        timestamp_check: Arc<StatefulTimestampCheck>,
+        _timestamp_check: Arc<StatefulTimestampCheck>,
     ) -> Self {
         InMemoryContext {
             rav_storage,
+            rav_history: Arc::new(RwLock::new(Vec::new())),
             receipt_storage,
             unique_id: Arc::new(RwLock::new(0)),
             sender_escrow_storage,
-            timestamp_check,
+            token_escrow_storage: Arc::new(RwLock::new(HashMap::new())),
+            reserved_escrow: Arc::new(RwLock::new(HashMap::new())),
+            reserved_token_escrow: Arc::new(RwLock::new(HashMap::new())),
             sender_address: None,
+            receipt_signers: Arc::new(RwLock::new(HashMap::new())),
+            below_min_timestamp_ids: Arc::new(RwLock::new(HashSet::new())),
+            #[cfg(test)]
+            fail_next_transaction: Arc::new(AtomicBool::new(false)),
+            receipt_capacity: None,
+            quarantine: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
@@ -68,6 +108,32 @@ impl InMemoryContext {
         self
     }
 
+    /// Holds the escrow of receipts denominated in a token in
+    /// `token_escrow_storage`, see [`EscrowHandler::try_reserve_in_token`]
+    pub fn with_token_escrow_storage(mut self, token_escrow_storage: TokenEscrowStorage) -> Self {
+        self.token_escrow_storage = token_escrow_storage;
+        self
+    }
+
+    /// Rejects receipts with [`crate::Error::StorageQuotaExceeded`] once
+    /// `receipt_capacity` receipts are stored, mimicking a storage quota
+    pub fn with_receipt_capacity(mut self, receipt_capacity: usize) -> Self {
+        self.receipt_capacity = Some(receipt_capacity);
+        self
+    }
+
+    /// Makes the next [`RavTransaction::commit_rav`] fail between storing the
+    /// RAV and removing the receipts, to exercise the rollback
+    #[cfg(test)]
+    fn fail_next_transaction(&self) {
+        self.fail_next_transaction.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns the ids of the receipts moved to the quarantine
+    pub fn quarantined_receipt_ids(&self) -> BTreeSet<u64> {
+        self.quarantine.read().unwrap().keys().copied().collect()
+    }
+
     pub async fn retrieve_receipt_by_id(
         &self,
         receipt_id: u64,
@@ -106,6 +172,11 @@ impl InMemoryContext {
 
     pub async fn remove_receipt_by_id(&mut self, receipt_id: u64) -> Result<(), InMemoryError> {
         let mut receipt_storage = self.receipt_storage.write().unwrap();
+        self.receipt_signers.write().unwrap().remove(&receipt_id);
+        self.below_min_timestamp_ids
+            .write()
+            .unwrap()
+            .remove(&receipt_id);
         receipt_storage
             .remove(&receipt_id)
             .map(|_| ())
@@ -128,21 +199,187 @@ impl InMemoryContext {
 impl RavStore<ReceiptAggregateVoucher> for InMemoryContext {
     type AdapterError = InMemoryError;
 
-    async fn update_last_rav(&self, rav: SignedRav) -> Result<(), Self::AdapterError> {
-        let mut rav_storage = self.rav_storage.write().unwrap();
-        let timestamp = rav.message.timestampNs;
-        *rav_storage = Some(rav);
-        self.timestamp_check.update_min_timestamp_ns(timestamp);
+    async fn update_last_rav(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+        rav: SignedRav,
+    ) -> Result<(), Self::AdapterError> {
+        let value_aggregate = rav.message.valueAggregate;
+        let replaced = self
+            .rav_storage
+            .write()
+            .unwrap()
+            .insert((sender, allocation_id), rav);
+        self.release_aggregated_escrow(sender, value_aggregate, replaced.as_ref());
+        if let Some(replaced) = replaced {
+            self.rav_history
+                .write()
+                .unwrap()
+                .push((sender, allocation_id, replaced));
+        }
         Ok(())
     }
 }
 
+#[async_trait]
+impl RavDelete for InMemoryContext {
+    type AdapterError = InMemoryError;
+
+    async fn prune_rav_history(&self, keep_latest: usize) -> Result<u64, Self::AdapterError> {
+        let mut rav_history = self.rav_history.write().unwrap();
+        let len_before = rav_history.len();
+        // walks from the latest replaced RAV, the latest RAV of each key being
+        // in the RAV storage and always kept
+        let mut kept: HashMap<(Address, Address), usize> = HashMap::new();
+        let mut keep: Vec<bool> = rav_history
+            .iter()
+            .rev()
+            .map(|(sender, allocation_id, _)| {
+                let count = kept.entry((*sender, *allocation_id)).or_insert(1);
+                *count += 1;
+                *count <= keep_latest
+            })
+            .collect();
+        keep.reverse();
+        let mut keep = keep.into_iter();
+        rav_history.retain(|_| keep.next().unwrap());
+        Ok((len_before - rav_history.len()) as u64)
+    }
+}
+
+/// Both storages stay locked for the whole transaction, so the intermediate
+/// state is never visible, and the RAV is taken out again on failure
+#[async_trait]
+impl RavTransaction<ReceiptAggregateVoucher> for InMemoryContext {
+    type AdapterError = InMemoryError;
+
+    async fn commit_rav<R: RangeBounds<u64> + std::marker::Send>(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+        rav: SignedRav,
+        receipts_timestamp_range_ns: R,
+    ) -> Result<u64, Self::AdapterError> {
+        let mut rav_storage = self.rav_storage.write().unwrap();
+        let mut receipt_storage = self.receipt_storage.write().unwrap();
+        let value_aggregate = rav.message.valueAggregate;
+        let replaced = rav_storage.insert((sender, allocation_id), rav);
+
+        #[cfg(test)]
+        if self.fail_next_transaction.swap(false, Ordering::SeqCst) {
+            match replaced {
+                Some(replaced) => rav_storage.insert((sender, allocation_id), replaced),
+                None => rav_storage.remove(&(sender, allocation_id)),
+            };
+            return Err(InMemoryError::AdapterError {
+                error: "transaction failed, rolled back".to_owned(),
+            });
+        }
+        self.release_aggregated_escrow(sender, value_aggregate, replaced.as_ref());
+        if let Some(replaced) = replaced {
+            self.rav_history
+                .write()
+                .unwrap()
+                .push((sender, allocation_id, replaced));
+        }
+        // only the receipts of the RAV key, like
+        // `remove_sender_receipts_in_timestamp_range`
+        let mut receipt_signers = self.receipt_signers.write().unwrap();
+        let len_before = receipt_storage.len();
+        receipt_storage.retain(|id, rx_receipt| {
+            !(receipts_timestamp_range_ns
+                .contains(&rx_receipt.signed_receipt().message.timestamp_ns)
+                && Self::is_sender_receipt(
+                    &receipt_signers,
+                    *id,
+                    rx_receipt,
+                    sender,
+                    allocation_id,
+                ))
+        });
+        receipt_signers.retain(|id, _| receipt_storage.contains_key(id));
+        self.below_min_timestamp_ids
+            .write()
+            .unwrap()
+            .retain(|id| receipt_storage.contains_key(id));
+        Ok((len_before - receipt_storage.len()) as u64)
+    }
+}
+
 #[async_trait]
 impl RavRead<ReceiptAggregateVoucher> for InMemoryContext {
     type AdapterError = InMemoryError;
 
-    async fn last_rav(&self) -> Result<Option<SignedRav>, Self::AdapterError> {
-        Ok(self.rav_storage.read().unwrap().clone())
+    async fn get_last_rav(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+    ) -> Result<Option<SignedRav>, Self::AdapterError> {
+        Ok(self
+            .rav_storage
+            .read()
+            .unwrap()
+            .get(&(sender, allocation_id))
+            .cloned())
+    }
+}
+
+#[async_trait]
+impl RavHistoryRead<ReceiptAggregateVoucher> for InMemoryContext {
+    type AdapterError = InMemoryError;
+
+    async fn get_rav_history(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+    ) -> Result<Vec<SignedRav>, Self::AdapterError> {
+        let mut ravs: Vec<_> = self
+            .rav_history
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(rav_sender, rav_allocation_id, _)| {
+                (*rav_sender, *rav_allocation_id) == (sender, allocation_id)
+            })
+            .map(|(_, _, rav)| rav.clone())
+            .collect();
+        ravs.extend(self.get_last_rav(sender, allocation_id).await?);
+        Ok(ravs)
+    }
+
+    async fn list_ravs_in_range<R: RangeBounds<u64> + std::marker::Send>(
+        &self,
+        timestamp_range_ns: R,
+    ) -> Result<Vec<SignedRav>, Self::AdapterError> {
+        let rav_history = self.rav_history.read().unwrap();
+        let rav_storage = self.rav_storage.read().unwrap();
+        let mut ravs: Vec<_> = rav_history
+            .iter()
+            .map(|(_, _, rav)| rav)
+            .chain(rav_storage.values())
+            .filter(|rav| timestamp_range_ns.contains(&rav.message.timestampNs))
+            .cloned()
+            .collect();
+        ravs.sort_by_key(|rav| rav.message.timestampNs);
+        Ok(ravs)
+    }
+}
+
+#[async_trait]
+impl RavListRead<ReceiptAggregateVoucher> for InMemoryContext {
+    type AdapterError = InMemoryError;
+
+    async fn get_last_ravs(
+        &self,
+    ) -> Result<Vec<(Address, Address, SignedRav)>, Self::AdapterError> {
+        Ok(self
+            .rav_storage
+            .read()
+            .unwrap()
+            .iter()
+            .map(|((sender, allocation_id), rav)| (*sender, *allocation_id, rav.clone()))
+            .collect())
     }
 }
 
@@ -154,13 +391,83 @@ impl ReceiptStore<SignedReceipt> for InMemoryContext {
         &self,
         receipt: ReceiptWithState<Checking, SignedReceipt>,
     ) -> Result<u64, Self::AdapterError> {
+        self.store_receipt_with_domain(receipt, None, false)
+    }
+
+    async fn store_receipt_in_domain(
+        &self,
+        receipt: ReceiptWithState<Checking, SignedReceipt>,
+        domain_separator: &Eip712Domain,
+    ) -> Result<u64, Self::AdapterError> {
+        self.store_receipt_with_domain(receipt, Some(domain_separator), false)
+    }
+
+    async fn store_receipt_below_min_timestamp(
+        &self,
+        receipt: ReceiptWithState<Checking, SignedReceipt>,
+        domain_separator: &Eip712Domain,
+    ) -> Result<u64, Self::AdapterError> {
+        self.store_receipt_with_domain(receipt, Some(domain_separator), true)
+    }
+}
+
+impl InMemoryContext {
+    /// Stores `receipt`, persisting its signer if recovered with
+    /// `domain_separator`, flagged if `below_min_timestamp`
+    fn store_receipt_with_domain(
+        &self,
+        receipt: ReceiptWithState<Checking, SignedReceipt>,
+        domain_separator: Option<&Eip712Domain>,
+        below_min_timestamp: bool,
+    ) -> Result<u64, InMemoryError> {
+        let signer = domain_separator
+            .map(|domain_separator| receipt.signed_receipt().recover_signer(domain_separator))
+            .transpose()
+            .map_err(|err| InMemoryError::AdapterError {
+                error: err.to_string(),
+            })?;
         let mut id_pointer = self.unique_id.write().unwrap();
         let id_previous = *id_pointer;
         let mut receipt_storage = self.receipt_storage.write().unwrap();
+        if let Some(receipt_capacity) = self.receipt_capacity {
+            if receipt_storage.len() >= receipt_capacity {
+                return Err(InMemoryError::StorageFull {
+                    source: crate::Error::StorageQuotaExceeded {
+                        message: format!("{receipt_capacity} receipts stored"),
+                    },
+                });
+            }
+        }
         receipt_storage.insert(*id_pointer, receipt);
+        if let Some(signer) = signer {
+            self.receipt_signers
+                .write()
+                .unwrap()
+                .insert(*id_pointer, signer);
+        }
+        if below_min_timestamp {
+            self.below_min_timestamp_ids
+                .write()
+                .unwrap()
+                .insert(*id_pointer);
+        }
         *id_pointer += 1;
         Ok(id_previous)
     }
+
+    /// Returns whether the receipt stored under `id` is one of `sender` for
+    /// `allocation_id`, the in-memory context taking the signer of a receipt
+    /// for its sender. Receipts stored without their signer belong to none.
+    fn is_sender_receipt(
+        receipt_signers: &HashMap<u64, Address>,
+        id: u64,
+        rx_receipt: &ReceiptWithState<Checking, SignedReceipt>,
+        sender: Address,
+        allocation_id: Address,
+    ) -> bool {
+        rx_receipt.signed_receipt().message.allocation_id == allocation_id
+            && receipt_signers.get(&id) == Some(&sender)
+    }
 }
 
 #[async_trait]
@@ -170,14 +477,98 @@ impl ReceiptDelete for InMemoryContext {
     async fn remove_receipts_in_timestamp_range<R: RangeBounds<u64> + std::marker::Send>(
         &self,
         timestamp_ns: R,
+    ) -> Result<(), Self::AdapterError> {
+        self.remove_receipts_in_timestamp_range_counted(timestamp_ns)
+            .await
+            .map(|_| ())
+    }
+
+    async fn remove_receipts_in_timestamp_range_counted<R: RangeBounds<u64> + std::marker::Send>(
+        &self,
+        timestamp_ns: R,
+    ) -> Result<u64, Self::AdapterError> {
+        let removed: Vec<_> = {
+            let mut receipt_storage = self.receipt_storage.write().unwrap();
+            let mut receipt_signers = self.receipt_signers.write().unwrap();
+            let mut below_min_timestamp_ids = self.below_min_timestamp_ids.write().unwrap();
+            let removed_ids: Vec<u64> = receipt_storage
+                .iter()
+                .filter(|(_, rx_receipt)| {
+                    timestamp_ns.contains(&rx_receipt.signed_receipt().message.timestamp_ns)
+                })
+                .map(|(id, _)| *id)
+                .collect();
+            removed_ids
+                .into_iter()
+                .filter_map(|id| {
+                    let rx_receipt = receipt_storage.remove(&id)?;
+                    below_min_timestamp_ids.remove(&id);
+                    Some((
+                        receipt_signers.remove(&id),
+                        rx_receipt.signed_receipt().clone(),
+                    ))
+                })
+                .collect()
+        };
+        let removed_count = removed.len() as u64;
+        self.release_unaggregated_escrow(removed);
+        Ok(removed_count)
+    }
+
+    async fn remove_sender_receipts_in_timestamp_range<R: RangeBounds<u64> + std::marker::Send>(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+        timestamp_ns: R,
     ) -> Result<(), Self::AdapterError> {
         let mut receipt_storage = self.receipt_storage.write().unwrap();
-        receipt_storage.retain(|_, rx_receipt| {
-            !timestamp_ns.contains(&rx_receipt.signed_receipt().message.timestamp_ns)
+        let mut receipt_signers = self.receipt_signers.write().unwrap();
+        receipt_storage.retain(|id, rx_receipt| {
+            !(timestamp_ns.contains(&rx_receipt.signed_receipt().message.timestamp_ns)
+                && Self::is_sender_receipt(
+                    &receipt_signers,
+                    *id,
+                    rx_receipt,
+                    sender,
+                    allocation_id,
+                ))
         });
+        receipt_signers.retain(|id, _| receipt_storage.contains_key(id));
+        self.below_min_timestamp_ids
+            .write()
+            .unwrap()
+            .retain(|id| receipt_storage.contains_key(id));
         Ok(())
     }
 }
+
+#[async_trait]
+impl ReceiptQuarantine for InMemoryContext {
+    type AdapterError = InMemoryError;
+
+    async fn quarantine_receipts(&self, receipt_ids: &[u64]) -> Result<u64, Self::AdapterError> {
+        let quarantined: Vec<_> = {
+            let mut receipt_storage = self.receipt_storage.write().unwrap();
+            let mut quarantine = self.quarantine.write().unwrap();
+            let mut receipt_signers = self.receipt_signers.write().unwrap();
+            let mut below_min_timestamp_ids = self.below_min_timestamp_ids.write().unwrap();
+            receipt_ids
+                .iter()
+                .filter_map(|receipt_id| {
+                    let receipt = receipt_storage.remove(receipt_id)?;
+                    let signed_receipt = receipt.signed_receipt().clone();
+                    quarantine.insert(*receipt_id, receipt);
+                    below_min_timestamp_ids.remove(receipt_id);
+                    Some((receipt_signers.remove(receipt_id), signed_receipt))
+                })
+                .collect()
+        };
+        let quarantined_count = quarantined.len() as u64;
+        self.release_unaggregated_escrow(quarantined);
+        Ok(quarantined_count)
+    }
+}
+
 #[async_trait]
 impl ReceiptRead<SignedReceipt> for InMemoryContext {
     type AdapterError = InMemoryError;
@@ -200,9 +591,194 @@ impl ReceiptRead<SignedReceipt> for InMemoryContext {
         }
         Ok(receipts_in_range.into_iter().collect())
     }
+
+    async fn retrieve_sender_receipts_in_timestamp_range<
+        R: RangeBounds<u64> + std::marker::Send,
+    >(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+        timestamp_range_ns: R,
+        limit: Option<u64>,
+    ) -> Result<Vec<ReceiptWithState<Checking, SignedReceipt>>, Self::AdapterError> {
+        let receipt_storage = self.receipt_storage.read().unwrap();
+        let receipt_signers = self.receipt_signers.read().unwrap();
+        let mut receipts_in_range: Vec<_> = receipt_storage
+            .iter()
+            .filter(|(id, rx_receipt)| {
+                timestamp_range_ns.contains(&rx_receipt.signed_receipt().message.timestamp_ns)
+                    && Self::is_sender_receipt(
+                        &receipt_signers,
+                        **id,
+                        rx_receipt,
+                        sender,
+                        allocation_id,
+                    )
+            })
+            .map(|(_, rx_receipt)| rx_receipt.clone())
+            .collect();
+
+        if let Some(limit) = limit {
+            safe_truncate_receipts(&mut receipts_in_range, limit);
+        }
+        Ok(receipts_in_range)
+    }
+}
+
+#[async_trait]
+impl ReceiptAllocationRead for InMemoryContext {
+    type AdapterError = InMemoryError;
+
+    async fn retrieve_allocation_ids(&self) -> Result<Vec<Address>, Self::AdapterError> {
+        let receipt_storage = self.receipt_storage.read().unwrap();
+        let allocation_ids: BTreeSet<Address> = receipt_storage
+            .values()
+            .map(|rx_receipt| rx_receipt.signed_receipt().message.allocation_id)
+            .collect();
+        Ok(allocation_ids.into_iter().collect())
+    }
+}
+
+#[async_trait]
+impl StoredReceiptRead<SignedReceipt> for InMemoryContext {
+    type AdapterError = InMemoryError;
+
+    async fn retrieve_stored_receipts_in_timestamp_range<
+        R: RangeBounds<u64> + std::marker::Send,
+    >(
+        &self,
+        timestamp_range_ns: R,
+    ) -> Result<Vec<StoredReceipt<SignedReceipt>>, Self::AdapterError> {
+        let receipt_storage = self.receipt_storage.read().unwrap();
+        let receipt_signers = self.receipt_signers.read().unwrap();
+        let below_min_timestamp_ids = self.below_min_timestamp_ids.read().unwrap();
+        receipt_storage
+            .iter()
+            .filter(|(_, rx_receipt)| {
+                timestamp_range_ns.contains(&rx_receipt.signed_receipt().message.timestamp_ns)
+            })
+            .map(|(&id, rx_receipt)| {
+                let signer = receipt_signers
+                    .get(&id)
+                    .ok_or(InMemoryError::AdapterError {
+                        error: format!("No signer persisted for receipt {id}"),
+                    })?;
+                Ok(
+                    StoredReceipt::new(id, *signer, rx_receipt.signed_receipt().clone())
+                        .with_below_min_timestamp(below_min_timestamp_ids.contains(&id)),
+                )
+            })
+            .collect()
+    }
+
+    async fn retrieve_stored_sender_receipts_in_timestamp_range<
+        R: RangeBounds<u64> + std::marker::Send,
+    >(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+        timestamp_range_ns: R,
+    ) -> Result<Vec<StoredReceipt<SignedReceipt>>, Self::AdapterError> {
+        let receipt_storage = self.receipt_storage.read().unwrap();
+        let receipt_signers = self.receipt_signers.read().unwrap();
+        let below_min_timestamp_ids = self.below_min_timestamp_ids.read().unwrap();
+        Ok(receipt_storage
+            .iter()
+            .filter(|(id, rx_receipt)| {
+                timestamp_range_ns.contains(&rx_receipt.signed_receipt().message.timestamp_ns)
+                    && Self::is_sender_receipt(
+                        &receipt_signers,
+                        **id,
+                        rx_receipt,
+                        sender,
+                        allocation_id,
+                    )
+            })
+            .map(|(&id, rx_receipt)| {
+                StoredReceipt::new(id, sender, rx_receipt.signed_receipt().clone())
+                    .with_below_min_timestamp(below_min_timestamp_ids.contains(&id))
+            })
+            .collect())
+    }
+
+    async fn retrieve_stored_receipts_by_ids(
+        &self,
+        receipt_ids: &[u64],
+    ) -> Result<Vec<StoredReceipt<SignedReceipt>>, Self::AdapterError> {
+        let receipt_storage = self.receipt_storage.read().unwrap();
+        let receipt_signers = self.receipt_signers.read().unwrap();
+        let below_min_timestamp_ids = self.below_min_timestamp_ids.read().unwrap();
+        receipt_ids
+            .iter()
+            .filter_map(|id| Some((*id, receipt_storage.get(id)?)))
+            .map(|(id, rx_receipt)| {
+                let signer = receipt_signers
+                    .get(&id)
+                    .ok_or(InMemoryError::AdapterError {
+                        error: format!("No signer persisted for receipt {id}"),
+                    })?;
+                Ok(
+                    StoredReceipt::new(id, *signer, rx_receipt.signed_receipt().clone())
+                        .with_below_min_timestamp(below_min_timestamp_ids.contains(&id)),
+                )
+            })
+            .collect()
+    }
+
+    async fn retrieve_stored_receipts_page(
+        &self,
+        from_id: u64,
+        limit: u64,
+    ) -> Result<Vec<StoredReceipt<SignedReceipt>>, Self::AdapterError> {
+        let mut receipt_ids: Vec<u64> = self
+            .receipt_storage
+            .read()
+            .unwrap()
+            .keys()
+            .copied()
+            .filter(|id| *id >= from_id)
+            .collect();
+        receipt_ids.sort_unstable();
+        receipt_ids.truncate(usize::try_from(limit).unwrap_or(usize::MAX));
+        self.retrieve_stored_receipts_by_ids(&receipt_ids).await
+    }
 }
 
 impl InMemoryContext {
+    /// Releases the escrow reserved by the receipts a RAV worth
+    /// `value_aggregate` aggregates on top of the `replaced` one, the RAV
+    /// then committing that escrow instead
+    fn release_aggregated_escrow(
+        &self,
+        sender: Address,
+        value_aggregate: u128,
+        replaced: Option<&SignedRav>,
+    ) {
+        let previous_value = replaced.map_or(0, |rav| rav.message.valueAggregate);
+        let mut reserved_escrow = self.reserved_escrow.write().unwrap();
+        if let Some(reserved) = reserved_escrow.get_mut(&sender) {
+            *reserved = reserved.saturating_sub(value_aggregate.saturating_sub(previous_value));
+        }
+    }
+
+    /// Releases the escrow reserved by receipts removed from the storage,
+    /// along with their signer, that no RAV aggregates yet. The escrow of the
+    /// receipts a RAV aggregates was released when the RAV was stored.
+    fn release_unaggregated_escrow(&self, removed: Vec<(Option<Address>, SignedReceipt)>) {
+        let rav_storage = self.rav_storage.read().unwrap();
+        for (signer, signed_receipt) in removed {
+            let Some(sender) = signer else {
+                continue;
+            };
+            let aggregated = rav_storage
+                .get(&(sender, signed_receipt.message.allocation_id))
+                .is_some_and(|rav| signed_receipt.message.timestamp_ns <= rav.message.timestampNs);
+            if !aggregated {
+                release_escrow(&self.reserved_escrow, sender, signed_receipt.message.value);
+            }
+        }
+    }
+
     pub fn escrow(&self, sender_id: Address) -> Result<u128, InMemoryError> {
         let sender_escrow_storage = self.sender_escrow_storage.read().unwrap();
         if let Some(escrow) = sender_escrow_storage.get(&sender_id) {
@@ -240,6 +816,116 @@ impl InMemoryContext {
     }
 }
 
+/// Reserves `amount` of the escrow of `key` if the balance minus what is
+/// already reserved covers it, with the reservations locked throughout
+fn try_reserve_escrow<K: Eq + std::hash::Hash>(
+    balances: &RwLock<HashMap<K, u128>>,
+    reservations: &RwLock<HashMap<K, u128>>,
+    key: K,
+    amount: u128,
+) -> bool {
+    let mut reservations = reservations.write().unwrap();
+    let balance = balances.read().unwrap().get(&key).copied().unwrap_or(0);
+    let reserved = reservations.entry(key).or_default();
+    match reserved.checked_add(amount) {
+        Some(total) if total <= balance => {
+            *reserved = total;
+            true
+        }
+        _ => false,
+    }
+}
+
+fn release_escrow<K: Eq + std::hash::Hash>(
+    reservations: &RwLock<HashMap<K, u128>>,
+    key: K,
+    amount: u128,
+) {
+    if let Some(reserved) = reservations.write().unwrap().get_mut(&key) {
+        *reserved = reserved.saturating_sub(amount);
+    }
+}
+
+fn available_escrow<K: Eq + std::hash::Hash>(
+    balances: &RwLock<HashMap<K, u128>>,
+    reservations: &RwLock<HashMap<K, u128>>,
+    key: K,
+) -> u128 {
+    let reservations = reservations.read().unwrap();
+    let balance = balances.read().unwrap().get(&key).copied().unwrap_or(0);
+    balance.saturating_sub(reservations.get(&key).copied().unwrap_or(0))
+}
+
+/// Reservations are kept in a ledger apart from the escrow balances. The
+/// escrow reserved by the receipts of a sender is released once a RAV
+/// aggregates them, see [`RavStore::update_last_rav`]: it is then committed
+/// to the RAV, e.g. see [`checks::EscrowCheck::with_rav_storage`]. It is
+/// also released when receipts no RAV aggregates are removed, see
+/// [`ReceiptDelete::remove_receipts_in_timestamp_range_counted`], or
+/// quarantined, see [`ReceiptQuarantine::quarantine_receipts`].
+#[async_trait]
+impl EscrowHandler for InMemoryContext {
+    type AdapterError = InMemoryError;
+
+    async fn try_reserve(&self, sender: Address, amount: u128) -> Result<bool, Self::AdapterError> {
+        Ok(try_reserve_escrow(
+            &self.sender_escrow_storage,
+            &self.reserved_escrow,
+            sender,
+            amount,
+        ))
+    }
+
+    async fn release(&self, sender: Address, amount: u128) -> Result<(), Self::AdapterError> {
+        release_escrow(&self.reserved_escrow, sender, amount);
+        Ok(())
+    }
+
+    async fn available_escrow(&self, sender: Address) -> Result<u128, Self::AdapterError> {
+        Ok(available_escrow(
+            &self.sender_escrow_storage,
+            &self.reserved_escrow,
+            sender,
+        ))
+    }
+
+    async fn try_reserve_in_token(
+        &self,
+        sender: Address,
+        token: Address,
+        amount: u128,
+    ) -> Result<bool, Self::AdapterError> {
+        Ok(try_reserve_escrow(
+            &self.token_escrow_storage,
+            &self.reserved_token_escrow,
+            (sender, token),
+            amount,
+        ))
+    }
+
+    async fn release_in_token(
+        &self,
+        sender: Address,
+        token: Address,
+        amount: u128,
+    ) -> Result<(), Self::AdapterError> {
+        release_escrow(&self.reserved_token_escrow, (sender, token), amount);
+        Ok(())
+    }
+
+    async fn available_escrow_in_token(
+        &self,
+        sender: Address,
+        token: Address,
+    ) -> Result<u128, Self::AdapterError> {
+        Ok(available_escrow(
+            &self.token_escrow_storage,
+            &self.reserved_token_escrow,
+            (sender, token),
+        ))
+    }
+}
+
 #[async_trait]
 impl SignatureChecker for InMemoryContext {
     type AdapterError = InMemoryError;
@@ -261,17 +947,24 @@ pub mod checks {
     use alloy::{dyn_abi::Eip712Domain, primitives::Address};
     use tap_graph::SignedReceipt;
 
+    use super::RAVStorage;
     use crate::{
+        manager::{adapters::EscrowHandler, DomainSeparators},
         receipt::{
             checks::{Check, CheckError, CheckResult, ReceiptCheck},
             state::Checking,
             Context, ReceiptError, ReceiptWithState,
         },
+        signature_cache::SignatureCache,
         signed_message::MessageId,
     };
 
+    /// `domain_separators` is either the default domain separator or the
+    /// [`DomainSeparators`] of the manager running the checks, so that the
+    /// signature check uses the domain of the [`ChainId`](crate::manager::ChainId)
+    /// found in the check [`Context`].
     pub fn get_full_list_of_checks(
-        domain_separator: Eip712Domain,
+        domain_separators: impl Into<DomainSeparators>,
         valid_signers: HashSet<Address>,
         allocation_ids: Arc<RwLock<HashSet<Address>>>,
         _query_appraisals: Arc<RwLock<HashMap<MessageId, u128>>>,
@@ -281,12 +974,163 @@ pub mod checks {
             // Arc::new(ValueCheck { query_appraisals }),
             Arc::new(AllocationIdCheck { allocation_ids }),
             Arc::new(SignatureCheck {
-                domain_separator,
+                domain_separators: domain_separators.into(),
+                valid_signers,
+                signature_cache: None,
+            }),
+        ]
+    }
+
+    /// Same as [`get_full_list_of_checks`], but the signature check looks up
+    /// recovered signers in `signature_cache` first.
+    pub fn get_full_list_of_checks_with_signature_cache(
+        domain_separators: impl Into<DomainSeparators>,
+        valid_signers: HashSet<Address>,
+        allocation_ids: Arc<RwLock<HashSet<Address>>>,
+        signature_cache: SignatureCache,
+    ) -> Vec<ReceiptCheck<SignedReceipt>> {
+        vec![
+            Arc::new(AllocationIdCheck { allocation_ids }),
+            Arc::new(SignatureCheck {
+                domain_separators: domain_separators.into(),
                 valid_signers,
+                signature_cache: Some(signature_cache),
             }),
         ]
     }
 
+    /// Reserves the value of the receipt out of the escrow of its sender,
+    /// through [`EscrowHandler::try_reserve`], so that concurrent receipts
+    /// never commit the same escrow twice. The reservation is released if the
+    /// receipt is rejected afterwards.
+    ///
+    /// By default the whole escrow balance is available. Once set up with
+    /// [`EscrowCheck::with_rav_storage`], the value already committed to the
+    /// latest RAV of each allocation of the sender is subtracted from the
+    /// balance first, so that escrow isn't promised twice.
+    pub struct EscrowCheck<E> {
+        domain_separator: Eip712Domain,
+        escrow: Arc<E>,
+        rav_storage: Option<RAVStorage>,
+    }
+
+    impl<E> EscrowCheck<E> {
+        pub fn new(domain_separator: Eip712Domain, escrow: Arc<E>) -> Self {
+            Self {
+                domain_separator,
+                escrow,
+                rav_storage: None,
+            }
+        }
+
+        /// Same as [`EscrowCheck::new`], as a [`ReceiptCheck`]
+        pub fn boxed(domain_separator: Eip712Domain, escrow: Arc<E>) -> ReceiptCheck<SignedReceipt>
+        where
+            E: EscrowHandler + 'static,
+        {
+            Arc::new(Self::new(domain_separator, escrow))
+        }
+
+        /// Subtracts the value committed to the RAVs in `rav_storage` from
+        /// the escrow balance
+        pub fn with_rav_storage(mut self, rav_storage: RAVStorage) -> Self {
+            self.rav_storage = Some(rav_storage);
+            self
+        }
+
+        fn committed_value(&self, sender: Address) -> u128 {
+            let Some(rav_storage) = &self.rav_storage else {
+                return 0;
+            };
+            rav_storage
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|((rav_sender, _), _)| *rav_sender == sender)
+                .map(|(_, rav)| rav.message.valueAggregate)
+                .fold(0u128, u128::saturating_add)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl<E: EscrowHandler> Check<SignedReceipt> for EscrowCheck<E> {
+        async fn check(
+            &self,
+            _: &Context,
+            receipt: &ReceiptWithState<Checking, SignedReceipt>,
+        ) -> CheckResult {
+            let signed_receipt = receipt.signed_receipt();
+            let sender = signed_receipt
+                .recover_signer(&self.domain_separator)
+                .map_err(|e| {
+                    CheckError::Failed(
+                        ReceiptError::InvalidSignature {
+                            source_error_message: e.to_string(),
+                        }
+                        .into(),
+                    )
+                })?;
+            let received_value = signed_receipt.message.value;
+            let committed_value = self.committed_value(sender);
+
+            let retryable = |e: E::AdapterError| CheckError::Retryable(anyhow::Error::new(e));
+            if self
+                .escrow
+                .try_reserve(sender, received_value)
+                .await
+                .map_err(retryable)?
+            {
+                // the value committed to RAVs isn't reserved, it must still
+                // fit in the escrow left once the receipt is reserved
+                let available_escrow = self
+                    .escrow
+                    .available_escrow(sender)
+                    .await
+                    .map_err(retryable)?;
+                if available_escrow >= committed_value {
+                    return Ok(());
+                }
+                self.escrow
+                    .release(sender, received_value)
+                    .await
+                    .map_err(retryable)?;
+            }
+            let available_escrow = self
+                .escrow
+                .available_escrow(sender)
+                .await
+                .map_err(retryable)?
+                .saturating_sub(committed_value);
+            Err(CheckError::Failed(
+                ReceiptError::NotEnoughEscrow {
+                    sender,
+                    available_escrow,
+                    received_value,
+                }
+                .into(),
+            ))
+        }
+
+        fn is_stateful(&self) -> bool {
+            true
+        }
+
+        async fn rollback(&self, _: &Context, receipt: &ReceiptWithState<Checking, SignedReceipt>) {
+            let signed_receipt = receipt.signed_receipt();
+            // the sender was recovered when reserving, it can't fail now
+            let Ok(sender) = signed_receipt.recover_signer(&self.domain_separator) else {
+                return;
+            };
+            if let Err(err) = self
+                .escrow
+                .release(sender, signed_receipt.message.value)
+                .await
+            {
+                log::error!("Failed to release the escrow reserved for a rejected receipt: {err}");
+            }
+        }
+    }
+
     struct AllocationIdCheck {
         allocation_ids: Arc<RwLock<HashSet<Address>>>,
     }
@@ -318,42 +1162,121 @@ pub mod checks {
     }
 
     struct SignatureCheck {
-        domain_separator: Eip712Domain,
+        domain_separators: DomainSeparators,
         valid_signers: HashSet<Address>,
+        signature_cache: Option<SignatureCache>,
     }
 
     #[async_trait::async_trait]
     impl Check<SignedReceipt> for SignatureCheck {
         async fn check(
             &self,
-            _: &Context,
+            ctx: &Context,
             receipt: &ReceiptWithState<Checking, SignedReceipt>,
         ) -> CheckResult {
-            let recovered_address = receipt
-                .signed_receipt()
-                .recover_signer(&self.domain_separator)
-                .map_err(|e| {
-                    CheckError::Failed(
-                        ReceiptError::InvalidSignature {
-                            source_error_message: e.to_string(),
-                        }
-                        .into(),
-                    )
-                })?;
-
-            if !self.valid_signers.contains(&recovered_address) {
-                Err(CheckError::Failed(
+            let domain_separators = self.domain_separators.select_all(ctx).map_err(|err| {
+                CheckError::Failed(
                     ReceiptError::InvalidSignature {
-                        source_error_message: "Invalid signer".to_string(),
+                        source_error_message: err.to_string(),
                     }
                     .into(),
-                ))
-            } else {
-                Ok(())
+                )
+            })?;
+            let signed_receipt = receipt.signed_receipt();
+            // the selected domain first, then the previous ones
+            let mut source_error_message = "Invalid signer".to_string();
+            for (index, domain_separator) in domain_separators.iter().enumerate() {
+                let recovered_address = match &self.signature_cache {
+                    Some(signature_cache) => {
+                        signature_cache.recover_signer(signed_receipt, domain_separator)
+                    }
+                    None => signed_receipt.recover_signer(domain_separator),
+                };
+                match recovered_address {
+                    Ok(address) if self.valid_signers.contains(&address) => {
+                        self.domain_separators.record_match(index);
+                        return Ok(());
+                    }
+                    Ok(_) => {}
+                    Err(e) if index == 0 => source_error_message = e.to_string(),
+                    Err(_) => {}
+                }
             }
+            Err(CheckError::Failed(
+                ReceiptError::InvalidSignature {
+                    source_error_message,
+                }
+                .into(),
+            ))
         }
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use alloy::signers::local::PrivateKeySigner;
+    use tap_graph::Receipt;
+
+    use super::*;
+    use crate::{signed_message::Eip712SignedMessage, tap_eip712_domain};
+
+    #[tokio::test]
+    async fn commit_rav_rolls_back_and_only_removes_the_rav_key_receipts() {
+        let domain_separator = tap_eip712_domain(1, Address::from([0x11u8; 20]));
+        let (signer, other_signer) = (PrivateKeySigner::random(), PrivateKeySigner::random());
+        let allocation_id = Address::from([0x22u8; 20]);
+        let context = InMemoryContext::new(
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(StatefulTimestampCheck::new(0)),
+        );
+
+        let mut receipt = Receipt::new(allocation_id, 10).unwrap();
+        receipt.timestamp_ns = 100;
+        for receipt_signer in [&signer, &other_signer] {
+            let signed_receipt =
+                Eip712SignedMessage::new(&domain_separator, receipt.clone(), receipt_signer)
+                    .unwrap();
+            context
+                .store_receipt_in_domain(ReceiptWithState::new(signed_receipt), &domain_separator)
+                .await
+                .unwrap();
+        }
+        let rav = Eip712SignedMessage::new(
+            &domain_separator,
+            ReceiptAggregateVoucher {
+                allocationId: allocation_id,
+                timestampNs: 100,
+                valueAggregate: 10,
+            },
+            &signer,
+        )
+        .unwrap();
+        let commit_rav =
+            || context.commit_rav(signer.address(), allocation_id, rav.clone(), ..=100);
+
+        // a failure once the RAV is stored rolls it back, the receipts are kept
+        context.fail_next_transaction();
+        assert!(commit_rav().await.is_err());
+        assert_eq!(
+            context
+                .get_last_rav(signer.address(), allocation_id)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(context.receipt_storage.read().unwrap().len(), 2);
+
+        // the receipt of the other sender isn't covered by the RAV
+        assert_eq!(commit_rav().await.unwrap(), 1);
+        assert_eq!(
+            context
+                .get_last_rav(signer.address(), allocation_id)
+                .await
+                .unwrap(),
+            Some(rav.clone())
+        );
+        assert_eq!(context.receipt_storage.read().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! EIP-712 domains signatures are verified against, see [`DomainSeparators`]
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use alloy::dyn_abi::Eip712Domain;
+
+use crate::{receipt::Context, Error};
+
+/// Chain id of the request being handled, inserted in the [`Context`] by the
+/// caller. It selects which EIP-712 domain is used when chain domains were
+/// set with [`DomainSeparators::set_chain_domains`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChainId(pub u64);
+
+#[derive(Debug)]
+struct Domains {
+    default: Eip712Domain,
+    chains: HashMap<u64, Eip712Domain>,
+    previous: Vec<Eip712Domain>,
+    /// Signatures matching the selected domain, then each previous domain
+    matches: Vec<AtomicU64>,
+}
+
+/// EIP-712 domain separators of a [`crate::manager::Manager`]: a default one,
+/// and one for each chain served, selected by the [`ChainId`] found in the
+/// request [`Context`]. While a verifying contract is upgraded, the previous
+/// domains set with [`DomainSeparators::set_previous_domains`] are accepted
+/// as well by the signature checks.
+///
+/// Clones share the same domains, so that the manager and the checks it runs
+/// always verify a signature against the same domain. Pass a clone to the
+/// signature checks, e.g.
+/// [`get_full_list_of_checks`](crate::manager::context::memory::checks::get_full_list_of_checks),
+/// rather than the domains themselves.
+#[derive(Debug, Clone)]
+pub struct DomainSeparators(Arc<RwLock<Domains>>);
+
+impl DomainSeparators {
+    pub fn new(default: Eip712Domain) -> Self {
+        Self(Arc::new(RwLock::new(Domains {
+            default,
+            chains: HashMap::new(),
+            previous: Vec::new(),
+            matches: vec![AtomicU64::new(0)],
+        })))
+    }
+
+    /// Sets the domain separator used for each chain id, replacing the
+    /// previous ones. Requests without a chain id keep using the default
+    /// domain separator.
+    pub fn set_chain_domains(&self, chain_domains: HashMap<u64, Eip712Domain>) {
+        self.0.write().unwrap().chains = chain_domains;
+    }
+
+    /// Sets the recent past domain separators, most recent first, still
+    /// accepted after the selected domain by the signature checks, e.g. while
+    /// receipts signed against the domain of an upgraded verifying contract
+    /// are in flight. Replaces the previous ones and resets
+    /// [`DomainSeparators::match_counts`]. Set none to stop accepting them.
+    pub fn set_previous_domains(&self, previous: Vec<Eip712Domain>) {
+        let mut domains = self.0.write().unwrap();
+        domains.matches = (0..=previous.len()).map(|_| AtomicU64::new(0)).collect();
+        domains.previous = previous;
+    }
+
+    /// Returns the default domain separator, used for requests without a
+    /// chain id
+    pub fn default_domain(&self) -> Eip712Domain {
+        self.0.read().unwrap().default.clone()
+    }
+
+    /// Returns the domain separator for the [`ChainId`] found in `ctx`, or
+    /// the default domain separator if `ctx` doesn't carry a chain id.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnknownChainId`] if no domain separator is set for
+    /// the chain id
+    ///
+    pub fn select(&self, ctx: &Context) -> Result<Eip712Domain, Error> {
+        let domains = self.0.read().unwrap();
+        match ctx.get::<ChainId>() {
+            Some(ChainId(chain_id)) => {
+                domains
+                    .chains
+                    .get(chain_id)
+                    .cloned()
+                    .ok_or(Error::UnknownChainId {
+                        chain_id: *chain_id,
+                    })
+            }
+            None => Ok(domains.default.clone()),
+        }
+    }
+
+    /// Returns the domain separators a signature is verified against, in
+    /// order: the one selected for `ctx` by [`DomainSeparators::select`],
+    /// then the previous domains.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnknownChainId`] if no domain separator is set for
+    /// the chain id
+    ///
+    pub fn select_all(&self, ctx: &Context) -> Result<Vec<Eip712Domain>, Error> {
+        let selected = self.select(ctx)?;
+        let domains = self.0.read().unwrap();
+        Ok(std::iter::once(selected)
+            .chain(domains.previous.iter().cloned())
+            .collect())
+    }
+
+    /// Records that a signature matched the domain at `index` of
+    /// [`DomainSeparators::select_all`]
+    pub fn record_match(&self, index: usize) {
+        if let Some(matches) = self.0.read().unwrap().matches.get(index) {
+            matches.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the number of signatures that matched the selected domain,
+    /// then each previous domain, to tell when the previous domains can be
+    /// dropped
+    pub fn match_counts(&self) -> Vec<u64> {
+        self.0
+            .read()
+            .unwrap()
+            .matches
+            .iter()
+            .map(|matches| matches.load(Ordering::Relaxed))
+            .collect()
+    }
+}
+
+impl From<Eip712Domain> for DomainSeparators {
+    fn from(default: Eip712Domain) -> Self {
+        Self::new(default)
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Detection of senders signing conflicting receipts for the same query, see
+//! [`crate::manager::Manager::with_equivocation_detection`]
+
+use std::{collections::HashMap, sync::Mutex};
+
+use alloy::primitives::Address;
+
+use super::RavKey;
+
+/// Receipt rejected for sharing the query id of a stored receipt of the same
+/// sender and allocation while differing in value, kept as evidence of the
+/// sender misbehaving, see [`crate::manager::Manager::take_equivocations`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Equivocation<Rcpt> {
+    /// Query id, i.e. nonce, shared by both receipts
+    pub query_id: u64,
+    /// Sender of both receipts
+    pub sender: Address,
+    /// Allocation of both receipts
+    pub allocation_id: Address,
+    /// Id of the receipt stored first, `None` if it was still being stored
+    pub stored_receipt_id: Option<u64>,
+    /// Value of the receipt stored first
+    pub stored_value: u128,
+    /// Receipt rejected
+    pub receipt: Rcpt,
+}
+
+/// Receipt stored for a query
+struct QueryReceipt {
+    value: u128,
+    timestamp_ns: u64,
+    /// `None` until the receipt is stored
+    receipt_id: Option<u64>,
+}
+
+/// Value of the stored receipts, by sender and allocation, then query id
+#[derive(Default)]
+pub(super) struct QueryIndex(Mutex<HashMap<RavKey, HashMap<u64, QueryReceipt>>>);
+
+impl QueryIndex {
+    /// Claims the query of a receipt about to be stored. Returns whether it
+    /// was claimed, i.e. not seen before, or the id and value of the stored
+    /// receipt if it has another value.
+    pub(super) fn claim(
+        &self,
+        rav_key: RavKey,
+        query_id: u64,
+        value: u128,
+        timestamp_ns: u64,
+    ) -> Result<bool, (Option<u64>, u128)> {
+        let mut queries = self.0.lock().unwrap();
+        let queries = queries.entry(rav_key).or_default();
+        match queries.get(&query_id) {
+            Some(stored) if stored.value != value => Err((stored.receipt_id, stored.value)),
+            Some(_) => Ok(false),
+            None => {
+                queries.insert(
+                    query_id,
+                    QueryReceipt {
+                        value,
+                        timestamp_ns,
+                        receipt_id: None,
+                    },
+                );
+                Ok(true)
+            }
+        }
+    }
+
+    /// Records the id of the receipt stored for a query claimed with
+    /// [`QueryIndex::claim`]
+    pub(super) fn stored(&self, rav_key: RavKey, query_id: u64, receipt_id: u64) {
+        let mut queries = self.0.lock().unwrap();
+        if let Some(stored) = queries
+            .get_mut(&rav_key)
+            .and_then(|queries| queries.get_mut(&query_id))
+        {
+            stored.receipt_id.get_or_insert(receipt_id);
+        }
+    }
+
+    /// Releases a query claimed with [`QueryIndex::claim`] whose receipt
+    /// couldn't be stored
+    pub(super) fn release(&self, rav_key: RavKey, query_id: u64) {
+        let mut queries = self.0.lock().unwrap();
+        if let Some(key_queries) = queries.get_mut(&rav_key) {
+            key_queries.remove(&query_id);
+            if key_queries.is_empty() {
+                queries.remove(&rav_key);
+            }
+        }
+    }
+
+    /// Forgets the queries of the receipts of `rav_key` older than
+    /// `min_timestamp_ns`, e.g. once a RAV covers them
+    pub(super) fn drop_before(&self, rav_key: RavKey, min_timestamp_ns: u64) {
+        let mut queries = self.0.lock().unwrap();
+        if let Some(key_queries) = queries.get_mut(&rav_key) {
+            key_queries.retain(|_, stored| stored.timestamp_ns >= min_timestamp_ns);
+            if key_queries.is_empty() {
+                queries.remove(&rav_key);
+            }
+        }
+    }
+
+    /// Forgets the queries of the receipts of every key older than
+    /// `min_timestamp_ns`
+    pub(super) fn drop_all_before(&self, min_timestamp_ns: u64) {
+        self.0.lock().unwrap().retain(|_, key_queries| {
+            key_queries.retain(|_, stored| stored.timestamp_ns >= min_timestamp_ns);
+            !key_queries.is_empty()
+        });
+    }
+
+    /// Replaces the index with `rebuilt`, e.g. rebuilt from storage
+    pub(super) fn replace(&self, rebuilt: QueryIndex) {
+        *self.0.lock().unwrap() = rebuilt.0.into_inner().unwrap();
+    }
+}
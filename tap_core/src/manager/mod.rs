@@ -77,8 +77,22 @@
 //!
 
 pub mod adapters;
+#[cfg(feature = "archive")]
+pub mod archive;
+pub mod checks;
 #[cfg(feature = "in_memory")]
 pub mod context;
+mod domains;
+mod equivocation;
+mod running_aggregate;
 mod tap_manager;
 
-pub use tap_manager::Manager;
+pub use domains::{ChainId, DomainSeparators};
+pub use equivocation::Equivocation;
+pub use running_aggregate::RunningAggregate;
+#[cfg(feature = "metrics")]
+pub use tap_manager::CheckTiming;
+pub use tap_manager::{
+    CheckTimings, CheckWarning, EscrowHealthReport, Manager, RavKey, RevalidationReport,
+    TimestampBoundary,
+};
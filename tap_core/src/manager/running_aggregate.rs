@@ -0,0 +1,147 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Running aggregates of the stored receipts of each sender and allocation,
+//! see [`crate::manager::Manager::with_running_aggregates`]
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+use tap_receipt::rav::AggregationError;
+
+use super::RavKey;
+
+/// Aggregate of the stored receipts of a sender and allocation not covered
+/// by a RAV yet, see [`crate::manager::Manager::running_aggregate`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RunningAggregate {
+    /// Sum of the receipt values
+    pub value_aggregate: u128,
+    /// Number of receipts
+    pub receipt_count: u64,
+    /// Latest receipt timestamp, 0 if there is no receipt
+    pub max_timestamp_ns: u64,
+}
+
+/// Receipts of a sender and allocation, summed up by timestamp so that the
+/// ones covered by a RAV can be dropped whatever order they arrived in
+#[derive(Default)]
+struct KeyAggregate {
+    by_timestamp_ns: BTreeMap<u64, (u128, u64)>,
+    value_aggregate: u128,
+    receipt_count: u64,
+}
+
+impl KeyAggregate {
+    /// Adds a receipt, unless the aggregate would overflow
+    fn add(&mut self, timestamp_ns: u64, value: u128) -> Result<(), AggregationError> {
+        // the bucket is part of the aggregate, it can't overflow if the
+        // aggregate doesn't
+        let value_aggregate = self
+            .value_aggregate
+            .checked_add(value)
+            .ok_or(AggregationError::AggregateOverflow)?;
+        let (bucket_value, bucket_count) = self.by_timestamp_ns.entry(timestamp_ns).or_default();
+        *bucket_value += value;
+        *bucket_count += 1;
+        self.value_aggregate = value_aggregate;
+        self.receipt_count += 1;
+        Ok(())
+    }
+
+    fn remove(&mut self, timestamp_ns: u64, value: u128) {
+        let Some((bucket_value, bucket_count)) = self.by_timestamp_ns.get_mut(&timestamp_ns) else {
+            return;
+        };
+        *bucket_value = bucket_value.saturating_sub(value);
+        *bucket_count -= 1;
+        if *bucket_count == 0 {
+            self.by_timestamp_ns.remove(&timestamp_ns);
+        }
+        self.value_aggregate = self.value_aggregate.saturating_sub(value);
+        self.receipt_count -= 1;
+    }
+
+    /// Drops the receipts older than `min_timestamp_ns`
+    fn drop_before(&mut self, min_timestamp_ns: u64) {
+        let kept = self.by_timestamp_ns.split_off(&min_timestamp_ns);
+        for (value, count) in std::mem::replace(&mut self.by_timestamp_ns, kept).into_values() {
+            self.value_aggregate = self.value_aggregate.saturating_sub(value);
+            self.receipt_count -= count;
+        }
+    }
+
+    fn snapshot(&self) -> RunningAggregate {
+        RunningAggregate {
+            value_aggregate: self.value_aggregate,
+            receipt_count: self.receipt_count,
+            max_timestamp_ns: self
+                .by_timestamp_ns
+                .last_key_value()
+                .map_or(0, |(timestamp_ns, _)| *timestamp_ns),
+        }
+    }
+}
+
+/// Running aggregates of all the senders and allocations
+#[derive(Default)]
+pub(super) struct RunningAggregates(Mutex<HashMap<RavKey, KeyAggregate>>);
+
+impl RunningAggregates {
+    /// Adds a receipt of `rav_key`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AggregationError::AggregateOverflow`] if the aggregate of
+    /// `rav_key` would overflow, the receipt isn't added then
+    ///
+    pub(super) fn add(
+        &self,
+        rav_key: RavKey,
+        timestamp_ns: u64,
+        value: u128,
+    ) -> Result<(), AggregationError> {
+        self.0
+            .lock()
+            .unwrap()
+            .entry(rav_key)
+            .or_default()
+            .add(timestamp_ns, value)
+    }
+
+    pub(super) fn remove(&self, rav_key: RavKey, timestamp_ns: u64, value: u128) {
+        if let Some(aggregate) = self.0.lock().unwrap().get_mut(&rav_key) {
+            aggregate.remove(timestamp_ns, value);
+        }
+    }
+
+    /// Drops the receipts older than `min_timestamp_ns` of every key, e.g.
+    /// once they are evicted from storage whoever sent them
+    pub(super) fn drop_all_before(&self, min_timestamp_ns: u64) {
+        for aggregate in self.0.lock().unwrap().values_mut() {
+            aggregate.drop_before(min_timestamp_ns);
+        }
+    }
+
+    /// Drops the receipts of `rav_key` older than `min_timestamp_ns`, then
+    /// returns the aggregate of the remaining ones
+    pub(super) fn aggregate_from(
+        &self,
+        rav_key: RavKey,
+        min_timestamp_ns: u64,
+    ) -> RunningAggregate {
+        let mut aggregates = self.0.lock().unwrap();
+        let Some(aggregate) = aggregates.get_mut(&rav_key) else {
+            return RunningAggregate::default();
+        };
+        aggregate.drop_before(min_timestamp_ns);
+        aggregate.snapshot()
+    }
+
+    /// Replaces every aggregate with the ones of `rebuilt`
+    pub(super) fn replace(&self, rebuilt: RunningAggregates) {
+        *self.0.lock().unwrap() = rebuilt.0.into_inner().unwrap();
+    }
+}
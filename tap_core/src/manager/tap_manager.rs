@@ -1,33 +1,319 @@
 // Copyright 2023-, Semiotic AI, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use alloy::{dyn_abi::Eip712Domain, sol_types::SolStruct};
-use tap_receipt::rav::Aggregate;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    future::Future,
+    ops::RangeBounds,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, OnceLock, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use alloy::{
+    dyn_abi::Eip712Domain,
+    primitives::Address,
+    sol_types::{SolStruct, SolValue},
+};
+use futures_util::{future, future::BoxFuture, stream, StreamExt};
+use tap_receipt::rav::{Aggregate, FromAggregate};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
 
-use super::adapters::{
-    RavRead, RavStore, ReceiptDelete, ReceiptRead, ReceiptStore, SignatureChecker,
+use super::{
+    adapters::{
+        EscrowHandler, EscrowMonitor, RavDelete, RavHistoryRead, RavListRead, RavRead, RavSink,
+        RavStore, RavTransaction, ReceiptAllocationRead, ReceiptDelete, ReceiptQuarantine,
+        ReceiptRead, ReceiptStore, SignatureChecker, StoredRav, StoredReceipt, StoredReceiptRead,
+    },
+    domains::DomainSeparators,
+    equivocation::{Equivocation, QueryIndex},
+    running_aggregate::{RunningAggregate, RunningAggregates},
 };
 use crate::{
-    rav_request::RavRequest,
+    audit::RavBundle,
+    rav_request::{compute_expected_rav, is_rav_worth_redeeming, RavRequest},
     receipt::{
-        checks::{CheckBatch, CheckList, TimestampCheck, UniqueCheck},
-        state::{Checked, Failed},
-        Context, ReceiptError, ReceiptWithState, WithUniqueId, WithValueAndTimestamp,
+        checks::{
+            AllocationCheck, CheckBatch, CheckList, CheckPipeline, CheckSeverity, ReceiptCheck,
+            TimestampCheck, UniqueCheck,
+        },
+        receipt_order_key,
+        state::{Checked, Checking, Failed},
+        Context, ReceiptError, ReceiptWithState, VersionedReceipt, WithAllocationId, WithNonce,
+        WithReceiptHash, WithUniqueId, WithValueAndTimestamp, WithVersion,
     },
+    signature_cache::SignatureCache,
     signed_message::Eip712SignedMessage,
     Error,
 };
 
+/// Sender and allocation of the RAV being requested or stored, inserted in
+/// the [`Context`] by the caller. RAVs are chained on, and stored under, this
+/// key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RavKey {
+    pub sender: Address,
+    pub allocation_id: Address,
+}
+
+/// Whether a receipt with a timestamp exactly at a timestamp bound is within
+/// it, see [`Manager::with_min_receipt_timestamp`]. Timestamp minimums are
+/// inclusive throughout the library, e.g. in
+/// [`StatefulTimestampCheck`](crate::receipt::checks::StatefulTimestampCheck),
+/// an exclusive bound being the inclusive minimum right after it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimestampBoundary {
+    /// A receipt at the bound is accepted
+    #[default]
+    Inclusive,
+    /// A receipt at the bound is rejected
+    Exclusive,
+}
+
+/// Outcome of probing the escrow adapter for known senders, see
+/// [`Manager::validate_escrow_source`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EscrowHealthReport {
+    /// Senders with escrow available
+    pub funded: Vec<Address>,
+    /// Senders without any escrow available
+    pub unfunded: Vec<Address>,
+    /// Senders the adapter failed to probe, along with the error
+    pub failed: Vec<(Address, String)>,
+}
+
+impl EscrowHealthReport {
+    /// Returns `true` if the adapter answered for every sender and found
+    /// escrow for at least one of them. An adapter reading the wrong
+    /// contract or an empty map would find none and reject every receipt.
+    pub fn is_healthy(&self) -> bool {
+        self.failed.is_empty() && (!self.funded.is_empty() || self.unfunded.is_empty())
+    }
+}
+
+/// Stored receipts failing the checks passed to
+/// [`Manager::revalidate_stored_receipts`]
+#[derive(Debug)]
+pub struct RevalidationReport<Rcpt> {
+    /// Number of stored receipts checked
+    pub checked: u64,
+    /// Receipts failing a check, along with the first failure
+    pub failed: Vec<(StoredReceipt<Rcpt>, ReceiptError)>,
+}
+
+impl<Rcpt> RevalidationReport<Rcpt> {
+    /// Returns the ids of the failed receipts whose failure isn't retryable,
+    /// i.e. those [`Manager::quarantine_receipts`] moves to the quarantine
+    pub fn invalid_receipt_ids(&self) -> Vec<u64> {
+        self.failed
+            .iter()
+            .filter(|(_, err)| !err.is_retryable())
+            .map(|(receipt, _)| receipt.id())
+            .collect()
+    }
+}
+
+/// Failure of a [`CheckSeverity::Warn`] check, which doesn't reject the
+/// receipt, see [`Manager::verify_and_store_receipt_with_warnings`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckWarning {
+    /// Name of the failed check, see
+    /// [`Check::name`](crate::receipt::checks::Check::name)
+    pub check_name: &'static str,
+    /// Why the check failed
+    pub message: String,
+}
+
+/// Time spent verifying a single receipt in each of the manager checks, by
+/// [`Check::name`](crate::receipt::checks::Check::name), see
+/// [`Manager::verify_and_store_receipt_with_timings`]. Checks sharing a name
+/// add up, disabled checks are left out.
+pub type CheckTimings = HashMap<&'static str, Duration>;
+
+/// Time spent in one of the manager checks, see [`Manager::check_timings`]
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckTiming {
+    /// Name of the check, see [`Check::name`](crate::receipt::checks::Check::name)
+    pub name: &'static str,
+    /// Number of times the check ran
+    pub calls: u64,
+    /// Total time spent in the check
+    pub total: Duration,
+    /// Longest single run of the check
+    pub max: Duration,
+}
+
+/// Reads the [`RavKey`] a receipt is kept under in the running aggregates
+/// and the query index, see [`Manager::with_running_aggregates`] and
+/// [`Manager::with_equivocation_detection`]
+struct RavKeyFields<E, Rcpt> {
+    /// Returns the allocation id of a receipt
+    allocation_id: fn(&Rcpt) -> Address,
+    /// Recovers the signer of a receipt
+    signer: fn(&Rcpt, &Eip712Domain) -> Result<Address, Error>,
+    /// Returns the sender a signer signs on behalf of, see
+    /// [`SignatureChecker::sender_of`]
+    sender_of: for<'a> fn(&'a E, Address) -> BoxFuture<'a, Result<Address, Error>>,
+}
+
+/// Returns the `(timestamp_ns, value)` a receipt adds to the running
+/// aggregates, see [`Manager::with_running_aggregates`]
+type AggregateFields<Rcpt> = fn(&Rcpt) -> (u64, u128);
+
+/// Returns the `(query_id, value, timestamp_ns)` of a receipt, see
+/// [`Manager::with_equivocation_detection`]
+type QueryFields<Rcpt> = fn(&Rcpt) -> (u64, u128, u64);
+
 pub struct Manager<E, Rcpt> {
     /// Context that implements adapters
     context: E,
 
     /// Checks that must be completed for each receipt before being confirmed or denied for rav request
-    checks: CheckList<Rcpt>,
+    checks: CheckPipeline<Rcpt>,
+
+    /// Checks evaluated on each incoming receipt whose failures are only
+    /// logged and counted, never rejecting the receipt
+    shadow_checks: CheckList<Rcpt>,
+
+    /// Number of shadow check failures observed so far
+    shadow_check_failures: AtomicU64,
 
     /// Struct responsible for doing checks for receipt. Ownership stays with manager allowing manager
     /// to update configuration ( like minimum timestamp ).
-    domain_separator: Eip712Domain,
+    domain_separators: DomainSeparators,
+
+    /// When set, new receipts are rejected with [`Error::IngestionPaused`]
+    paused: AtomicBool,
+
+    /// When set, new receipts are rejected with
+    /// [`Error::StorageQuotaExceeded`], see [`Manager::is_storage_full`]
+    storage_full: AtomicBool,
+
+    /// Version of the receipts accepted by
+    /// [`Manager::verify_and_store_versioned_receipt`], set by
+    /// [`Manager::with_supported_receipt_version`]
+    supported_receipt_version: Option<u8>,
+
+    /// Reads the key of the receipts in `running_aggregates` and
+    /// `query_index`, set along with `running_aggregate_fields` or
+    /// `query_fields`
+    rav_key_fields: Option<RavKeyFields<E, Rcpt>>,
+
+    /// Reads the fields of the receipts summed up in `running_aggregates`,
+    /// set by [`Manager::with_running_aggregates`]
+    running_aggregate_fields: Option<AggregateFields<Rcpt>>,
+
+    /// Aggregates of the stored receipts not covered by a RAV yet, by
+    /// sender and allocation
+    running_aggregates: RunningAggregates,
+
+    /// Reads the query of the receipts indexed in `query_index`, set by
+    /// [`Manager::with_equivocation_detection`]
+    query_fields: Option<QueryFields<Rcpt>>,
+
+    /// Values of the stored receipts, by sender and allocation, then query id
+    query_index: QueryIndex,
+
+    /// Number of receipts rejected with [`Error::ReceiptEquivocation`] kept
+    /// in `equivocations`
+    max_equivocations: usize,
+
+    /// Latest receipts rejected with [`Error::ReceiptEquivocation`], drained
+    /// by [`Manager::take_equivocations`]
+    equivocations: Mutex<VecDeque<Equivocation<Rcpt>>>,
+
+    /// High-water mark of pending receipts above which new receipts are
+    /// rejected with [`Error::BackpressureLimitReached`]
+    max_pending_receipts: Option<u64>,
+
+    /// Number of stored receipts not yet aggregated into a RAV
+    pending_receipts: AtomicU64,
+
+    /// Number of receipts collected by the last RAV request of each key,
+    /// drained from `pending_receipts` once its RAV is stored
+    last_rav_request_receipts: Mutex<HashMap<RavKey, u64>>,
+
+    /// Include the EIP-712 digest in [`Error::SignatureMismatch`]
+    signature_debug: bool,
+
+    /// Minimum age of a receipt before it can be aggregated into a RAV
+    eligibility_delay_ns: u64,
+
+    /// Number of receipts checked concurrently while creating a RAV request
+    verification_concurrency: usize,
+
+    /// Number of receipts checked and stored concurrently by
+    /// [`Manager::verify_and_store_receipts`]
+    receipt_concurrency: usize,
+
+    /// Producer side of the ingestion channel, set by [`Manager::start_ingestion`]
+    ingest_sender: OnceLock<mpsc::Sender<Rcpt>>,
+
+    /// Sinks notified of stored RAVs, with whether their failures are
+    /// returned rather than logged
+    rav_sinks: Vec<(Arc<dyn RavSink>, bool)>,
+
+    /// Minimum value of a RAV produced by [`Manager::create_rav_request`]
+    min_profitable_rav_value: Option<u128>,
+
+    /// Maximum timestamp range of the receipts aggregated by a single RAV
+    max_rav_range_ns: Option<u64>,
+
+    /// RAVs adding less value than this over the previous RAV are logged and
+    /// counted in `low_value_ravs`
+    low_value_rav_threshold: Option<u128>,
+
+    /// Number of RAV requests adding less than `low_value_rav_threshold`
+    low_value_ravs: AtomicU64,
+
+    /// Maximum number of valid receipts returned by [`Manager::create_rav_request`]
+    valid_receipts_sample_size: Option<usize>,
+
+    /// Receipts older than this are rejected on ingestion. Always
+    /// inclusive, an exclusive floor is stored as the timestamp right after
+    /// it.
+    min_receipt_timestamp_ns: AtomicU64,
+
+    /// Timestamp of the last RAV of each key, recovered by
+    /// [`Manager::new_recovering`] and raised as RAVs are stored
+    last_rav_timestamps: RwLock<HashMap<RavKey, u64>>,
+
+    /// Until then, receipts below `min_receipt_timestamp_ns` are accepted
+    /// and flagged in storage instead of rejected
+    below_min_timestamp_grace_until: Option<Instant>,
+
+    /// Set once the grace period is over, so that the clock isn't read
+    /// again
+    below_min_timestamp_grace_expired: AtomicBool,
+
+    /// Number of receipts accepted below the minimum timestamp
+    below_min_timestamp_receipts: AtomicU64,
+
+    /// Signers recovered from RAV signatures
+    signature_cache: Option<SignatureCache>,
+
+    /// Notified of receipts rejected for lack of escrow
+    escrow_monitor: Option<Arc<dyn EscrowMonitor>>,
+
+    /// Number of receipts rejected for lack of escrow
+    escrow_insufficient_rejections: AtomicU64,
+
+    /// Time after which any check is aborted, unless overridden in `check_timeouts`
+    check_timeout: Option<Duration>,
+
+    /// Time after which a check is aborted, keyed by check name
+    check_timeouts: HashMap<&'static str, Duration>,
+
+    /// Names of the checks skipped at runtime, see [`Manager::set_check_enabled`]
+    disabled_checks: RwLock<HashSet<&'static str>>,
+
+    /// Time spent in each of `checks`, in the same order
+    #[cfg(feature = "metrics")]
+    check_timings: Mutex<Vec<CheckTiming>>,
 }
 
 impl<E, Rcpt> Manager<E, Rcpt> {
@@ -35,241 +321,2844 @@ impl<E, Rcpt> Manager<E, Rcpt> {
     /// will complete all `required_checks` before being accepted or declined from RAV.
     /// `starting_min_timestamp` will be used as min timestamp until the first RAV request is created.
     ///
+    /// `domain_separators` is either the default domain separator or the
+    /// [`DomainSeparators`] shared with the signature checks.
     pub fn new(
-        domain_separator: Eip712Domain,
+        domain_separators: impl Into<DomainSeparators>,
         context: E,
-        checks: impl Into<CheckList<Rcpt>>,
+        checks: impl Into<CheckPipeline<Rcpt>>,
     ) -> Self {
+        let domain_separators = domain_separators.into();
+        log::info!(
+            "TAP manager domain separator: {:?}",
+            domain_separators.default_domain()
+        );
+        let checks = checks.into();
+        #[cfg(feature = "metrics")]
+        let check_timings = checks
+            .iter()
+            .map(|check| CheckTiming {
+                name: check.name(),
+                calls: 0,
+                total: Duration::ZERO,
+                max: Duration::ZERO,
+            })
+            .collect();
         Self {
             context,
-            domain_separator,
-            checks: checks.into(),
+            domain_separators,
+            checks,
+            shadow_checks: CheckList::empty(),
+            shadow_check_failures: AtomicU64::new(0),
+            paused: AtomicBool::new(false),
+            storage_full: AtomicBool::new(false),
+            supported_receipt_version: None,
+            rav_key_fields: None,
+            running_aggregate_fields: None,
+            running_aggregates: RunningAggregates::default(),
+            query_fields: None,
+            query_index: QueryIndex::default(),
+            max_equivocations: 0,
+            equivocations: Mutex::new(VecDeque::new()),
+            max_pending_receipts: None,
+            pending_receipts: AtomicU64::new(0),
+            last_rav_request_receipts: Mutex::new(HashMap::new()),
+            signature_debug: false,
+            eligibility_delay_ns: 0,
+            verification_concurrency: 1,
+            receipt_concurrency: 1,
+            ingest_sender: OnceLock::new(),
+            rav_sinks: Vec::new(),
+            min_profitable_rav_value: None,
+            max_rav_range_ns: None,
+            low_value_rav_threshold: None,
+            low_value_ravs: AtomicU64::new(0),
+            valid_receipts_sample_size: None,
+            min_receipt_timestamp_ns: AtomicU64::new(0),
+            last_rav_timestamps: RwLock::new(HashMap::new()),
+            below_min_timestamp_grace_until: None,
+            below_min_timestamp_grace_expired: AtomicBool::new(false),
+            below_min_timestamp_receipts: AtomicU64::new(0),
+            signature_cache: None,
+            escrow_monitor: None,
+            escrow_insufficient_rejections: AtomicU64::new(0),
+            check_timeout: None,
+            check_timeouts: HashMap::new(),
+            disabled_checks: RwLock::new(HashSet::new()),
+            #[cfg(feature = "metrics")]
+            check_timings: Mutex::new(check_timings),
         }
     }
 
-    async fn get_previous_rav<Rav: SolStruct>(
-        &self,
-    ) -> Result<Option<Eip712SignedMessage<Rav>>, Error>
+    /// Same as [`Manager::new`], but first loads the last RAV of every sender
+    /// and allocation from storage, e.g. after a restart. Receipts of a
+    /// [`RavKey`] not newer than its recovered RAV are then rejected by
+    /// [`Manager::verify_and_store_receipt`] right away, instead of being
+    /// stored only to be left out of the next RAV.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if unable to fetch the last RAVs
+    ///
+    pub async fn new_recovering<Rav>(
+        domain_separators: impl Into<DomainSeparators>,
+        context: E,
+        checks: impl Into<CheckPipeline<Rcpt>>,
+    ) -> Result<Self, Error>
     where
-        E: RavRead<Rav>,
+        E: RavListRead<Rav>,
+        Rav: SolStruct + WithValueAndTimestamp,
     {
-        let previous_rav = self
+        let manager = Self::new(domain_separators, context, checks);
+        let last_ravs = manager
             .context
-            .last_rav()
+            .get_last_ravs()
             .await
-            .map_err(|err| Error::AdapterError {
-                source_error: anyhow::Error::new(err),
-            })?;
-        Ok(previous_rav)
+            .map_err(storage_error)?;
+        for (sender, allocation_id, last_rav) in last_ravs {
+            manager.raise_last_rav_timestamp(
+                RavKey {
+                    sender,
+                    allocation_id,
+                },
+                last_rav.message.timestamp_ns(),
+            );
+        }
+        Ok(manager)
     }
 
-    /// Verify `signed_rav` matches all values on `expected_rav`, and that `signed_rav` has a valid signer.
+    /// Same as [`Manager::new`], but first checks that `domain_separator` has
+    /// `expected_verifying_contract` as verifying contract. A wrong verifying
+    /// contract silently fails the verification of every receipt, so this
+    /// catches it before any receipt comes in.
     ///
     /// # Errors
     ///
-    /// Returns [`Error::AdapterError`] if there are any errors while storing RAV
+    /// Returns [`Error::VerifyingContractMismatch`] if the verifying contract
+    /// of `domain_separator` is missing or isn't `expected_verifying_contract`
     ///
-    pub async fn verify_and_store_rav<Rav>(
-        &self,
-        expected_rav: Rav,
-        signed_rav: Eip712SignedMessage<Rav>,
-    ) -> std::result::Result<(), Error>
-    where
-        E: RavStore<Rav> + SignatureChecker,
-        Rav: SolStruct + PartialEq<Rav> + Sync + std::fmt::Debug,
-    {
-        self.context
-            .check_signature(&signed_rav, &self.domain_separator)
-            .await?;
-
-        if signed_rav.message != expected_rav {
-            return Err(Error::InvalidReceivedRav {
-                received_rav: format!("{:?}", signed_rav.message),
-                expected_rav: format!("{:?}", expected_rav),
+    pub fn new_validated(
+        domain_separators: impl Into<DomainSeparators>,
+        context: E,
+        checks: impl Into<CheckPipeline<Rcpt>>,
+        expected_verifying_contract: Address,
+    ) -> Result<Self, Error> {
+        let domain_separators = domain_separators.into();
+        let domain_separator = domain_separators.default_domain();
+        if domain_separator.verifying_contract != Some(expected_verifying_contract) {
+            log::error!(
+                "TAP manager domain separator {domain_separator:?} doesn't have the expected verifying contract {expected_verifying_contract}"
+            );
+            return Err(Error::VerifyingContractMismatch {
+                expected: expected_verifying_contract,
+                configured: domain_separator.verifying_contract,
             });
         }
+        Ok(Self::new(domain_separators, context, checks))
+    }
 
-        self.context
-            .update_last_rav(signed_rav)
-            .await
-            .map_err(|err| Error::AdapterError {
-                source_error: anyhow::Error::new(err),
-            })?;
+    /// Returns the minimum timestamp of receipts accepted by
+    /// [`Manager::verify_and_store_receipt`], `0` unless set with
+    /// [`Manager::with_min_receipt_timestamp_ns`]. A receipt at exactly this
+    /// timestamp is accepted.
+    pub fn min_receipt_timestamp_ns(&self) -> u64 {
+        self.min_receipt_timestamp_ns.load(Ordering::SeqCst)
+    }
 
-        Ok(())
+    /// Records `rav_timestamp_ns` as the timestamp of the last RAV of
+    /// `rav_key`, unless a later one is known already
+    fn raise_last_rav_timestamp(&self, rav_key: RavKey, rav_timestamp_ns: u64) {
+        let mut last_rav_timestamps = self.last_rav_timestamps.write().unwrap();
+        let last_rav_timestamp_ns = last_rav_timestamps.entry(rav_key).or_default();
+        *last_rav_timestamp_ns = (*last_rav_timestamp_ns).max(rav_timestamp_ns);
     }
-}
 
-impl<E, Rcpt> Manager<E, Rcpt>
-where
-    E: ReceiptRead<Rcpt>,
-    Rcpt: WithUniqueId + WithValueAndTimestamp,
-{
-    async fn collect_receipts(
-        &self,
-        ctx: &Context,
-        timestamp_buffer_ns: u64,
+    /// Sets the minimum timestamp of receipts accepted by
+    /// [`Manager::verify_and_store_receipt`], older receipts are rejected with
+    /// [`ReceiptError::BelowMinTimestamp`]. The minimum is inclusive, a
+    /// receipt at exactly `min_timestamp_ns` is accepted, see
+    /// [`Manager::with_min_receipt_timestamp`] otherwise.
+    pub fn with_min_receipt_timestamp_ns(self, min_timestamp_ns: u64) -> Self {
+        self.with_min_receipt_timestamp(min_timestamp_ns, TimestampBoundary::Inclusive)
+    }
+
+    /// Same as [`Manager::with_min_receipt_timestamp_ns`], with `boundary`
+    /// telling whether a receipt at exactly `min_timestamp_ns` is accepted.
+    /// [`TimestampBoundary::Exclusive`] suits a floor taken from the
+    /// timestamp of a RAV, which covers receipts at its own timestamp.
+    pub fn with_min_receipt_timestamp(
+        self,
         min_timestamp_ns: u64,
-        limit: Option<u64>,
-    ) -> Result<
-        (
-            Vec<ReceiptWithState<Checked, Rcpt>>,
-            Vec<ReceiptWithState<Failed, Rcpt>>,
-        ),
-        Error,
-    > {
-        let max_timestamp_ns = crate::get_current_timestamp_u64_ns()? - timestamp_buffer_ns;
+        boundary: TimestampBoundary,
+    ) -> Self {
+        let min_timestamp_ns = match boundary {
+            TimestampBoundary::Inclusive => min_timestamp_ns,
+            TimestampBoundary::Exclusive => min_timestamp_ns.saturating_add(1),
+        };
+        self.min_receipt_timestamp_ns
+            .store(min_timestamp_ns, Ordering::SeqCst);
+        self
+    }
 
-        if min_timestamp_ns > max_timestamp_ns {
-            return Err(Error::TimestampRangeError {
-                min_timestamp_ns,
-                max_timestamp_ns,
-            });
-        }
-        let checking_receipts = self
-            .context
-            .retrieve_receipts_in_timestamp_range(min_timestamp_ns..max_timestamp_ns, limit)
-            .await
-            .map_err(|err| Error::AdapterError {
-                source_error: anyhow::Error::new(err),
-            })?;
+    /// Accepts receipts below the minimum timestamp for `grace_period_ns`
+    /// from now, e.g. while starting up with a floor that may be too
+    /// aggressive. Such receipts are logged, counted in
+    /// [`Manager::below_min_timestamp_receipts`] and stored with
+    /// [`ReceiptStore::store_receipt_below_min_timestamp`] instead of being
+    /// rejected with [`ReceiptError::BelowMinTimestamp`].
+    ///
+    /// Only receipts whose `ctx` carries their [`RavKey`] are accepted, so
+    /// that a receipt already covered by the last RAV of its key, e.g. one
+    /// recovered with [`Manager::new_recovering`], is still rejected with
+    /// [`Error::ReceiptTimestampLowerThanRav`].
+    pub fn with_below_min_timestamp_grace_period_ns(mut self, grace_period_ns: u64) -> Self {
+        self.below_min_timestamp_grace_until =
+            Instant::now().checked_add(Duration::from_nanos(grace_period_ns));
+        self.below_min_timestamp_grace_expired
+            .store(false, Ordering::Relaxed);
+        self
+    }
 
-        let mut checked_receipts = vec![];
-        let mut failed_receipts = vec![];
+    /// Returns whether receipts below the minimum timestamp are still
+    /// accepted, see [`Manager::with_below_min_timestamp_grace_period_ns`]
+    fn in_below_min_timestamp_grace_period(&self) -> bool {
+        let Some(grace_until) = self.below_min_timestamp_grace_until else {
+            return false;
+        };
+        if self
+            .below_min_timestamp_grace_expired
+            .load(Ordering::Relaxed)
+        {
+            return false;
+        }
+        if Instant::now() < grace_until {
+            return true;
+        }
+        self.below_min_timestamp_grace_expired
+            .store(true, Ordering::Relaxed);
+        false
+    }
 
-        // check for timestamp
-        let (checking_receipts, already_failed) =
-            TimestampCheck(min_timestamp_ns).check_batch(checking_receipts);
-        failed_receipts.extend(already_failed);
+    /// Returns the number of receipts accepted below the minimum timestamp
+    /// during the grace period set with
+    /// [`Manager::with_below_min_timestamp_grace_period_ns`]
+    pub fn below_min_timestamp_receipts(&self) -> u64 {
+        self.below_min_timestamp_receipts.load(Ordering::Relaxed)
+    }
 
-        // check for uniqueness
-        let (checking_receipts, already_failed) = UniqueCheck.check_batch(checking_receipts);
-        failed_receipts.extend(already_failed);
+    /// Sets the minimum value for a RAV to be worth redeeming on-chain.
+    /// [`Manager::create_rav_request`] refuses to produce RAVs below it with
+    /// [`Error::RavBelowProfitabilityThreshold`], leaving the receipts pending.
+    pub fn with_min_profitable_rav_value(mut self, min_profitable_rav_value: u128) -> Self {
+        self.min_profitable_rav_value = Some(min_profitable_rav_value);
+        self
+    }
 
-        for receipt in checking_receipts.into_iter() {
-            let receipt = receipt
-                .finalize_receipt_checks(ctx, &self.checks)
-                .await
-                .map_err(|e| Error::ReceiptError(ReceiptError::RetryableCheck(e)))?;
+    /// Caps the timestamp range covered by a single RAV produced by
+    /// [`Manager::create_rav_request`] to `max_range_ns`, starting at the
+    /// earliest pending receipt. Later receipts are left for the next RAV
+    /// requests.
+    pub fn with_max_rav_range_ns(mut self, max_range_ns: u64) -> Self {
+        self.max_rav_range_ns = Some(max_range_ns);
+        self
+    }
 
-            match receipt {
-                Ok(checked) => checked_receipts.push(checked),
-                Err(failed) => failed_receipts.push(failed),
-            }
-        }
+    /// Warns about RAV requests whose
+    /// [`escrow_delta`](RavRequest::escrow_delta) is below `threshold`, i.e.
+    /// RAVs adding next to nothing over the previous one, which hints at RAVs
+    /// being requested too often. Unlike
+    /// [`Manager::with_min_profitable_rav_value`], the RAV request still
+    /// succeeds: it is only logged and counted in [`Manager::low_value_ravs`].
+    pub fn with_low_value_rav_warning(mut self, threshold: u128) -> Self {
+        self.low_value_rav_threshold = Some(threshold);
+        self
+    }
 
-        Ok((checked_receipts, failed_receipts))
+    /// Returns the number of RAV requests below the threshold set with
+    /// [`Manager::with_low_value_rav_warning`]
+    pub fn low_value_ravs(&self) -> u64 {
+        self.low_value_ravs.load(Ordering::Relaxed)
     }
 
-    /// Completes remaining checks on all receipts up to
-    /// (current time - `timestamp_buffer_ns`). Returns them in two lists
-    /// (valid receipts and invalid receipts) along with the expected RAV that
-    /// should be received for aggregating list of valid receipts.
+    /// Memoizes the signers recovered from RAV signatures in
+    /// `signature_cache`, e.g. the previous RAV checked by every
+    /// [`Manager::create_rav_request`]. The recovered signer is still
+    /// authorized with [`SignatureChecker::verify_signer`] every time, in
+    /// place of [`SignatureChecker::check_signature`]: don't set a cache if
+    /// the adapter overrides the latter.
     ///
-    /// Returns [`Error::AggregateOverflow`] if any receipt value causes
-    /// aggregate value to overflow while generating expected RAV
+    /// Pass a clone of the same cache to the receipt signature check to also
+    /// memoize receipt signers.
+    pub fn with_signature_cache(mut self, signature_cache: SignatureCache) -> Self {
+        self.signature_cache = Some(signature_cache);
+        self
+    }
+
+    /// Returns the cache set with [`Manager::with_signature_cache`]
+    pub fn signature_cache(&self) -> Option<&SignatureCache> {
+        self.signature_cache.as_ref()
+    }
+
+    /// Checks the signature of `signed_message` with
+    /// [`SignatureChecker::check_signature`] and returns its signer. With a
+    /// signature cache, the signer is recovered through the cache and
+    /// authorized with [`SignatureChecker::verify_signer`] instead, see
+    /// [`Manager::with_signature_cache`].
     ///
-    /// Returns [`Error::AdapterError`] if unable to fetch previous RAV or
-    /// if unable to fetch previous receipts
+    /// # Errors
     ///
-    /// Returns [`Error::TimestampRangeError`] if the max timestamp of the
-    /// previous RAV is greater than the min timestamp. Caused by timestamp
-    /// buffer being too large, or requests coming too soon.
+    /// Returns [`Error::InvalidRecoveredSigner`] if the signer is not authorized
     ///
-    pub async fn create_rav_request<Rav>(
+    async fn check_signature<T: SolStruct + Sync>(
         &self,
-        ctx: &Context,
-        timestamp_buffer_ns: u64,
-        receipts_limit: Option<u64>,
-    ) -> Result<RavRequest<Rcpt, Rav>, Error>
+        signed_message: &Eip712SignedMessage<T>,
+        domain_separator: &Eip712Domain,
+    ) -> Result<Address, Error>
     where
-        E: RavRead<Rav>,
-        Rav: SolStruct + WithValueAndTimestamp + Clone + Aggregate<Rcpt>,
+        E: SignatureChecker,
     {
-        let previous_rav = self.get_previous_rav().await?;
-        let min_timestamp_ns = previous_rav
-            .as_ref()
-            .map(|rav| rav.message.timestamp_ns() + 1)
-            .unwrap_or(0);
+        let Some(signature_cache) = &self.signature_cache else {
+            self.context
+                .check_signature(signed_message, domain_separator)
+                .await?;
+            return Ok(signed_message.recover_signer(domain_separator)?);
+        };
+        let recovered_address = signature_cache.recover_signer(signed_message, domain_separator)?;
+        if self
+            .context
+            .verify_signer(recovered_address)
+            .await
+            .map_err(|e| Error::FailedToVerifySigner(e.to_string()))?
+        {
+            Ok(recovered_address)
+        } else {
+            Err(Error::InvalidRecoveredSigner {
+                address: recovered_address,
+            })
+        }
+    }
 
-        let (valid_receipts, invalid_receipts) = self
-            .collect_receipts(ctx, timestamp_buffer_ns, min_timestamp_ns, receipts_limit)
-            .await?;
+    /// Returns the sender `signer` signs on behalf of, see
+    /// [`SignatureChecker::sender_of`]
+    async fn sender_of(&self, signer: Address) -> Result<Address, Error>
+    where
+        E: SignatureChecker,
+    {
+        self.context
+            .sender_of(signer)
+            .await
+            .map_err(|e| Error::FailedToVerifySigner(e.to_string()))
+    }
 
-        let expected_rav = Rav::aggregate_receipts(&valid_receipts, previous_rav.clone());
+    /// Bounds the size of RAV requests for allocations with many receipts:
+    /// when more than `sample_size` receipts are valid,
+    /// [`Manager::create_rav_request`] still aggregates all of them into the
+    /// expected RAV, but only returns `sample_size` of them, evenly spread, in
+    /// [`RavRequest::valid_receipts`]. The others are counted in
+    /// [`RavRequest::omitted_receipts`].
+    ///
+    /// Only use it if the receipts aren't needed once aggregated, e.g. when
+    /// the RAV is signed from the expected RAV rather than from the receipts.
+    /// All aggregated receipts are still removed by
+    /// [`Manager::remove_obsolete_receipts`].
+    pub fn with_valid_receipts_sample_size(mut self, sample_size: usize) -> Self {
+        self.valid_receipts_sample_size = Some(sample_size);
+        self
+    }
 
-        Ok(RavRequest {
-            valid_receipts,
-            previous_rav,
-            invalid_receipts,
-            expected_rav,
-        })
+    /// Adds a sink called with every RAV stored by
+    /// [`Manager::verify_and_store_rav`], whatever its type. Sinks are called
+    /// in the order they were added. Sink failures are logged, the RAV stays
+    /// stored.
+    pub fn with_rav_sink(mut self, sink: Arc<dyn RavSink>) -> Self {
+        self.rav_sinks.push((sink, false));
+        self
     }
-}
 
-impl<E, Rcpt> Manager<E, Rcpt>
-where
-    E: ReceiptDelete,
-{
-    /// Removes obsolete receipts from storage. Obsolete receipts are receipts
-    /// that are older than the last RAV, and therefore already aggregated into the RAV.
-    /// This function should be called after a new RAV is received to limit the
-    /// number of receipts stored. No-op if there is no last RAV.
+    /// Same as [`Manager::with_rav_sink`], but a failure of `sink` is
+    /// returned as [`Error::RavSinkFailed`], e.g. for an archive that must
+    /// not miss a RAV. The RAV stays stored and the other sinks are still
+    /// called.
+    pub fn with_required_rav_sink(mut self, sink: Arc<dyn RavSink>) -> Self {
+        self.rav_sinks.push((sink, true));
+        self
+    }
+
+    /// Sets how many receipts [`Manager::create_rav_request`] checks
+    /// concurrently, which mostly speeds up signature verification of large
+    /// batches. Receipts are checked one at a time by default; `0` is treated
+    /// as `1`. The resulting RAV doesn't depend on this setting.
+    pub fn with_verification_concurrency(mut self, verification_concurrency: usize) -> Self {
+        self.verification_concurrency = verification_concurrency.max(1);
+        self
+    }
+
+    /// Sets how many receipts [`Manager::verify_and_store_receipts`] checks
+    /// and stores concurrently. A low value suits CPU-bound storage such as
+    /// an in-memory one, a higher one IO-bound storage such as a remote
+    /// database. Receipts are handled one at a time by default; `0` is
+    /// treated as `1`.
+    pub fn with_receipt_concurrency(mut self, receipt_concurrency: usize) -> Self {
+        self.receipt_concurrency = receipt_concurrency.max(1);
+        self
+    }
+
+    /// Returns how many receipts are checked and stored concurrently, see
+    /// [`Manager::with_receipt_concurrency`]
+    pub fn receipt_concurrency(&self) -> usize {
+        self.receipt_concurrency
+    }
+
+    /// Sets a grace period during which freshly received receipts can't be
+    /// aggregated, giving out-of-order receipts time to settle.
+    /// [`Manager::create_rav_request`] uses it as its timestamp buffer
+    /// whenever the buffer passed to the call is smaller.
+    pub fn with_eligibility_delay_ns(mut self, eligibility_delay_ns: u64) -> Self {
+        self.eligibility_delay_ns = eligibility_delay_ns;
+        self
+    }
+
+    /// When enabled, [`Error::SignatureMismatch`] errors carry the EIP-712
+    /// digest computed for the rejected message, so it can be compared with
+    /// what the client intended to sign. Disabled by default since it costs
+    /// an extra hash per rejection.
+    pub fn with_signature_debug(mut self, enabled: bool) -> Self {
+        self.signature_debug = enabled;
+        self
+    }
+
+    /// Sets the maximum number of receipts that can be pending aggregation.
+    /// Once reached, [`Manager::verify_and_store_receipt`] rejects new receipts
+    /// with [`Error::BackpressureLimitReached`] until a RAV is stored with
+    /// [`Manager::verify_and_store_rav`]. Receipts being checked count as
+    /// pending, so concurrent receipts can't overshoot the limit.
+    ///
+    /// The count starts at 0, see [`Manager::recover_pending_receipts`] to
+    /// account for the receipts stored before a restart.
+    pub fn with_max_pending_receipts(mut self, max_pending_receipts: u64) -> Self {
+        self.max_pending_receipts = Some(max_pending_receipts);
+        self
+    }
+
+    /// Sets the receipt version supported by this manager.
+    /// [`Manager::verify_and_store_versioned_receipt`] then rejects receipts
+    /// sent as any other version with [`Error::UnsupportedReceiptVersion`].
+    pub fn with_supported_receipt_version(mut self, version: u8) -> Self {
+        self.supported_receipt_version = Some(version);
+        self
+    }
+
+    /// Checks that `receipt` was sent as the version set with
+    /// [`Manager::with_supported_receipt_version`], if any
     ///
     /// # Errors
     ///
-    /// Returns [`Error::AdapterError`] if there are any errors while retrieving
-    /// last RAV or removing receipts
+    /// Returns [`Error::UnsupportedReceiptVersion`] if `receipt` was sent as
+    /// another version
     ///
-    pub async fn remove_obsolete_receipts<Rav>(&self) -> Result<(), Error>
-    where
-        E: RavRead<Rav>,
-        Rav: SolStruct + WithValueAndTimestamp,
-    {
-        match self.get_previous_rav().await? {
-            Some(last_rav) => {
-                self.context
-                    .remove_receipts_in_timestamp_range(..=last_rav.message.timestamp_ns())
-                    .await
-                    .map_err(|err| Error::AdapterError {
-                        source_error: anyhow::Error::new(err),
-                    })?;
-                Ok(())
+    pub fn validate_version(&self, receipt: &impl WithVersion) -> Result<(), Error> {
+        let received = receipt.version();
+        match self.supported_receipt_version {
+            Some(expected) if received != expected => {
+                Err(Error::UnsupportedReceiptVersion { received, expected })
             }
-            None => Ok(()),
+            _ => Ok(()),
         }
     }
-}
 
-impl<E, Rcpt> Manager<E, Rcpt>
-where
-    E: ReceiptStore<Rcpt>,
-{
-    /// Runs `initial_checks` on `signed_receipt` for initial verification,
-    /// then stores received receipt.
-    /// The provided `query_id` will be used as a key when chaecking query appraisal.
+    /// Returns the latest receipts rejected with
+    /// [`Error::ReceiptEquivocation`] since the last call, if kept, see
+    /// [`Manager::with_equivocation_detection`]
+    pub fn take_equivocations(&self) -> Vec<Equivocation<Rcpt>> {
+        self.equivocations.lock().unwrap().drain(..).collect()
+    }
+
+    /// Returns the number of receipts stored by this manager that were not
+    /// aggregated into a RAV yet
+    pub fn pending_receipts(&self) -> u64 {
+        self.pending_receipts.load(Ordering::SeqCst)
+    }
+
+    /// Sets a cap on the number of checks run on each receipt. This is a
+    /// guardrail against a misconfiguration rather than a hard limit: all
+    /// the checks still run, but a warning is logged if there are more than
+    /// `max_checks` of them.
+    pub fn with_max_checks(self, max_checks: usize) -> Self {
+        if self.checks.len() > max_checks {
+            log::warn!(
+                "{} checks are configured, more than the cap of {max_checks}",
+                self.checks.len()
+            );
+        }
+        self
+    }
+
+    /// Sets a monitor notified whenever a check rejects a receipt with
+    /// [`ReceiptError::NotEnoughEscrow`], e.g.
+    /// [`TokenEscrowCheck`](super::checks::TokenEscrowCheck), on ingestion as
+    /// well as while creating a RAV request. Stateful checks reserving escrow
+    /// only run on ingestion, see
+    /// [`Check::is_stateful`](crate::receipt::checks::Check::is_stateful), so
+    /// RAV requests only report the rejections of the other ones.
+    pub fn with_escrow_monitor(mut self, escrow_monitor: Arc<dyn EscrowMonitor>) -> Self {
+        self.escrow_monitor = Some(escrow_monitor);
+        self
+    }
+
+    /// Returns the number of receipts rejected for lack of escrow so far,
+    /// apart from other check failures
+    pub fn escrow_insufficient_rejections(&self) -> u64 {
+        self.escrow_insufficient_rejections.load(Ordering::Relaxed)
+    }
+
+    /// Sets the time after which a check is aborted. The receipt is then
+    /// rejected with [`ReceiptError::CheckTimeout`], which is retryable,
+    /// instead of stalling on e.g. an unresponsive external service.
+    /// Applies to every check without a timeout of its own, see
+    /// [`Manager::with_check_timeouts`].
+    pub fn with_check_timeout(mut self, timeout: Duration) -> Self {
+        self.check_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the time after which a check is aborted for individual checks,
+    /// keyed by [`Check::name`](crate::receipt::checks::Check::name). These
+    /// take precedence over [`Manager::with_check_timeout`].
+    pub fn with_check_timeouts(mut self, check_timeouts: HashMap<&'static str, Duration>) -> Self {
+        self.check_timeouts = check_timeouts;
+        self
+    }
+
+    /// Enables or disables at runtime the manager check named `check_name`,
+    /// see [`Check::name`](crate::receipt::checks::Check::name). Disabled
+    /// checks are skipped, on ingestion as well as while creating a RAV
+    /// request, e.g. to work around a misbehaving check without redeploying.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnknownCheck`] if no manager check has this name
+    ///
+    pub fn set_check_enabled(&self, check_name: &str, enabled: bool) -> Result<(), Error> {
+        let check_name = self
+            .checks
+            .iter()
+            .map(|check| check.name())
+            .find(|name| *name == check_name)
+            .ok_or_else(|| Error::UnknownCheck {
+                check_name: check_name.to_string(),
+            })?;
+        let mut disabled_checks = self.disabled_checks.write().unwrap();
+        if enabled {
+            disabled_checks.remove(check_name);
+        } else {
+            disabled_checks.insert(check_name);
+        }
+        Ok(())
+    }
+
+    /// Returns the names of the manager checks, in the order they run
+    pub fn check_names(&self) -> Vec<&'static str> {
+        self.checks.iter().map(|check| check.name()).collect()
+    }
+
+    /// Returns whether the manager check named `check_name` is run, i.e. it
+    /// wasn't disabled with [`Manager::set_check_enabled`]
+    pub fn is_check_enabled(&self, check_name: &str) -> bool {
+        !self.disabled_checks.read().unwrap().contains(check_name)
+    }
+
+    /// Returns the time spent in each of the manager checks so far, in the
+    /// order they run, to find out which check dominates the latency of
+    /// receipt verification
+    #[cfg(feature = "metrics")]
+    pub fn check_timings(&self) -> Vec<CheckTiming> {
+        self.check_timings.lock().unwrap().clone()
+    }
+
+    /// Runs the manager checks on `receipt`, recording the time spent in
+    /// each of them in `timings` if set, and returns the failures of the
+    /// [`CheckSeverity::Warn`] checks along with the positions of the
+    /// stateful checks that passed, to roll them back with
+    /// [`Manager::rollback_checks`] if the receipt is rejected later on.
+    ///
+    /// If a check fails, the stateful checks that passed before it are
+    /// rolled back. A dry run skips the stateful checks, see
+    /// [`Check::is_stateful`](crate::receipt::checks::Check::is_stateful).
+    async fn perform_checks(
+        &self,
+        ctx: &Context,
+        receipt: &ReceiptWithState<Checking, Rcpt>,
+        timings: Option<&std::sync::Mutex<CheckTimings>>,
+        dry_run: bool,
+    ) -> Result<(Vec<CheckWarning>, Vec<usize>), ReceiptError> {
+        let warnings = std::sync::Mutex::new(Vec::new());
+        let warnings_ref = &warnings;
+        let passed = std::sync::Mutex::new(Vec::new());
+        let passed_ref = &passed;
+        let result = self
+            .checks
+            .run(|index, check| async move {
+                if dry_run && check.is_stateful() {
+                    log::debug!("Skipped stateful check {} in a dry run", check.name());
+                    return Ok(());
+                }
+                let start = Instant::now();
+                let result = self.perform_check(ctx, receipt, check, warnings_ref).await;
+                let elapsed = start.elapsed();
+                #[cfg(feature = "metrics")]
+                {
+                    let mut timings = self.check_timings.lock().unwrap();
+                    let timing = &mut timings[index];
+                    timing.calls += 1;
+                    timing.total += elapsed;
+                    timing.max = timing.max.max(elapsed);
+                }
+                #[cfg(not(feature = "metrics"))]
+                let _ = index;
+                if let Some(timings) = timings.filter(|_| self.is_check_enabled(check.name())) {
+                    *timings.lock().unwrap().entry(check.name()).or_default() += elapsed;
+                }
+                if matches!(result, Ok(true)) && check.is_stateful() {
+                    passed_ref.lock().unwrap().push(index);
+                }
+                result.map(|_| ())
+            })
+            .await;
+        let passed = passed.into_inner().unwrap();
+        if let Err(err) = result {
+            self.rollback_checks(ctx, receipt, &passed).await;
+            return Err(err);
+        }
+        Ok((warnings.into_inner().unwrap(), passed))
+    }
+
+    /// Rolls back the stateful checks at the `passed` positions returned by
+    /// [`Manager::perform_checks`], last one first, see
+    /// [`Check::rollback`](crate::receipt::checks::Check::rollback)
+    async fn rollback_checks(
+        &self,
+        ctx: &Context,
+        receipt: &ReceiptWithState<Checking, Rcpt>,
+        passed: &[usize],
+    ) {
+        for &index in passed.iter().rev() {
+            self.checks[index].rollback(ctx, receipt).await;
+        }
+    }
+
+    /// Runs `check` on `receipt`, aborting it once its timeout elapses, and
+    /// returns whether it ran and passed. Disabled checks pass without
+    /// running, and failures of [`CheckSeverity::Warn`] checks are pushed to
+    /// `warnings` instead.
+    async fn perform_check(
+        &self,
+        ctx: &Context,
+        receipt: &ReceiptWithState<Checking, Rcpt>,
+        check: &ReceiptCheck<Rcpt>,
+        warnings: &std::sync::Mutex<Vec<CheckWarning>>,
+    ) -> Result<bool, ReceiptError> {
+        if !self.is_check_enabled(check.name()) {
+            log::debug!("Skipped disabled check {}", check.name());
+            return Ok(false);
+        }
+        let result = self.run_check(ctx, receipt, check).await;
+        match result {
+            Err(err) if check.severity() == CheckSeverity::Warn => {
+                log::warn!("Check {} failed with a warning: {err}", check.name());
+                let message = match err {
+                    ReceiptError::CheckFailure(message) | ReceiptError::RetryableCheck(message) => {
+                        message
+                    }
+                    err => err.to_string(),
+                };
+                warnings.lock().unwrap().push(CheckWarning {
+                    check_name: check.name(),
+                    message,
+                });
+                Ok(false)
+            }
+            result => result.map(|_| true),
+        }
+    }
+
+    /// Runs `check` on `receipt`, aborting it once its timeout elapses, and
+    /// reports a rejection for lack of escrow to the escrow monitor
+    async fn run_check(
+        &self,
+        ctx: &Context,
+        receipt: &ReceiptWithState<Checking, Rcpt>,
+        check: &ReceiptCheck<Rcpt>,
+    ) -> Result<(), ReceiptError> {
+        let timeout = self
+            .check_timeouts
+            .get(check.name())
+            .or(self.check_timeout.as_ref());
+        let result = match timeout {
+            Some(timeout) => tokio::time::timeout(*timeout, receipt.perform_check(ctx, check))
+                .await
+                .map_err(|_| ReceiptError::CheckTimeout {
+                    check_name: check.name().to_string(),
+                })?,
+            None => receipt.perform_check(ctx, check).await,
+        };
+        if let Err(ReceiptError::NotEnoughEscrow {
+            sender,
+            available_escrow,
+            received_value,
+        }) = &result
+        {
+            self.escrow_insufficient_rejections
+                .fetch_add(1, Ordering::Relaxed);
+            if let Some(escrow_monitor) = &self.escrow_monitor {
+                escrow_monitor.on_escrow_insufficient(*sender, *received_value, *available_escrow);
+            }
+        }
+        result
+    }
+
+    /// Sets checks to run in shadow mode. Shadow checks are evaluated on every
+    /// receipt passed to [`Manager::verify_and_store_receipt`], but a failure
+    /// is only logged and counted in [`Manager::shadow_check_failures`].
+    /// Useful to measure the impact of a new check before enforcing it.
+    pub fn with_shadow_checks(mut self, shadow_checks: Vec<ReceiptCheck<Rcpt>>) -> Self {
+        self.shadow_checks = CheckList::new(shadow_checks);
+        self
+    }
+
+    /// Sets the EIP-712 domain separators used for each chain id, allowing a
+    /// single manager to serve several chains. The domain is selected from the
+    /// [`ChainId`](super::ChainId) found in the request [`Context`]; requests
+    /// without one keep using the default domain separator.
+    ///
+    /// The domains are set on the [`DomainSeparators`] the manager was
+    /// created with, so checks sharing them see the chain domains as well.
+    pub fn with_chain_domains(self, chain_domains: HashMap<u64, Eip712Domain>) -> Self {
+        self.domain_separators.set_chain_domains(chain_domains);
+        self
+    }
+
+    /// Keeps accepting receipts signed against the `previous_domains`, most
+    /// recent first, e.g. while the verifying contract is upgraded, see
+    /// [`DomainSeparators::set_previous_domains`]. Only the signature checks
+    /// sharing the [`DomainSeparators`] of the manager try them.
+    pub fn with_previous_domains(self, previous_domains: Vec<Eip712Domain>) -> Self {
+        self.domain_separators
+            .set_previous_domains(previous_domains);
+        self
+    }
+
+    /// Returns the domain separators of this manager, shared with any check
+    /// holding a clone of them
+    pub fn domain_separators(&self) -> &DomainSeparators {
+        &self.domain_separators
+    }
+
+    /// Returns the domain separator for the [`ChainId`](super::ChainId) found
+    /// in `ctx`, see [`DomainSeparators::select`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnknownChainId`] if no domain separator is configured
+    /// for the chain id
+    ///
+    pub fn domain_separator(&self, ctx: &Context) -> Result<Eip712Domain, Error> {
+        self.domain_separators.select(ctx)
+    }
+
+    /// Returns the number of shadow check failures observed so far
+    pub fn shadow_check_failures(&self) -> u64 {
+        self.shadow_check_failures.load(Ordering::Relaxed)
+    }
+
+    /// Pauses or resumes receipt ingestion. While paused,
+    /// [`Manager::verify_and_store_receipt`] rejects every receipt with
+    /// [`Error::IngestionPaused`]. RAV requests are not affected.
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if receipt ingestion is currently paused
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the storage rejected a receipt for exceeding its
+    /// quota. Until space frees up, [`Manager::verify_and_store_receipt`]
+    /// rejects every receipt with [`Error::StorageQuotaExceeded`] without
+    /// checking it. Cleared once the manager removes receipts, e.g. with
+    /// [`Manager::remove_obsolete_receipts`], or by
+    /// [`Manager::clear_storage_full`].
+    pub fn is_storage_full(&self) -> bool {
+        self.storage_full.load(Ordering::SeqCst)
+    }
+
+    /// Accepts receipts again after the storage rejected one for exceeding its
+    /// quota, e.g. once the quota was raised or receipts were removed without
+    /// the manager
+    pub fn clear_storage_full(&self) {
+        self.storage_full.store(false, Ordering::SeqCst);
+    }
+
+    /// Returns the producer handle of the ingestion channel, or `None` if
+    /// [`Manager::start_ingestion`] wasn't called. Sending waits while the
+    /// channel is full.
+    pub fn ingest_sender(&self) -> Option<mpsc::Sender<Rcpt>> {
+        self.ingest_sender.get().cloned()
+    }
+
+    /// Runs the manager checks on `signed_receipt` without storing it, e.g.
+    /// to let a client probe whether a receipt would be accepted. Ingestion
+    /// pauses and backpressure don't apply, and shadow checks are not run.
+    /// Stateful checks, see
+    /// [`Check::is_stateful`](crate::receipt::checks::Check::is_stateful),
+    /// are skipped too, so that the probe doesn't get the receipt rejected
+    /// as a duplicate once actually submitted.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ReceiptError`] if a check fails
+    ///
+    /// Returns [`Error::UnknownChainId`] if `ctx` carries a chain id with no
+    /// configured domain separator
+    ///
+    pub async fn check_receipt(
+        &self,
+        ctx: &Context,
+        signed_receipt: Rcpt,
+    ) -> std::result::Result<(), Error> {
+        self.domain_separator(ctx)?;
+        let receipt = ReceiptWithState::new(signed_receipt);
+        self.perform_checks(ctx, &receipt, None, true).await?;
+        Ok(())
+    }
+
+    /// Returns the [`RavKey`] found in `ctx`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingRavKey`] if `ctx` doesn't carry a [`RavKey`]
+    ///
+    fn rav_key(ctx: &Context) -> Result<RavKey, Error> {
+        ctx.get::<RavKey>().copied().ok_or(Error::MissingRavKey)
+    }
+
+    /// Returns the [`RavKey`] a receipt about to be stored is kept under in
+    /// the running aggregates and the query index: the one found in `ctx` if
+    /// any, otherwise the sender of its signer and its allocation
+    async fn receipt_rav_key(
+        &self,
+        ctx: &Context,
+        domain_separator: &Eip712Domain,
+        rav_key_fields: &RavKeyFields<E, Rcpt>,
+        signed_receipt: &Rcpt,
+    ) -> Result<RavKey, Error> {
+        if let Some(rav_key) = ctx.get::<RavKey>() {
+            return Ok(*rav_key);
+        }
+        let signer = (rav_key_fields.signer)(signed_receipt, domain_separator)?;
+        Ok(RavKey {
+            sender: (rav_key_fields.sender_of)(&self.context, signer).await?,
+            allocation_id: (rav_key_fields.allocation_id)(signed_receipt),
+        })
+    }
+
+    /// Returns the [`RavKey`] of a stored receipt, i.e. the sender of its
+    /// persisted signer and its allocation, caching the sender of each
+    /// signer in `senders_of_signers`
+    async fn stored_receipt_rav_key(
+        &self,
+        rav_key_fields: &RavKeyFields<E, Rcpt>,
+        senders_of_signers: &mut HashMap<Address, Address>,
+        stored_receipt: &StoredReceipt<Rcpt>,
+    ) -> Result<RavKey, Error> {
+        let signer = stored_receipt.signer();
+        let sender = match senders_of_signers.get(&signer) {
+            Some(sender) => *sender,
+            None => {
+                let sender = (rav_key_fields.sender_of)(&self.context, signer).await?;
+                senders_of_signers.insert(signer, sender);
+                sender
+            }
+        };
+        Ok(RavKey {
+            sender,
+            allocation_id: (rav_key_fields.allocation_id)(stored_receipt.signed_receipt()),
+        })
+    }
+
+    async fn get_previous_rav<Rav: SolStruct>(
+        &self,
+        rav_key: RavKey,
+    ) -> Result<Option<Eip712SignedMessage<Rav>>, Error>
+    where
+        E: RavRead<Rav>,
+    {
+        let previous_rav = self
+            .context
+            .get_last_rav(rav_key.sender, rav_key.allocation_id)
+            .await
+            .map_err(storage_error)?;
+        Ok(previous_rav)
+    }
+
+    /// Returns the timestamp up to which receipts are obsolete, i.e. the
+    /// timestamp of the last RAV of the [`RavKey`] found in `ctx`, or `None`
+    /// if there is no last RAV
+    async fn obsolete_receipts_max_timestamp_ns<Rav>(
+        &self,
+        ctx: &Context,
+    ) -> Result<Option<u64>, Error>
+    where
+        E: RavRead<Rav>,
+        Rav: SolStruct + WithValueAndTimestamp,
+    {
+        Ok(self
+            .get_previous_rav::<Rav>(Self::rav_key(ctx)?)
+            .await?
+            .map(|last_rav| last_rav.message.timestamp_ns()))
+    }
+
+    /// Returns the latest RAV issued by `sender` for `allocation_id`, read
+    /// from storage, or `None` if there is none.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if unable to fetch the RAV
+    ///
+    pub async fn latest_rav<Rav: SolStruct>(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+    ) -> Result<Option<Eip712SignedMessage<Rav>>, Error>
+    where
+        E: RavRead<Rav>,
+    {
+        self.get_previous_rav(RavKey {
+            sender,
+            allocation_id,
+        })
+        .await
+    }
+
+    /// Returns the lowest timestamp a receipt of `sender` for `allocation_id`
+    /// can have to be aggregated into the next RAV, i.e. the highest of
+    /// [`Manager::min_receipt_timestamp_ns`] and the timestamp right after
+    /// the one of [`Manager::latest_rav`]. Handy to tell why receipts are
+    /// rejected or left out of RAVs.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if unable to fetch the RAV
+    ///
+    pub async fn current_min_timestamp<Rav>(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+    ) -> Result<u64, Error>
+    where
+        E: RavRead<Rav>,
+        Rav: SolStruct + WithValueAndTimestamp,
+    {
+        let rav_floor_ns = self
+            .latest_rav::<Rav>(sender, allocation_id)
+            .await?
+            .map_or(0, |rav| rav.message.timestamp_ns().saturating_add(1));
+        Ok(self.min_receipt_timestamp_ns().max(rav_floor_ns))
+    }
+
+    /// Returns the value aggregated so far by `sender` for `allocation_id`,
+    /// i.e. the value of [`Manager::latest_rav`] since RAVs are cumulative,
+    /// or `0` if there is no RAV yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if unable to fetch the RAV
+    ///
+    pub async fn total_aggregated_value<Rav>(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+    ) -> Result<u128, Error>
+    where
+        E: RavRead<Rav>,
+        Rav: SolStruct + WithValueAndTimestamp,
+    {
+        Ok(self
+            .latest_rav::<Rav>(sender, allocation_id)
+            .await?
+            .map_or(0, |rav| rav.message.value()))
+    }
+
+    /// Returns the running aggregate of the stored receipts of the [`RavKey`]
+    /// found in `ctx` newer than its latest RAV, see
+    /// [`Manager::with_running_aggregates`]
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::RunningAggregatesDisabled`] if running aggregates
+    /// aren't enabled
+    ///
+    /// Returns [`Error::MissingRavKey`] if `ctx` doesn't carry a [`RavKey`]
+    ///
+    /// Returns [`Error::AdapterError`] if unable to fetch the RAV
+    ///
+    pub async fn running_aggregate<Rav>(&self, ctx: &Context) -> Result<RunningAggregate, Error>
+    where
+        E: RavRead<Rav>,
+        Rav: SolStruct + WithValueAndTimestamp,
+    {
+        Ok(self.running_aggregate_since::<Rav>(ctx).await?.1)
+    }
+
+    /// Returns the RAV aggregating the stored receipts of the [`RavKey`]
+    /// found in `ctx` into its latest RAV, like
+    /// [`Manager::create_rav_request_final`] but from the running aggregate
+    /// instead of the receipts, see [`Manager::with_running_aggregates`].
+    /// The receipts were checked when stored and aren't checked again.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NoValidReceiptsForRavRequest`] if there is no receipt
+    /// newer than the latest RAV
+    ///
+    /// Returns [`Error::AggregateOverflow`] if the value of the RAV overflows
+    ///
+    /// Same as [`Manager::running_aggregate`] otherwise
+    ///
+    pub async fn running_rav<Rav>(&self, ctx: &Context) -> Result<Rav, Error>
+    where
+        E: RavRead<Rav>,
+        Rav: SolStruct + WithValueAndTimestamp + FromAggregate,
+    {
+        let (previous_rav, aggregate) = self.running_aggregate_since::<Rav>(ctx).await?;
+        if aggregate.receipt_count == 0 {
+            return Err(Error::NoValidReceiptsForRavRequest);
+        }
+        let (previous_value, previous_timestamp_ns) = previous_rav.map_or((0, 0), |rav| {
+            (rav.message.value(), rav.message.timestamp_ns())
+        });
+        let value_aggregate = previous_value
+            .checked_add(aggregate.value_aggregate)
+            .ok_or(Error::AggregateOverflow)?;
+        Ok(Rav::from_aggregate(
+            Self::rav_key(ctx)?.allocation_id,
+            previous_timestamp_ns.max(aggregate.max_timestamp_ns),
+            value_aggregate,
+        ))
+    }
+
+    /// Returns the latest RAV of the [`RavKey`] found in `ctx` along with the
+    /// running aggregate of the receipts newer than it
+    async fn running_aggregate_since<Rav>(
+        &self,
+        ctx: &Context,
+    ) -> Result<(Option<Eip712SignedMessage<Rav>>, RunningAggregate), Error>
+    where
+        E: RavRead<Rav>,
+        Rav: SolStruct + WithValueAndTimestamp,
+    {
+        if self.running_aggregate_fields.is_none() {
+            return Err(Error::RunningAggregatesDisabled);
+        }
+        let rav_key = Self::rav_key(ctx)?;
+        let previous_rav = self.get_previous_rav::<Rav>(rav_key).await?;
+        let min_timestamp_ns = previous_rav
+            .as_ref()
+            .map_or(0, |rav| rav.message.timestamp_ns().saturating_add(1));
+        let aggregate = self
+            .running_aggregates
+            .aggregate_from(rav_key, min_timestamp_ns);
+        Ok((previous_rav, aggregate))
+    }
+
+    /// Returns every RAV issued by `sender` for `allocation_id`, in the order
+    /// they were stored, e.g. to replay a dispute. The last one is
+    /// [`Manager::latest_rav`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if unable to fetch the RAVs
+    ///
+    pub async fn rav_history<Rav: SolStruct>(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+    ) -> Result<Vec<Eip712SignedMessage<Rav>>, Error>
+    where
+        E: RavHistoryRead<Rav>,
+    {
+        self.context
+            .get_rav_history(sender, allocation_id)
+            .await
+            .map_err(storage_error)
+    }
+
+    /// Returns every RAV stored with a timestamp within `timestamp_range_ns`,
+    /// ordered by timestamp, e.g. for a report of the RAVs issued over a
+    /// month. Unlike [`Manager::latest_rav`], RAVs that were since replaced
+    /// are included.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if unable to fetch the RAVs
+    ///
+    pub async fn list_ravs_in_range<Rav: SolStruct>(
+        &self,
+        timestamp_range_ns: impl RangeBounds<u64> + Send,
+    ) -> Result<Vec<Eip712SignedMessage<Rav>>, Error>
+    where
+        E: RavHistoryRead<Rav>,
+    {
+        self.context
+            .list_ravs_in_range(timestamp_range_ns)
+            .await
+            .map_err(storage_error)
+    }
+
+    /// Checks that `signed_rav` is signed by an authorized signer and matches
+    /// `expected_rav`, returning the signer
+    async fn verify_signed_rav<Rav>(
+        &self,
+        domain_separator: &Eip712Domain,
+        expected_rav: &Rav,
+        signed_rav: &Eip712SignedMessage<Rav>,
+    ) -> Result<Address, Error>
+    where
+        E: SignatureChecker,
+        Rav: SolStruct + PartialEq<Rav> + Sync + std::fmt::Debug,
+    {
+        let signer = self
+            .check_signature(signed_rav, domain_separator)
+            .await
+            .map_err(|err| match err {
+                Error::InvalidRecoveredSigner { address } => Error::SignatureMismatch {
+                    recovered_address: address,
+                    digest: self
+                        .signature_debug
+                        .then(|| signed_rav.message.eip712_signing_hash(domain_separator)),
+                },
+                err => err,
+            })?;
+
+        if signed_rav.message != *expected_rav {
+            return Err(Error::InvalidReceivedRav {
+                received_rav: format!("{:?}", signed_rav.message),
+                expected_rav: format!("{:?}", expected_rav),
+            });
+        }
+        Ok(signer)
+    }
+
+    /// Verifies a batch of RAVs, e.g. before redeeming them on-chain together.
+    /// Each signed RAV is checked as in [`Manager::verify_and_store_rav`]
+    /// against its expected RAV, and must not be older, or worth less, than
+    /// the last RAV stored for its sender, see [`SignatureChecker::sender_of`],
+    /// and allocation. RAVs are verified
+    /// concurrently and nothing is stored.
+    ///
+    /// Returns the signer of each RAV, or why it is invalid, in the order of
+    /// `ravs`. An invalid RAV doesn't prevent the others from being verified.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnknownChainId`] for every RAV if `ctx` carries a
+    /// chain id with no configured domain separator
+    ///
+    /// Returns [`Error::SignatureMismatch`] for a RAV whose recovered signer
+    /// is not authorized
+    ///
+    /// Returns [`Error::InvalidReceivedRav`] for a RAV that doesn't match its
+    /// expected RAV
+    ///
+    /// Returns [`Error::RavNotChained`] for a RAV that can't follow the last
+    /// stored RAV
+    ///
+    pub async fn verify_ravs<Rav>(
+        &self,
+        ctx: &Context,
+        ravs: Vec<(Rav, Eip712SignedMessage<Rav>)>,
+    ) -> Vec<Result<Address, Error>>
+    where
+        E: RavRead<Rav> + SignatureChecker,
+        Rav: SolStruct
+            + PartialEq<Rav>
+            + Sync
+            + std::fmt::Debug
+            + WithAllocationId
+            + WithValueAndTimestamp,
+    {
+        future::join_all(ravs.iter().map(|(expected_rav, signed_rav)| async move {
+            let domain_separator = self.domain_separator(ctx)?;
+            let signer = self
+                .verify_signed_rav(&domain_separator, expected_rav, signed_rav)
+                .await?;
+            let rav_key = RavKey {
+                sender: self.sender_of(signer).await?,
+                allocation_id: signed_rav.message.allocation_id(),
+            };
+            if let Some(last_rav) = self.get_previous_rav(rav_key).await? {
+                let (last, rav) = (&last_rav.message, &signed_rav.message);
+                if last.timestamp_ns() > rav.timestamp_ns() || last.value() > rav.value() {
+                    return Err(Error::RavNotChained {
+                        timestamp_ns: rav.timestamp_ns(),
+                        value_aggregate: rav.value(),
+                        last_timestamp_ns: last.timestamp_ns(),
+                        last_value_aggregate: last.value(),
+                    });
+                }
+            }
+            Ok(signer)
+        }))
+        .await
+    }
+
+    /// Verify `signed_rav` matches all values on `expected_rav`, and that `signed_rav` has a valid signer.
+    /// The signature is checked against the default domain separator, and
+    /// the RAV is stored under the sender of its signer, see
+    /// [`SignatureChecker::sender_of`], and its allocation. See
+    /// [`Manager::verify_and_store_rav_with_context`] otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if there are any errors while storing RAV
+    ///
+    /// Returns [`Error::SignatureMismatch`] if the recovered signer is not
+    /// authorized
+    ///
+    /// Returns [`Error::RavSinkFailed`] if a sink added with
+    /// [`Manager::with_required_rav_sink`] fails, the RAV being stored
+    ///
+    pub async fn verify_and_store_rav<Rav>(
+        &self,
+        expected_rav: Rav,
+        signed_rav: Eip712SignedMessage<Rav>,
+    ) -> std::result::Result<(), Error>
+    where
+        E: RavStore<Rav> + SignatureChecker,
+        Rav: SolStruct
+            + SolValue
+            + PartialEq<Rav>
+            + Clone
+            + Sync
+            + std::fmt::Debug
+            + WithAllocationId
+            + WithValueAndTimestamp
+            + 'static,
+    {
+        self.verify_and_store_rav_with_context(&Context::new(), expected_rav, signed_rav)
+            .await
+    }
+
+    /// Same as [`Manager::verify_and_store_rav`], but the signature is
+    /// checked against the domain separator selected by `ctx`, and the RAV is
+    /// stored under the [`RavKey`] found in `ctx`, if any. Otherwise it is
+    /// stored for the sender of its signer, see [`SignatureChecker::sender_of`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnknownChainId`] if `ctx` carries a chain id with no
+    /// configured domain separator
+    ///
+    /// Same as [`Manager::verify_and_store_rav`] otherwise
+    ///
+    pub async fn verify_and_store_rav_with_context<Rav>(
+        &self,
+        ctx: &Context,
+        expected_rav: Rav,
+        signed_rav: Eip712SignedMessage<Rav>,
+    ) -> std::result::Result<(), Error>
+    where
+        E: RavStore<Rav> + SignatureChecker,
+        Rav: SolStruct
+            + SolValue
+            + PartialEq<Rav>
+            + Clone
+            + Sync
+            + std::fmt::Debug
+            + WithAllocationId
+            + WithValueAndTimestamp
+            + 'static,
+    {
+        let domain_separator = self.domain_separator(ctx)?;
+        let signer = self
+            .verify_signed_rav(&domain_separator, &expected_rav, &signed_rav)
+            .await?;
+        let rav_key = match ctx.get::<RavKey>() {
+            Some(rav_key) => *rav_key,
+            None => RavKey {
+                sender: self.sender_of(signer).await?,
+                allocation_id: signed_rav.message.allocation_id(),
+            },
+        };
+
+        let sink_rav = (!self.rav_sinks.is_empty())
+            .then(|| StoredRav::new(rav_key.sender, rav_key.allocation_id, &signed_rav));
+        let rav_timestamp_ns = signed_rav.message.timestamp_ns();
+        self.context
+            .update_last_rav(rav_key.sender, rav_key.allocation_id, signed_rav)
+            .await
+            .map_err(storage_error)?;
+        self.rav_stored(rav_key, rav_timestamp_ns, sink_rav).await
+    }
+
+    /// Same as [`Manager::verify_and_store_rav_with_context`], but also removes the
+    /// receipts aggregated by the RAV, i.e. those up to its timestamp like
+    /// [`Manager::remove_obsolete_receipts`], in the same
+    /// [`RavTransaction`]. A failure leaves storage untouched.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Manager::verify_and_store_rav`], [`Error::AdapterError`]
+    /// being returned if the transaction fails
+    ///
+    pub async fn verify_and_commit_rav<Rav>(
+        &self,
+        ctx: &Context,
+        expected_rav: Rav,
+        signed_rav: Eip712SignedMessage<Rav>,
+    ) -> std::result::Result<(), Error>
+    where
+        E: RavTransaction<Rav> + SignatureChecker,
+        Rav: SolStruct
+            + SolValue
+            + WithValueAndTimestamp
+            + PartialEq<Rav>
+            + Clone
+            + Sync
+            + std::fmt::Debug
+            + 'static,
+    {
+        let rav_key = Self::rav_key(ctx)?;
+        let domain_separator = self.domain_separator(ctx)?;
+        self.verify_signed_rav(&domain_separator, &expected_rav, &signed_rav)
+            .await?;
+
+        let sink_rav = (!self.rav_sinks.is_empty())
+            .then(|| StoredRav::new(rav_key.sender, rav_key.allocation_id, &signed_rav));
+        let rav_timestamp_ns = signed_rav.message.timestamp_ns();
+        self.context
+            .commit_rav(
+                rav_key.sender,
+                rav_key.allocation_id,
+                signed_rav,
+                ..=rav_timestamp_ns,
+            )
+            .await
+            .map_err(storage_error)?;
+        self.clear_storage_full();
+        self.rav_stored(rav_key, rav_timestamp_ns, sink_rav).await
+    }
+
+    /// Hands the stored RAV to the [`RavSink`]s, if any, takes the receipts
+    /// of the last RAV request of `rav_key` out of the pending receipts, and
+    /// rejects the receipts of `rav_key` up to `rav_timestamp_ns` from now on.
+    /// The checks are told, see
+    /// [`Check::rav_stored`](crate::receipt::checks::Check::rav_stored).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::RavSinkFailed`] with the first failure of a sink added
+    /// with [`Manager::with_required_rav_sink`], once everything else is done
+    ///
+    async fn rav_stored(
+        &self,
+        rav_key: RavKey,
+        rav_timestamp_ns: u64,
+        rav: Option<StoredRav>,
+    ) -> std::result::Result<(), Error> {
+        self.raise_last_rav_timestamp(rav_key, rav_timestamp_ns);
+        for check in self.checks.iter() {
+            check
+                .rav_stored(rav_key.sender, rav_key.allocation_id, rav_timestamp_ns)
+                .await;
+        }
+        let mut sink_error = None;
+        if let Some(rav) = rav {
+            for (rav_sink, required) in &self.rav_sinks {
+                if let Err(err) = rav_sink.on_rav_signed(&rav).await {
+                    log::error!("RAV sink failed, the RAV is stored but was not delivered: {err}");
+                    if *required && sink_error.is_none() {
+                        sink_error = Some(err);
+                    }
+                }
+            }
+        }
+
+        let aggregated = self
+            .last_rav_request_receipts
+            .lock()
+            .unwrap()
+            .remove(&rav_key)
+            .unwrap_or(0);
+        self.release_pending_receipts(aggregated);
+        match sink_error {
+            Some(source_error) => Err(Error::RavSinkFailed { source_error }),
+            None => Ok(()),
+        }
+    }
+
+    /// Takes `count` receipts out of the pending receipts
+    fn release_pending_receipts(&self, count: u64) {
+        let _ = self
+            .pending_receipts
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+                Some(pending.saturating_sub(count))
+            });
+    }
+}
+
+impl<E, Rcpt> Manager<E, Rcpt>
+where
+    E: ReceiptRead<Rcpt>,
+{
+    /// Sets the number of pending receipts, see [`Manager::pending_receipts`],
+    /// to the number of receipts in storage, e.g. after a restart so that the
+    /// limit set with [`Manager::with_max_pending_receipts`] accounts for the
+    /// receipts stored before. Receipts covered by a RAV count as pending
+    /// until removed, e.g. by [`Manager::remove_obsolete_receipts`].
+    ///
+    /// Returns the number of pending receipts.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if unable to fetch the receipts
+    ///
+    pub async fn recover_pending_receipts(&self) -> Result<u64, Error> {
+        let pending = self
+            .context
+            .retrieve_receipts_in_timestamp_range(.., None)
+            .await
+            .map_err(storage_error)?
+            .len() as u64;
+        self.pending_receipts.store(pending, Ordering::SeqCst);
+        Ok(pending)
+    }
+}
+
+impl<E, Rcpt> Manager<E, Rcpt>
+where
+    E: ReceiptAllocationRead,
+{
+    /// Returns the distinct allocation ids of the receipts currently in
+    /// storage, i.e. the allocations that still have receipts to aggregate.
+    /// A scheduler can use it to only request RAVs for those allocations.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if unable to fetch the allocation ids
+    ///
+    pub async fn allocations_with_pending_receipts(&self) -> Result<Vec<Address>, Error> {
+        self.context
+            .retrieve_allocation_ids()
+            .await
+            .map_err(storage_error)
+    }
+}
+
+impl<E, Rcpt> Manager<E, Rcpt>
+where
+    E: ReceiptRead<Rcpt> + Sync,
+    Rcpt: WithUniqueId + WithValueAndTimestamp + WithAllocationId + WithReceiptHash,
+{
+    async fn collect_receipts(
+        &self,
+        ctx: &Context,
+        rav_key: RavKey,
+        min_timestamp_ns: u64,
+        max_timestamp_ns: u64,
+        max_range_ns: Option<u64>,
+        limit: Option<u64>,
+    ) -> Result<
+        (
+            Vec<ReceiptWithState<Checked, Rcpt>>,
+            Vec<ReceiptWithState<Failed, Rcpt>>,
+        ),
+        Error,
+    > {
+        if min_timestamp_ns > max_timestamp_ns {
+            return Err(Error::TimestampRangeError {
+                min_timestamp_ns,
+                max_timestamp_ns,
+            });
+        }
+        let mut checking_receipts = self
+            .context
+            .retrieve_sender_receipts_in_timestamp_range(
+                rav_key.sender,
+                rav_key.allocation_id,
+                min_timestamp_ns..max_timestamp_ns,
+                limit,
+            )
+            .await
+            .map_err(storage_error)?;
+        // adapters aren't required to return receipts in any order, and the
+        // same receipts must make the same request whatever order they come in
+        checking_receipts.sort_by_cached_key(|receipt| receipt_order_key(receipt.signed_receipt()));
+
+        let mut checked_receipts = vec![];
+        let mut failed_receipts = vec![];
+
+        // check for timestamp
+        let (mut checking_receipts, already_failed) =
+            TimestampCheck(min_timestamp_ns).check_batch(checking_receipts);
+        failed_receipts.extend(already_failed);
+
+        // the window starts at the earliest pending receipt, so that gaps
+        // longer than the window don't stall RAVs
+        if let (Some(max_range_ns), Some(earliest)) = (max_range_ns, checking_receipts.first()) {
+            let end_ns = earliest
+                .signed_receipt()
+                .timestamp_ns()
+                .saturating_add(max_range_ns);
+            checking_receipts.retain(|receipt| receipt.signed_receipt().timestamp_ns() < end_ns);
+        }
+
+        // don't trust storage to only return receipts of the requested
+        // allocation, but leave the others out rather than report them as
+        // invalid, they belong to the RAV of their own allocation
+        let (checking_receipts, _other_allocations) =
+            AllocationCheck(rav_key.allocation_id).check_batch(checking_receipts);
+
+        // check for uniqueness
+        let (checking_receipts, already_failed) = UniqueCheck.check_batch(checking_receipts);
+        failed_receipts.extend(already_failed);
+
+        let cancellation = ctx.get::<CancellationToken>();
+        let cancelled = async {
+            match cancellation {
+                Some(token) => token.cancelled().await,
+                None => future::pending().await,
+            }
+        };
+        let mut results: Vec<_> = stream::iter(checking_receipts.into_iter().enumerate())
+            .map(|(index, receipt)| async move {
+                // the stateful checks already ran, and recorded the receipt,
+                // when it was stored
+                let all_checks_passed = self
+                    .perform_checks(ctx, &receipt, None, true)
+                    .await
+                    .map(|_| ());
+                (index, receipt.complete_checks(all_checks_passed))
+            })
+            .buffer_unordered(self.verification_concurrency)
+            .take_until(cancelled)
+            .collect()
+            .await;
+        if cancellation.is_some_and(CancellationToken::is_cancelled) {
+            return Err(Error::Cancelled);
+        }
+        // restore the sorted order so the outcome doesn't depend on which
+        // check finished first
+        results.sort_unstable_by_key(|(index, _)| *index);
+
+        for (_, receipt) in results {
+            let receipt =
+                receipt.map_err(|e| Error::ReceiptError(ReceiptError::RetryableCheck(e)))?;
+
+            match receipt {
+                Ok(checked) => checked_receipts.push(checked),
+                Err(failed) => failed_receipts.push(failed),
+            }
+        }
+
+        Ok((checked_receipts, failed_receipts))
+    }
+
+    /// Completes remaining checks on all receipts up to
+    /// (current time - `timestamp_buffer_ns`), or (current time - eligibility
+    /// delay) if the manager's eligibility delay is larger. Returns them in two lists
+    /// (valid receipts and invalid receipts) along with the expected RAV that
+    /// should be received for aggregating list of valid receipts, sorted by
+    /// timestamp then receipt hash so that the same receipts always make the
+    /// same request. The RAV is
+    /// chained on the last RAV of the [`RavKey`] found in `ctx`, and only
+    /// aggregates the receipts of that sender and allocation. Receipts of any
+    /// other key are left out of the request, neither valid nor invalid.
+    ///
+    /// When the range is capped with [`Manager::with_max_rav_range_ns`], only
+    /// the receipts in `[earliest, earliest + max_range_ns)` are aggregated,
+    /// `earliest` being the timestamp of the earliest pending receipt. Later
+    /// receipts are left for the next RAV requests, so that a single RAV never
+    /// covers more than `max_range_ns` of history.
+    ///
+    /// When `ctx` carries a [`CancellationToken`], cancelling it aborts the
+    /// receipt checks and returns [`Error::Cancelled`]. Nothing is stored or
+    /// removed then, the receipts are left for the next RAV request.
+    ///
+    /// Returns [`Error::MissingRavKey`] if `ctx` doesn't carry a [`RavKey`]
+    ///
+    /// Returns [`Error::AggregateOverflow`] if any receipt value causes
+    /// aggregate value to overflow while generating expected RAV
+    ///
+    /// Returns [`Error::AdapterError`] if unable to fetch previous RAV or
+    /// if unable to fetch previous receipts
+    ///
+    /// Returns [`Error::TimestampRangeError`] if the max timestamp of the
+    /// previous RAV is greater than the min timestamp. Caused by timestamp
+    /// buffer being too large, or requests coming too soon.
+    ///
+    /// Returns [`Error::CorruptPreviousRav`] if the previous RAV is not
+    /// signed by an authorized signer
+    ///
+    /// Returns [`Error::RavBelowProfitabilityThreshold`] if the expected RAV
+    /// is worth less than the threshold set with
+    /// [`Manager::with_min_profitable_rav_value`]
+    ///
+    /// Returns [`Error::AggregateMismatch`] if the value of the expected RAV
+    /// isn't the sum of the receipts and previous RAV it aggregates
+    ///
+    pub async fn create_rav_request<Rav>(
+        &self,
+        ctx: &Context,
+        timestamp_buffer_ns: u64,
+        receipts_limit: Option<u64>,
+    ) -> Result<RavRequest<Rcpt, Rav>, Error>
+    where
+        E: RavRead<Rav> + SignatureChecker,
+        Rav: SolStruct + WithValueAndTimestamp + Clone + Aggregate<Rcpt> + WithAllocationId + Sync,
+    {
+        let timestamp_buffer_ns = timestamp_buffer_ns.max(self.eligibility_delay_ns);
+        // a buffer reaching back before the epoch leaves nothing eligible
+        let max_timestamp_ns =
+            crate::get_current_timestamp_u64_ns()?.saturating_sub(timestamp_buffer_ns);
+        self.rav_request_up_to(
+            ctx,
+            max_timestamp_ns,
+            receipts_limit,
+            self.max_rav_range_ns,
+            true,
+        )
+        .await
+    }
+
+    /// Same as [`Manager::create_rav_request`] for the [`RavKey`] found in
+    /// `ctx`, but aggregates every stored receipt of the allocation right
+    /// away, ignoring the timestamp buffer and the eligibility delay, e.g. to
+    /// close out the allocation. The profitability threshold isn't enforced
+    /// either, as nothing can be aggregated later.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Manager::create_rav_request`], except for
+    /// [`Error::RavBelowProfitabilityThreshold`]
+    ///
+    pub async fn create_rav_request_final<Rav>(
+        &self,
+        ctx: &Context,
+    ) -> Result<RavRequest<Rcpt, Rav>, Error>
+    where
+        E: RavRead<Rav> + SignatureChecker,
+        Rav: SolStruct + WithValueAndTimestamp + Clone + Aggregate<Rcpt> + WithAllocationId + Sync,
+    {
+        self.rav_request_up_to(ctx, u64::MAX, None, None, false)
+            .await
+    }
+
+    /// Creates the RAV request of the receipts timestamped before
+    /// `max_timestamp_ns`, see [`Manager::create_rav_request`]
+    async fn rav_request_up_to<Rav>(
+        &self,
+        ctx: &Context,
+        max_timestamp_ns: u64,
+        receipts_limit: Option<u64>,
+        max_range_ns: Option<u64>,
+        enforce_profitability: bool,
+    ) -> Result<RavRequest<Rcpt, Rav>, Error>
+    where
+        E: RavRead<Rav> + SignatureChecker,
+        Rav: SolStruct + WithValueAndTimestamp + Clone + Aggregate<Rcpt> + WithAllocationId + Sync,
+    {
+        let rav_key = Self::rav_key(ctx)?;
+        let previous_rav = self.get_previous_rav(rav_key).await?;
+        // don't chain on a RAV that storage could have tampered with
+        if let Some(previous_rav) = &previous_rav {
+            self.check_signature(previous_rav, &self.domain_separator(ctx)?)
+                .await
+                .map_err(|err| Error::CorruptPreviousRav {
+                    source_error_message: err.to_string(),
+                })?;
+        }
+        let min_timestamp_ns = previous_rav
+            .as_ref()
+            .map(|rav| rav.message.timestamp_ns().saturating_add(1))
+            .unwrap_or(0);
+
+        let (valid_receipts, invalid_receipts) = self
+            .collect_receipts(
+                ctx,
+                rav_key,
+                min_timestamp_ns,
+                max_timestamp_ns,
+                max_range_ns,
+                receipts_limit,
+            )
+            .await?;
+
+        let expected_rav =
+            compute_expected_rav(rav_key.allocation_id, previous_rav.clone(), &valid_receipts);
+        if let (Ok(rav), Some(min_profitable_value), true) = (
+            &expected_rav,
+            self.min_profitable_rav_value,
+            enforce_profitability,
+        ) {
+            if !is_rav_worth_redeeming(rav.value(), min_profitable_value) {
+                return Err(Error::RavBelowProfitabilityThreshold {
+                    rav_value: rav.value(),
+                    min_profitable_value,
+                });
+            }
+        }
+        let collected_receipts = (valid_receipts.len() + invalid_receipts.len()) as u64;
+        let mut rav_request = RavRequest {
+            valid_receipts,
+            previous_rav,
+            invalid_receipts,
+            expected_rav,
+            omitted_receipts: 0,
+            omitted_value: 0,
+        };
+        if let Some(sample_size) = self.valid_receipts_sample_size {
+            rav_request.sample_valid_receipts(sample_size);
+        }
+        // never hand out an expected RAV that doesn't add up
+        rav_request.validate_aggregate()?;
+        if let (Ok(_), Some(threshold)) = (&rav_request.expected_rav, self.low_value_rav_threshold)
+        {
+            let escrow_delta = rav_request.escrow_delta();
+            if escrow_delta < threshold {
+                log::warn!(
+                    "RAV for allocation {} only adds {escrow_delta}, below {threshold}",
+                    rav_key.allocation_id
+                );
+                self.low_value_ravs.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.last_rav_request_receipts
+            .lock()
+            .unwrap()
+            .insert(rav_key, collected_receipts);
+
+        Ok(rav_request)
+    }
+}
+
+impl<E, T> Manager<E, Eip712SignedMessage<T>>
+where
+    E: SignatureChecker + Sync,
+    T: SolStruct + WithValueAndTimestamp + WithAllocationId,
+{
+    /// Keeps a running aggregate of the receipts stored by
+    /// [`Manager::verify_and_store_receipt`] for each sender and allocation,
+    /// so that [`Manager::running_rav`] builds the next RAV without reading
+    /// the receipts back from storage. A receipt is aggregated under the
+    /// [`RavKey`] found in the context it is stored with, if any, otherwise
+    /// under the sender of its signer, see [`SignatureChecker::sender_of`].
+    ///
+    /// The aggregates live in memory, rebuild them with
+    /// [`Manager::rebuild_running_aggregates`] after a restart.
+    pub fn with_running_aggregates(mut self) -> Self {
+        self.rav_key_fields = Some(Self::signed_rav_key_fields());
+        self.running_aggregate_fields = Some(|receipt| (receipt.timestamp_ns(), receipt.value()));
+        self
+    }
+
+    /// Rejects receipts sharing their query id, i.e. nonce, with a receipt of
+    /// the same sender and allocation stored by
+    /// [`Manager::verify_and_store_receipt`] but differing in value, with
+    /// [`Error::ReceiptEquivocation`]. The sender and allocation of a receipt
+    /// are found like in [`Manager::with_running_aggregates`]. Up to
+    /// `max_evidence` of the latest rejected receipts are kept along with the
+    /// id of the stored one, see [`Manager::take_equivocations`], none with 0.
+    ///
+    /// Stored receipts are indexed in memory and forgotten once removed by
+    /// [`Manager::remove_obsolete_receipts`] or
+    /// [`Manager::evict_receipts_older_than`]. Rebuild the index with
+    /// [`Manager::rebuild_query_index`] after a restart.
+    pub fn with_equivocation_detection(mut self, max_evidence: usize) -> Self
+    where
+        T: WithNonce,
+    {
+        self.rav_key_fields = Some(Self::signed_rav_key_fields());
+        self.query_fields =
+            Some(|receipt| (receipt.nonce(), receipt.value(), receipt.timestamp_ns()));
+        self.max_equivocations = max_evidence;
+        self
+    }
+
+    /// Returns the [`RavKeyFields`] of signed receipts
+    fn signed_rav_key_fields() -> RavKeyFields<E, Eip712SignedMessage<T>> {
+        RavKeyFields {
+            allocation_id: |receipt| receipt.allocation_id(),
+            signer: |receipt, domain_separator| Ok(receipt.recover_signer(domain_separator)?),
+            sender_of: |context, signer| {
+                Box::pin(async move {
+                    context
+                        .sender_of(signer)
+                        .await
+                        .map_err(|e| Error::FailedToVerifySigner(e.to_string()))
+                })
+            },
+        }
+    }
+}
+
+impl<E, T> Manager<E, Eip712SignedMessage<T>>
+where
+    E: ReceiptRead<Eip712SignedMessage<T>> + Sync,
+    T: SolStruct + WithValueAndTimestamp + WithAllocationId,
+{
+    /// Returns the pairs of consecutive timestamps of the receipts stored for
+    /// `sender` and `allocation_id` that are more than `max_gap_ns` apart, in
+    /// timestamp order. Well-behaved senders issue receipts steadily, so gaps
+    /// may point to lost receipts or a misbehaving client.
+    ///
+    /// The receipts of `sender` are the ones the adapter attributes to it,
+    /// see [`ReceiptRead::retrieve_sender_receipts_in_timestamp_range`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if unable to fetch the receipts
+    ///
+    pub async fn detect_timestamp_gaps(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+        max_gap_ns: u64,
+    ) -> Result<Vec<(u64, u64)>, Error> {
+        let receipts = self
+            .context
+            .retrieve_sender_receipts_in_timestamp_range(sender, allocation_id, .., None)
+            .await
+            .map_err(storage_error)?;
+        let mut timestamps_ns: Vec<u64> = receipts
+            .iter()
+            .map(|receipt| receipt.signed_receipt().timestamp_ns())
+            .collect();
+        timestamps_ns.sort_unstable();
+
+        Ok(timestamps_ns
+            .windows(2)
+            .map(|pair| (pair[0], pair[1]))
+            .filter(|(previous, next)| next - previous > max_gap_ns)
+            .collect())
+    }
+
+    /// Returns the latest RAV of `sender` for `allocation_id` along with the
+    /// RAV it was chained on and the stored receipts it aggregates, i.e. the
+    /// receipts of the allocation timestamped after the previous RAV and up
+    /// to the latest one, for a third party to verify with
+    /// [`RavBundle::verify`].
+    ///
+    /// The receipts must still be in storage, so the bundle should be
+    /// exported before [`Manager::remove_obsolete_receipts`] runs.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NoRav`] if there is no RAV yet
+    ///
+    /// Returns [`Error::AdapterError`] if unable to fetch the RAVs or the
+    /// receipts
+    ///
+    pub async fn export_rav_bundle<Rav>(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+    ) -> Result<RavBundle<Eip712SignedMessage<T>, Rav>, Error>
+    where
+        E: RavHistoryRead<Rav>,
+        T: Clone,
+        Rav: SolStruct + WithValueAndTimestamp,
+    {
+        let mut history = self.rav_history::<Rav>(sender, allocation_id).await?;
+        let rav = history.pop().ok_or(Error::NoRav {
+            sender,
+            allocation_id,
+        })?;
+        let previous_rav = history.pop();
+        let min_timestamp_ns = previous_rav
+            .as_ref()
+            .map_or(0, |rav| rav.message.timestamp_ns().saturating_add(1));
+
+        let mut receipts: Vec<_> = self
+            .context
+            .retrieve_sender_receipts_in_timestamp_range(
+                sender,
+                allocation_id,
+                min_timestamp_ns..=rav.message.timestamp_ns(),
+                None,
+            )
+            .await
+            .map_err(storage_error)?
+            .into_iter()
+            .map(ReceiptWithState::into_signed_receipt)
+            .collect();
+        receipts.sort_by_cached_key(receipt_order_key);
+
+        Ok(RavBundle {
+            rav,
+            previous_rav,
+            receipts,
+        })
+    }
+}
+
+impl<E, Rcpt> Manager<E, Rcpt>
+where
+    E: EscrowHandler,
+{
+    /// Probes the escrow adapter for `sample_senders`, senders known to have
+    /// escrow, e.g. at startup to catch a misconfigured adapter before it
+    /// rejects every receipt for lack of escrow.
+    ///
+    /// Each sender is probed by reading its available escrow, see
+    /// [`EscrowHandler::available_escrow`], so the probe never changes the
+    /// escrow. Senders the adapter fails to probe are reported in
+    /// [`EscrowHealthReport::failed`], as long as it answers for one of them.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] with the first error if the adapter
+    /// fails to probe every sender, i.e. fails as a whole
+    ///
+    pub async fn validate_escrow_source(
+        &self,
+        sample_senders: &[Address],
+    ) -> Result<EscrowHealthReport, Error> {
+        let mut report = EscrowHealthReport::default();
+        let mut first_error = None;
+        for &sender in sample_senders {
+            match self.context.available_escrow(sender).await {
+                Ok(0) => report.unfunded.push(sender),
+                Ok(_) => report.funded.push(sender),
+                Err(err) => {
+                    report.failed.push((sender, err.to_string()));
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        match first_error {
+            Some(err) if report.failed.len() == sample_senders.len() => Err(storage_error(err)),
+            _ => Ok(report),
+        }
+    }
+}
+
+impl<E, Rcpt> Manager<E, Rcpt>
+where
+    E: RavDelete,
+{
+    /// Removes all but the `keep_latest` most recent RAVs of every sender and
+    /// allocation from the RAV history, to bound its storage while keeping a
+    /// recent audit trail. The latest RAV is always kept, even if
+    /// `keep_latest` is 0.
+    ///
+    /// Returns the number of RAVs removed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if there are any errors while removing
+    /// RAVs
+    ///
+    pub async fn prune_rav_history(&self, keep_latest: usize) -> Result<u64, Error> {
+        self.context
+            .prune_rav_history(keep_latest.max(1))
+            .await
+            .map_err(storage_error)
+    }
+}
+
+impl<E, Rcpt> Manager<E, Rcpt>
+where
+    E: ReceiptDelete,
+{
+    /// Removes obsolete receipts from storage. Obsolete receipts are receipts
+    /// that are older than the last RAV of the [`RavKey`] found in `ctx`, and
+    /// therefore already aggregated into the RAV.
+    /// This function should be called after a new RAV is received to limit the
+    /// number of receipts stored. No-op if there is no last RAV.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if there are any errors while retrieving
+    /// last RAV or removing receipts
+    ///
+    /// Returns [`Error::MissingRavKey`] if `ctx` doesn't carry a [`RavKey`]
+    ///
+    pub async fn remove_obsolete_receipts<Rav>(&self, ctx: &Context) -> Result<(), Error>
+    where
+        E: RavRead<Rav> + Sync,
+        Rav: SolStruct + WithValueAndTimestamp,
+    {
+        let rav_key = Self::rav_key(ctx)?;
+        match self.obsolete_receipts_max_timestamp_ns::<Rav>(ctx).await? {
+            Some(max_timestamp_ns) => {
+                self.context
+                    .remove_sender_receipts_in_timestamp_range(
+                        rav_key.sender,
+                        rav_key.allocation_id,
+                        ..=max_timestamp_ns,
+                    )
+                    .await
+                    .map_err(storage_error)?;
+                self.clear_storage_full();
+                self.query_index
+                    .drop_before(rav_key, max_timestamp_ns.saturating_add(1));
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Removes receipts with a timestamp older than `age_ns` before now,
+    /// whether or not a RAV covers them. Unlike
+    /// [`Manager::remove_obsolete_receipts`], this bounds storage for senders
+    /// that never request a RAV, at the cost of dropping unaggregated value.
+    /// The adapter releases the escrow reserved by the receipts removed, see
+    /// [`ReceiptDelete::remove_receipts_in_timestamp_range_counted`].
+    ///
+    /// Returns the number of receipts removed, also taken out of the pending
+    /// receipts, see [`Manager::pending_receipts`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if there are any errors while removing
+    /// receipts
+    ///
+    pub async fn evict_receipts_older_than(&self, age_ns: u64) -> Result<u64, Error>
+    where
+        E: Sync,
+    {
+        let cutoff_ns = crate::get_current_timestamp_u64_ns()?.saturating_sub(age_ns);
+        let removed = self
+            .context
+            .remove_receipts_in_timestamp_range_counted(..cutoff_ns)
+            .await
+            .map_err(storage_error)?;
+        self.clear_storage_full();
+        self.running_aggregates.drop_all_before(cutoff_ns);
+        self.query_index.drop_all_before(cutoff_ns);
+        self.release_pending_receipts(removed);
+        Ok(removed)
+    }
+}
+
+impl<E, Rcpt> Manager<E, Rcpt>
+where
+    E: StoredReceiptRead<Rcpt>,
+{
+    /// Rebuilds the running aggregates from the receipts in storage, e.g.
+    /// after a restart, see [`Manager::with_running_aggregates`]. The
+    /// receipts are read `page_size` at a time, see
+    /// [`StoredReceiptRead::retrieve_stored_receipts_page`], and aggregated
+    /// under the sender of their persisted signer. Receipts already covered
+    /// by a RAV are dropped as soon as the aggregate of their key is read.
+    ///
+    /// Returns the number of receipts aggregated.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::RunningAggregatesDisabled`] if running aggregates
+    /// aren't enabled
+    ///
+    /// Returns [`Error::AdapterError`] if unable to fetch the receipts
+    ///
+    /// Returns [`Error::FailedToVerifySigner`] if unable to tell the sender
+    /// of a signer
+    ///
+    /// Returns [`Error::AggregationError`] if the aggregate of a sender and
+    /// allocation overflows, the previous aggregates are kept then
+    ///
+    pub async fn rebuild_running_aggregates(&self, page_size: u64) -> Result<u64, Error>
+    where
+        E: Sync,
+        Rcpt: Send,
+    {
+        let (Some(rav_key_fields), Some(aggregate_fields)) =
+            (&self.rav_key_fields, &self.running_aggregate_fields)
+        else {
+            return Err(Error::RunningAggregatesDisabled);
+        };
+        let rebuilt = RunningAggregates::default();
+        let aggregated = self
+            .scan_stored_receipts(page_size, rav_key_fields, |rav_key, stored_receipt| {
+                let (timestamp_ns, value) = aggregate_fields(stored_receipt.signed_receipt());
+                Ok(rebuilt.add(rav_key, timestamp_ns, value)?)
+            })
+            .await?;
+        self.running_aggregates.replace(rebuilt);
+        Ok(aggregated)
+    }
+
+    /// Rebuilds the query index of the equivocation detection from the
+    /// receipts in storage, e.g. after a restart, see
+    /// [`Manager::with_equivocation_detection`]. The receipts are read
+    /// `page_size` at a time, see
+    /// [`StoredReceiptRead::retrieve_stored_receipts_page`], and indexed
+    /// under the sender of their persisted signer. Of stored receipts
+    /// already conflicting, the first one stored is indexed.
+    ///
+    /// Returns the number of receipts read.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::EquivocationDetectionDisabled`] if equivocation
+    /// detection isn't enabled
+    ///
+    /// Returns [`Error::AdapterError`] if unable to fetch the receipts
+    ///
+    /// Returns [`Error::FailedToVerifySigner`] if unable to tell the sender
+    /// of a signer
+    ///
+    pub async fn rebuild_query_index(&self, page_size: u64) -> Result<u64, Error>
+    where
+        E: Sync,
+        Rcpt: Send,
+    {
+        let (Some(rav_key_fields), Some(query_fields)) = (&self.rav_key_fields, &self.query_fields)
+        else {
+            return Err(Error::EquivocationDetectionDisabled);
+        };
+        let rebuilt = QueryIndex::default();
+        let indexed = self
+            .scan_stored_receipts(page_size, rav_key_fields, |rav_key, stored_receipt| {
+                let (query_id, value, timestamp_ns) = query_fields(stored_receipt.signed_receipt());
+                if let Ok(true) = rebuilt.claim(rav_key, query_id, value, timestamp_ns) {
+                    rebuilt.stored(rav_key, query_id, stored_receipt.id());
+                }
+                Ok(())
+            })
+            .await?;
+        self.query_index.replace(rebuilt);
+        Ok(indexed)
+    }
+
+    /// Calls `visit` on every stored receipt along with its [`RavKey`],
+    /// reading them `page_size` at a time in id order, and returns the number
+    /// of receipts read
+    async fn scan_stored_receipts(
+        &self,
+        page_size: u64,
+        rav_key_fields: &RavKeyFields<E, Rcpt>,
+        mut visit: impl FnMut(RavKey, &StoredReceipt<Rcpt>) -> Result<(), Error>,
+    ) -> Result<u64, Error>
+    where
+        E: Sync,
+        Rcpt: Send,
+    {
+        let page_size = page_size.max(1);
+        let mut senders_of_signers = HashMap::new();
+        let mut from_id = 0;
+        let mut read = 0;
+        loop {
+            let page = self
+                .context
+                .retrieve_stored_receipts_page(from_id, page_size)
+                .await
+                .map_err(storage_error)?;
+            for stored_receipt in &page {
+                let rav_key = self
+                    .stored_receipt_rav_key(rav_key_fields, &mut senders_of_signers, stored_receipt)
+                    .await?;
+                visit(rav_key, stored_receipt)?;
+            }
+            read += page.len() as u64;
+            match page.last() {
+                Some(last) if page.len() as u64 == page_size && last.id() < u64::MAX => {
+                    from_id = last.id() + 1;
+                }
+                _ => break,
+            }
+        }
+        Ok(read)
+    }
+
+    /// Runs `checks` against the receipts stored with a timestamp within
+    /// `timestamp_range_ns`, e.g. to apply a check added after they were
+    /// stored, and reports the receipts failing one of them. The checks run
+    /// as given, regardless of the checks of the manager and of their
+    /// settings, and stateful checks such as [`UniqueCheck`] should be left
+    /// out as every stored receipt has already been seen.
+    ///
+    /// Nothing is removed, see [`Manager::quarantine_receipts`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if unable to fetch the receipts
+    ///
+    pub async fn revalidate_stored_receipts<R>(
+        &self,
+        timestamp_range_ns: R,
+        checks: &CheckList<Rcpt>,
+    ) -> Result<RevalidationReport<Rcpt>, Error>
+    where
+        R: RangeBounds<u64> + Send,
+        Rcpt: Clone,
+    {
+        let stored_receipts = self
+            .context
+            .retrieve_stored_receipts_in_timestamp_range(timestamp_range_ns)
+            .await
+            .map_err(storage_error)?;
+        let ctx = Context::new();
+        let mut report = RevalidationReport {
+            checked: stored_receipts.len() as u64,
+            failed: Vec::new(),
+        };
+        for stored_receipt in stored_receipts {
+            let mut receipt = ReceiptWithState::new(stored_receipt.signed_receipt().clone());
+            if let Err(err) = receipt.perform_checks(&ctx, checks).await {
+                report.failed.push((stored_receipt, err));
+            }
+        }
+        Ok(report)
+    }
+
+    /// Returns the receipts that [`Manager::remove_obsolete_receipts`] would
+    /// remove for the same `ctx`, without removing them, e.g. to review the
+    /// scope of a cleanup first. Empty if there is no last RAV.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if there are any errors while retrieving
+    /// last RAV or the receipts
+    ///
+    /// Returns [`Error::MissingRavKey`] if `ctx` doesn't carry a [`RavKey`]
+    ///
+    pub async fn preview_obsolete_receipts<Rav>(
+        &self,
+        ctx: &Context,
+    ) -> Result<Vec<StoredReceipt<Rcpt>>, Error>
+    where
+        E: RavRead<Rav>,
+        Rav: SolStruct + WithValueAndTimestamp,
+    {
+        let rav_key = Self::rav_key(ctx)?;
+        match self.obsolete_receipts_max_timestamp_ns::<Rav>(ctx).await? {
+            Some(max_timestamp_ns) => self
+                .context
+                .retrieve_stored_sender_receipts_in_timestamp_range(
+                    rav_key.sender,
+                    rav_key.allocation_id,
+                    ..=max_timestamp_ns,
+                )
+                .await
+                .map_err(storage_error),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns the value `sender` owes across all its allocations, e.g. to
+    /// bound the exposure to a sender: the value of the latest RAV of each
+    /// allocation plus the value of the stored receipts newer than it. The
+    /// receipts of `sender` are those whose persisted signer signs on its
+    /// behalf, see [`SignatureChecker::sender_of`].
+    ///
+    /// The manager doesn't track on-chain redemptions, so RAVs count as not
+    /// redeemed. Every stored RAV and receipt is read, this is meant for
+    /// occasional reports rather than the receipt path.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if unable to fetch the RAVs or the
+    /// receipts
+    ///
+    /// Returns [`Error::FailedToVerifySigner`] if unable to tell the sender
+    /// of a signer
+    ///
+    /// Returns [`Error::AggregateOverflow`] if the total overflows
+    ///
+    pub async fn sender_outstanding<Rav>(&self, sender: Address) -> Result<u128, Error>
+    where
+        E: RavRead<Rav> + RavHistoryRead<Rav> + SignatureChecker,
+        Rav: SolStruct + WithValueAndTimestamp + WithAllocationId,
+        Rcpt: WithValueAndTimestamp + WithAllocationId,
+    {
+        let mut senders_of_signers = HashMap::new();
+        let mut stored_receipts = Vec::new();
+        for stored_receipt in self
+            .context
+            .retrieve_stored_receipts_in_timestamp_range(..)
+            .await
+            .map_err(storage_error)?
+        {
+            let signer = stored_receipt.signer();
+            let receipt_sender = match senders_of_signers.get(&signer) {
+                Some(receipt_sender) => *receipt_sender,
+                None => {
+                    let receipt_sender = self.sender_of(signer).await?;
+                    senders_of_signers.insert(signer, receipt_sender);
+                    receipt_sender
+                }
+            };
+            if receipt_sender == sender {
+                stored_receipts.push(stored_receipt);
+            }
+        }
+        let mut allocation_ids: HashSet<_> = self
+            .context
+            .list_ravs_in_range(..)
+            .await
+            .map_err(storage_error)?
+            .iter()
+            .map(|rav| rav.message.allocation_id())
+            .collect();
+        allocation_ids.extend(
+            stored_receipts
+                .iter()
+                .map(|stored_receipt| stored_receipt.signed_receipt().allocation_id()),
+        );
+
+        let mut outstanding = 0u128;
+        for allocation_id in allocation_ids {
+            let last_rav = self.latest_rav::<Rav>(sender, allocation_id).await?;
+            let aggregated_until_ns = last_rav.as_ref().map(|rav| rav.message.timestamp_ns());
+            let unaggregated_values = stored_receipts
+                .iter()
+                .map(StoredReceipt::signed_receipt)
+                .filter(|receipt| {
+                    receipt.allocation_id() == allocation_id
+                        && aggregated_until_ns
+                            .is_none_or(|until_ns| receipt.timestamp_ns() > until_ns)
+                })
+                .map(|receipt| receipt.value());
+            for value in last_rav
+                .map(|rav| rav.message.value())
+                .into_iter()
+                .chain(unaggregated_values)
+            {
+                outstanding = outstanding
+                    .checked_add(value)
+                    .ok_or(Error::AggregateOverflow)?;
+            }
+        }
+        Ok(outstanding)
+    }
+}
+
+impl<E, Rcpt> Manager<E, Rcpt>
+where
+    E: ReceiptQuarantine,
+{
+    /// Moves the receipts of `report` failing for good, see
+    /// [`RevalidationReport::invalid_receipt_ids`], to the quarantine of the
+    /// storage so that they are no longer aggregated into RAVs. The adapter
+    /// releases the escrow they reserved, see
+    /// [`ReceiptQuarantine::quarantine_receipts`].
+    ///
+    /// Returns the number of receipts quarantined.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if unable to quarantine the receipts
+    ///
+    pub async fn quarantine_receipts(
+        &self,
+        report: &RevalidationReport<Rcpt>,
+    ) -> Result<u64, Error> {
+        let invalid_receipt_ids = report.invalid_receipt_ids();
+        // resolved first, so that a failure leaves the receipts in place
+        let mut aggregated = Vec::new();
+        if let (Some(rav_key_fields), Some(aggregate_fields)) =
+            (&self.rav_key_fields, &self.running_aggregate_fields)
+        {
+            let mut senders_of_signers = HashMap::new();
+            for (receipt, _) in &report.failed {
+                if invalid_receipt_ids.contains(&receipt.id()) {
+                    let rav_key = self
+                        .stored_receipt_rav_key(rav_key_fields, &mut senders_of_signers, receipt)
+                        .await?;
+                    let (timestamp_ns, value) = aggregate_fields(receipt.signed_receipt());
+                    aggregated.push((rav_key, timestamp_ns, value));
+                }
+            }
+        }
+        let quarantined = self
+            .context
+            .quarantine_receipts(&invalid_receipt_ids)
+            .await
+            .map_err(storage_error)?;
+        for (rav_key, timestamp_ns, value) in aggregated {
+            self.running_aggregates.remove(rav_key, timestamp_ns, value);
+        }
+        self.release_pending_receipts(quarantined);
+        Ok(quarantined)
+    }
+}
+
+impl<E, Rcpt> Manager<E, Rcpt>
+where
+    E: ReceiptStore<Rcpt> + Sync,
+    Rcpt: WithValueAndTimestamp + Clone + Send,
+{
+    /// Runs `initial_checks` on `signed_receipt` for initial verification,
+    /// then stores received receipt.
+    /// The provided `query_id` will be used as a key when chaecking query appraisal.
+    ///
+    /// When `ctx` carries the [`RavKey`] of the receipt, a receipt not newer
+    /// than the last RAV of the key is rejected, see
+    /// [`Manager::new_recovering`].
     ///
     /// # Errors
     ///
     /// Returns [`Error::AdapterError`] if there are any errors while storing receipts
     ///
+    /// Returns [`Error::ReceiptTimestampLowerThanRav`] if the receipt is
+    /// already covered by the last RAV of the [`RavKey`] found in `ctx`
+    ///
+    /// Returns [`Error::IngestionPaused`] if ingestion was paused with
+    /// [`Manager::set_paused`]
+    ///
+    /// Returns [`Error::StorageQuotaExceeded`] if the storage is full, see
+    /// [`Manager::is_storage_full`]
+    ///
+    /// Returns [`Error::UnknownChainId`] if `ctx` carries a chain id with no
+    /// configured domain separator
+    ///
+    /// Returns [`Error::BackpressureLimitReached`] if the number of pending
+    /// receipts reached the limit set with [`Manager::with_max_pending_receipts`]
+    ///
+    /// Returns [`Error::ReceiptEquivocation`] if the receipt conflicts with a
+    /// stored one, see [`Manager::with_equivocation_detection`]
+    ///
+    /// Returns [`Error::ReceiptError`] if a check fails, with
+    /// [`ReceiptError::BelowMinTimestamp`] if the receipt is older than
+    /// [`Manager::min_receipt_timestamp_ns`]
+    ///
     pub async fn verify_and_store_receipt(
         &self,
         ctx: &Context,
         signed_receipt: Rcpt,
     ) -> std::result::Result<(), Error> {
-        let mut received_receipt = ReceiptWithState::new(signed_receipt);
+        self.verify_and_store_receipt_with_warnings(ctx, signed_receipt)
+            .await?;
+        Ok(())
+    }
 
-        // perform checks
-        received_receipt.perform_checks(ctx, &self.checks).await?;
+    /// Same as [`Manager::verify_and_store_receipt`], for a receipt sent
+    /// along with its version, checked first with
+    /// [`Manager::validate_version`]
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnsupportedReceiptVersion`] if the receipt wasn't sent
+    /// as the version set with [`Manager::with_supported_receipt_version`]
+    ///
+    /// See [`Manager::verify_and_store_receipt`] for the other errors
+    ///
+    pub async fn verify_and_store_versioned_receipt(
+        &self,
+        ctx: &Context,
+        versioned_receipt: VersionedReceipt<Rcpt>,
+    ) -> std::result::Result<(), Error> {
+        self.validate_version(&versioned_receipt)?;
+        self.verify_and_store_receipt(ctx, versioned_receipt.receipt)
+            .await
+    }
 
-        // store the receipt
-        self.context
-            .store_receipt(received_receipt)
+    /// Same as [`Manager::verify_and_store_receipt`], returning the failures
+    /// of the [`CheckSeverity::Warn`] checks, which don't prevent the
+    /// receipt from being stored, e.g. to pass them on to the sender.
+    ///
+    /// # Errors
+    ///
+    /// See [`Manager::verify_and_store_receipt`]
+    ///
+    pub async fn verify_and_store_receipt_with_warnings(
+        &self,
+        ctx: &Context,
+        signed_receipt: Rcpt,
+    ) -> std::result::Result<Vec<CheckWarning>, Error> {
+        self.verify_and_store_receipt_timed(ctx, signed_receipt, None)
             .await
-            .map_err(|err| Error::AdapterError {
-                source_error: anyhow::Error::new(err),
-            })?;
-        Ok(())
+    }
+
+    /// Same as [`Manager::verify_and_store_receipt`], returning the time spent
+    /// in each check on this receipt, e.g. to find out which check to cache or
+    /// run later. Unlike `Manager::check_timings`, this doesn't need the
+    /// `metrics` feature.
+    ///
+    /// # Errors
+    ///
+    /// See [`Manager::verify_and_store_receipt`]
+    ///
+    pub async fn verify_and_store_receipt_with_timings(
+        &self,
+        ctx: &Context,
+        signed_receipt: Rcpt,
+    ) -> std::result::Result<CheckTimings, Error> {
+        let timings = std::sync::Mutex::new(CheckTimings::new());
+        self.verify_and_store_receipt_timed(ctx, signed_receipt, Some(&timings))
+            .await?;
+        Ok(timings.into_inner().unwrap())
+    }
+
+    /// See [`Manager::verify_and_store_receipt_with_warnings`], recording the
+    /// time spent in each check in `timings` if set
+    async fn verify_and_store_receipt_timed(
+        &self,
+        ctx: &Context,
+        signed_receipt: Rcpt,
+        timings: Option<&std::sync::Mutex<CheckTimings>>,
+    ) -> std::result::Result<Vec<CheckWarning>, Error> {
+        if self.is_paused() {
+            return Err(Error::IngestionPaused);
+        }
+        if self.is_storage_full() {
+            return Err(Error::StorageQuotaExceeded {
+                message: "Receipts are rejected until storage space frees up".to_string(),
+            });
+        }
+        let domain_separator = self.domain_separator(ctx)?;
+        // the receipt counts as pending right away, so that concurrent
+        // receipts can't all pass the limit
+        let limit = self.max_pending_receipts.unwrap_or(u64::MAX);
+        self.pending_receipts
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+                (pending < limit).then_some(pending + 1)
+            })
+            .map_err(|pending| Error::BackpressureLimitReached { pending, limit })?;
+        let result = self
+            .check_and_store_receipt(ctx, &domain_separator, signed_receipt, timings)
+            .await;
+        if result.is_err() {
+            self.release_pending_receipts(1);
+        }
+        result
+    }
+
+    /// Runs the checks on `signed_receipt` and stores it, see
+    /// [`Manager::verify_and_store_receipt_timed`]
+    async fn check_and_store_receipt(
+        &self,
+        ctx: &Context,
+        domain_separator: &Eip712Domain,
+        signed_receipt: Rcpt,
+        timings: Option<&std::sync::Mutex<CheckTimings>>,
+    ) -> std::result::Result<Vec<CheckWarning>, Error> {
+        let received_receipt = ReceiptWithState::new(signed_receipt);
+
+        // shadow checks never reject the receipt
+        for (index, check) in self.shadow_checks.iter().enumerate() {
+            if let Err(err) = check.check(ctx, &received_receipt).await {
+                self.shadow_check_failures.fetch_add(1, Ordering::Relaxed);
+                log::warn!("Shadow check #{index} failed: {err}");
+            }
+        }
+
+        // e.g. already covered by a RAV. During the grace period, only a
+        // receipt that can be checked against the last RAV of its key below
+        let timestamp_ns = received_receipt.signed_receipt().timestamp_ns();
+        let min_timestamp_ns = self.min_receipt_timestamp_ns();
+        let below_min_timestamp = timestamp_ns < min_timestamp_ns;
+        if below_min_timestamp
+            && (!ctx.contains::<RavKey>() || !self.in_below_min_timestamp_grace_period())
+        {
+            return Err(ReceiptError::BelowMinTimestamp {
+                receipt_ts: timestamp_ns,
+                floor: min_timestamp_ns,
+            }
+            .into());
+        }
+
+        // already covered by the last RAV of its key
+        if let Some(rav_key) = ctx.get::<RavKey>() {
+            if let Some(&rav_ts) = self.last_rav_timestamps.read().unwrap().get(rav_key) {
+                if timestamp_ns <= rav_ts {
+                    return Err(Error::ReceiptTimestampLowerThanRav {
+                        rav_ts,
+                        receipt_ts: timestamp_ns,
+                    });
+                }
+            }
+        }
+
+        // perform checks
+        let (warnings, passed) = self
+            .perform_checks(ctx, &received_receipt, timings, false)
+            .await?;
+
+        // key of the receipt in the running aggregates and the query index
+        let mut rav_key = None;
+        if let Some(rav_key_fields) = &self.rav_key_fields {
+            let signed_receipt = received_receipt.signed_receipt();
+            match self
+                .receipt_rav_key(ctx, domain_separator, rav_key_fields, signed_receipt)
+                .await
+            {
+                Ok(key) => rav_key = Some(key),
+                Err(err) => {
+                    self.rollback_checks(ctx, &received_receipt, &passed).await;
+                    return Err(err);
+                }
+            }
+        }
+
+        // reject a receipt conflicting with a stored one of the same query
+        let mut claimed_query = None;
+        if let (Some(query_fields), Some(rav_key)) = (&self.query_fields, rav_key) {
+            let (query_id, value, _) = query_fields(received_receipt.signed_receipt());
+            match self
+                .query_index
+                .claim(rav_key, query_id, value, timestamp_ns)
+            {
+                Ok(claimed) => claimed_query = claimed.then_some((rav_key, query_id)),
+                Err((stored_receipt_id, stored_value)) => {
+                    log::warn!(
+                        "Receipt equivocation of sender {} on allocation {} for query id {query_id}: value {value}, {stored_value} stored",
+                        rav_key.sender,
+                        rav_key.allocation_id
+                    );
+                    self.rollback_checks(ctx, &received_receipt, &passed).await;
+                    if self.max_equivocations > 0 {
+                        let mut equivocations = self.equivocations.lock().unwrap();
+                        if equivocations.len() == self.max_equivocations {
+                            equivocations.pop_front();
+                        }
+                        equivocations.push_back(Equivocation {
+                            query_id,
+                            sender: rav_key.sender,
+                            allocation_id: rav_key.allocation_id,
+                            stored_receipt_id,
+                            stored_value,
+                            receipt: received_receipt.into_signed_receipt(),
+                        });
+                    }
+                    return Err(Error::ReceiptEquivocation { query_id });
+                }
+            }
+        }
+
+        // added to the running aggregate of its key right away, so that a
+        // receipt that would overflow it is rejected before being stored
+        let mut aggregated = None;
+        if let (Some(aggregate_fields), Some(rav_key)) = (&self.running_aggregate_fields, rav_key) {
+            let (timestamp_ns, value) = aggregate_fields(received_receipt.signed_receipt());
+            if let Err(err) = self.running_aggregates.add(rav_key, timestamp_ns, value) {
+                if let Some((rav_key, query_id)) = claimed_query {
+                    self.query_index.release(rav_key, query_id);
+                }
+                self.rollback_checks(ctx, &received_receipt, &passed).await;
+                return Err(err.into());
+            }
+            aggregated = Some((rav_key, timestamp_ns, value));
+        }
+
+        // store the receipt, kept for the stateful checks to commit or roll
+        // back once stored or not
+        let checked_receipt = self
+            .checks
+            .iter()
+            .any(|check| check.is_stateful())
+            .then(|| received_receipt.clone());
+        let stored = if below_min_timestamp {
+            self.context
+                .store_receipt_below_min_timestamp(received_receipt, domain_separator)
+                .await
+        } else {
+            self.context
+                .store_receipt_in_domain(received_receipt, domain_separator)
+                .await
+        };
+        let receipt_id = match stored {
+            Ok(receipt_id) => receipt_id,
+            Err(err) => {
+                if let Some((rav_key, query_id)) = claimed_query {
+                    self.query_index.release(rav_key, query_id);
+                }
+                if let Some((rav_key, timestamp_ns, value)) = aggregated {
+                    self.running_aggregates.remove(rav_key, timestamp_ns, value);
+                }
+                if let Some(receipt) = &checked_receipt {
+                    self.rollback_checks(ctx, receipt, &passed).await;
+                }
+                let err = storage_error(err);
+                if matches!(err, Error::StorageQuotaExceeded { .. }) {
+                    self.storage_full.store(true, Ordering::SeqCst);
+                }
+                return Err(err);
+            }
+        };
+        if let Some((rav_key, query_id)) = claimed_query {
+            self.query_index.stored(rav_key, query_id, receipt_id);
+        }
+        // every check passed and the receipt is stored, stateful checks can
+        // record it
+        if let Some(receipt) = &checked_receipt {
+            for check in self.checks.iter() {
+                if self.is_check_enabled(check.name()) {
+                    check.commit(ctx, receipt).await;
+                }
+            }
+        }
+        if below_min_timestamp {
+            self.below_min_timestamp_receipts
+                .fetch_add(1, Ordering::Relaxed);
+            log::warn!(
+                "Accepted receipt {receipt_id} of timestamp {timestamp_ns} below the minimum timestamp {min_timestamp_ns} during the grace period"
+            );
+        }
+        Ok(warnings)
+    }
+
+    /// Runs [`Manager::verify_and_store_receipt`] on every receipt of
+    /// `signed_receipts`, up to [`Manager::with_receipt_concurrency`] at a
+    /// time, in order when handled one at a time. One receipt failing doesn't
+    /// prevent the other ones from being stored.
+    ///
+    /// Returns one result per receipt, in the same order as `signed_receipts`.
+    ///
+    pub async fn verify_and_store_receipts(
+        &self,
+        ctx: &Context,
+        signed_receipts: Vec<Rcpt>,
+    ) -> Vec<std::result::Result<(), Error>> {
+        let mut results: Vec<_> = stream::iter(signed_receipts.into_iter().enumerate())
+            .map(|(index, signed_receipt)| async move {
+                (
+                    index,
+                    self.verify_and_store_receipt(ctx, signed_receipt).await,
+                )
+            })
+            .buffer_unordered(self.receipt_concurrency)
+            .collect()
+            .await;
+        results.sort_unstable_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+}
+
+impl<E, Rcpt> Manager<E, Rcpt>
+where
+    E: ReceiptStore<Rcpt> + Send + Sync + 'static,
+    Rcpt: WithValueAndTimestamp + Clone + Send + Sync + 'static,
+{
+    /// Starts a background task storing the receipts sent through
+    /// [`Manager::ingest_sender`], so that callers can hand receipts off
+    /// without waiting for them to be checked and stored.
+    ///
+    /// The channel holds at most `channel_capacity` receipts. The task stores
+    /// them in batches of up to `batch_size` with
+    /// [`Manager::verify_and_store_receipts`], logging the rejected ones.
+    /// It stops once the manager is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IngestionAlreadyStarted`] if the task was already started
+    ///
+    pub fn start_ingestion(
+        self: &Arc<Self>,
+        channel_capacity: usize,
+        batch_size: usize,
+    ) -> Result<JoinHandle<()>, Error> {
+        let (sender, mut receiver) = mpsc::channel(channel_capacity);
+        self.ingest_sender
+            .set(sender)
+            .map_err(|_| Error::IngestionAlreadyStarted)?;
+
+        // the manager owns the sender, so the task only keeps a weak
+        // reference to let the channel close when the manager is dropped
+        let manager = Arc::downgrade(self);
+        let batch_size = batch_size.max(1);
+        Ok(tokio::spawn(async move {
+            let mut batch = Vec::with_capacity(batch_size);
+            while receiver.recv_many(&mut batch, batch_size).await > 0 {
+                let Some(manager) = manager.upgrade() else {
+                    break;
+                };
+                let results = manager
+                    .verify_and_store_receipts(&Context::new(), std::mem::take(&mut batch))
+                    .await;
+                for err in results.into_iter().filter_map(Result::err) {
+                    log::warn!("Ingested receipt rejected: {err}");
+                }
+            }
+        }))
+    }
+}
+
+impl<E, Rcpt> Manager<E, Rcpt>
+where
+    E: Send + Sync + 'static,
+    Rcpt: Send + Sync + 'static,
+{
+    /// Starts a background task replacing the content of `allocation_ids`,
+    /// the set shared with the allocation check, with the set returned by
+    /// `resolver` right away and then every `interval`. If `resolver` fails,
+    /// the error is logged and the previous set is kept until the next
+    /// refresh. The task stops once the manager is dropped.
+    pub fn start_allocation_refresh<F, Fut>(
+        self: &Arc<Self>,
+        allocation_ids: Arc<RwLock<HashSet<Address>>>,
+        resolver: F,
+        interval: Duration,
+    ) -> JoinHandle<()>
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<HashSet<Address>, Error>> + Send,
+    {
+        // only tracks whether the manager is still alive
+        let manager = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                if manager.strong_count() == 0 {
+                    break;
+                }
+                match resolver().await {
+                    Ok(refreshed) => *allocation_ids.write().unwrap() = refreshed,
+                    Err(err) => {
+                        log::warn!("Allocation refresh failed, keeping the previous set: {err}")
+                    }
+                }
+            }
+        })
+    }
+
+    /// Starts a background task running [`Manager::prune_rav_history`] with
+    /// `keep_latest` right away and then every `interval`, logging failures.
+    /// The task stops once the manager is dropped.
+    pub fn start_rav_history_pruning(
+        self: &Arc<Self>,
+        keep_latest: usize,
+        interval: Duration,
+    ) -> JoinHandle<()>
+    where
+        E: RavDelete,
+    {
+        let manager = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                let Some(manager) = manager.upgrade() else {
+                    break;
+                };
+                if let Err(err) = manager.prune_rav_history(keep_latest).await {
+                    log::warn!("RAV history pruning failed: {err}");
+                }
+            }
+        })
+    }
+}
+
+/// Wraps the error of a context adapter in an [`Error::AdapterError`], unless
+/// the storage reports its quota is exceeded
+fn storage_error<Err>(err: Err) -> Error
+where
+    Err: std::error::Error + Send + Sync + 'static,
+{
+    let source_error = anyhow::Error::new(err);
+    let quota_exceeded = source_error
+        .chain()
+        .find_map(|err| match err.downcast_ref() {
+            Some(Error::StorageQuotaExceeded { message }) => Some(message.clone()),
+            _ => None,
+        });
+    match quota_exceeded {
+        Some(message) => Error::StorageQuotaExceeded { message },
+        None => Error::AdapterError { source_error },
     }
 }
@@ -0,0 +1,120 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Merkle commitment over receipts
+//!
+//! Commits to the receipts aggregated into a RAV with a single 32 bytes root,
+//! see [`crate::rav_request::RavRequest::receipt_merkle_root`]. A
+//! [`MerkleProof`] then shows that a given receipt was part of it, without
+//! having to disclose the other receipts.
+//!
+//! Leaves and inner nodes are hashed with distinct prefixes, so that an inner
+//! node can't be passed off as a leaf. A node without a sibling is moved up
+//! to the next level unchanged.
+
+use alloy::primitives::keccak256;
+use serde::{Deserialize, Serialize};
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+fn hash_leaf(leaf: &[u8; 32]) -> [u8; 32] {
+    let mut preimage = [0u8; 33];
+    preimage[0] = LEAF_PREFIX;
+    preimage[1..].copy_from_slice(leaf);
+    keccak256(preimage).0
+}
+
+fn hash_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut preimage = [0u8; 65];
+    preimage[0] = NODE_PREFIX;
+    preimage[1..33].copy_from_slice(left);
+    preimage[33..].copy_from_slice(right);
+    keccak256(preimage).0
+}
+
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_node(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+/// Returns the Merkle root of `leaves`, or all zeroes if there are none
+pub fn merkle_root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    if leaves.is_empty() {
+        return [0u8; 32];
+    }
+    let mut level: Vec<_> = leaves.iter().map(hash_leaf).collect();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level[0]
+}
+
+/// Proof that a leaf is included in a Merkle root computed with [`merkle_root`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    /// Position of the leaf
+    pub index: usize,
+    /// Number of leaves of the tree
+    pub leaves_count: usize,
+    /// Siblings of the nodes on the path from the leaf to the root
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl MerkleProof {
+    /// Returns the proof of inclusion of the leaf at `index`, or `None` if
+    /// `index` is out of bounds
+    pub fn new(leaves: &[[u8; 32]], index: usize) -> Option<Self> {
+        if index >= leaves.len() {
+            return None;
+        }
+        let mut siblings = Vec::new();
+        let mut level: Vec<_> = leaves.iter().map(hash_leaf).collect();
+        let mut position = index;
+        while level.len() > 1 {
+            if let Some(sibling) = level.get(position ^ 1) {
+                siblings.push(*sibling);
+            }
+            level = next_level(&level);
+            position /= 2;
+        }
+        Some(Self {
+            index,
+            leaves_count: leaves.len(),
+            siblings,
+        })
+    }
+
+    /// Returns `true` if `leaf` is included at `self.index` in the tree of
+    /// Merkle root `root`
+    pub fn verify(&self, leaf: &[u8; 32], root: &[u8; 32]) -> bool {
+        if self.index >= self.leaves_count {
+            return false;
+        }
+        let mut node = hash_leaf(leaf);
+        let mut siblings = self.siblings.iter();
+        let (mut position, mut level_len) = (self.index, self.leaves_count);
+        while level_len > 1 {
+            // the last node of an odd level has no sibling
+            if (position ^ 1) < level_len {
+                let Some(sibling) = siblings.next() else {
+                    return false;
+                };
+                node = if position % 2 == 0 {
+                    hash_node(&node, sibling)
+                } else {
+                    hash_node(sibling, &node)
+                };
+            }
+            position /= 2;
+            level_len = level_len.div_ceil(2);
+        }
+        siblings.next().is_none() && node == *root
+    }
+}
@@ -3,15 +3,23 @@
 
 //! Request to Tap Aggregator
 
-use alloy::sol_types::SolStruct;
-use tap_receipt::rav::AggregationError;
+use alloy::{
+    dyn_abi::Eip712Domain,
+    primitives::{Address, B256},
+    signers::{local::PrivateKeySigner, Signer},
+    sol_types::SolStruct,
+};
+use serde::{Deserialize, Serialize};
+use tap_receipt::rav::{Aggregate, AggregationError};
 
 use crate::{
+    merkle::{merkle_root, MerkleProof},
     receipt::{
         state::{Checked, Failed},
-        ReceiptWithState,
+        ReceiptWithState, WithAllocationId, WithReceiptHash, WithValueAndTimestamp,
     },
     signed_message::Eip712SignedMessage,
+    Error,
 };
 
 /// Request to `tap_aggregator` to aggregate receipts into a Signed RAV.
@@ -25,4 +33,364 @@ pub struct RavRequest<Rcpt, Rav: SolStruct> {
     pub invalid_receipts: Vec<ReceiptWithState<Failed, Rcpt>>,
     /// Expected RAV to be created
     pub expected_rav: Result<Rav, AggregationError>,
+    /// Number of valid receipts aggregated into `expected_rav` but left out
+    /// of `valid_receipts`, see
+    /// [`crate::manager::Manager::with_valid_receipts_sample_size`]
+    pub omitted_receipts: u64,
+    /// Total value of the omitted receipts
+    pub omitted_value: u128,
+}
+
+impl<Rcpt, Rav> RavRequest<Rcpt, Rav>
+where
+    Rcpt: WithValueAndTimestamp,
+    Rav: SolStruct + WithValueAndTimestamp,
+{
+    /// Keeps at most `sample_size` valid receipts, evenly spread over the
+    /// list, moving the count and value of the others to `omitted_receipts`
+    /// and `omitted_value`. `expected_rav` is left untouched.
+    pub(crate) fn sample_valid_receipts(&mut self, sample_size: usize) {
+        let receipts_count = self.valid_receipts.len();
+        if receipts_count <= sample_size {
+            return;
+        }
+        let mut sampled = Vec::with_capacity(sample_size);
+        for (index, receipt) in std::mem::take(&mut self.valid_receipts)
+            .into_iter()
+            .enumerate()
+        {
+            // keeps one receipt every receipts_count / sample_size
+            if index * sample_size / receipts_count != (index + 1) * sample_size / receipts_count {
+                sampled.push(receipt);
+            } else {
+                self.omitted_receipts += 1;
+                self.omitted_value = self
+                    .omitted_value
+                    .saturating_add(receipt.signed_receipt().value());
+            }
+        }
+        self.valid_receipts = sampled;
+    }
+
+    /// Checks that the value of the expected RAV is the sum of the values of
+    /// the valid receipts, of the omitted receipts and of the previous RAV,
+    /// if any. This is a last
+    /// safety net against aggregation bugs before the RAV gets signed.
+    ///
+    /// Does nothing if the expected RAV couldn't be aggregated, as there is
+    /// nothing to sign then.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AggregateOverflow`] if the sum overflows
+    ///
+    /// Returns [`Error::AggregateMismatch`] if the sum differs from the value
+    /// of the expected RAV
+    ///
+    pub fn validate_aggregate(&self) -> Result<(), Error> {
+        let Ok(expected_rav) = &self.expected_rav else {
+            return Ok(());
+        };
+        let recomputed_value = self
+            .valid_receipts
+            .iter()
+            .map(|receipt| receipt.signed_receipt().value())
+            .chain(self.previous_rav.as_ref().map(|rav| rav.message.value()))
+            .chain(std::iter::once(self.omitted_value))
+            .try_fold(0u128, u128::checked_add)
+            .ok_or(Error::AggregateOverflow)?;
+        if recomputed_value != expected_rav.value() {
+            return Err(Error::AggregateMismatch {
+                expected_rav_value: expected_rav.value(),
+                recomputed_value,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<Rcpt, Rav> RavRequest<Rcpt, Rav>
+where
+    Rav: SolStruct + WithValueAndTimestamp,
+{
+    /// Returns the escrow newly committed by the expected RAV, i.e. its value
+    /// minus the value of the previous RAV, if any. Together with the escrow
+    /// balance of the sender, this tells whether the RAV can be redeemed.
+    /// It is also the new value checked against
+    /// [`crate::manager::Manager::with_low_value_rav_warning`].
+    ///
+    /// Returns 0 if the expected RAV couldn't be aggregated, or if its value
+    /// is below the previous RAV's, which [`RavRequest::validate_aggregate`]
+    /// rejects.
+    pub fn escrow_delta(&self) -> u128 {
+        let Ok(expected_rav) = &self.expected_rav else {
+            return 0;
+        };
+        let previous_value = self
+            .previous_rav
+            .as_ref()
+            .map_or(0, |rav| rav.message.value());
+        expected_rav.value().saturating_sub(previous_value)
+    }
+}
+
+impl<Rcpt, Rav> RavRequest<Rcpt, Rav>
+where
+    Rcpt: WithReceiptHash,
+    Rav: SolStruct,
+{
+    fn receipt_hashes(&self) -> Vec<[u8; 32]> {
+        self.valid_receipts
+            .iter()
+            .map(|receipt| receipt.signed_receipt().receipt_hash())
+            .collect()
+    }
+
+    /// Returns the Merkle root of the hashes of `valid_receipts`, in order,
+    /// or all zeroes if there are none.
+    ///
+    /// With [`RavRequest::merkle_proof`], this commits to the receipts of the
+    /// RAV so that the inclusion of any of them can later be proven.
+    pub fn receipt_merkle_root(&self) -> [u8; 32] {
+        merkle_root(&self.receipt_hashes())
+    }
+
+    /// Returns the proof that the valid receipt at `index` is included in
+    /// [`RavRequest::receipt_merkle_root`], or `None` if `index` is out of
+    /// bounds
+    pub fn merkle_proof(&self, index: usize) -> Option<MerkleProof> {
+        MerkleProof::new(&self.receipt_hashes(), index)
+    }
+}
+
+impl<Rcpt, Rav> RavRequest<Rcpt, Rav>
+where
+    Rav: SolStruct + Clone,
+{
+    /// Returns what a remote signer needs to sign the expected RAV, leaving
+    /// out the receipts, or `None` if the expected RAV couldn't be aggregated
+    pub fn to_signing_payload(
+        &self,
+        domain_separator: &Eip712Domain,
+    ) -> Option<SigningPayload<Rav>> {
+        let rav = self.expected_rav.as_ref().ok()?.clone();
+        Some(SigningPayload {
+            rav,
+            domain_separator: domain_separator.separator(),
+        })
+    }
+}
+
+/// Expected RAV of a [`RavRequest`], sent to a signer running in another
+/// process, see [`RavRequest::to_signing_payload`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SigningPayload<Rav> {
+    /// RAV to sign
+    pub rav: Rav,
+    /// Hash of the EIP-712 domain separator the RAV must be signed with
+    pub domain_separator: B256,
+}
+
+impl<Rav: SolStruct> SigningPayload<Rav> {
+    /// Signs the RAV with `signing_wallet`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::SigningDomainMismatch`] if `domain_separator` isn't
+    /// the domain the payload was built for
+    ///
+    /// Returns [`Error::SignatureError`] if the wallet fails to sign
+    ///
+    pub fn sign(
+        self,
+        domain_separator: &Eip712Domain,
+        signing_wallet: &PrivateKeySigner,
+    ) -> Result<Eip712SignedMessage<Rav>, Error> {
+        let received = domain_separator.separator();
+        if received != self.domain_separator {
+            return Err(Error::SigningDomainMismatch {
+                expected: self.domain_separator,
+                received,
+            });
+        }
+        Ok(Eip712SignedMessage::new(
+            domain_separator,
+            self.rav,
+            signing_wallet,
+        )?)
+    }
+}
+
+/// Signs `rav` with both the `local` key and the `remote` signer, e.g. a
+/// KMS, and checks that both signatures recover to the same address. Run it
+/// while migrating to a remote signer, before trusting it in production.
+///
+/// # Errors
+///
+/// Returns [`Error::SignerMismatch`] if the signatures recover to different
+/// addresses
+///
+/// Returns [`Error::WalletError`] or [`Error::SignatureError`] if either
+/// signer fails to sign, or a signature can't be recovered
+///
+pub async fn dual_sign_and_verify<Rav, S>(
+    rav: &Rav,
+    domain_separator: &Eip712Domain,
+    local: &PrivateKeySigner,
+    remote: &S,
+) -> Result<(), Error>
+where
+    Rav: SolStruct + Clone,
+    S: Signer + Sync,
+{
+    let local_rav = Eip712SignedMessage::new(domain_separator, rav.clone(), local)?;
+    let remote_rav = Eip712SignedMessage {
+        message: rav.clone(),
+        signature: remote
+            .sign_hash(&rav.eip712_signing_hash(domain_separator))
+            .await?,
+    };
+    let local = local_rav.recover_signer(domain_separator)?;
+    let remote = remote_rav.recover_signer(domain_separator)?;
+    if local != remote {
+        return Err(Error::SignerMismatch { local, remote });
+    }
+    Ok(())
+}
+
+/// Computes the RAV aggregating `receipts` on top of `previous_rav`, if any,
+/// for `allocation_id`, without any storage or [`crate::manager::Manager`].
+/// This is the aggregation done by
+/// [`crate::manager::Manager::create_rav_request`], e.g. for offline tooling.
+///
+/// The receipts are expected to be checked already, their signatures are
+/// not verified.
+///
+/// # Errors
+///
+/// Returns [`AggregationError::AllocationIdMismatch`] if a receipt or the
+/// previous RAV belongs to another allocation
+///
+/// Returns [`AggregationError::AggregateOverflow`] if the aggregate value
+/// overflows
+///
+/// Returns [`AggregationError::NoValidReceiptsForRavRequest`] if there are
+/// no receipts
+///
+pub fn compute_expected_rav<Rcpt, Rav>(
+    allocation_id: Address,
+    previous_rav: Option<Eip712SignedMessage<Rav>>,
+    receipts: &[ReceiptWithState<Checked, Rcpt>],
+) -> Result<Rav, AggregationError>
+where
+    Rcpt: WithAllocationId,
+    Rav: Aggregate<Rcpt> + WithAllocationId,
+{
+    let allocation_ids = receipts
+        .iter()
+        .map(|receipt| receipt.signed_receipt().allocation_id())
+        .chain(previous_rav.as_ref().map(|rav| rav.message.allocation_id()));
+    for received in allocation_ids {
+        if received != allocation_id {
+            return Err(AggregationError::AllocationIdMismatch {
+                expected: allocation_id,
+                received,
+            });
+        }
+    }
+    Rav::aggregate_receipts(receipts, previous_rav)
+}
+
+/// Returns `true` if a RAV worth `rav_value` covers the `min_profitable_value`,
+/// i.e. the expected on-chain cost of redeeming it.
+pub fn is_rav_worth_redeeming(rav_value: u128, min_profitable_value: u128) -> bool {
+    rav_value >= min_profitable_value
+}
+
+/// Result of [`simulate_redemption`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedemptionOutcome {
+    /// Amount paid out of the escrow by the redemption
+    pub redeemable: u128,
+    /// Amount owed by the RAV that the escrow can't cover
+    pub shortfall: u128,
+}
+
+impl RedemptionOutcome {
+    /// Returns `true` if the escrow covers everything the RAV owes
+    pub fn is_full(&self) -> bool {
+        self.shortfall == 0
+    }
+}
+
+/// Simulates the on-chain redemption of a RAV worth `rav_value`. As RAVs are
+/// cumulative, the contract pays out the value not redeemed yet, i.e.
+/// `rav_value - previous_redeemed`, capped by the `escrow_balance` of the
+/// sender.
+pub fn simulate_redemption(
+    rav_value: u128,
+    previous_redeemed: u128,
+    escrow_balance: u128,
+) -> RedemptionOutcome {
+    let owed = rav_value.saturating_sub(previous_redeemed);
+    let redeemable = owed.min(escrow_balance);
+    RedemptionOutcome {
+        redeemable,
+        shortfall: owed - redeemable,
+    }
+}
+
+/// Verifies a RAV history, oldest first, as returned by
+/// [`crate::manager::adapters::RavHistoryRead::get_rav_history`], e.g. after a
+/// migration or for an audit: every RAV must be signed by `expected_signer`,
+/// belong to the allocation of the first one, and follow the RAV before it
+/// with a later timestamp and no lower value.
+///
+/// # Errors
+///
+/// Returns [`Error::SignatureError`] if the signer of a RAV can't be recovered
+///
+/// Returns [`Error::InvalidRecoveredSigner`] if a RAV recovers to another
+/// address than `expected_signer`, e.g. because it was altered after signing
+///
+/// Returns [`Error::RavAllocationIdMismatch`] if a RAV is for another
+/// allocation than the first one
+///
+/// Returns [`Error::BrokenRavChain`] if a RAV doesn't follow the previous one
+///
+pub fn verify_rav_chain<Rav>(
+    ravs: &[Eip712SignedMessage<Rav>],
+    domain_separator: &Eip712Domain,
+    expected_signer: Address,
+) -> Result<(), Error>
+where
+    Rav: SolStruct + WithAllocationId + WithValueAndTimestamp,
+{
+    let mut previous: Option<&Rav> = None;
+    for (index, signed_rav) in ravs.iter().enumerate() {
+        let signer = signed_rav.recover_signer(domain_separator)?;
+        if signer != expected_signer {
+            return Err(Error::InvalidRecoveredSigner { address: signer });
+        }
+        let rav = &signed_rav.message;
+        if let Some(previous) = previous {
+            if rav.allocation_id() != previous.allocation_id() {
+                return Err(Error::RavAllocationIdMismatch {
+                    prev_id: previous.allocation_id().to_string(),
+                    new_id: rav.allocation_id().to_string(),
+                });
+            }
+            if rav.timestamp_ns() <= previous.timestamp_ns() || rav.value() < previous.value() {
+                return Err(Error::BrokenRavChain {
+                    index,
+                    timestamp_ns: rav.timestamp_ns(),
+                    value_aggregate: rav.value(),
+                    previous_timestamp_ns: previous.timestamp_ns(),
+                    previous_value_aggregate: previous.value(),
+                });
+            }
+        }
+        previous = Some(rav);
+    }
+    Ok(())
 }
@@ -0,0 +1,180 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Cache of recovered signers
+//!
+//! The same receipt is usually verified several times: when it is received,
+//! then again when it is aggregated into a RAV. [`SignatureCache`] memoizes
+//! the result of the ECDSA recovery so that only the first verification pays
+//! for it.
+//!
+//! [`SignerDenylistCheck`] rejects receipts of revoked signers, sharing a
+//! cache with the signature check so that the signer is recovered only once.
+
+use std::{
+    collections::HashSet,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+use alloy::{
+    dyn_abi::Eip712Domain,
+    primitives::{Address, B256},
+    sol_types::SolStruct,
+};
+use lru::LruCache;
+
+use crate::{
+    manager::DomainSeparators,
+    receipt::{
+        checks::{Check, CheckError, CheckResult, ReceiptCheck},
+        state::Checking,
+        Context, ReceiptError, ReceiptWithState,
+    },
+    signed_message::{Eip712Error, Eip712SignedMessage, SignatureBytes, SignatureBytesExt},
+};
+
+/// Bounded cache mapping signed messages to their recovered signer,
+/// evicting the least recently used entries once full.
+///
+/// Entries are keyed by the EIP-712 signing hash, which covers both the
+/// message and the domain separator, along with the signature. The same
+/// message recovered for another domain is a cache miss.
+///
+/// Clones share the same entries.
+#[derive(Clone)]
+pub struct SignatureCache {
+    entries: Arc<Mutex<LruCache<(B256, SignatureBytes), Address>>>,
+    recoveries: Arc<AtomicU64>,
+}
+
+impl SignatureCache {
+    /// Creates a cache holding up to `capacity` recovered signers
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(LruCache::new(capacity))),
+            recoveries: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Same as [`Eip712SignedMessage::recover_signer`], but returns the
+    /// cached signer if `signed_message` was already recovered for
+    /// `domain_separator`. Failed recoveries are not cached.
+    pub fn recover_signer<T: SolStruct>(
+        &self,
+        signed_message: &Eip712SignedMessage<T>,
+        domain_separator: &Eip712Domain,
+    ) -> Result<Address, Eip712Error> {
+        let key = (
+            signed_message.message.eip712_signing_hash(domain_separator),
+            signed_message.signature.get_signature_bytes(),
+        );
+        if let Some(signer) = self.entries.lock().unwrap().get(&key) {
+            return Ok(*signer);
+        }
+
+        // recover without holding the lock, it's the expensive part
+        self.recoveries.fetch_add(1, Ordering::Relaxed);
+        let signer = signed_message.recover_signer(domain_separator)?;
+        self.entries.lock().unwrap().put(key, signer);
+        Ok(signer)
+    }
+
+    /// Returns the number of ECDSA recoveries performed, i.e. cache misses
+    pub fn recoveries(&self) -> u64 {
+        self.recoveries.load(Ordering::Relaxed)
+    }
+}
+
+/// Rejects receipts whose recovered signer is in a set of revoked signers,
+/// e.g. a compromised key of an otherwise authorized sender. Signers added to
+/// the set are rejected from the next receipt on.
+///
+/// The signer is recovered with every domain the manager accepts, see
+/// [`DomainSeparators::select_all`], so that a revoked key signing with a
+/// previous domain is rejected as well.
+///
+/// Set up with [`SignerDenylistCheck::with_signature_cache`], the signer is
+/// looked up in the cache filled by the signature check run before it,
+/// instead of being recovered again.
+pub struct SignerDenylistCheck {
+    domain_separators: DomainSeparators,
+    revoked_signers: Arc<RwLock<HashSet<Address>>>,
+    signature_cache: Option<SignatureCache>,
+}
+
+impl SignerDenylistCheck {
+    /// `domain_separators` is either the default domain separator or the
+    /// [`DomainSeparators`] of the manager running the check.
+    pub fn new(
+        domain_separators: impl Into<DomainSeparators>,
+        revoked_signers: Arc<RwLock<HashSet<Address>>>,
+    ) -> Self {
+        Self {
+            domain_separators: domain_separators.into(),
+            revoked_signers,
+            signature_cache: None,
+        }
+    }
+
+    /// Recovers signers through `signature_cache`
+    pub fn with_signature_cache(mut self, signature_cache: SignatureCache) -> Self {
+        self.signature_cache = Some(signature_cache);
+        self
+    }
+
+    /// Same as [`SignerDenylistCheck::new`], as a [`ReceiptCheck`]
+    pub fn boxed<T>(
+        domain_separators: impl Into<DomainSeparators>,
+        revoked_signers: Arc<RwLock<HashSet<Address>>>,
+    ) -> ReceiptCheck<Eip712SignedMessage<T>>
+    where
+        T: SolStruct + Send + Sync,
+    {
+        Arc::new(Self::new(domain_separators, revoked_signers))
+    }
+}
+
+#[async_trait::async_trait]
+impl<T> Check<Eip712SignedMessage<T>> for SignerDenylistCheck
+where
+    T: SolStruct + Send + Sync,
+{
+    async fn check(
+        &self,
+        ctx: &Context,
+        receipt: &ReceiptWithState<Checking, Eip712SignedMessage<T>>,
+    ) -> CheckResult {
+        let invalid_signature = |source_error_message: String| {
+            CheckError::Failed(
+                ReceiptError::InvalidSignature {
+                    source_error_message,
+                }
+                .into(),
+            )
+        };
+        let domain_separators = self
+            .domain_separators
+            .select_all(ctx)
+            .map_err(|e| invalid_signature(e.to_string()))?;
+        let signed_receipt = receipt.signed_receipt();
+        for domain_separator in &domain_separators {
+            let signer = match &self.signature_cache {
+                Some(signature_cache) => {
+                    signature_cache.recover_signer(signed_receipt, domain_separator)
+                }
+                None => signed_receipt.recover_signer(domain_separator),
+            }
+            .map_err(|e| invalid_signature(e.to_string()))?;
+            if self.revoked_signers.read().unwrap().contains(&signer) {
+                return Err(CheckError::Failed(
+                    ReceiptError::RevokedSigner { signer }.into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
@@ -24,3 +24,35 @@
 //!
 
 pub use ::tap_eip712_message::*;
+use alloy::{dyn_abi::Eip712Domain, primitives::Address, sol_types::SolStruct};
+
+use crate::Error;
+
+/// Recovers the signers of `signed_messages` and returns their common
+/// sender, e.g. to make sure a bundle forwarded on behalf of one sender
+/// doesn't mix in receipts of another.
+///
+/// # Errors
+///
+/// Returns [`Error::EmptyReceiptBatch`] if there are no messages
+///
+/// Returns [`Error::SignatureError`] if a signer can't be recovered
+///
+/// Returns [`Error::MixedSenders`] if the messages have different signers
+///
+pub fn verify_same_sender<M: SolStruct>(
+    signed_messages: &[Eip712SignedMessage<M>],
+    domain_separator: &Eip712Domain,
+) -> Result<Address, Error> {
+    let (first, others) = signed_messages
+        .split_first()
+        .ok_or(Error::EmptyReceiptBatch)?;
+    let expected = first.recover_signer(domain_separator)?;
+    for signed_message in others {
+        let received = signed_message.recover_signer(domain_separator)?;
+        if received != expected {
+            return Err(Error::MixedSenders { expected, received });
+        }
+    }
+    Ok(expected)
+}
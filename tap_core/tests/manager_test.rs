@@ -1,13 +1,17 @@
 // Copyright 2023-, Semiotic AI, Inc.
 // SPDX-License-Identifier: Apache-2.0
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    ops::RangeBounds,
     str::FromStr,
-    sync::{atomic::AtomicBool, Arc, RwLock},
-    time::{SystemTime, UNIX_EPOCH},
+    sync::{atomic::AtomicBool, Arc, Mutex, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use alloy::{dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner};
+use alloy::{
+    dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner,
+    sol_types::SolStruct,
+};
 use anyhow::anyhow;
 use rstest::*;
 
@@ -15,23 +19,43 @@ fn get_current_timestamp_u64_ns() -> anyhow::Result<u64> {
     Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64)
 }
 
+#[cfg(feature = "archive")]
+use tap_core::manager::archive::{ArchiveRotation, RavArchiveSink};
 use tap_core::{
+    audit::RavBundle,
     manager::{
-        adapters::ReceiptRead,
+        adapters::{
+            EscrowHandler, EscrowMonitor, RavHistoryRead, RavRead, RavSink, RavStore,
+            ReceiptDelete, ReceiptRead, ReceiptStore, SignatureChecker, StoredRav, StoredReceipt,
+            StoredReceiptRead,
+        },
+        checks::{
+            AllocationAuthorizationCheck, DedupCheck, DedupScope, ReplayWindowCheck,
+            TokenEscrowCheck,
+        },
         context::memory::{
-            checks::get_full_list_of_checks, EscrowStorage, InMemoryContext, QueryAppraisals,
+            checks::{get_full_list_of_checks, EscrowCheck},
+            EscrowStorage, InMemoryContext, InMemoryError, QueryAppraisals,
         },
-        Manager,
+        ChainId, CheckWarning, DomainSeparators, Equivocation, Manager, RavKey, RevalidationReport,
+        TimestampBoundary,
+    },
+    rav_request::{
+        is_rav_worth_redeeming, simulate_redemption, RavRequest, RedemptionOutcome, SigningPayload,
     },
     receipt::{
-        checks::{Check, CheckError, CheckList, StatefulTimestampCheck},
+        checks::{
+            Check, CheckError, CheckList, CheckSeverity, NonZeroValueCheck, ReceiptCheck,
+            StatefulTimestampCheck,
+        },
         state::Checking,
-        Context, ReceiptWithState,
+        Context, ReceiptWithState, VersionedReceipt,
     },
     signed_message::Eip712SignedMessage,
     tap_eip712_domain,
 };
-use tap_graph::{Receipt, ReceiptAggregateVoucher, SignedReceipt};
+use tap_graph::{Receipt, ReceiptAggregateVoucher, SignedRav, SignedReceipt};
+use tokio_util::sync::CancellationToken;
 
 #[fixture]
 fn signer() -> PrivateKeySigner {
@@ -62,6 +86,16 @@ fn sender_ids(signer: PrivateKeySigner) -> (PrivateKeySigner, Vec<Address>) {
     )
 }
 
+/// Request context of the RAVs of `sender` for `allocation_id`
+fn rav_ctx(sender: Address, allocation_id: Address) -> Context {
+    let mut ctx = Context::new();
+    ctx.insert(RavKey {
+        sender,
+        allocation_id,
+    });
+    ctx
+}
+
 #[fixture]
 fn domain_separator() -> Eip712Domain {
     tap_eip712_domain(1, Address::from([0x11u8; 20]))
@@ -83,7 +117,7 @@ fn context(
 ) -> ContextFixture {
     let (signer, sender_ids) = sender_ids;
     let escrow_storage = Arc::new(RwLock::new(HashMap::new()));
-    let rav_storage = Arc::new(RwLock::new(None));
+    let rav_storage = Arc::new(RwLock::new(HashMap::new()));
     let query_appraisals = Arc::new(RwLock::new(HashMap::new()));
     let receipt_storage = Arc::new(RwLock::new(HashMap::new()));
     let timestamp_check = Arc::new(StatefulTimestampCheck::new(0));
@@ -188,7 +222,9 @@ async fn manager_create_rav_request_all_valid_receipts(
             .await
             .is_ok());
     }
-    let rav_request_result = manager.create_rav_request(&Context::new(), 0, None).await;
+    let rav_request_result = manager
+        .create_rav_request(&rav_ctx(signer.address(), allocation_ids[0]), 0, None)
+        .await;
     assert!(rav_request_result.is_ok());
 
     let rav_request = rav_request_result.unwrap();
@@ -210,6 +246,157 @@ async fn manager_create_rav_request_all_valid_receipts(
         .is_ok());
 }
 
+#[rstest]
+#[tokio::test]
+async fn manager_commits_rav_and_receipt_removal_together(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    for _ in 0..10 {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 20).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+    // receipt of another allocation, not covered by the RAV
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[1], 20).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await
+        .unwrap();
+    let ctx = rav_ctx(signer.address(), allocation_ids[0]);
+    let rav_request = manager.create_rav_request(&ctx, 0, None).await.unwrap();
+    let expected_rav = rav_request.expected_rav.unwrap();
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+    let stored_receipts = || async {
+        context
+            .retrieve_receipts_in_timestamp_range(.., None)
+            .await
+            .unwrap()
+            .len()
+    };
+
+    manager
+        .verify_and_commit_rav(&ctx, expected_rav, signed_rav.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        manager
+            .latest_rav::<ReceiptAggregateVoucher>(signer.address(), allocation_ids[0])
+            .await
+            .unwrap(),
+        Some(signed_rav)
+    );
+    assert_eq!(stored_receipts().await, 1);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_exports_verifiable_rav_bundle(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context, checks);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+    let ctx = rav_ctx(signer.address(), allocation_ids[0]);
+
+    assert!(matches!(
+        manager
+            .export_rav_bundle::<ReceiptAggregateVoucher>(signer.address(), allocation_ids[0])
+            .await,
+        Err(tap_core::Error::NoRav { .. })
+    ));
+
+    // two chained RAVs, the bundle is made of the latest one
+    for value in [10, 20] {
+        for _ in 0..3 {
+            let signed_receipt = Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(allocation_ids[0], value).unwrap(),
+                &signer,
+            )
+            .unwrap();
+            manager
+                .verify_and_store_receipt(&Context::new(), signed_receipt)
+                .await
+                .unwrap();
+        }
+        let expected_rav = manager
+            .create_rav_request(&ctx, 0, None)
+            .await
+            .unwrap()
+            .expected_rav
+            .unwrap();
+        let signed_rav =
+            Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+        manager
+            .verify_and_store_rav_with_context(&ctx, expected_rav, signed_rav)
+            .await
+            .unwrap();
+    }
+
+    let bundle = manager
+        .export_rav_bundle::<ReceiptAggregateVoucher>(signer.address(), allocation_ids[0])
+        .await
+        .unwrap();
+    assert_eq!(bundle.rav.message.valueAggregate, 90);
+    assert_eq!(
+        bundle.previous_rav.as_ref().unwrap().message.valueAggregate,
+        30
+    );
+    assert_eq!(bundle.receipts.len(), 3);
+
+    let json = serde_json::to_string(&bundle).unwrap();
+    let mut bundle: RavBundle<SignedReceipt, ReceiptAggregateVoucher> =
+        serde_json::from_str(&json).unwrap();
+    let report = bundle.verify(&domain_separator).await.unwrap();
+    assert!(report.is_consistent());
+    assert_eq!(report.recomputed_value_aggregate, 90);
+
+    // a receipt left out of the bundle doesn't go unnoticed
+    bundle.receipts.pop();
+    let report = bundle.verify(&domain_separator).await.unwrap();
+    assert!(!report.is_consistent());
+    assert_eq!(report.recomputed_value_aggregate, 70);
+}
+
 #[rstest]
 #[tokio::test]
 async fn deny_rav_due_to_wrong_value(domain_separator: Eip712Domain, context: ContextFixture) {
@@ -284,7 +471,9 @@ async fn manager_create_multiple_rav_requests_all_valid_receipts(
             .is_ok());
         expected_accumulated_value += value;
     }
-    let rav_request_result = manager.create_rav_request(&Context::new(), 0, None).await;
+    let rav_request_result = manager
+        .create_rav_request(&rav_ctx(signer.address(), allocation_ids[0]), 0, None)
+        .await;
     assert!(rav_request_result.is_ok());
 
     let rav_request = rav_request_result.unwrap();
@@ -328,7 +517,9 @@ async fn manager_create_multiple_rav_requests_all_valid_receipts(
             .is_ok());
         expected_accumulated_value += value;
     }
-    let rav_request_result = manager.create_rav_request(&Context::new(), 0, None).await;
+    let rav_request_result = manager
+        .create_rav_request(&rav_ctx(signer.address(), allocation_ids[0]), 0, None)
+        .await;
     assert!(rav_request_result.is_ok());
 
     let rav_request = rav_request_result.unwrap();
@@ -400,10 +591,15 @@ async fn manager_create_multiple_rav_requests_all_valid_receipts_consecutive_tim
     // Remove old receipts if requested
     // This shouldn't do anything since there has been no rav created yet
     if remove_old_receipts {
-        manager.remove_obsolete_receipts().await.unwrap();
+        manager
+            .remove_obsolete_receipts(&rav_ctx(signer.address(), allocation_ids[0]))
+            .await
+            .unwrap();
     }
 
-    let rav_request_1_result = manager.create_rav_request(&Context::new(), 0, None).await;
+    let rav_request_1_result = manager
+        .create_rav_request(&rav_ctx(signer.address(), allocation_ids[0]), 0, None)
+        .await;
     assert!(rav_request_1_result.is_ok());
 
     let rav_request_1 = rav_request_1_result.unwrap();
@@ -446,7 +642,10 @@ async fn manager_create_multiple_rav_requests_all_valid_receipts_consecutive_tim
 
     // Remove old receipts if requested
     if remove_old_receipts {
-        manager.remove_obsolete_receipts().await.unwrap();
+        manager
+            .remove_obsolete_receipts(&rav_ctx(signer.address(), allocation_ids[0]))
+            .await
+            .unwrap();
         // We expect to have 10 receipts left in receipt storage
         assert_eq!(
             context
@@ -458,7 +657,9 @@ async fn manager_create_multiple_rav_requests_all_valid_receipts_consecutive_tim
         );
     }
 
-    let rav_request_2_result = manager.create_rav_request(&Context::new(), 0, None).await;
+    let rav_request_2_result = manager
+        .create_rav_request(&rav_ctx(signer.address(), allocation_ids[0]), 0, None)
+        .await;
     assert!(rav_request_2_result.is_ok());
 
     let rav_request_2 = rav_request_2_result.unwrap();
@@ -524,7 +725,7 @@ async fn manager_create_rav_and_ignore_invalid_receipts(
     }
 
     let rav_request = manager
-        .create_rav_request(&Context::new(), 0, None)
+        .create_rav_request(&rav_ctx(signer.address(), allocation_ids[0]), 0, None)
         .await
         .unwrap();
     let expected_rav = rav_request.expected_rav.unwrap();
@@ -538,81 +739,5329 @@ async fn manager_create_rav_and_ignore_invalid_receipts(
 
 #[rstest]
 #[tokio::test]
-async fn test_retryable_checks(
+async fn manager_excludes_receipts_of_other_allocations(
     allocation_ids: Vec<Address>,
     domain_separator: Eip712Domain,
     context: ContextFixture,
 ) {
-    struct RetryableCheck(Arc<AtomicBool>);
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context, checks);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
 
-    #[async_trait::async_trait]
-    impl Check<SignedReceipt> for RetryableCheck {
-        async fn check(
-            &self,
-            _: &Context,
-            receipt: &ReceiptWithState<Checking, SignedReceipt>,
-        ) -> Result<(), CheckError> {
-            // we want to fail only if nonce is 5 and if is create rav step
-            if self.0.load(std::sync::atomic::Ordering::SeqCst)
-                && receipt.signed_receipt().message.nonce == 5
-            {
-                Err(CheckError::Retryable(anyhow!("Retryable error")))
-            } else {
-                Ok(())
-            }
-        }
+    // a receipt of another allocation, left out of the RAV request
+    for allocation_id in [allocation_ids[0], allocation_ids[0], allocation_ids[1]] {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_id, 20).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        query_appraisals
+            .write()
+            .unwrap()
+            .insert(signed_receipt.unique_hash(), 20);
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
     }
 
+    let rav_request = manager
+        .create_rav_request::<ReceiptAggregateVoucher>(
+            &rav_ctx(signer.address(), allocation_ids[0]),
+            0,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(rav_request.valid_receipts.len(), 2);
+    assert_eq!(rav_request.expected_rav.unwrap().valueAggregate, 40);
+    assert!(rav_request.invalid_receipts.is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_keeps_receipts_of_other_senders(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
     let ContextFixture {
         context,
-        checks,
+        query_appraisals,
         escrow_storage,
         signer,
         ..
     } = context;
+    let other_signer = PrivateKeySigner::random();
+    let checks = CheckList::new(get_full_list_of_checks(
+        domain_separator.clone(),
+        [signer.address(), other_signer.address()].into(),
+        Arc::new(RwLock::new(allocation_ids.iter().cloned().collect())),
+        query_appraisals.clone(),
+    ));
+    let manager = Manager::new(domain_separator.clone(), context, checks);
 
-    let is_create_rav = Arc::new(AtomicBool::new(false));
+    for receipt_signer in [&signer, &signer, &other_signer] {
+        escrow_storage
+            .write()
+            .unwrap()
+            .insert(receipt_signer.address(), 999999);
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 20).unwrap(),
+            receipt_signer,
+        )
+        .unwrap();
+        query_appraisals
+            .write()
+            .unwrap()
+            .insert(signed_receipt.unique_hash(), 20);
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
 
-    let mut checks: Vec<Arc<dyn Check<SignedReceipt> + Send + Sync>> =
-        checks.iter().cloned().collect();
-    checks.push(Arc::new(RetryableCheck(is_create_rav.clone())));
+    let signer_ctx = rav_ctx(signer.address(), allocation_ids[0]);
+    let rav_request = manager
+        .create_rav_request::<ReceiptAggregateVoucher>(&signer_ctx, 0, None)
+        .await
+        .unwrap();
+    assert_eq!(rav_request.valid_receipts.len(), 2);
+    let expected_rav = rav_request.expected_rav.unwrap();
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+    manager
+        .verify_and_store_rav_with_context(&signer_ctx, expected_rav, signed_rav)
+        .await
+        .unwrap();
+    manager
+        .remove_obsolete_receipts::<ReceiptAggregateVoucher>(&signer_ctx)
+        .await
+        .unwrap();
 
-    let manager = Manager::new(
-        domain_separator.clone(),
-        context.clone(),
-        CheckList::new(checks),
-    );
+    // the receipt of the other sender survives the removal, and is still
+    // aggregated into its own RAV
+    let rav_request = manager
+        .create_rav_request::<ReceiptAggregateVoucher>(
+            &rav_ctx(other_signer.address(), allocation_ids[0]),
+            0,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(rav_request.valid_receipts.len(), 1);
+    assert_eq!(rav_request.expected_rav.unwrap().valueAggregate, 20);
+}
 
+#[rstest]
+#[tokio::test]
+async fn manager_rav_request_commits_to_receipts(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context, checks);
     escrow_storage
         .write()
         .unwrap()
         .insert(signer.address(), 999999);
 
-    let mut stored_signed_receipts = Vec::new();
-    for i in 0..10 {
-        let receipt = Receipt {
-            allocation_id: allocation_ids[0],
-            timestamp_ns: i + 1,
-            nonce: i,
-            value: 20u128,
-        };
-        let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
-        stored_signed_receipts.push(signed_receipt.clone());
+    let rav_ctx = rav_ctx(signer.address(), allocation_ids[0]);
+    let rav_request = manager
+        .create_rav_request::<ReceiptAggregateVoucher>(&rav_ctx, 0, None)
+        .await
+        .unwrap();
+    assert_eq!(rav_request.receipt_merkle_root(), [0u8; 32]);
+
+    // an odd count, for a node without sibling
+    for _ in 0..5 {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 20).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        query_appraisals
+            .write()
+            .unwrap()
+            .insert(signed_receipt.unique_hash(), 20);
         manager
             .verify_and_store_receipt(&Context::new(), signed_receipt)
             .await
             .unwrap();
     }
+    let rav_request = manager
+        .create_rav_request::<ReceiptAggregateVoucher>(&rav_ctx, 0, None)
+        .await
+        .unwrap();
+    let root = rav_request.receipt_merkle_root();
+    let hashes: Vec<_> = rav_request
+        .valid_receipts
+        .iter()
+        .map(|receipt| receipt.signed_receipt().unique_hash().0)
+        .collect();
+    assert_eq!(hashes.len(), 5);
 
-    is_create_rav.store(true, std::sync::atomic::Ordering::SeqCst);
+    for (index, hash) in hashes.iter().enumerate() {
+        let proof = rav_request.merkle_proof(index).unwrap();
+        assert!(proof.verify(hash, &root));
+        // the proof doesn't hold for another receipt or position
+        assert!(!proof.verify(&hashes[(index + 1) % hashes.len()], &root));
+    }
+    assert!(rav_request.merkle_proof(hashes.len()).is_none());
+}
 
-    let rav_request = manager.create_rav_request(&Context::new(), 0, None).await;
+#[rstest]
+#[tokio::test]
+async fn rav_request_signing_payload_signs_expected_rav(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context, checks);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
 
-    assert_eq!(
-        rav_request.expect_err("Didn't fail").to_string(),
-        tap_core::Error::ReceiptError(tap_core::receipt::ReceiptError::RetryableCheck(
-            "Retryable error".to_string()
+    for _ in 0..3 {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 20).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        query_appraisals
+            .write()
+            .unwrap()
+            .insert(signed_receipt.unique_hash(), 20);
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+    let rav_request = manager
+        .create_rav_request::<ReceiptAggregateVoucher>(
+            &rav_ctx(signer.address(), allocation_ids[0]),
+            0,
+            None,
+        )
+        .await
+        .unwrap();
+
+    let payload = rav_request.to_signing_payload(&domain_separator).unwrap();
+    // the payload a remote signer would receive
+    let payload: SigningPayload<ReceiptAggregateVoucher> =
+        serde_json::from_str(&serde_json::to_string(&payload).unwrap()).unwrap();
+    let expected_rav = rav_request.expected_rav.unwrap();
+    assert_eq!(
+        payload.clone().sign(&domain_separator, &signer).unwrap(),
+        Eip712SignedMessage::new(&domain_separator, expected_rav, &signer).unwrap()
+    );
+
+    let other_domain = tap_eip712_domain(2, Address::from([0x22u8; 20]));
+    assert!(matches!(
+        payload.sign(&other_domain, &signer),
+        Err(tap_core::Error::SigningDomainMismatch { .. })
+    ));
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_rav_request_is_deterministic(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    sender_ids: (PrivateKeySigner, Vec<Address>),
+) {
+    let signer = sender_ids.0.clone();
+    // receipts sharing timestamps, so that some are only ordered by hash
+    let timestamp_ns = get_current_timestamp_u64_ns().unwrap();
+    let receipts: Vec<_> = (0..8u64)
+        .map(|nonce| {
+            let receipt = Receipt {
+                allocation_id: allocation_ids[0],
+                timestamp_ns: timestamp_ns - nonce % 2,
+                nonce,
+                value: 20u128,
+            };
+            Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap()
+        })
+        .collect();
+
+    let mut runs = Vec::new();
+    for reversed in [false, true] {
+        let ContextFixture {
+            context,
+            checks,
+            query_appraisals,
+            escrow_storage,
+            ..
+        } = context(
+            domain_separator.clone(),
+            allocation_ids.clone(),
+            sender_ids.clone(),
+        );
+        let manager = Manager::new(domain_separator.clone(), context, checks);
+        escrow_storage
+            .write()
+            .unwrap()
+            .insert(signer.address(), 999999);
+
+        let mut receipts = receipts.clone();
+        if reversed {
+            receipts.reverse();
+        }
+        for signed_receipt in receipts {
+            query_appraisals
+                .write()
+                .unwrap()
+                .insert(signed_receipt.unique_hash(), 20);
+            manager
+                .verify_and_store_receipt(&Context::new(), signed_receipt)
+                .await
+                .unwrap();
+        }
+        runs.push(
+            manager
+                .create_rav_request::<ReceiptAggregateVoucher>(
+                    &rav_ctx(signer.address(), allocation_ids[0]),
+                    0,
+                    None,
+                )
+                .await
+                .unwrap(),
+        );
+    }
+
+    let signed_receipts = |rav_request: &RavRequest<SignedReceipt, ReceiptAggregateVoucher>| {
+        rav_request
+            .valid_receipts
+            .iter()
+            .map(|receipt| receipt.signed_receipt().clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(signed_receipts(&runs[0]), signed_receipts(&runs[1]));
+    assert_eq!(runs[0].receipt_merkle_root(), runs[1].receipt_merkle_root());
+    assert_eq!(
+        runs[0].expected_rav.as_ref().unwrap(),
+        runs[1].expected_rav.as_ref().unwrap()
+    );
+    assert!(signed_receipts(&runs[0])
+        .is_sorted_by_key(|receipt| (receipt.message.timestamp_ns, receipt.unique_hash().0)));
+}
+
+/// Storage returning receipts newest first
+struct ReversedReceiptStorage(InMemoryContext);
+
+#[async_trait::async_trait]
+impl ReceiptRead<SignedReceipt> for ReversedReceiptStorage {
+    type AdapterError = InMemoryError;
+
+    async fn retrieve_receipts_in_timestamp_range<R: RangeBounds<u64> + Send>(
+        &self,
+        timestamp_range_ns: R,
+        limit: Option<u64>,
+    ) -> Result<Vec<ReceiptWithState<Checking, SignedReceipt>>, Self::AdapterError> {
+        let mut receipts = self
+            .0
+            .retrieve_receipts_in_timestamp_range(timestamp_range_ns, limit)
+            .await?;
+        receipts.sort_by_key(|receipt| {
+            std::cmp::Reverse(receipt.signed_receipt().message.timestamp_ns)
+        });
+        Ok(receipts)
+    }
+}
+
+#[async_trait::async_trait]
+impl RavRead<ReceiptAggregateVoucher> for ReversedReceiptStorage {
+    type AdapterError = InMemoryError;
+
+    async fn get_last_rav(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+    ) -> Result<Option<SignedRav>, Self::AdapterError> {
+        self.0.get_last_rav(sender, allocation_id).await
+    }
+}
+
+#[async_trait::async_trait]
+impl SignatureChecker for ReversedReceiptStorage {
+    type AdapterError = InMemoryError;
+
+    async fn verify_signer(&self, signer_address: Address) -> Result<bool, Self::AdapterError> {
+        self.0.verify_signer(signer_address).await
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_sorts_receipts_returned_out_of_order(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+    } = context;
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let timestamp_ns = get_current_timestamp_u64_ns().unwrap();
+    for (index, value) in (10..15u128).enumerate() {
+        let receipt = Receipt {
+            allocation_id: allocation_ids[0],
+            timestamp_ns: timestamp_ns - 1000 + index as u64,
+            nonce: index as u64,
+            value,
+        };
+        let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
+        query_appraisals
+            .write()
+            .unwrap()
+            .insert(signed_receipt.unique_hash(), value);
+        context
+            .store_receipt(ReceiptWithState::new(signed_receipt))
+            .await
+            .unwrap();
+    }
+
+    let manager = Manager::new(
+        domain_separator.clone(),
+        ReversedReceiptStorage(context),
+        checks,
+    );
+    let rav_request = manager
+        .create_rav_request::<ReceiptAggregateVoucher>(
+            &rav_ctx(signer.address(), allocation_ids[0]),
+            0,
+            None,
+        )
+        .await
+        .unwrap();
+    assert!(rav_request.invalid_receipts.is_empty());
+    assert!(rav_request
+        .valid_receipts
+        .is_sorted_by_key(|receipt| receipt.signed_receipt().message.timestamp_ns));
+    let expected_rav = rav_request.expected_rav.unwrap();
+    assert_eq!(expected_rav.valueAggregate, 60);
+    assert_eq!(expected_rav.timestampNs, timestamp_ns - 1000 + 4);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_leaves_out_receipts_of_other_allocations_returned_by_storage(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+    } = context;
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    for allocation_id in [allocation_ids[0], allocation_ids[0], allocation_ids[1]] {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_id, 20).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        query_appraisals
+            .write()
+            .unwrap()
+            .insert(signed_receipt.unique_hash(), 20);
+        context
+            .store_receipt(ReceiptWithState::new(signed_receipt))
+            .await
+            .unwrap();
+    }
+
+    // the storage doesn't scope its retrieval to the allocation, and returns
+    // the receipt of the other allocation too
+    let manager = Manager::new(
+        domain_separator.clone(),
+        ReversedReceiptStorage(context),
+        checks,
+    );
+    let rav_request = manager
+        .create_rav_request::<ReceiptAggregateVoucher>(
+            &rav_ctx(signer.address(), allocation_ids[0]),
+            0,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(rav_request.valid_receipts.len(), 2);
+    assert!(rav_request.invalid_receipts.is_empty());
+    assert_eq!(rav_request.expected_rav.unwrap().valueAggregate, 40);
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_retryable_checks(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    struct RetryableCheck(Arc<AtomicBool>);
+
+    #[async_trait::async_trait]
+    impl Check<SignedReceipt> for RetryableCheck {
+        async fn check(
+            &self,
+            _: &Context,
+            receipt: &ReceiptWithState<Checking, SignedReceipt>,
+        ) -> Result<(), CheckError> {
+            // we want to fail only if nonce is 5 and if is create rav step
+            if self.0.load(std::sync::atomic::Ordering::SeqCst)
+                && receipt.signed_receipt().message.nonce == 5
+            {
+                Err(CheckError::Retryable(anyhow!("Retryable error")))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+
+    let is_create_rav = Arc::new(AtomicBool::new(false));
+
+    let mut checks: Vec<Arc<dyn Check<SignedReceipt> + Send + Sync>> =
+        checks.iter().cloned().collect();
+    checks.push(Arc::new(RetryableCheck(is_create_rav.clone())));
+
+    let manager = Manager::new(
+        domain_separator.clone(),
+        context.clone(),
+        CheckList::new(checks),
+    );
+
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let mut stored_signed_receipts = Vec::new();
+    for i in 0..10 {
+        let receipt = Receipt {
+            allocation_id: allocation_ids[0],
+            timestamp_ns: i + 1,
+            nonce: i,
+            value: 20u128,
+        };
+        let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
+        stored_signed_receipts.push(signed_receipt.clone());
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+
+    is_create_rav.store(true, std::sync::atomic::Ordering::SeqCst);
+
+    let rav_request = manager
+        .create_rav_request(&rav_ctx(signer.address(), allocation_ids[0]), 0, None)
+        .await;
+
+    assert_eq!(
+        rav_request.expect_err("Didn't fail").to_string(),
+        tap_core::Error::ReceiptError(tap_core::receipt::ReceiptError::RetryableCheck(
+            "Retryable error".to_string()
         ))
         .to_string()
     );
 }
+
+#[rstest]
+#[tokio::test]
+async fn manager_rejects_receipts_while_paused(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context, checks);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let value = 20u128;
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], value).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    query_appraisals
+        .write()
+        .unwrap()
+        .insert(signed_receipt.unique_hash(), value);
+
+    manager.set_paused(true);
+    let result = manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt.clone())
+        .await;
+    assert!(matches!(result, Err(tap_core::Error::IngestionPaused)));
+
+    manager.set_paused(false);
+    assert!(manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await
+        .is_ok());
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_rejects_receipts_of_unsupported_version(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context, checks)
+        .with_supported_receipt_version(Receipt::VERSION);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let versioned_receipt = |version| {
+        let value = 20u128;
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        query_appraisals
+            .write()
+            .unwrap()
+            .insert(signed_receipt.unique_hash(), value);
+        VersionedReceipt::new(version, signed_receipt)
+    };
+
+    // e.g. a v2 receipt sent to a v1 receiver
+    let result = manager
+        .verify_and_store_versioned_receipt(
+            &Context::new(),
+            versioned_receipt(Receipt::VERSION + 1),
+        )
+        .await;
+    assert!(matches!(
+        result,
+        Err(tap_core::Error::UnsupportedReceiptVersion { received, expected })
+            if received == Receipt::VERSION + 1 && expected == Receipt::VERSION
+    ));
+    assert_eq!(manager.pending_receipts(), 0);
+
+    manager
+        .verify_and_store_versioned_receipt(&Context::new(), versioned_receipt(Receipt::VERSION))
+        .await
+        .unwrap();
+    assert_eq!(manager.pending_receipts(), 1);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_skips_disabled_checks(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context, signer, ..
+    } = context;
+    // the sender has no escrow
+    let escrow_check = EscrowCheck::new(domain_separator.clone(), Arc::new(context.clone()));
+    let escrow_check_name = escrow_check.name();
+    let manager = Manager::new(
+        domain_separator.clone(),
+        context,
+        CheckList::new(vec![Arc::new(escrow_check)]),
+    );
+
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], 20).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    assert!(manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt.clone())
+        .await
+        .is_err());
+
+    manager.set_check_enabled(escrow_check_name, false).unwrap();
+    assert!(!manager.is_check_enabled(escrow_check_name));
+    manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        manager.set_check_enabled("NoSuchCheck", false),
+        Err(tap_core::Error::UnknownCheck { check_name }) if check_name == "NoSuchCheck"
+    ));
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_stores_receipt_when_shadow_check_fails(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    struct FailingCheck;
+
+    #[async_trait::async_trait]
+    impl Check<SignedReceipt> for FailingCheck {
+        async fn check(
+            &self,
+            _: &Context,
+            _: &ReceiptWithState<Checking, SignedReceipt>,
+        ) -> Result<(), CheckError> {
+            Err(CheckError::Failed(anyhow!("Shadow check failed")))
+        }
+    }
+
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks)
+        .with_shadow_checks(vec![Arc::new(FailingCheck)]);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let value = 20u128;
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], value).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    query_appraisals
+        .write()
+        .unwrap()
+        .insert(signed_receipt.unique_hash(), value);
+
+    manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await
+        .unwrap();
+
+    assert_eq!(manager.shadow_check_failures(), 1);
+    assert_eq!(
+        context
+            .retrieve_receipts_in_timestamp_range(.., None)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_selects_domain_by_chain_id(
+    allocation_ids: Vec<Address>,
+    sender_ids: (PrivateKeySigner, Vec<Address>),
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        query_appraisals,
+        escrow_storage,
+        ..
+    } = context;
+    let (signer, sender_ids) = sender_ids;
+    let context = context.with_sender_address(signer.address());
+    let verifying_contract = Address::from([0x11u8; 20]);
+    let chain_domains = HashMap::from([
+        (1, tap_eip712_domain(1, verifying_contract)),
+        (2, tap_eip712_domain(2, verifying_contract)),
+    ]);
+    let domain_separators = DomainSeparators::new(chain_domains[&1].clone());
+    let checks = get_full_list_of_checks(
+        domain_separators.clone(),
+        sender_ids.iter().cloned().collect(),
+        Arc::new(RwLock::new(allocation_ids.iter().cloned().collect())),
+        query_appraisals,
+    );
+    let manager = Manager::new(domain_separators, context, CheckList::new(checks))
+        .with_chain_domains(chain_domains.clone());
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let ctx_for_chain = |chain_id| {
+        let mut ctx = rav_ctx(signer.address(), allocation_ids[0]);
+        ctx.insert(ChainId(chain_id));
+        ctx
+    };
+
+    for chain_id in [1, 2] {
+        let signed_receipt = Eip712SignedMessage::new(
+            &chain_domains[&chain_id],
+            Receipt::new(allocation_ids[0], 20).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        manager
+            .verify_and_store_receipt(&ctx_for_chain(chain_id), signed_receipt)
+            .await
+            .unwrap();
+    }
+
+    // a receipt signed for chain 2 doesn't verify against the chain 1 domain
+    let signed_receipt = Eip712SignedMessage::new(
+        &chain_domains[&2],
+        Receipt::new(allocation_ids[0], 20).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    assert!(manager
+        .verify_and_store_receipt(&ctx_for_chain(1), signed_receipt.clone())
+        .await
+        .is_err());
+    assert!(matches!(
+        manager
+            .verify_and_store_receipt(&ctx_for_chain(3), signed_receipt)
+            .await,
+        Err(tap_core::Error::UnknownChainId { chain_id: 3 })
+    ));
+
+    let rav = ReceiptAggregateVoucher {
+        allocationId: allocation_ids[0],
+        timestampNs: 1232442,
+        valueAggregate: 20u128,
+    };
+    let signed_rav = Eip712SignedMessage::new(&chain_domains[&2], rav.clone(), &signer).unwrap();
+    assert!(manager
+        .verify_and_store_rav_with_context(&ctx_for_chain(1), rav.clone(), signed_rav.clone())
+        .await
+        .is_err());
+    manager
+        .verify_and_store_rav_with_context(&ctx_for_chain(2), rav, signed_rav.clone())
+        .await
+        .unwrap();
+
+    // the final request verifies the previous RAV against the chain domain
+    let rav_request = manager
+        .create_rav_request_final::<ReceiptAggregateVoucher>(&ctx_for_chain(2))
+        .await
+        .unwrap();
+    assert_eq!(rav_request.previous_rav, Some(signed_rav));
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_accepts_previous_domains(
+    allocation_ids: Vec<Address>,
+    sender_ids: (PrivateKeySigner, Vec<Address>),
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        query_appraisals,
+        escrow_storage,
+        ..
+    } = context;
+    let (signer, sender_ids) = sender_ids;
+    let context = context.with_sender_address(signer.address());
+    let old_domain = tap_eip712_domain(1, Address::from([0x11u8; 20]));
+    let new_domain = tap_eip712_domain(1, Address::from([0x22u8; 20]));
+    let domain_separators = DomainSeparators::new(new_domain.clone());
+    let checks = get_full_list_of_checks(
+        domain_separators.clone(),
+        sender_ids.iter().cloned().collect(),
+        Arc::new(RwLock::new(allocation_ids.iter().cloned().collect())),
+        query_appraisals,
+    );
+    let manager = Manager::new(domain_separators.clone(), context, CheckList::new(checks))
+        .with_previous_domains(vec![old_domain.clone()]);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let signed_receipt = |domain| {
+        Eip712SignedMessage::new(
+            domain,
+            Receipt::new(allocation_ids[0], 20).unwrap(),
+            &signer,
+        )
+        .unwrap()
+    };
+
+    // receipts signed against the old domain still verify during the
+    // transition, and the domain they matched is recorded
+    for domain in [&new_domain, &old_domain, &old_domain] {
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt(domain))
+            .await
+            .unwrap();
+    }
+    assert_eq!(domain_separators.match_counts(), vec![1, 2]);
+
+    // but not against any other domain
+    let unknown_domain = tap_eip712_domain(1, Address::from([0x33u8; 20]));
+    assert!(manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt(&unknown_domain))
+        .await
+        .is_err());
+
+    // nor once the transition is over
+    domain_separators.set_previous_domains(vec![]);
+    assert!(manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt(&old_domain))
+        .await
+        .is_err());
+    assert_eq!(domain_separators.match_counts(), vec![0]);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_applies_backpressure_on_pending_receipts(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager =
+        Manager::new(domain_separator.clone(), context, checks).with_max_pending_receipts(5);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let new_receipt = || {
+        Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 20).unwrap(),
+            &signer,
+        )
+        .unwrap()
+    };
+
+    for _ in 0..5 {
+        manager
+            .verify_and_store_receipt(&Context::new(), new_receipt())
+            .await
+            .unwrap();
+    }
+    assert_eq!(manager.pending_receipts(), 5);
+    assert!(matches!(
+        manager
+            .verify_and_store_receipt(&Context::new(), new_receipt())
+            .await,
+        Err(tap_core::Error::BackpressureLimitReached {
+            pending: 5,
+            limit: 5
+        })
+    ));
+
+    let rav_request = manager
+        .create_rav_request(&rav_ctx(signer.address(), allocation_ids[0]), 0, None)
+        .await
+        .unwrap();
+    let expected_rav = rav_request.expected_rav.unwrap();
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+    manager
+        .verify_and_store_rav(expected_rav, signed_rav)
+        .await
+        .unwrap();
+    assert_eq!(manager.pending_receipts(), 0);
+
+    manager
+        .verify_and_store_receipt(&Context::new(), new_receipt())
+        .await
+        .unwrap();
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_backpressure_holds_under_concurrency(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context, checks)
+        .with_max_pending_receipts(5)
+        .with_receipt_concurrency(10);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let receipts = (0..10)
+        .map(|_| {
+            Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(allocation_ids[0], 20).unwrap(),
+                &signer,
+            )
+            .unwrap()
+        })
+        .collect();
+    let results = manager
+        .verify_and_store_receipts(&Context::new(), receipts)
+        .await;
+
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 5);
+    assert_eq!(manager.pending_receipts(), 5);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_recovers_pending_receipts_from_storage(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks);
+    for _ in 0..3 {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 20).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+
+    // e.g. after a restart
+    let restarted = Manager::<_, SignedReceipt>::new(domain_separator, context, CheckList::empty())
+        .with_max_pending_receipts(3);
+    assert_eq!(restarted.pending_receipts(), 0);
+    assert_eq!(restarted.recover_pending_receipts().await.unwrap(), 3);
+    assert_eq!(restarted.pending_receipts(), 3);
+}
+
+#[rstest]
+#[tokio::test]
+async fn signature_mismatch_includes_digest_when_debugging(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+    #[values(true, false)] signature_debug: bool,
+) {
+    let ContextFixture {
+        context, checks, ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context, checks)
+        .with_signature_debug(signature_debug);
+
+    let wrong_signer = PrivateKeySigner::random();
+    let rav = ReceiptAggregateVoucher {
+        allocationId: allocation_ids[0],
+        timestampNs: 1232442,
+        valueAggregate: 20u128,
+    };
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, rav.clone(), &wrong_signer).unwrap();
+
+    let err = manager
+        .verify_and_store_rav(rav.clone(), signed_rav)
+        .await
+        .unwrap_err();
+
+    match err {
+        tap_core::Error::SignatureMismatch {
+            recovered_address,
+            digest,
+        } => {
+            assert_eq!(recovered_address, wrong_signer.address());
+            assert_eq!(
+                digest,
+                signature_debug.then(|| rav.eip712_signing_hash(&domain_separator))
+            );
+        }
+        err => panic!("Unexpected error: {err}"),
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_excludes_receipts_within_eligibility_delay(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    // receipts are backdated rather than waited on, the manager reads the
+    // system clock, which paused tokio time doesn't drive
+    let eligibility_delay_ns = 60_000_000_000;
+    let manager = Manager::new(domain_separator.clone(), context, checks)
+        .with_eligibility_delay_ns(eligibility_delay_ns);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let timestamp_ns = get_current_timestamp_u64_ns().unwrap();
+    let sign = |nonce: u64, timestamp_ns: u64| {
+        let receipt = Receipt {
+            allocation_id: allocation_ids[0],
+            timestamp_ns,
+            nonce,
+            value: 20,
+        };
+        Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap()
+    };
+    manager
+        .verify_and_store_receipt(&Context::new(), sign(0, timestamp_ns))
+        .await
+        .unwrap();
+
+    // the receipt is too fresh, even with no timestamp buffer
+    let rav_request = manager
+        .create_rav_request(&rav_ctx(signer.address(), allocation_ids[0]), 0, None)
+        .await
+        .unwrap();
+    assert!(rav_request.valid_receipts.is_empty());
+
+    // older than the delay: eligible, unlike the fresh one
+    manager
+        .verify_and_store_receipt(
+            &Context::new(),
+            sign(1, timestamp_ns - eligibility_delay_ns - 1_000),
+        )
+        .await
+        .unwrap();
+    let rav_request = manager
+        .create_rav_request(&rav_ctx(signer.address(), allocation_ids[0]), 0, None)
+        .await
+        .unwrap();
+    assert_eq!(rav_request.valid_receipts.len(), 1);
+    assert_eq!(
+        rav_request.valid_receipts[0].signed_receipt().message.nonce,
+        1
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_eligibility_delay_longer_than_the_clock(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager =
+        Manager::new(domain_separator.clone(), context, checks).with_eligibility_delay_ns(u64::MAX);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], 20).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await
+        .unwrap();
+
+    // no receipt is ever eligible
+    let rav_request = manager
+        .create_rav_request(&rav_ctx(signer.address(), allocation_ids[0]), 0, None)
+        .await
+        .unwrap();
+    assert!(rav_request.valid_receipts.is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_lists_allocations_with_pending_receipts(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context, checks);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    assert!(manager
+        .allocations_with_pending_receipts()
+        .await
+        .unwrap()
+        .is_empty());
+
+    for allocation_id in [allocation_ids[2], allocation_ids[0], allocation_ids[2]] {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_id, 20).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+
+    let mut expected = vec![allocation_ids[0], allocation_ids[2]];
+    expected.sort();
+    assert_eq!(
+        manager.allocations_with_pending_receipts().await.unwrap(),
+        expected
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_rav_request_independent_of_verification_concurrency(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+    #[values(1, 4, 32)] concurrency: usize,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context, checks)
+        .with_verification_concurrency(concurrency);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let mut stored_signed_receipts = Vec::new();
+    for value in 1..=50u128 {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        stored_signed_receipts.push(signed_receipt.clone());
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+
+    let rav_request = manager
+        .create_rav_request(&rav_ctx(signer.address(), allocation_ids[0]), 0, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        rav_request.valid_receipts.len(),
+        stored_signed_receipts.len()
+    );
+    let expected_rav: ReceiptAggregateVoucher = rav_request.expected_rav.unwrap();
+    assert_eq!(expected_rav.valueAggregate, (1..=50u128).sum::<u128>());
+    assert_eq!(
+        expected_rav.timestampNs,
+        stored_signed_receipts
+            .iter()
+            .map(|receipt| receipt.message.timestamp_ns)
+            .max()
+            .unwrap()
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_checks_receipt_without_storing_it(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context, checks);
+
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], 20).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    manager
+        .check_receipt(&Context::new(), signed_receipt)
+        .await
+        .unwrap();
+
+    let unknown_allocation = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(Address::ZERO, 20).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    assert!(matches!(
+        manager
+            .check_receipt(&Context::new(), unknown_allocation)
+            .await,
+        Err(tap_core::Error::ReceiptError(
+            tap_core::receipt::ReceiptError::InvalidAllocationID {
+                received_allocation_id: Address::ZERO
+            }
+        ))
+    ));
+
+    assert_eq!(manager.pending_receipts(), 0);
+    assert!(manager
+        .allocations_with_pending_receipts()
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn replay_window_check_keeps_a_watermark_per_sender(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context, signer, ..
+    } = context;
+    let receipt_at = |signer: &PrivateKeySigner, domain: &Eip712Domain, timestamp_ns| {
+        let receipt = Receipt {
+            allocation_id: allocation_ids[0],
+            timestamp_ns,
+            nonce: timestamp_ns,
+            value: 10,
+        };
+        ReceiptWithState::new(Eip712SignedMessage::new(domain, receipt, signer).unwrap())
+    };
+    let check = ReplayWindowCheck::new(domain_separator.clone(), Arc::new(context.clone()), 1_000);
+    let ctx = Context::new();
+
+    // checking alone doesn't raise the watermark
+    assert!(Check::<SignedReceipt>::check(
+        &check,
+        &ctx,
+        &receipt_at(&signer, &domain_separator, 10_000)
+    )
+    .await
+    .is_ok());
+    assert!(Check::<SignedReceipt>::check(
+        &check,
+        &ctx,
+        &receipt_at(&signer, &domain_separator, 5_000)
+    )
+    .await
+    .is_ok());
+
+    Check::<SignedReceipt>::commit(
+        &check,
+        &ctx,
+        &receipt_at(&signer, &domain_separator, 10_000),
+    )
+    .await;
+    // out of order, but within the tolerance
+    assert!(Check::<SignedReceipt>::check(
+        &check,
+        &ctx,
+        &receipt_at(&signer, &domain_separator, 9_500)
+    )
+    .await
+    .is_ok());
+    // clearly replayed
+    assert!(matches!(
+        Check::<SignedReceipt>::check(&check, &ctx, &receipt_at(&signer, &domain_separator, 5_000))
+            .await,
+        Err(CheckError::Failed(_))
+    ));
+    // the watermark is kept per sender
+    let other_signer = PrivateKeySigner::random();
+    assert!(Check::<SignedReceipt>::check(
+        &check,
+        &ctx,
+        &receipt_at(&other_signer, &domain_separator, 5_000)
+    )
+    .await
+    .is_ok());
+
+    // forgotten once a RAV of the sender covers it
+    Check::<SignedReceipt>::rav_stored(&check, signer.address(), allocation_ids[0], 9_999).await;
+    assert_eq!(check.len(), 1);
+    Check::<SignedReceipt>::rav_stored(&check, signer.address(), allocation_ids[0], 10_000).await;
+    assert!(check.is_empty());
+
+    // signers of the same sender share its watermark, whatever the domain
+    // they signed with
+    let old_domain = tap_eip712_domain(1, Address::from([0x22u8; 20]));
+    let domain_separators = DomainSeparators::new(domain_separator.clone());
+    domain_separators.set_previous_domains(vec![old_domain.clone()]);
+    let check = ReplayWindowCheck::new(
+        domain_separators,
+        Arc::new(DelegatedSigner {
+            context: context.with_sender_address(other_signer.address()),
+            sender: signer.address(),
+            checked_signatures: Arc::new(Mutex::new(0)),
+        }),
+        1_000,
+    );
+    Check::<SignedReceipt>::commit(
+        &check,
+        &ctx,
+        &receipt_at(&other_signer, &old_domain, 10_000),
+    )
+    .await;
+    assert!(matches!(
+        Check::<SignedReceipt>::check(&check, &ctx, &receipt_at(&signer, &domain_separator, 5_000))
+            .await,
+        Err(CheckError::Failed(_))
+    ));
+}
+
+#[rstest]
+#[tokio::test]
+async fn allocation_authorization_check_resolves_the_sender(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context, signer, ..
+    } = context;
+    let unauthorized_signer = PrivateKeySigner::random();
+    let receipt = |signer: &PrivateKeySigner, domain: &Eip712Domain| {
+        ReceiptWithState::new(
+            Eip712SignedMessage::new(domain, Receipt::new(allocation_ids[0], 10).unwrap(), signer)
+                .unwrap(),
+        )
+    };
+    let authorizations = HashSet::from([(allocation_ids[0], signer.address())]);
+    let resolver = move |allocation_id, sender| {
+        let authorized = authorizations.contains(&(allocation_id, sender));
+        async move { Ok(authorized) }
+    };
+    let check = AllocationAuthorizationCheck::new(
+        domain_separator.clone(),
+        Arc::new(context.clone()),
+        resolver.clone(),
+    );
+    let ctx = Context::new();
+
+    assert!(
+        Check::<SignedReceipt>::check(&check, &ctx, &receipt(&signer, &domain_separator))
+            .await
+            .is_ok()
+    );
+    assert!(matches!(
+        Check::<SignedReceipt>::check(
+            &check,
+            &ctx,
+            &receipt(&unauthorized_signer, &domain_separator)
+        )
+        .await,
+        Err(CheckError::Failed(_))
+    ));
+    // the sender of the RAV key found in the context prevails
+    assert!(Check::<SignedReceipt>::check(
+        &check,
+        &rav_ctx(signer.address(), allocation_ids[0]),
+        &receipt(&unauthorized_signer, &domain_separator)
+    )
+    .await
+    .is_ok());
+
+    // a signer of the sender, signing with a previous domain, is authorized
+    let old_domain = tap_eip712_domain(1, Address::from([0x22u8; 20]));
+    let domain_separators = DomainSeparators::new(domain_separator.clone());
+    domain_separators.set_previous_domains(vec![old_domain.clone()]);
+    let check = AllocationAuthorizationCheck::new(
+        domain_separators,
+        Arc::new(DelegatedSigner {
+            context: context
+                .clone()
+                .with_sender_address(unauthorized_signer.address()),
+            sender: signer.address(),
+            checked_signatures: Arc::new(Mutex::new(0)),
+        }),
+        resolver,
+    );
+    assert!(Check::<SignedReceipt>::check(
+        &check,
+        &ctx,
+        &receipt(&unauthorized_signer, &old_domain)
+    )
+    .await
+    .is_ok());
+
+    // the resolver being unreachable doesn't decline the receipt for good
+    let check = AllocationAuthorizationCheck::new(
+        domain_separator.clone(),
+        Arc::new(context),
+        |_, _| async { Err(anyhow!("resolver unreachable")) },
+    );
+    assert!(matches!(
+        Check::<SignedReceipt>::check(&check, &ctx, &receipt(&signer, &domain_separator)).await,
+        Err(CheckError::Retryable(_))
+    ));
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_commits_stateful_checks_once_all_checks_pass(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context, signer, ..
+    } = context;
+    let checks = CheckList::new(vec![
+        ReplayWindowCheck::boxed(domain_separator.clone(), Arc::new(context.clone()), 1_000),
+        NonZeroValueCheck::boxed(),
+    ]);
+    let manager = Manager::new(domain_separator.clone(), context, checks);
+
+    let timestamp_ns = get_current_timestamp_u64_ns().unwrap();
+    let sign = |nonce: u64, timestamp_ns: u64, value: u128| {
+        let receipt = Receipt {
+            allocation_id: allocation_ids[0],
+            timestamp_ns,
+            nonce,
+            value,
+        };
+        Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap()
+    };
+    // rejected by the last check, the watermark stays put
+    assert!(manager
+        .verify_and_store_receipt(&Context::new(), sign(0, timestamp_ns + 1_000_000, 0))
+        .await
+        .is_err());
+    manager
+        .verify_and_store_receipt(&Context::new(), sign(1, timestamp_ns, 20))
+        .await
+        .unwrap();
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_commits_stateful_checks_once_stored(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context, signer, ..
+    } = context;
+    let replay_window_check =
+        ReplayWindowCheck::boxed(domain_separator.clone(), Arc::new(context.clone()), 1_000);
+    let full_manager = Manager::new(
+        domain_separator.clone(),
+        context.clone().with_receipt_capacity(0),
+        CheckList::new(vec![replay_window_check.clone()]),
+    );
+    let manager = Manager::new(
+        domain_separator.clone(),
+        context,
+        CheckList::new(vec![replay_window_check]),
+    );
+
+    let timestamp_ns = get_current_timestamp_u64_ns().unwrap();
+    let sign = |nonce: u64, timestamp_ns: u64| {
+        let receipt = Receipt {
+            allocation_id: allocation_ids[0],
+            timestamp_ns,
+            nonce,
+            value: 20,
+        };
+        Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap()
+    };
+    // not stored, the watermark stays put
+    assert!(matches!(
+        full_manager
+            .verify_and_store_receipt(&Context::new(), sign(0, timestamp_ns + 1_000_000))
+            .await,
+        Err(tap_core::Error::StorageQuotaExceeded { .. })
+    ));
+    manager
+        .verify_and_store_receipt(&Context::new(), sign(1, timestamp_ns))
+        .await
+        .unwrap();
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_check_receipt_skips_stateful_checks(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+    let checks = CheckList::new(
+        checks
+            .iter()
+            .cloned()
+            .chain([DedupCheck::boxed(
+                domain_separator.clone(),
+                Arc::new(context.clone()),
+                DedupScope::Global,
+            )])
+            .collect(),
+    );
+    let manager = Manager::new(domain_separator.clone(), context, checks);
+
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], 20).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    // probing the receipt doesn't get it seen by the dedup check
+    for _ in 0..2 {
+        manager
+            .check_receipt(&Context::new(), signed_receipt.clone())
+            .await
+            .unwrap();
+    }
+    manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt.clone())
+        .await
+        .unwrap();
+    assert!(manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await
+        .is_err());
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_dedup_check_forgets_receipts_covered_by_rav(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+    let dedup_check = Arc::new(DedupCheck::new(
+        domain_separator.clone(),
+        Arc::new(context.clone()),
+        DedupScope::PerSender,
+    ));
+
+    // the same message signed by two senders makes two receipts, but a
+    // single sender can't replay
+    let message = Receipt::new(allocation_ids[0], 20).unwrap();
+    let other_signer = PrivateKeySigner::random();
+    let receipt = |signer: &PrivateKeySigner| {
+        ReceiptWithState::new(
+            Eip712SignedMessage::new(&domain_separator, message.clone(), signer).unwrap(),
+        )
+    };
+    assert!(dedup_check
+        .check(&Context::new(), &receipt(&signer))
+        .await
+        .is_ok());
+    assert!(dedup_check
+        .check(&Context::new(), &receipt(&other_signer))
+        .await
+        .is_ok());
+    assert!(matches!(
+        dedup_check.check(&Context::new(), &receipt(&signer)).await,
+        Err(CheckError::Failed(_))
+    ));
+
+    // a RAV of one sender only forgets the receipts it covers
+    Check::<SignedReceipt>::rav_stored(
+        dedup_check.as_ref(),
+        signer.address(),
+        allocation_ids[0],
+        message.timestamp_ns,
+    )
+    .await;
+    assert_eq!(dedup_check.len(), 1);
+
+    // through the manager, the receipts are forgotten once the RAV is stored
+    let manager = Manager::new(
+        domain_separator.clone(),
+        context,
+        CheckList::new(
+            checks
+                .iter()
+                .cloned()
+                .chain([dedup_check.clone() as ReceiptCheck<SignedReceipt>])
+                .collect(),
+        ),
+    );
+    manager
+        .verify_and_store_receipt(&Context::new(), receipt(&signer).signed_receipt().clone())
+        .await
+        .unwrap();
+    assert_eq!(dedup_check.len(), 2);
+    let ctx = rav_ctx(signer.address(), allocation_ids[0]);
+    let expected_rav = manager
+        .create_rav_request(&ctx, 0, None)
+        .await
+        .unwrap()
+        .expected_rav
+        .unwrap();
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+    manager
+        .verify_and_store_rav(expected_rav, signed_rav)
+        .await
+        .unwrap();
+    assert_eq!(dedup_check.len(), 1);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_stores_receipts_sent_through_ingestion_channel(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Arc::new(Manager::new(domain_separator.clone(), context, checks));
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    assert!(manager.ingest_sender().is_none());
+    let handle = manager.start_ingestion(4, 3).unwrap();
+    assert!(matches!(
+        manager.start_ingestion(4, 3),
+        Err(tap_core::Error::IngestionAlreadyStarted)
+    ));
+
+    let sender = manager.ingest_sender().unwrap();
+    for value in 1..=10 {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        sender.send(signed_receipt).await.unwrap();
+    }
+
+    tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        while manager.pending_receipts() < 10 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("Receipts were not stored in time");
+
+    // the task stops once the manager is gone
+    drop(sender);
+    drop(manager);
+    handle.await.unwrap();
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_rejects_corrupt_previous_rav(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        signer,
+        ..
+    } = context;
+
+    // a RAV that the authorized signer never signed
+    let forged_rav = Eip712SignedMessage::new(
+        &domain_separator,
+        ReceiptAggregateVoucher {
+            allocationId: allocation_ids[0],
+            timestampNs: 1,
+            valueAggregate: 1_000_000,
+        },
+        &PrivateKeySigner::random(),
+    )
+    .unwrap();
+    context
+        .update_last_rav(signer.address(), allocation_ids[0], forged_rav)
+        .await
+        .unwrap();
+
+    let manager = Manager::new(domain_separator.clone(), context, checks);
+    let result = manager
+        .create_rav_request::<ReceiptAggregateVoucher>(
+            &rav_ctx(signer.address(), allocation_ids[0]),
+            0,
+            None,
+        )
+        .await;
+    assert!(matches!(
+        result,
+        Err(tap_core::Error::CorruptPreviousRav { .. })
+    ));
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_chains_on_previous_rav_of_same_allocation(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        signer,
+        ..
+    } = context;
+
+    let previous_rav = Eip712SignedMessage::new(
+        &domain_separator,
+        ReceiptAggregateVoucher {
+            allocationId: allocation_ids[1],
+            timestampNs: 1,
+            valueAggregate: 100,
+        },
+        &signer,
+    )
+    .unwrap();
+    context
+        .update_last_rav(signer.address(), allocation_ids[1], previous_rav.clone())
+        .await
+        .unwrap();
+
+    let manager = Manager::new(domain_separator.clone(), context, checks);
+    let rav_request = manager
+        .create_rav_request::<ReceiptAggregateVoucher>(
+            &rav_ctx(signer.address(), allocation_ids[1]),
+            0,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(rav_request.previous_rav, Some(previous_rav));
+
+    // the RAV of another allocation isn't chained on
+    let rav_request = manager
+        .create_rav_request::<ReceiptAggregateVoucher>(
+            &rav_ctx(signer.address(), allocation_ids[0]),
+            0,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(rav_request.previous_rav, None);
+
+    assert!(matches!(
+        manager
+            .create_rav_request::<ReceiptAggregateVoucher>(&Context::new(), 0, None)
+            .await,
+        Err(tap_core::Error::MissingRavKey)
+    ));
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_lists_ravs_in_range(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        signer,
+        ..
+    } = context;
+
+    // the RAV of timestamp 10 is replaced by the one of timestamp 20
+    for (allocation_id, timestamp_ns) in [
+        (allocation_ids[0], 10),
+        (allocation_ids[0], 20),
+        (allocation_ids[1], 30),
+    ] {
+        let rav = Eip712SignedMessage::new(
+            &domain_separator,
+            ReceiptAggregateVoucher {
+                allocationId: allocation_id,
+                timestampNs: timestamp_ns,
+                valueAggregate: 100,
+            },
+            &signer,
+        )
+        .unwrap();
+        context
+            .update_last_rav(signer.address(), allocation_id, rav)
+            .await
+            .unwrap();
+    }
+
+    let manager = Manager::new(domain_separator, context, checks);
+    let timestamps = |ravs: Vec<SignedRav>| {
+        ravs.into_iter()
+            .map(|rav| rav.message.timestampNs)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        timestamps(manager.list_ravs_in_range(..=20).await.unwrap()),
+        vec![10, 20]
+    );
+    assert_eq!(
+        timestamps(manager.list_ravs_in_range(15..).await.unwrap()),
+        vec![20, 30]
+    );
+    assert!(manager
+        .list_ravs_in_range::<ReceiptAggregateVoucher>(31..)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn escrow_check_subtracts_committed_rav_value(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    signer: PrivateKeySigner,
+    #[values(false, true)] subtract_committed: bool,
+) {
+    let escrow_storage = Arc::new(RwLock::new(HashMap::from([(signer.address(), 100)])));
+    // 80 of the 100 in escrow are already committed to a RAV
+    let rav = Eip712SignedMessage::new(
+        &domain_separator,
+        ReceiptAggregateVoucher {
+            allocationId: allocation_ids[0],
+            timestampNs: 1,
+            valueAggregate: 80,
+        },
+        &signer,
+    )
+    .unwrap();
+    let rav_storage = Arc::new(RwLock::new(HashMap::from([(
+        (signer.address(), allocation_ids[0]),
+        rav,
+    )])));
+    let context = InMemoryContext::new(
+        rav_storage.clone(),
+        Arc::new(RwLock::new(HashMap::new())),
+        escrow_storage,
+        Arc::new(StatefulTimestampCheck::new(0)),
+    );
+    let mut escrow_check = EscrowCheck::new(domain_separator.clone(), Arc::new(context.clone()));
+    if subtract_committed {
+        escrow_check = escrow_check.with_rav_storage(rav_storage);
+    }
+    let manager = Manager::new(
+        domain_separator.clone(),
+        context,
+        CheckList::new(vec![Arc::new(escrow_check)]),
+    );
+
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], 30).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    let result = manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await;
+    if subtract_committed {
+        assert!(matches!(
+            result,
+            Err(tap_core::Error::ReceiptError(
+                tap_core::receipt::ReceiptError::NotEnoughEscrow {
+                    available_escrow: 20,
+                    received_value: 30,
+                    ..
+                }
+            ))
+        ));
+    } else {
+        result.unwrap();
+    }
+}
+
+#[rstest]
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn escrow_reservations_never_exceed_balance(context: ContextFixture) {
+    let ContextFixture {
+        context,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let sender = signer.address();
+    escrow_storage.write().unwrap().insert(sender, 100);
+
+    // only 3 reservations of 30 fit in an escrow of 100
+    let barrier = Arc::new(tokio::sync::Barrier::new(10));
+    let tasks: Vec<_> = (0..10)
+        .map(|_| {
+            let context = context.clone();
+            let barrier = barrier.clone();
+            tokio::spawn(async move {
+                barrier.wait().await;
+                context.try_reserve(sender, 30).await.unwrap()
+            })
+        })
+        .collect();
+    let mut reserved = 0;
+    for task in tasks {
+        if task.await.unwrap() {
+            reserved += 1;
+        }
+    }
+    assert_eq!(reserved, 3);
+    assert_eq!(context.available_escrow(sender).await.unwrap(), 10);
+    // reservations are kept apart from the balance
+    assert_eq!(context.escrow(sender).unwrap(), 100);
+
+    // a failed receipt gives its reservation back
+    context.release(sender, 30).await.unwrap();
+    assert!(context.try_reserve(sender, 40).await.unwrap());
+    assert!(!context.try_reserve(sender, 1).await.unwrap());
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_reserves_escrow_per_token(
+    context: ContextFixture,
+    domain_separator: Eip712Domain,
+    allocation_ids: Vec<Address>,
+) {
+    // Stores v3 receipts, which carry the token they are denominated in
+    #[derive(Default)]
+    struct TokenReceiptStorage(Mutex<Vec<tap_graph::v3::SignedReceipt>>);
+
+    #[async_trait::async_trait]
+    impl ReceiptStore<tap_graph::v3::SignedReceipt> for TokenReceiptStorage {
+        type AdapterError = InMemoryError;
+
+        async fn store_receipt(
+            &self,
+            receipt: ReceiptWithState<Checking, tap_graph::v3::SignedReceipt>,
+        ) -> Result<u64, Self::AdapterError> {
+            let mut receipts = self.0.lock().unwrap();
+            receipts.push(receipt.signed_receipt().clone());
+            Ok(receipts.len() as u64)
+        }
+    }
+
+    let ContextFixture {
+        context,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let sender = signer.address();
+    let token_a = Address::from([0xaa; 20]);
+    let token_b = Address::from([0xbb; 20]);
+    // the sender deposited in both tokens, with different amounts, and
+    // nothing without a token
+    let token_escrow_storage = Arc::new(RwLock::new(HashMap::from([
+        ((sender, token_a), 100),
+        ((sender, token_b), 10),
+    ])));
+    let escrow = Arc::new(context.with_token_escrow_storage(token_escrow_storage.clone()));
+    let manager = Manager::<_, tap_graph::v3::SignedReceipt>::new(
+        domain_separator.clone(),
+        TokenReceiptStorage::default(),
+        CheckList::new(vec![TokenEscrowCheck::boxed(
+            domain_separator.clone(),
+            escrow.clone(),
+        )]),
+    );
+    let receipt = |token, value| {
+        Eip712SignedMessage::new(
+            &domain_separator,
+            tap_graph::v3::Receipt::new(
+                allocation_ids[0],
+                sender,
+                Address::ZERO,
+                Address::ZERO,
+                token,
+                value,
+            )
+            .unwrap(),
+            &signer,
+        )
+        .unwrap()
+    };
+    let ctx = Context::new();
+
+    manager
+        .verify_and_store_receipt(&ctx, receipt(token_a, 50))
+        .await
+        .unwrap();
+    manager
+        .verify_and_store_receipt(&ctx, receipt(token_b, 10))
+        .await
+        .unwrap();
+    // the token A escrow left doesn't cover token B receipts
+    let result = manager
+        .verify_and_store_receipt(&ctx, receipt(token_b, 20))
+        .await;
+    assert!(
+        matches!(
+            &result,
+            Err(tap_core::Error::ReceiptError(
+                tap_core::receipt::ReceiptError::NotEnoughEscrow {
+                    available_escrow: 0,
+                    received_value: 20,
+                    ..
+                }
+            ))
+        ),
+        "{result:?}"
+    );
+    assert_eq!(manager.escrow_insufficient_rejections(), 1);
+
+    assert_eq!(
+        escrow
+            .available_escrow_in_token(sender, token_a)
+            .await
+            .unwrap(),
+        50
+    );
+    assert_eq!(
+        escrow
+            .available_escrow_in_token(sender, token_b)
+            .await
+            .unwrap(),
+        0
+    );
+    assert!(escrow_storage.read().unwrap().is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn escrow_check_releases_reservations(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    struct FailingCheck;
+
+    #[async_trait::async_trait]
+    impl Check<SignedReceipt> for FailingCheck {
+        async fn check(
+            &self,
+            _: &Context,
+            _: &ReceiptWithState<Checking, SignedReceipt>,
+        ) -> Result<(), CheckError> {
+            Err(CheckError::Failed(anyhow!("rejected")))
+        }
+    }
+
+    let ContextFixture {
+        context,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let sender = signer.address();
+    escrow_storage.write().unwrap().insert(sender, 20);
+    let receipt = || {
+        Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 20).unwrap(),
+            &signer,
+        )
+        .unwrap()
+    };
+
+    // the receipt reserves the escrow, then a later check rejects it
+    let manager = Manager::new(
+        domain_separator.clone(),
+        context.clone(),
+        CheckList::new(vec![
+            EscrowCheck::boxed(domain_separator.clone(), Arc::new(context.clone())),
+            Arc::new(FailingCheck),
+        ]),
+    );
+    assert!(manager
+        .verify_and_store_receipt(&Context::new(), receipt())
+        .await
+        .is_err());
+    assert_eq!(context.available_escrow(sender).await.unwrap(), 20);
+
+    let manager = Manager::new(
+        domain_separator.clone(),
+        context.clone(),
+        CheckList::new(vec![EscrowCheck::boxed(
+            domain_separator.clone(),
+            Arc::new(context.clone()),
+        )]),
+    );
+    manager
+        .verify_and_store_receipt(&Context::new(), receipt())
+        .await
+        .unwrap();
+    assert_eq!(context.available_escrow(sender).await.unwrap(), 0);
+
+    // the RAV aggregating the receipt commits its escrow instead
+    let rav = Eip712SignedMessage::new(
+        &domain_separator,
+        ReceiptAggregateVoucher {
+            allocationId: allocation_ids[0],
+            timestampNs: 1,
+            valueAggregate: 20,
+        },
+        &signer,
+    )
+    .unwrap();
+    context
+        .update_last_rav(sender, allocation_ids[0], rav)
+        .await
+        .unwrap();
+    assert_eq!(context.available_escrow(sender).await.unwrap(), 20);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_evicts_receipts_older_than_age(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let day_ns = 24 * 60 * 60 * 1_000_000_000u64;
+    let now_ns = get_current_timestamp_u64_ns().unwrap();
+    let timestamps = [
+        now_ns - 10 * day_ns,
+        now_ns,
+        now_ns - 8 * day_ns,
+        now_ns - day_ns,
+    ];
+    for (nonce, timestamp_ns) in (0..).zip(timestamps) {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt {
+                allocation_id: allocation_ids[0],
+                timestamp_ns,
+                nonce,
+                value: 20,
+            },
+            &signer,
+        )
+        .unwrap();
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+
+    let removed = manager.evict_receipts_older_than(7 * day_ns).await.unwrap();
+    assert_eq!(removed, 2);
+    assert_eq!(manager.pending_receipts(), 2);
+
+    let remaining = context
+        .retrieve_receipts_in_timestamp_range(.., None)
+        .await
+        .unwrap();
+    assert_eq!(remaining.len(), 2);
+    assert!(remaining
+        .iter()
+        .all(|receipt| receipt.signed_receipt().message.timestamp_ns >= now_ns - day_ns));
+}
+
+#[rstest]
+#[tokio::test]
+async fn evicted_receipts_release_their_escrow(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 100);
+    let manager = Manager::new(
+        domain_separator.clone(),
+        context.clone(),
+        CheckList::new(vec![EscrowCheck::boxed(
+            domain_separator.clone(),
+            Arc::new(context.clone()),
+        )]),
+    );
+
+    let day_ns = 24 * 60 * 60 * 1_000_000_000u64;
+    let now_ns = get_current_timestamp_u64_ns().unwrap();
+    let receipt = |timestamp_ns, nonce| {
+        Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt {
+                allocation_id: allocation_ids[0],
+                timestamp_ns,
+                nonce,
+                value: 100,
+            },
+            &signer,
+        )
+        .unwrap()
+    };
+    manager
+        .verify_and_store_receipt(&Context::new(), receipt(now_ns - 10 * day_ns, 0))
+        .await
+        .unwrap();
+    // the old receipt reserves the whole balance
+    assert!(matches!(
+        manager
+            .verify_and_store_receipt(&Context::new(), receipt(now_ns, 1))
+            .await,
+        Err(tap_core::Error::ReceiptError(
+            tap_core::receipt::ReceiptError::NotEnoughEscrow { .. }
+        ))
+    ));
+
+    assert_eq!(
+        manager.evict_receipts_older_than(7 * day_ns).await.unwrap(),
+        1
+    );
+    assert_eq!(
+        context.available_escrow(signer.address()).await.unwrap(),
+        100
+    );
+    manager
+        .verify_and_store_receipt(&Context::new(), receipt(now_ns, 1))
+        .await
+        .unwrap();
+}
+
+struct RecordingSink {
+    ravs: Mutex<Vec<SignedRav>>,
+    fail: bool,
+}
+
+#[async_trait::async_trait]
+impl RavSink for RecordingSink {
+    async fn on_rav_signed(&self, rav: &StoredRav) -> anyhow::Result<()> {
+        self.ravs.lock().unwrap().push(rav.decode()?);
+        if self.fail {
+            return Err(anyhow!("Queue unavailable"));
+        }
+        Ok(())
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_notifies_rav_sink_after_storing_rav(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+    #[values(false, true)] fail: bool,
+    #[values(false, true)] required: bool,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let sink = Arc::new(RecordingSink {
+        ravs: Mutex::new(vec![]),
+        fail,
+    });
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks);
+    let manager = if required {
+        manager.with_required_rav_sink(sink.clone())
+    } else {
+        manager.with_rav_sink(sink.clone())
+    };
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], 20).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await
+        .unwrap();
+    let rav_request = manager
+        .create_rav_request(&rav_ctx(signer.address(), allocation_ids[0]), 0, None)
+        .await
+        .unwrap();
+    let expected_rav = rav_request.expected_rav.unwrap();
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+
+    // a failing sink doesn't prevent the RAV from being stored, only the
+    // failure of a required one is returned
+    let result = manager
+        .verify_and_store_rav(expected_rav, signed_rav.clone())
+        .await;
+    if fail && required {
+        assert!(matches!(result, Err(tap_core::Error::RavSinkFailed { .. })));
+    } else {
+        result.unwrap();
+    }
+    assert_eq!(*sink.ravs.lock().unwrap(), vec![signed_rav.clone()]);
+    assert_eq!(
+        context
+            .get_last_rav(signer.address(), allocation_ids[0])
+            .await
+            .unwrap(),
+        Some(signed_rav)
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_refuses_unprofitable_rav(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager =
+        Manager::new(domain_separator.clone(), context, checks).with_min_profitable_rav_value(100);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    for value in [40, 60] {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+
+        let result = manager
+            .create_rav_request(&rav_ctx(signer.address(), allocation_ids[0]), 0, None)
+            .await;
+        if value == 40 {
+            assert!(matches!(
+                result,
+                Err(tap_core::Error::RavBelowProfitabilityThreshold {
+                    rav_value: 40,
+                    min_profitable_value: 100,
+                })
+            ));
+        } else {
+            let expected_rav: ReceiptAggregateVoucher = result.unwrap().expected_rav.unwrap();
+            assert_eq!(expected_rav.valueAggregate, 100);
+        }
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_rav_request_validates_aggregate(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context, checks);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    for value in [40, 60] {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+
+    let mut rav_request = manager
+        .create_rav_request::<ReceiptAggregateVoucher>(
+            &rav_ctx(signer.address(), allocation_ids[0]),
+            0,
+            None,
+        )
+        .await
+        .unwrap();
+    rav_request.validate_aggregate().unwrap();
+
+    rav_request.expected_rav.as_mut().unwrap().valueAggregate += 1;
+    assert!(matches!(
+        rav_request.validate_aggregate(),
+        Err(tap_core::Error::AggregateMismatch {
+            expected_rav_value: 101,
+            recomputed_value: 100,
+        })
+    ));
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_rav_request_escrow_delta(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let previous_rav = Eip712SignedMessage::new(
+        &domain_separator,
+        ReceiptAggregateVoucher {
+            allocationId: allocation_ids[1],
+            timestampNs: 1,
+            valueAggregate: 100,
+        },
+        &signer,
+    )
+    .unwrap();
+    context
+        .update_last_rav(signer.address(), allocation_ids[1], previous_rav)
+        .await
+        .unwrap();
+
+    let manager = Manager::new(domain_separator.clone(), context, checks);
+    for allocation_id in &allocation_ids[..2] {
+        for value in [40, 60] {
+            let signed_receipt = Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(*allocation_id, value).unwrap(),
+                &signer,
+            )
+            .unwrap();
+            manager
+                .verify_and_store_receipt(&Context::new(), signed_receipt)
+                .await
+                .unwrap();
+        }
+    }
+
+    // without a previous RAV, the whole value is committed
+    let rav_request = manager
+        .create_rav_request::<ReceiptAggregateVoucher>(
+            &rav_ctx(signer.address(), allocation_ids[0]),
+            0,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        rav_request.expected_rav.as_ref().unwrap().valueAggregate,
+        100
+    );
+    assert_eq!(rav_request.escrow_delta(), 100);
+
+    // with one, only the value on top of it is
+    let rav_request = manager
+        .create_rav_request::<ReceiptAggregateVoucher>(
+            &rav_ctx(signer.address(), allocation_ids[1]),
+            0,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        rav_request.expected_rav.as_ref().unwrap().valueAggregate,
+        200
+    );
+    assert_eq!(rav_request.escrow_delta(), 100);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_detects_timestamp_gaps(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks);
+
+    let store = |allocation_id: Address, timestamp_ns: u64, signer: PrivateKeySigner| {
+        let context = context.clone();
+        let domain_separator = domain_separator.clone();
+        async move {
+            let mut receipt = Receipt::new(allocation_id, 10).unwrap();
+            receipt.timestamp_ns = timestamp_ns;
+            let signed_receipt =
+                Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
+            context
+                .store_receipt_in_domain(ReceiptWithState::new(signed_receipt), &domain_separator)
+                .await
+                .unwrap();
+        }
+    };
+    // a deliberate gap between 300 and 1_000, stored out of order
+    for timestamp_ns in [1_000, 100, 300, 200, 1_100] {
+        store(allocation_ids[0], timestamp_ns, signer.clone()).await;
+    }
+    // neither other allocations nor other senders fill or open gaps
+    store(allocation_ids[1], 600, signer.clone()).await;
+    store(allocation_ids[0], 5_000, PrivateKeySigner::random()).await;
+
+    assert_eq!(
+        manager
+            .detect_timestamp_gaps(signer.address(), allocation_ids[0], 100)
+            .await
+            .unwrap(),
+        vec![(300, 1_000)]
+    );
+    assert!(manager
+        .detect_timestamp_gaps(signer.address(), allocation_ids[0], 700)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_samples_valid_receipts_of_large_rav_requests(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager =
+        Manager::new(domain_separator.clone(), context, checks).with_valid_receipts_sample_size(3);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    for value in 1..=10 {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+
+    let rav_request = manager
+        .create_rav_request::<ReceiptAggregateVoucher>(
+            &rav_ctx(signer.address(), allocation_ids[0]),
+            0,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(rav_request.valid_receipts.len(), 3);
+    assert_eq!(rav_request.omitted_receipts, 7);
+    let sampled_value: u128 = rav_request
+        .valid_receipts
+        .iter()
+        .map(|receipt| receipt.signed_receipt().message.value)
+        .sum();
+    assert_eq!(rav_request.omitted_value + sampled_value, 55);
+    rav_request.validate_aggregate().unwrap();
+
+    // every receipt is aggregated, and removed once the RAV is stored
+    let expected_rav = rav_request.expected_rav.unwrap();
+    assert_eq!(expected_rav.valueAggregate, 55);
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+    manager
+        .verify_and_store_rav(expected_rav, signed_rav)
+        .await
+        .unwrap();
+    manager
+        .remove_obsolete_receipts::<ReceiptAggregateVoucher>(&rav_ctx(
+            signer.address(),
+            allocation_ids[0],
+        ))
+        .await
+        .unwrap();
+    assert!(manager
+        .allocations_with_pending_receipts()
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(manager.pending_receipts(), 0);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_caps_rav_timestamp_range(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager =
+        Manager::new(domain_separator.clone(), context, checks).with_max_rav_range_ns(1_500);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    // two bursts of receipts, further apart than the range of a RAV
+    let start_timestamp_ns = get_current_timestamp_u64_ns().unwrap() - 20_000_000;
+    for offset in [0, 1_000, 10_000_000, 10_001_000] {
+        let mut receipt = Receipt::new(allocation_ids[0], 10).unwrap();
+        receipt.timestamp_ns = start_timestamp_ns + offset;
+        let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+
+    // the range starts at the earliest pending receipt, each RAV covers a
+    // single burst and the gap between them doesn't stall the second RAV
+    let mut value_aggregates = vec![];
+    for _ in 0..2 {
+        let rav_request = manager
+            .create_rav_request::<ReceiptAggregateVoucher>(
+                &rav_ctx(signer.address(), allocation_ids[0]),
+                0,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(rav_request.valid_receipts.len(), 2);
+        let expected_rav = rav_request.expected_rav.unwrap();
+        value_aggregates.push(expected_rav.valueAggregate);
+        let signed_rav =
+            Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+        manager
+            .verify_and_store_rav(expected_rav, signed_rav)
+            .await
+            .unwrap();
+    }
+    assert_eq!(value_aggregates, vec![20, 40]);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_recovers_last_rav_from_storage(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture { checks, signer, .. } = context;
+
+    // RAVs of two allocations stored before the restart
+    let rav_timestamp_ns = get_current_timestamp_u64_ns().unwrap();
+    let rav_at = |allocation_id, timestamp_ns| {
+        Eip712SignedMessage::new(
+            &domain_separator,
+            ReceiptAggregateVoucher {
+                allocationId: allocation_id,
+                timestampNs: timestamp_ns,
+                valueAggregate: 100,
+            },
+            &signer,
+        )
+        .unwrap()
+    };
+    let context = InMemoryContext::new(
+        Arc::new(RwLock::new(HashMap::from([
+            (
+                (signer.address(), allocation_ids[0]),
+                rav_at(allocation_ids[0], rav_timestamp_ns),
+            ),
+            (
+                (signer.address(), allocation_ids[1]),
+                rav_at(allocation_ids[1], rav_timestamp_ns - 1_000),
+            ),
+        ]))),
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(StatefulTimestampCheck::new(0)),
+    )
+    .with_sender_address(signer.address());
+
+    let manager = Manager::new_recovering::<ReceiptAggregateVoucher>(
+        domain_separator.clone(),
+        context,
+        checks,
+    )
+    .await
+    .unwrap();
+    // the floors are kept per key, not raised for every key
+    assert_eq!(manager.min_receipt_timestamp_ns(), 0);
+
+    let receipt_at = |allocation_id, timestamp_ns| {
+        let mut receipt = Receipt::new(allocation_id, 10).unwrap();
+        receipt.timestamp_ns = timestamp_ns;
+        Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap()
+    };
+    // already covered by the recovered RAV of the first allocation
+    let ctx = rav_ctx(signer.address(), allocation_ids[0]);
+    assert!(matches!(
+        manager
+            .verify_and_store_receipt(&ctx, receipt_at(allocation_ids[0], rav_timestamp_ns))
+            .await,
+        Err(tap_core::Error::ReceiptTimestampLowerThanRav { rav_ts, .. }) if rav_ts == rav_timestamp_ns
+    ));
+    manager
+        .verify_and_store_receipt(&ctx, receipt_at(allocation_ids[0], rav_timestamp_ns + 1))
+        .await
+        .unwrap();
+    // newer than the recovered RAV of the second allocation
+    let ctx = rav_ctx(signer.address(), allocation_ids[1]);
+    manager
+        .verify_and_store_receipt(&ctx, receipt_at(allocation_ids[1], rav_timestamp_ns))
+        .await
+        .unwrap();
+    assert!(matches!(
+        manager
+            .verify_and_store_receipt(
+                &ctx,
+                receipt_at(allocation_ids[1], rav_timestamp_ns - 1_000)
+            )
+            .await,
+        Err(tap_core::Error::ReceiptTimestampLowerThanRav { .. })
+    ));
+    assert_eq!(manager.pending_receipts(), 2);
+}
+
+#[rstest]
+#[case(99, false)]
+#[case(100, true)]
+#[case(101, true)]
+fn rav_worth_redeeming(#[case] rav_value: u128, #[case] expected: bool) {
+    assert_eq!(is_rav_worth_redeeming(rav_value, 100), expected);
+}
+
+#[rstest]
+// full redemption
+#[case(150, 50, 1000, 100, 0)]
+// partial redemption, capped by the escrow
+#[case(150, 50, 40, 40, 60)]
+// no escrow left
+#[case(150, 50, 0, 0, 100)]
+// nothing new to redeem
+#[case(50, 50, 1000, 0, 0)]
+fn rav_redemption_simulation(
+    #[case] rav_value: u128,
+    #[case] previous_redeemed: u128,
+    #[case] escrow_balance: u128,
+    #[case] redeemable: u128,
+    #[case] shortfall: u128,
+) {
+    let outcome = simulate_redemption(rav_value, previous_redeemed, escrow_balance);
+    assert_eq!(
+        outcome,
+        RedemptionOutcome {
+            redeemable,
+            shortfall
+        }
+    );
+    assert_eq!(outcome.is_full(), shortfall == 0);
+}
+
+/// Keeps the messages of warnings logged while the tests run
+struct WarningCapture(Mutex<Vec<String>>);
+
+impl log::Log for WarningCapture {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static WARNINGS: WarningCapture = WarningCapture(Mutex::new(Vec::new()));
+
+#[rstest]
+#[tokio::test]
+async fn manager_warns_when_checks_exceed_cap(
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    // the logger may already be installed by another test
+    let _ = log::set_logger(&WARNINGS);
+    log::set_max_level(log::LevelFilter::Warn);
+
+    let ContextFixture {
+        context, checks, ..
+    } = context;
+    let checks_count = checks.len();
+    let _manager = Manager::<_, SignedReceipt>::new(domain_separator, context, checks)
+        .with_max_checks(checks_count - 1);
+
+    let expected = format!(
+        "{checks_count} checks are configured, more than the cap of {}",
+        checks_count - 1
+    );
+    assert!(WARNINGS.0.lock().unwrap().contains(&expected));
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_returns_check_timings_of_receipt(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let check_names: HashSet<_> = checks.iter().map(|check| check.name()).collect();
+    let manager = Manager::new(domain_separator.clone(), context, checks);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+    let signed_receipt = |value| {
+        Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], value).unwrap(),
+            &signer,
+        )
+        .unwrap()
+    };
+
+    let timings = manager
+        .verify_and_store_receipt_with_timings(&Context::new(), signed_receipt(20))
+        .await
+        .unwrap();
+    assert_eq!(timings.keys().copied().collect::<HashSet<_>>(), check_names);
+
+    // disabled checks don't run, so they aren't timed
+    let disabled_check = *check_names.iter().next().unwrap();
+    manager.set_check_enabled(disabled_check, false).unwrap();
+    let timings = manager
+        .verify_and_store_receipt_with_timings(&Context::new(), signed_receipt(30))
+        .await
+        .unwrap();
+    assert_eq!(timings.len(), check_names.len() - 1);
+    assert!(!timings.contains_key(disabled_check));
+}
+
+#[cfg(feature = "metrics")]
+#[rstest]
+#[tokio::test]
+async fn manager_records_check_timings(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let checks_count = checks.len();
+    let manager = Manager::new(domain_separator.clone(), context, checks);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let value = 20u128;
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], value).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    query_appraisals
+        .write()
+        .unwrap()
+        .insert(signed_receipt.unique_hash(), value);
+
+    manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await
+        .unwrap();
+
+    let timings = manager.check_timings();
+    assert_eq!(timings.len(), checks_count);
+    for timing in timings {
+        assert_eq!(timing.calls, 1);
+        assert!(timing.max <= timing.total);
+        assert!(!timing.name.is_empty());
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_rejects_receipt_when_check_times_out(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+    #[values(false, true)] per_check: bool,
+) {
+    struct SleepingCheck;
+
+    #[async_trait::async_trait]
+    impl Check<SignedReceipt> for SleepingCheck {
+        async fn check(
+            &self,
+            _: &Context,
+            _: &ReceiptWithState<Checking, SignedReceipt>,
+        ) -> Result<(), CheckError> {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok(())
+        }
+
+        fn name(&self) -> &'static str {
+            "sleeping"
+        }
+    }
+
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let mut checks = checks.to_vec();
+    checks.push(Arc::new(SleepingCheck));
+    let manager = Manager::new(domain_separator.clone(), context, CheckList::new(checks));
+    let manager = if per_check {
+        manager
+            .with_check_timeout(Duration::from_secs(60))
+            .with_check_timeouts(HashMap::from([("sleeping", Duration::from_millis(50))]))
+    } else {
+        manager.with_check_timeout(Duration::from_millis(50))
+    };
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let value = 20u128;
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], value).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    query_appraisals
+        .write()
+        .unwrap()
+        .insert(signed_receipt.unique_hash(), value);
+
+    let result = manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await;
+
+    assert!(matches!(
+        result,
+        Err(tap_core::Error::ReceiptError(
+            tap_core::receipt::ReceiptError::CheckTimeout { check_name }
+        )) if check_name == "sleeping"
+    ));
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_verifies_batch_of_ravs(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        signer,
+        ..
+    } = context;
+    let rav = |allocation_id, timestamp_ns, value_aggregate| ReceiptAggregateVoucher {
+        allocationId: allocation_id,
+        timestampNs: timestamp_ns,
+        valueAggregate: value_aggregate,
+    };
+    let sign = |rav: ReceiptAggregateVoucher, signer: &PrivateKeySigner| {
+        Eip712SignedMessage::new(&domain_separator, rav, signer).unwrap()
+    };
+    context
+        .update_last_rav(
+            signer.address(),
+            allocation_ids[1],
+            sign(rav(allocation_ids[1], 20, 100), &signer),
+        )
+        .await
+        .unwrap();
+    let manager = Manager::new(domain_separator.clone(), context, checks);
+
+    let unauthorized_signer = PrivateKeySigner::random();
+    let ravs = vec![
+        // valid
+        (
+            rav(allocation_ids[0], 10, 50),
+            sign(rav(allocation_ids[0], 10, 50), &signer),
+        ),
+        // signed by an unknown signer
+        (
+            rav(allocation_ids[0], 10, 50),
+            sign(rav(allocation_ids[0], 10, 50), &unauthorized_signer),
+        ),
+        // not the expected RAV
+        (
+            rav(allocation_ids[0], 10, 50),
+            sign(rav(allocation_ids[0], 10, 60), &signer),
+        ),
+        // worth less than the stored RAV
+        (
+            rav(allocation_ids[1], 30, 90),
+            sign(rav(allocation_ids[1], 30, 90), &signer),
+        ),
+        // follows the stored RAV
+        (
+            rav(allocation_ids[1], 30, 150),
+            sign(rav(allocation_ids[1], 30, 150), &signer),
+        ),
+    ];
+
+    let results = manager.verify_ravs(&Context::new(), ravs).await;
+
+    assert_eq!(results.len(), 5);
+    assert_eq!(results[0].as_ref().unwrap(), &signer.address());
+    assert!(matches!(
+        results[1],
+        Err(tap_core::Error::SignatureMismatch { recovered_address, .. })
+            if recovered_address == unauthorized_signer.address()
+    ));
+    assert!(matches!(
+        results[2],
+        Err(tap_core::Error::InvalidReceivedRav { .. })
+    ));
+    assert!(matches!(
+        results[3],
+        Err(tap_core::Error::RavNotChained {
+            last_value_aggregate: 100,
+            ..
+        })
+    ));
+    assert_eq!(results[4].as_ref().unwrap(), &signer.address());
+}
+
+/// Storage of RAVs signed by a signer on behalf of `sender`, counting the
+/// signatures it checks
+struct DelegatedSigner {
+    context: InMemoryContext,
+    sender: Address,
+    checked_signatures: Arc<Mutex<u64>>,
+}
+
+#[async_trait::async_trait]
+impl RavRead<ReceiptAggregateVoucher> for DelegatedSigner {
+    type AdapterError = InMemoryError;
+
+    async fn get_last_rav(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+    ) -> Result<Option<SignedRav>, Self::AdapterError> {
+        self.context.get_last_rav(sender, allocation_id).await
+    }
+}
+
+#[async_trait::async_trait]
+impl SignatureChecker for DelegatedSigner {
+    type AdapterError = InMemoryError;
+
+    async fn verify_signer(&self, signer_address: Address) -> Result<bool, Self::AdapterError> {
+        self.context.verify_signer(signer_address).await
+    }
+
+    async fn sender_of(&self, _signer_address: Address) -> Result<Address, Self::AdapterError> {
+        Ok(self.sender)
+    }
+
+    async fn check_signature<T: SolStruct + Sync>(
+        &self,
+        signed_message: &Eip712SignedMessage<T>,
+        domain_separator: &Eip712Domain,
+    ) -> Result<(), tap_core::Error> {
+        *self.checked_signatures.lock().unwrap() += 1;
+        let signer = signed_message.recover_signer(domain_separator)?;
+        match self.context.verify_signer(signer).await {
+            Ok(true) => Ok(()),
+            _ => Err(tap_core::Error::InvalidRecoveredSigner { address: signer }),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RavHistoryRead<ReceiptAggregateVoucher> for DelegatedSigner {
+    type AdapterError = InMemoryError;
+
+    async fn get_rav_history(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+    ) -> Result<Vec<SignedRav>, Self::AdapterError> {
+        self.context.get_rav_history(sender, allocation_id).await
+    }
+
+    async fn list_ravs_in_range<R: RangeBounds<u64> + std::marker::Send>(
+        &self,
+        timestamp_range_ns: R,
+    ) -> Result<Vec<SignedRav>, Self::AdapterError> {
+        self.context.list_ravs_in_range(timestamp_range_ns).await
+    }
+}
+
+#[async_trait::async_trait]
+impl StoredReceiptRead<SignedReceipt> for DelegatedSigner {
+    type AdapterError = InMemoryError;
+
+    async fn retrieve_stored_receipts_in_timestamp_range<
+        R: RangeBounds<u64> + std::marker::Send,
+    >(
+        &self,
+        timestamp_range_ns: R,
+    ) -> Result<Vec<StoredReceipt<SignedReceipt>>, Self::AdapterError> {
+        self.context
+            .retrieve_stored_receipts_in_timestamp_range(timestamp_range_ns)
+            .await
+    }
+
+    async fn retrieve_stored_sender_receipts_in_timestamp_range<
+        R: RangeBounds<u64> + std::marker::Send,
+    >(
+        &self,
+        sender: Address,
+        allocation_id: Address,
+        timestamp_range_ns: R,
+    ) -> Result<Vec<StoredReceipt<SignedReceipt>>, Self::AdapterError> {
+        self.context
+            .retrieve_stored_sender_receipts_in_timestamp_range(
+                sender,
+                allocation_id,
+                timestamp_range_ns,
+            )
+            .await
+    }
+
+    async fn retrieve_stored_receipts_by_ids(
+        &self,
+        receipt_ids: &[u64],
+    ) -> Result<Vec<StoredReceipt<SignedReceipt>>, Self::AdapterError> {
+        self.context
+            .retrieve_stored_receipts_by_ids(receipt_ids)
+            .await
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_keys_ravs_by_sender_of_signer(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        signer,
+        ..
+    } = context;
+    let sender = Address::from([0x5e; 20]);
+    let rav = |timestamp_ns, value_aggregate| ReceiptAggregateVoucher {
+        allocationId: allocation_ids[0],
+        timestampNs: timestamp_ns,
+        valueAggregate: value_aggregate,
+    };
+    let sign = |rav| Eip712SignedMessage::new(&domain_separator, rav, &signer).unwrap();
+    // the last RAV of the sender, nothing stored for the signer
+    context
+        .update_last_rav(sender, allocation_ids[0], sign(rav(20, 100)))
+        .await
+        .unwrap();
+    let checked_signatures = Arc::new(Mutex::new(0));
+    let manager = Manager::new(
+        domain_separator.clone(),
+        DelegatedSigner {
+            context,
+            sender,
+            checked_signatures: checked_signatures.clone(),
+        },
+        checks,
+    );
+
+    let results = manager
+        .verify_ravs(&Context::new(), vec![(rav(10, 50), sign(rav(10, 50)))])
+        .await;
+    assert!(matches!(
+        results[0],
+        Err(tap_core::Error::RavNotChained {
+            last_value_aggregate: 100,
+            ..
+        })
+    ));
+    // the signature went through the adapter
+    assert_eq!(*checked_signatures.lock().unwrap(), 1);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_handles_receipts_below_min_timestamp(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+    #[values(false, true)] grace_period: bool,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+    } = context;
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+    let floor = get_current_timestamp_u64_ns().unwrap();
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks)
+        .with_min_receipt_timestamp_ns(floor);
+    let manager = if grace_period {
+        manager.with_below_min_timestamp_grace_period_ns(60_000_000_000)
+    } else {
+        manager
+    };
+
+    let receipt_at = |timestamp_ns| {
+        let mut receipt = Receipt::new(allocation_ids[0], 10).unwrap();
+        receipt.timestamp_ns = timestamp_ns;
+        let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
+        query_appraisals
+            .write()
+            .unwrap()
+            .insert(signed_receipt.unique_hash(), 10);
+        signed_receipt
+    };
+    let is_below_min_timestamp = |result| {
+        matches!(
+            result,
+            Err(tap_core::Error::ReceiptError(
+                tap_core::receipt::ReceiptError::BelowMinTimestamp { receipt_ts, floor: received_floor }
+            )) if receipt_ts == floor - 1 && received_floor == floor
+        )
+    };
+
+    // can't be checked against the last RAV of its key, rejected anyway
+    assert!(is_below_min_timestamp(
+        manager
+            .verify_and_store_receipt(&Context::new(), receipt_at(floor - 1))
+            .await
+    ));
+
+    let ctx = rav_ctx(signer.address(), allocation_ids[0]);
+    let result = manager
+        .verify_and_store_receipt(&ctx, receipt_at(floor - 1))
+        .await;
+    if grace_period {
+        result.unwrap();
+        assert_eq!(manager.below_min_timestamp_receipts(), 1);
+        assert_eq!(manager.pending_receipts(), 1);
+    } else {
+        assert!(is_below_min_timestamp(result));
+        assert_eq!(manager.below_min_timestamp_receipts(), 0);
+        assert_eq!(manager.pending_receipts(), 0);
+    }
+
+    // flagged in storage, unlike receipts above the floor
+    manager
+        .verify_and_store_receipt(&ctx, receipt_at(floor))
+        .await
+        .unwrap();
+    let mut stored_receipts = context
+        .retrieve_stored_receipts_in_timestamp_range(..)
+        .await
+        .unwrap();
+    stored_receipts.sort_by_key(|stored_receipt| stored_receipt.id());
+    let flags: Vec<_> = stored_receipts
+        .iter()
+        .map(|stored_receipt| stored_receipt.below_min_timestamp())
+        .collect();
+    if grace_period {
+        assert_eq!(flags, vec![true, false]);
+    } else {
+        assert_eq!(flags, vec![false]);
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_rejects_receipts_covered_by_rav_during_grace_period(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture { checks, signer, .. } = context;
+
+    let floor = get_current_timestamp_u64_ns().unwrap();
+    let rav = Eip712SignedMessage::new(
+        &domain_separator,
+        ReceiptAggregateVoucher {
+            allocationId: allocation_ids[0],
+            timestampNs: floor - 10,
+            valueAggregate: 100,
+        },
+        &signer,
+    )
+    .unwrap();
+    let context = InMemoryContext::new(
+        Arc::new(RwLock::new(HashMap::from([(
+            (signer.address(), allocation_ids[0]),
+            rav,
+        )]))),
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(StatefulTimestampCheck::new(0)),
+    );
+    let manager = Manager::new_recovering::<ReceiptAggregateVoucher>(
+        domain_separator.clone(),
+        context,
+        checks,
+    )
+    .await
+    .unwrap()
+    .with_min_receipt_timestamp_ns(floor)
+    .with_below_min_timestamp_grace_period_ns(60_000_000_000);
+
+    let mut receipt = Receipt::new(allocation_ids[0], 10).unwrap();
+    receipt.timestamp_ns = floor - 10;
+    let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
+    assert!(matches!(
+        manager
+            .verify_and_store_receipt(&rav_ctx(signer.address(), allocation_ids[0]), signed_receipt)
+            .await,
+        Err(tap_core::Error::ReceiptTimestampLowerThanRav { rav_ts, .. }) if rav_ts == floor - 10
+    ));
+    assert_eq!(manager.below_min_timestamp_receipts(), 0);
+    assert_eq!(manager.pending_receipts(), 0);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_notifies_escrow_monitor_of_insufficient_escrow(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    signer: PrivateKeySigner,
+) {
+    #[derive(Default)]
+    struct RecordingMonitor(Mutex<Vec<(Address, u128, u128)>>);
+
+    impl EscrowMonitor for RecordingMonitor {
+        fn on_escrow_insufficient(&self, sender: Address, needed: u128, available: u128) {
+            self.0.lock().unwrap().push((sender, needed, available));
+        }
+    }
+
+    let escrow_storage = Arc::new(RwLock::new(HashMap::from([(signer.address(), 20)])));
+    let context = InMemoryContext::new(
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(RwLock::new(HashMap::new())),
+        escrow_storage,
+        Arc::new(StatefulTimestampCheck::new(0)),
+    );
+    let escrow_check = EscrowCheck::new(domain_separator.clone(), Arc::new(context.clone()));
+    let monitor = Arc::new(RecordingMonitor::default());
+    let manager = Manager::new(
+        domain_separator.clone(),
+        context,
+        CheckList::new(vec![Arc::new(escrow_check)]),
+    )
+    .with_escrow_monitor(monitor.clone());
+
+    for value in [10, 30] {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        let _ = manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await;
+    }
+
+    assert_eq!(manager.escrow_insufficient_rejections(), 1);
+    // the first receipt reserved 10 of the 20 in escrow
+    assert_eq!(*monitor.0.lock().unwrap(), vec![(signer.address(), 30, 10)]);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_notifies_escrow_monitor_of_rav_request_rejections(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    #[derive(Default)]
+    struct RecordingMonitor(Mutex<Vec<(Address, u128, u128)>>);
+
+    impl EscrowMonitor for RecordingMonitor {
+        fn on_escrow_insufficient(&self, sender: Address, needed: u128, available: u128) {
+            self.0.lock().unwrap().push((sender, needed, available));
+        }
+    }
+
+    // reads the escrow balance without reserving it, so it runs again while
+    // creating a RAV request
+    struct BalanceCheck {
+        escrow_storage: Arc<RwLock<HashMap<Address, u128>>>,
+        sender: Address,
+    }
+
+    #[async_trait::async_trait]
+    impl Check<SignedReceipt> for BalanceCheck {
+        async fn check(
+            &self,
+            _: &Context,
+            receipt: &ReceiptWithState<Checking, SignedReceipt>,
+        ) -> Result<(), CheckError> {
+            let available_escrow = self
+                .escrow_storage
+                .read()
+                .unwrap()
+                .get(&self.sender)
+                .copied()
+                .unwrap_or(0);
+            let received_value = receipt.signed_receipt().message.value;
+            if available_escrow < received_value {
+                return Err(CheckError::Failed(
+                    tap_core::receipt::ReceiptError::NotEnoughEscrow {
+                        sender: self.sender,
+                        available_escrow,
+                        received_value,
+                    }
+                    .into(),
+                ));
+            }
+            Ok(())
+        }
+    }
+
+    let ContextFixture {
+        context,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    escrow_storage.write().unwrap().insert(signer.address(), 20);
+    let monitor = Arc::new(RecordingMonitor::default());
+    let manager = Manager::new(
+        domain_separator.clone(),
+        context,
+        CheckList::new(vec![Arc::new(BalanceCheck {
+            escrow_storage: escrow_storage.clone(),
+            sender: signer.address(),
+        })]),
+    )
+    .with_escrow_monitor(monitor.clone());
+
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], 10).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    let ctx = rav_ctx(signer.address(), allocation_ids[0]);
+    manager
+        .verify_and_store_receipt(&ctx, signed_receipt)
+        .await
+        .unwrap();
+    assert_eq!(manager.escrow_insufficient_rejections(), 0);
+
+    // the sender withdrew most of its escrow in the meantime
+    escrow_storage.write().unwrap().insert(signer.address(), 5);
+    let rav_request = manager.create_rav_request(&ctx, 0, None).await.unwrap();
+    assert_eq!(rav_request.invalid_receipts.len(), 1);
+    assert_eq!(manager.escrow_insufficient_rejections(), 1);
+    assert_eq!(*monitor.0.lock().unwrap(), vec![(signer.address(), 10, 5)]);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_total_aggregated_value(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        signer,
+        ..
+    } = context;
+    for (timestamp_ns, value_aggregate) in [(10, 100), (20, 250)] {
+        let rav = Eip712SignedMessage::new(
+            &domain_separator,
+            ReceiptAggregateVoucher {
+                allocationId: allocation_ids[0],
+                timestampNs: timestamp_ns,
+                valueAggregate: value_aggregate,
+            },
+            &signer,
+        )
+        .unwrap();
+        context
+            .update_last_rav(signer.address(), allocation_ids[0], rav)
+            .await
+            .unwrap();
+    }
+    let manager = Manager::new(domain_separator, context, checks);
+
+    // RAVs are cumulative, the latest one carries the total
+    assert_eq!(
+        manager
+            .total_aggregated_value::<ReceiptAggregateVoucher>(signer.address(), allocation_ids[0])
+            .await
+            .unwrap(),
+        250
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_total_aggregated_value_without_rav(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator, context, checks);
+
+    assert_eq!(
+        manager
+            .total_aggregated_value::<ReceiptAggregateVoucher>(signer.address(), allocation_ids[0])
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_final_rav_request_includes_pending_receipts(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context, checks)
+        .with_eligibility_delay_ns(60_000_000_000)
+        .with_min_profitable_rav_value(1_000_000);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    for value in [20, 30] {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+
+    // the receipts are too fresh for a regular request
+    let rav_request = manager
+        .create_rav_request(&rav_ctx(signer.address(), allocation_ids[0]), 0, None)
+        .await
+        .unwrap();
+    assert!(rav_request.valid_receipts.is_empty());
+
+    // the final request takes them anyway, below the profitability threshold
+    let rav_request = manager
+        .create_rav_request_final(&rav_ctx(signer.address(), allocation_ids[0]))
+        .await
+        .unwrap();
+    assert_eq!(rav_request.valid_receipts.len(), 2);
+    assert_eq!(rav_request.expected_rav.unwrap().valueAggregate, 50);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_warns_about_low_value_ravs(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager =
+        Manager::new(domain_separator.clone(), context, checks).with_low_value_rav_warning(100);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    for value in [500, 1] {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+        let rav_request = manager
+            .create_rav_request(&rav_ctx(signer.address(), allocation_ids[0]), 0, None)
+            .await
+            .unwrap();
+        // a dust RAV is still produced
+        assert_eq!(rav_request.escrow_delta(), value);
+        let expected_rav = rav_request.expected_rav.unwrap();
+        let signed_rav =
+            Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+        manager
+            .verify_and_store_rav(expected_rav, signed_rav)
+            .await
+            .unwrap();
+    }
+    assert_eq!(manager.low_value_ravs(), 1);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_refreshes_allocations(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        escrow_storage,
+        query_appraisals,
+        signer,
+        ..
+    } = context;
+    let allocations = Arc::new(RwLock::new(HashSet::new()));
+    let checks = CheckList::new(get_full_list_of_checks(
+        domain_separator.clone(),
+        HashSet::from([signer.address()]),
+        allocations.clone(),
+        query_appraisals,
+    ));
+    let manager = Arc::new(Manager::new(domain_separator.clone(), context, checks));
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    // what the resolver returns, `None` making it fail
+    let resolved = Arc::new(Mutex::new(Some(HashSet::from([allocation_ids[0]]))));
+    let handle = manager.start_allocation_refresh(
+        allocations.clone(),
+        {
+            let resolved = resolved.clone();
+            move || {
+                let resolved = resolved.lock().unwrap().clone();
+                async move {
+                    resolved.ok_or_else(|| tap_core::Error::AdapterError {
+                        source_error: anyhow!("resolver unavailable"),
+                    })
+                }
+            }
+        },
+        Duration::from_millis(20),
+    );
+    let wait_for = |expected: HashSet<Address>| {
+        let allocations = allocations.clone();
+        tokio::time::timeout(Duration::from_secs(5), async move {
+            while *allocations.read().unwrap() != expected {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+    };
+    let ctx = Context::new();
+    let store = |allocation_id: Address| {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_id, 20).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        manager.verify_and_store_receipt(&ctx, signed_receipt)
+    };
+
+    wait_for(HashSet::from([allocation_ids[0]])).await.unwrap();
+    assert!(store(allocation_ids[0]).await.is_ok());
+    assert!(store(allocation_ids[1]).await.is_err());
+
+    *resolved.lock().unwrap() = Some(HashSet::from([allocation_ids[1]]));
+    wait_for(HashSet::from([allocation_ids[1]])).await.unwrap();
+    assert!(store(allocation_ids[0]).await.is_err());
+    assert!(store(allocation_ids[1]).await.is_ok());
+
+    // failures keep the previous set
+    *resolved.lock().unwrap() = None;
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert_eq!(
+        *allocations.read().unwrap(),
+        HashSet::from([allocation_ids[1]])
+    );
+
+    // the task stops once the manager is gone
+    drop(manager);
+    tokio::time::timeout(Duration::from_secs(5), handle)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_cancels_rav_request(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    // cancels the RAV request on its 5th receipt, then hangs
+    struct CancellingCheck(Arc<AtomicBool>);
+
+    #[async_trait::async_trait]
+    impl Check<SignedReceipt> for CancellingCheck {
+        async fn check(
+            &self,
+            ctx: &Context,
+            receipt: &ReceiptWithState<Checking, SignedReceipt>,
+        ) -> Result<(), CheckError> {
+            if self.0.load(std::sync::atomic::Ordering::SeqCst)
+                && receipt.signed_receipt().message.nonce == 5
+            {
+                ctx.get::<CancellationToken>().unwrap().cancel();
+                std::future::pending::<()>().await;
+            }
+            Ok(())
+        }
+    }
+
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let is_create_rav = Arc::new(AtomicBool::new(false));
+    let mut checks: Vec<Arc<dyn Check<SignedReceipt> + Send + Sync>> =
+        checks.iter().cloned().collect();
+    checks.push(Arc::new(CancellingCheck(is_create_rav.clone())));
+    let manager = Manager::new(
+        domain_separator.clone(),
+        context.clone(),
+        CheckList::new(checks),
+    );
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    for i in 0..10 {
+        let receipt = Receipt {
+            allocation_id: allocation_ids[0],
+            timestamp_ns: i + 1,
+            nonce: i,
+            value: 20u128,
+        };
+        let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+    is_create_rav.store(true, std::sync::atomic::Ordering::SeqCst);
+
+    let mut ctx = rav_ctx(signer.address(), allocation_ids[0]);
+    ctx.insert(CancellationToken::new());
+    let rav_request = tokio::time::timeout(
+        Duration::from_secs(5),
+        manager.create_rav_request::<ReceiptAggregateVoucher>(&ctx, 0, None),
+    )
+    .await
+    .expect("The cancelled RAV request didn't return");
+    assert!(matches!(rav_request, Err(tap_core::Error::Cancelled)));
+
+    // nothing was consumed
+    assert_eq!(manager.pending_receipts(), 10);
+    assert_eq!(
+        context
+            .retrieve_receipts_in_timestamp_range(.., None)
+            .await
+            .unwrap()
+            .len(),
+        10
+    );
+    assert!(RavRead::<ReceiptAggregateVoucher>::get_last_rav(
+        &context,
+        signer.address(),
+        allocation_ids[0]
+    )
+    .await
+    .unwrap()
+    .is_none());
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_validates_escrow_source(context: ContextFixture) {
+    struct BrokenEscrow;
+
+    #[async_trait::async_trait]
+    impl EscrowHandler for BrokenEscrow {
+        type AdapterError = InMemoryError;
+
+        async fn try_reserve(&self, _: Address, _: u128) -> Result<bool, Self::AdapterError> {
+            unreachable!()
+        }
+
+        async fn release(&self, _: Address, _: u128) -> Result<(), Self::AdapterError> {
+            unreachable!()
+        }
+
+        async fn available_escrow(&self, sender: Address) -> Result<u128, Self::AdapterError> {
+            if sender == Address::from([0x01; 20]) {
+                return Err(InMemoryError::AdapterError {
+                    error: "escrow contract unreachable".to_owned(),
+                });
+            }
+            Ok(100)
+        }
+    }
+
+    let ContextFixture {
+        context,
+        escrow_storage,
+        ..
+    } = context;
+    let funded_sender = Address::from([0x01; 20]);
+    let unfunded_sender = Address::from([0x02; 20]);
+    escrow_storage.write().unwrap().insert(funded_sender, 100);
+
+    let manager = Manager::<_, SignedReceipt>::new(
+        tap_eip712_domain(1, Address::ZERO),
+        context,
+        CheckList::empty(),
+    );
+    let report = manager
+        .validate_escrow_source(&[funded_sender, unfunded_sender])
+        .await
+        .unwrap();
+    assert_eq!(report.funded, vec![funded_sender]);
+    assert_eq!(report.unfunded, vec![unfunded_sender]);
+    assert!(report.is_healthy());
+    // the probe leaves the escrow untouched
+    assert_eq!(escrow_storage.read().unwrap()[&funded_sender], 100);
+
+    // an adapter reading an empty map finds no escrow at all
+    let report = manager
+        .validate_escrow_source(&[unfunded_sender])
+        .await
+        .unwrap();
+    assert!(!report.is_healthy());
+
+    let manager = Manager::<_, SignedReceipt>::new(
+        tap_eip712_domain(1, Address::ZERO),
+        BrokenEscrow,
+        CheckList::empty(),
+    );
+    // a sender failing to be probed is reported
+    let report = manager
+        .validate_escrow_source(&[funded_sender, unfunded_sender])
+        .await
+        .unwrap();
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].0, funded_sender);
+    assert!(!report.is_healthy());
+    // while an adapter failing for every sender is an error
+    assert!(matches!(
+        manager.validate_escrow_source(&[funded_sender]).await,
+        Err(tap_core::Error::AdapterError { .. })
+    ));
+}
+
+#[cfg(feature = "archive")]
+#[rstest]
+#[tokio::test]
+async fn manager_archives_ravs(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+    #[values(ArchiveRotation::Daily, ArchiveRotation::MaxBytes(1))] rotation: ArchiveRotation,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let archive_dir = std::env::temp_dir().join(format!("rav-archive-{}", signer.address()));
+    let sink = Arc::new(RavArchiveSink::new(&archive_dir, rotation).with_fsync(true));
+    let manager =
+        Manager::new(domain_separator.clone(), context, checks).with_required_rav_sink(sink);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let mut signed_ravs = vec![];
+    for value in [20, 30] {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+        let rav_request = manager
+            .create_rav_request(&rav_ctx(signer.address(), allocation_ids[0]), 0, None)
+            .await
+            .unwrap();
+        let expected_rav = rav_request.expected_rav.unwrap();
+        let signed_rav =
+            Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+        manager
+            .verify_and_store_rav(expected_rav, signed_rav.clone())
+            .await
+            .unwrap();
+        signed_ravs.push(signed_rav);
+    }
+
+    let mut files: Vec<_> = std::fs::read_dir(&archive_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    files.sort();
+    let archived: Vec<SignedRav> = files
+        .iter()
+        .flat_map(|file| {
+            std::fs::read_to_string(file)
+                .unwrap()
+                .lines()
+                .map(|line| {
+                    serde_json::from_str::<StoredRav>(line)
+                        .unwrap()
+                        .decode()
+                        .unwrap()
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    std::fs::remove_dir_all(&archive_dir).unwrap();
+
+    assert_eq!(archived, signed_ravs);
+    // a single line already exceeds the size limit
+    let expected_files = match rotation {
+        ArchiveRotation::Daily => 1,
+        ArchiveRotation::MaxBytes(_) => 2,
+    };
+    assert_eq!(files.len(), expected_files);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_stores_receipt_with_warning(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    struct LowEscrowCheck;
+
+    #[async_trait::async_trait]
+    impl Check<SignedReceipt> for LowEscrowCheck {
+        async fn check(
+            &self,
+            _: &Context,
+            _: &ReceiptWithState<Checking, SignedReceipt>,
+        ) -> Result<(), CheckError> {
+            Err(CheckError::Failed(anyhow!("Escrow is running low")))
+        }
+
+        fn name(&self) -> &'static str {
+            "low_escrow"
+        }
+
+        fn severity(&self) -> CheckSeverity {
+            CheckSeverity::Warn
+        }
+    }
+
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let mut checks: Vec<Arc<dyn Check<SignedReceipt> + Send + Sync>> =
+        checks.iter().cloned().collect();
+    checks.push(Arc::new(LowEscrowCheck));
+    let manager = Manager::new(
+        domain_separator.clone(),
+        context.clone(),
+        CheckList::new(checks),
+    );
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], 20).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    let warnings = manager
+        .verify_and_store_receipt_with_warnings(&Context::new(), signed_receipt)
+        .await
+        .unwrap();
+    assert_eq!(
+        warnings,
+        vec![CheckWarning {
+            check_name: "low_escrow",
+            message: "Escrow is running low".to_string(),
+        }]
+    );
+    assert_eq!(manager.pending_receipts(), 1);
+
+    // the receipt is aggregated as well
+    let rav_request = manager
+        .create_rav_request(&rav_ctx(signer.address(), allocation_ids[0]), 0, None)
+        .await
+        .unwrap();
+    assert_eq!(rav_request.valid_receipts.len(), 1);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_prunes_rav_history(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        signer,
+        ..
+    } = context;
+    let manager = Arc::new(Manager::new(
+        domain_separator.clone(),
+        context.clone(),
+        checks,
+    ));
+
+    let mut ravs: HashMap<Address, Vec<SignedRav>> = HashMap::new();
+    for allocation_id in &allocation_ids[..2] {
+        let mut previous_rav = None;
+        for value in [10, 20, 30, 40, 50] {
+            let receipt = Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(*allocation_id, value).unwrap(),
+                &signer,
+            )
+            .unwrap();
+            let signed_rav = Eip712SignedMessage::new(
+                &domain_separator,
+                ReceiptAggregateVoucher::aggregate_receipts(
+                    *allocation_id,
+                    &[receipt],
+                    previous_rav,
+                )
+                .unwrap(),
+                &signer,
+            )
+            .unwrap();
+            context
+                .update_last_rav(signer.address(), *allocation_id, signed_rav.clone())
+                .await
+                .unwrap();
+            previous_rav = Some(signed_rav.clone());
+            ravs.entry(*allocation_id).or_default().push(signed_rav);
+        }
+    }
+
+    assert_eq!(manager.prune_rav_history(2).await.unwrap(), 6);
+    for allocation_id in &allocation_ids[..2] {
+        assert_eq!(
+            context
+                .get_rav_history(signer.address(), *allocation_id)
+                .await
+                .unwrap(),
+            ravs[allocation_id][3..]
+        );
+    }
+
+    // the latest RAV always stays, even when scheduled to keep none
+    let handle = manager.start_rav_history_pruning(0, Duration::from_millis(20));
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while context
+            .get_rav_history(signer.address(), allocation_ids[0])
+            .await
+            .unwrap()
+            .len()
+            > 1
+        {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .unwrap();
+    for allocation_id in &allocation_ids[..2] {
+        assert_eq!(
+            context
+                .get_rav_history(signer.address(), *allocation_id)
+                .await
+                .unwrap(),
+            ravs[allocation_id][4..]
+        );
+    }
+
+    drop(manager);
+    tokio::time::timeout(Duration::from_secs(5), handle)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[rstest]
+#[case::sequential(1)]
+#[case::concurrent(8)]
+#[tokio::test]
+async fn manager_stores_receipts_concurrently(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+    #[case] receipt_concurrency: usize,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context, checks)
+        .with_receipt_concurrency(receipt_concurrency);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    // every third receipt is for an unknown allocation
+    let unknown_allocation_id = Address::from([0x99u8; 20]);
+    let mut signed_receipts = Vec::new();
+    for index in 0..30u128 {
+        let allocation_id = if index % 3 == 2 {
+            unknown_allocation_id
+        } else {
+            allocation_ids[0]
+        };
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_id, index + 1).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        query_appraisals
+            .write()
+            .unwrap()
+            .insert(signed_receipt.unique_hash(), index + 1);
+        signed_receipts.push(signed_receipt);
+    }
+
+    let results = manager
+        .verify_and_store_receipts(&Context::new(), signed_receipts)
+        .await;
+    assert_eq!(results.len(), 30);
+    for (index, result) in results.iter().enumerate() {
+        assert_eq!(result.is_ok(), index % 3 != 2, "receipt {index}");
+    }
+    assert_eq!(manager.pending_receipts(), 20);
+
+    let rav_request = manager
+        .create_rav_request::<ReceiptAggregateVoucher>(
+            &rav_ctx(signer.address(), allocation_ids[0]),
+            0,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(rav_request.valid_receipts.len(), 20);
+    assert_eq!(
+        rav_request.expected_rav.unwrap().valueAggregate,
+        (1..=30).filter(|value| value % 3 != 0).sum::<u128>()
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_reports_storage_quota_exceeded(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+    } = context;
+    let manager = Manager::new(
+        domain_separator.clone(),
+        context.clone().with_receipt_capacity(2),
+        checks,
+    );
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+    let ctx = Context::new();
+    let store_receipt = |value: u128| {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        query_appraisals
+            .write()
+            .unwrap()
+            .insert(signed_receipt.unique_hash(), value);
+        manager.verify_and_store_receipt(&ctx, signed_receipt)
+    };
+
+    for value in 1..=3u128 {
+        let result = store_receipt(value).await;
+        if value <= 2 {
+            result.unwrap();
+        } else {
+            let err = result.unwrap_err();
+            assert!(matches!(err, tap_core::Error::StorageQuotaExceeded { .. }));
+            assert!(err.is_retryable());
+        }
+    }
+    assert_eq!(manager.pending_receipts(), 2);
+    assert!(manager.is_storage_full());
+
+    // space freed behind the manager's back goes unnoticed until cleared
+    context
+        .remove_receipts_in_timestamp_range(..)
+        .await
+        .unwrap();
+    assert!(matches!(
+        store_receipt(4).await,
+        Err(tap_core::Error::StorageQuotaExceeded { .. })
+    ));
+    manager.clear_storage_full();
+    store_receipt(5).await.unwrap();
+    store_receipt(6).await.unwrap();
+    assert!(store_receipt(7).await.is_err());
+
+    // receipts removed by the manager free space right away
+    manager.evict_receipts_older_than(0).await.unwrap();
+    assert!(!manager.is_storage_full());
+    store_receipt(8).await.unwrap();
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_revalidates_stored_receipts(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    for (value, allocation_id) in [(10, 0), (20, 1), (30, 0), (40, 1)] {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[allocation_id], value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        query_appraisals
+            .write()
+            .unwrap()
+            .insert(signed_receipt.unique_hash(), value);
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+
+    // the second allocation is no longer accepted
+    let stricter_checks = CheckList::new(get_full_list_of_checks(
+        domain_separator.clone(),
+        HashSet::from([signer.address()]),
+        Arc::new(RwLock::new(HashSet::from([allocation_ids[0]]))),
+        query_appraisals.clone(),
+    ));
+    let report: RevalidationReport<SignedReceipt> = manager
+        .revalidate_stored_receipts(.., &stricter_checks)
+        .await
+        .unwrap();
+    assert_eq!(report.checked, 4);
+    let mut failed_values: Vec<u128> = report
+        .failed
+        .iter()
+        .map(|(receipt, _)| receipt.signed_receipt().message.value)
+        .collect();
+    failed_values.sort_unstable();
+    assert_eq!(failed_values, vec![20, 40]);
+    assert!(report.failed.iter().all(|(receipt, _)| receipt
+        .signed_receipt()
+        .message
+        .allocation_id
+        == allocation_ids[1]));
+
+    // quarantined receipts are no longer read back
+    assert_eq!(manager.quarantine_receipts(&report).await.unwrap(), 2);
+    assert_eq!(
+        context.quarantined_receipt_ids(),
+        report.invalid_receipt_ids().into_iter().collect()
+    );
+    assert_eq!(manager.pending_receipts(), 2);
+    let remaining = context
+        .retrieve_receipts_in_timestamp_range(.., None)
+        .await
+        .unwrap();
+    assert_eq!(remaining.len(), 2);
+    assert!(manager
+        .revalidate_stored_receipts(.., &stricter_checks)
+        .await
+        .unwrap()
+        .failed
+        .is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_reports_current_min_timestamp(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager =
+        Manager::new(domain_separator.clone(), context, checks).with_min_receipt_timestamp_ns(1);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    // the starting floor applies until there is a RAV
+    assert_eq!(
+        manager
+            .current_min_timestamp::<ReceiptAggregateVoucher>(signer.address(), allocation_ids[0])
+            .await
+            .unwrap(),
+        1
+    );
+
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], 20).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await
+        .unwrap();
+    let ctx = rav_ctx(signer.address(), allocation_ids[0]);
+    let expected_rav = manager
+        .create_rav_request(&ctx, 0, None)
+        .await
+        .unwrap()
+        .expected_rav
+        .unwrap();
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+    manager
+        .verify_and_store_rav_with_context(&ctx, expected_rav.clone(), signed_rav)
+        .await
+        .unwrap();
+
+    // receipts up to the RAV timestamp are aggregated already
+    assert_eq!(
+        manager
+            .current_min_timestamp::<ReceiptAggregateVoucher>(signer.address(), allocation_ids[0])
+            .await
+            .unwrap(),
+        expected_rav.timestampNs + 1
+    );
+    assert_eq!(
+        manager
+            .current_min_timestamp::<ReceiptAggregateVoucher>(signer.address(), allocation_ids[1])
+            .await
+            .unwrap(),
+        1
+    );
+}
+
+#[rstest]
+fn manager_validates_verifying_contract(domain_separator: Eip712Domain, context: ContextFixture) {
+    let ContextFixture {
+        context, checks, ..
+    } = context;
+    let expected_contract = Address::from([0x22u8; 20]);
+
+    let result = Manager::<_, SignedReceipt>::new_validated(
+        domain_separator.clone(),
+        context.clone(),
+        CheckList::empty(),
+        expected_contract,
+    );
+    let Err(err) = result else {
+        panic!("the verifying contract mismatch went unnoticed");
+    };
+    assert!(matches!(
+        err,
+        tap_core::Error::VerifyingContractMismatch { expected, configured }
+            if expected == expected_contract
+                && configured == Some(Address::from([0x11u8; 20]))
+    ));
+    assert!(err.to_string().contains(&expected_contract.to_string()));
+
+    assert!(Manager::<_, SignedReceipt>::new_validated(
+        domain_separator,
+        context,
+        checks,
+        Address::from([0x11u8; 20]),
+    )
+    .is_ok());
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_previews_obsolete_receipts(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+    let ctx = rav_ctx(signer.address(), allocation_ids[0]);
+
+    let store_receipts = |count: u128| {
+        let manager = &manager;
+        let domain_separator = &domain_separator;
+        let signer = &signer;
+        let allocation_id = allocation_ids[0];
+        async move {
+            for value in 1..=count {
+                let signed_receipt = Eip712SignedMessage::new(
+                    domain_separator,
+                    Receipt::new(allocation_id, value).unwrap(),
+                    signer,
+                )
+                .unwrap();
+                manager
+                    .verify_and_store_receipt(&Context::new(), signed_receipt)
+                    .await
+                    .unwrap();
+            }
+        }
+    };
+
+    // nothing is obsolete without a RAV
+    store_receipts(3).await;
+    assert!(manager
+        .preview_obsolete_receipts::<ReceiptAggregateVoucher>(&ctx)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let expected_rav = manager
+        .create_rav_request(&ctx, 0, None)
+        .await
+        .unwrap()
+        .expected_rav
+        .unwrap();
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+    manager
+        .verify_and_store_rav_with_context(&ctx, expected_rav, signed_rav)
+        .await
+        .unwrap();
+    store_receipts(2).await;
+
+    let stored_ids = |context: InMemoryContext| async move {
+        context
+            .retrieve_stored_receipts_in_timestamp_range(..)
+            .await
+            .unwrap()
+            .iter()
+            .map(|stored_receipt| stored_receipt.id())
+            .collect::<HashSet<_>>()
+    };
+    let preview: HashSet<_> = manager
+        .preview_obsolete_receipts::<ReceiptAggregateVoucher>(&ctx)
+        .await
+        .unwrap()
+        .iter()
+        .map(|stored_receipt| stored_receipt.id())
+        .collect();
+    assert_eq!(preview.len(), 3);
+
+    // the preview removed nothing, the cleanup removes exactly the preview
+    let before = stored_ids(context.clone()).await;
+    assert_eq!(before.len(), 5);
+    manager
+        .remove_obsolete_receipts::<ReceiptAggregateVoucher>(&ctx)
+        .await
+        .unwrap();
+    let after = stored_ids(context).await;
+    assert_eq!(
+        before.difference(&after).copied().collect::<HashSet<_>>(),
+        preview
+    );
+}
+
+#[rstest]
+#[case(TimestampBoundary::Inclusive, [false, true, true])]
+#[case(TimestampBoundary::Exclusive, [false, false, true])]
+#[tokio::test]
+async fn manager_min_timestamp_boundary(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+    #[case] boundary: TimestampBoundary,
+    #[case] expected: [bool; 3],
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let floor = get_current_timestamp_u64_ns().unwrap();
+    let manager = Manager::new(domain_separator.clone(), context, checks)
+        .with_min_receipt_timestamp(floor, boundary);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let mut accepted = Vec::new();
+    for timestamp_ns in [floor - 1, floor, floor + 1] {
+        let mut receipt = Receipt::new(allocation_ids[0], 20).unwrap();
+        receipt.timestamp_ns = timestamp_ns;
+        let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
+        let result = manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await;
+        if let Err(err) = &result {
+            assert!(matches!(
+                err,
+                tap_core::Error::ReceiptError(
+                    tap_core::receipt::ReceiptError::BelowMinTimestamp { receipt_ts, .. }
+                )
+                    if *receipt_ts == timestamp_ns
+            ));
+        }
+        accepted.push(result.is_ok());
+    }
+    assert_eq!(accepted, expected);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_sums_sender_outstanding(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+    let receipt_ctx = Context::new();
+    let store_receipt = |allocation_id: Address, value: u128| {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_id, value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        manager.verify_and_store_receipt(&receipt_ctx, signed_receipt)
+    };
+
+    // a RAV of 30 and an unaggregated receipt of 5 on the first allocation
+    store_receipt(allocation_ids[0], 10).await.unwrap();
+    store_receipt(allocation_ids[0], 20).await.unwrap();
+    let ctx = rav_ctx(signer.address(), allocation_ids[0]);
+    let expected_rav = manager
+        .create_rav_request(&ctx, 0, None)
+        .await
+        .unwrap()
+        .expected_rav
+        .unwrap();
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+    manager
+        .verify_and_store_rav_with_context(&ctx, expected_rav, signed_rav)
+        .await
+        .unwrap();
+    store_receipt(allocation_ids[0], 5).await.unwrap();
+
+    // unaggregated receipts only on the second allocation
+    store_receipt(allocation_ids[1], 7).await.unwrap();
+
+    // receipts of other senders are left out
+    let other_signer = PrivateKeySigner::random();
+    let other_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[1], 1000).unwrap(),
+        &other_signer,
+    )
+    .unwrap();
+    context
+        .store_receipt_in_domain(ReceiptWithState::new(other_receipt), &domain_separator)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        manager
+            .sender_outstanding::<ReceiptAggregateVoucher>(signer.address())
+            .await
+            .unwrap(),
+        30 + 5 + 7
+    );
+    assert_eq!(
+        manager
+            .sender_outstanding::<ReceiptAggregateVoucher>(other_signer.address())
+            .await
+            .unwrap(),
+        1000
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_sums_sender_outstanding_of_delegated_signers(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context, signer, ..
+    } = context;
+    let sender = Address::from([0x5e; 20]);
+    let rav = ReceiptAggregateVoucher {
+        allocationId: allocation_ids[0],
+        timestampNs: 20,
+        valueAggregate: 100,
+    };
+    context
+        .update_last_rav(
+            sender,
+            allocation_ids[0],
+            Eip712SignedMessage::new(&domain_separator, rav, &signer).unwrap(),
+        )
+        .await
+        .unwrap();
+    // the receipts persist their signer, which signs on behalf of the sender
+    for (allocation_id, value) in [(allocation_ids[0], 5), (allocation_ids[1], 7)] {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_id, value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        context
+            .store_receipt_in_domain(ReceiptWithState::new(signed_receipt), &domain_separator)
+            .await
+            .unwrap();
+    }
+    let manager = Manager::<_, SignedReceipt>::new(
+        domain_separator,
+        DelegatedSigner {
+            context,
+            sender,
+            checked_signatures: Arc::new(Mutex::new(0)),
+        },
+        CheckList::empty(),
+    );
+
+    assert_eq!(
+        manager
+            .sender_outstanding::<ReceiptAggregateVoucher>(sender)
+            .await
+            .unwrap(),
+        100 + 5 + 7
+    );
+    assert_eq!(
+        manager
+            .sender_outstanding::<ReceiptAggregateVoucher>(signer.address())
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_keeps_running_aggregates(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager =
+        Manager::new(domain_separator.clone(), context.clone(), checks).with_running_aggregates();
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+    let receipt_ctx = Context::new();
+    let store_receipt = |allocation_id: Address, value: u128| {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_id, value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        manager.verify_and_store_receipt(&receipt_ctx, signed_receipt)
+    };
+    let ctx = rav_ctx(signer.address(), allocation_ids[0]);
+    let full_scan_rav = || async {
+        manager
+            .create_rav_request::<ReceiptAggregateVoucher>(&ctx, 0, None)
+            .await
+            .unwrap()
+            .expected_rav
+            .unwrap()
+    };
+
+    assert!(matches!(
+        manager.running_rav::<ReceiptAggregateVoucher>(&ctx).await,
+        Err(tap_core::Error::NoValidReceiptsForRavRequest)
+    ));
+    for value in [10, 20, 30] {
+        store_receipt(allocation_ids[0], value).await.unwrap();
+    }
+    store_receipt(allocation_ids[1], 1000).await.unwrap();
+
+    let running_rav = manager
+        .running_rav::<ReceiptAggregateVoucher>(&ctx)
+        .await
+        .unwrap();
+    assert_eq!(running_rav, full_scan_rav().await);
+    assert_eq!(running_rav.valueAggregate, 60);
+
+    // receipts covered by the stored RAV are left out
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, running_rav.clone(), &signer).unwrap();
+    manager
+        .verify_and_store_rav_with_context(&ctx, running_rav, signed_rav)
+        .await
+        .unwrap();
+    store_receipt(allocation_ids[0], 5).await.unwrap();
+    let aggregate = manager
+        .running_aggregate::<ReceiptAggregateVoucher>(&ctx)
+        .await
+        .unwrap();
+    assert_eq!((aggregate.value_aggregate, aggregate.receipt_count), (5, 1));
+    let running_rav = manager
+        .running_rav::<ReceiptAggregateVoucher>(&ctx)
+        .await
+        .unwrap();
+    assert_eq!(running_rav, full_scan_rav().await);
+    assert_eq!(running_rav.valueAggregate, 65);
+
+    // after a restart, the aggregates are rebuilt from storage
+    let restarted = Manager::new(
+        domain_separator.clone(),
+        context.clone(),
+        CheckList::empty(),
+    );
+    assert!(matches!(
+        restarted.running_rav::<ReceiptAggregateVoucher>(&ctx).await,
+        Err(tap_core::Error::RunningAggregatesDisabled)
+    ));
+    let restarted = restarted.with_running_aggregates();
+    assert_eq!(restarted.rebuild_running_aggregates(2).await.unwrap(), 5);
+    assert_eq!(
+        restarted
+            .running_rav::<ReceiptAggregateVoucher>(&ctx)
+            .await
+            .unwrap(),
+        running_rav
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_keeps_running_aggregates_per_sender(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context, signer, ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context, CheckList::empty())
+        .with_running_aggregates();
+    let other_sender = Address::from([0x33u8; 20]);
+    let signer_ctx = rav_ctx(signer.address(), allocation_ids[0]);
+    let other_ctx = rav_ctx(other_sender, allocation_ids[0]);
+    let signed_receipt = |value: u128| {
+        Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], value).unwrap(),
+            &signer,
+        )
+        .unwrap()
+    };
+    let aggregate = |ctx| {
+        let manager = &manager;
+        async move {
+            let aggregate = manager
+                .running_aggregate::<ReceiptAggregateVoucher>(ctx)
+                .await
+                .unwrap();
+            (aggregate.value_aggregate, aggregate.receipt_count)
+        }
+    };
+
+    // without a RAV key in the context, the sender of the signer is used
+    manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt(10))
+        .await
+        .unwrap();
+    manager
+        .verify_and_store_receipt(&other_ctx, signed_receipt(20))
+        .await
+        .unwrap();
+    assert_eq!(aggregate(&signer_ctx).await, (10, 1));
+    assert_eq!(aggregate(&other_ctx).await, (20, 1));
+
+    // a receipt overflowing the aggregate of its key is rejected
+    let ctx = Context::new();
+    assert!(matches!(
+        manager
+            .verify_and_store_receipt(&ctx, signed_receipt(u128::MAX))
+            .await,
+        Err(tap_core::Error::AggregationError(_))
+    ));
+    assert_eq!(aggregate(&signer_ctx).await, (10, 1));
+    assert_eq!(manager.rebuild_running_aggregates(10).await.unwrap(), 2);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_detects_receipt_equivocation(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks)
+        .with_equivocation_detection(1);
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+    let receipt_ctx = Context::new();
+    let sign =
+        |receipt: Receipt| Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
+
+    let receipt = Receipt::new(allocation_ids[0], 10).unwrap();
+    manager
+        .verify_and_store_receipt(&receipt_ctx, sign(receipt.clone()))
+        .await
+        .unwrap();
+
+    // same query id, another value
+    let conflicting_receipt = sign(Receipt {
+        value: 20,
+        ..receipt.clone()
+    });
+    assert!(matches!(
+        manager
+            .verify_and_store_receipt(&receipt_ctx, conflicting_receipt.clone())
+            .await,
+        Err(tap_core::Error::ReceiptEquivocation { query_id }) if query_id == receipt.nonce
+    ));
+    let stored_receipts = context
+        .retrieve_receipts_in_timestamp_range(.., None)
+        .await
+        .unwrap();
+    assert_eq!(stored_receipts.len(), 1);
+    assert_eq!(
+        manager.take_equivocations(),
+        vec![Equivocation {
+            query_id: receipt.nonce,
+            sender: signer.address(),
+            allocation_id: allocation_ids[0],
+            stored_receipt_id: Some(0),
+            stored_value: 10,
+            receipt: conflicting_receipt.clone(),
+        }]
+    );
+    assert!(manager.take_equivocations().is_empty());
+
+    // only the latest rejected receipts are kept
+    for value in [30, 40] {
+        let _ = manager
+            .verify_and_store_receipt(
+                &receipt_ctx,
+                sign(Receipt {
+                    value,
+                    ..receipt.clone()
+                }),
+            )
+            .await;
+    }
+    let equivocations = manager.take_equivocations();
+    assert_eq!(equivocations.len(), 1);
+    assert_eq!(equivocations[0].receipt.message.value, 40);
+
+    // the same query id on another allocation doesn't conflict
+    manager
+        .verify_and_store_receipt(
+            &receipt_ctx,
+            sign(Receipt {
+                allocation_id: allocation_ids[1],
+                value: 20,
+                ..receipt.clone()
+            }),
+        )
+        .await
+        .unwrap();
+
+    // nor does the same query id of another sender
+    manager
+        .verify_and_store_receipt(
+            &rav_ctx(Address::from([0x33u8; 20]), allocation_ids[0]),
+            sign(Receipt {
+                value: 20,
+                ..receipt.clone()
+            }),
+        )
+        .await
+        .unwrap();
+
+    // after a restart, the index is rebuilt from storage
+    let restarted = Manager::new(
+        domain_separator.clone(),
+        context.clone(),
+        CheckList::empty(),
+    );
+    assert!(matches!(
+        restarted.rebuild_query_index(2).await,
+        Err(tap_core::Error::EquivocationDetectionDisabled)
+    ));
+    let restarted = restarted.with_equivocation_detection(0);
+    assert_eq!(restarted.rebuild_query_index(2).await.unwrap(), 3);
+    assert!(matches!(
+        restarted
+            .verify_and_store_receipt(&receipt_ctx, conflicting_receipt)
+            .await,
+        Err(tap_core::Error::ReceiptEquivocation { query_id }) if query_id == receipt.nonce
+    ));
+    assert!(restarted.take_equivocations().is_empty());
+}
@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     str::FromStr,
     sync::{Arc, RwLock},
 };
@@ -15,13 +15,20 @@ use alloy::{
 };
 use rstest::*;
 use tap_core::{
+    audit::{audit_rav_receipts, verify_rav_against_receipts, verify_rav_receipt_commitment},
     manager::{
-        adapters::{RavRead, RavStore},
+        adapters::{RavHistoryRead, RavRead, RavStore},
         context::memory::InMemoryContext,
     },
-    receipt::checks::StatefulTimestampCheck,
-    signed_message::Eip712SignedMessage,
-    tap_eip712_domain,
+    merkle::merkle_root,
+    rav_request::{compute_expected_rav, dual_sign_and_verify, verify_rav_chain},
+    receipt::{
+        checks::{CheckList, StatefulTimestampCheck},
+        rav::AggregationError,
+        Context, ReceiptWithState, WithReceiptHash,
+    },
+    signed_message::{Eip712Error, Eip712MultiSignedMessage, Eip712SignedMessage, MultiSigner},
+    tap_eip712_domain, Error,
 };
 use tap_graph::{Receipt, ReceiptAggregateVoucher};
 
@@ -33,7 +40,7 @@ fn domain_separator() -> Eip712Domain {
 #[fixture]
 fn context() -> InMemoryContext {
     let escrow_storage = Arc::new(RwLock::new(HashMap::new()));
-    let rav_storage = Arc::new(RwLock::new(None));
+    let rav_storage = Arc::new(RwLock::new(HashMap::new()));
     let receipt_storage = Arc::new(RwLock::new(HashMap::new()));
 
     let timestamp_check = Arc::new(StatefulTimestampCheck::new(0));
@@ -117,12 +124,29 @@ async fn rav_storage_adapter_test(domain_separator: Eip712Domain, context: InMem
     )
     .unwrap();
 
-    context.update_last_rav(signed_rav.clone()).await.unwrap();
+    let sender = wallet.address();
+    context
+        .update_last_rav(sender, allocation_id, signed_rav.clone())
+        .await
+        .unwrap();
 
     // Retreive rav
-    let retrieved_rav = context.last_rav().await;
+    let retrieved_rav = context.get_last_rav(sender, allocation_id).await;
     assert!(retrieved_rav.unwrap().unwrap() == signed_rav);
 
+    // RAVs of other senders or allocations are stored separately
+    let other_address = Address::from_str("0xcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcd").unwrap();
+    assert!(context
+        .get_last_rav(sender, other_address)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(context
+        .get_last_rav(other_address, allocation_id)
+        .await
+        .unwrap()
+        .is_none());
+
     // Testing the last rav update...
 
     // Create more receipts
@@ -146,9 +170,492 @@ async fn rav_storage_adapter_test(domain_separator: Eip712Domain, context: InMem
     .unwrap();
 
     // Update the last rav
-    context.update_last_rav(signed_rav.clone()).await.unwrap();
+    context
+        .update_last_rav(sender, allocation_id, signed_rav.clone())
+        .await
+        .unwrap();
 
     // Retreive rav
-    let retrieved_rav = context.last_rav().await;
+    let retrieved_rav = context.get_last_rav(sender, allocation_id).await;
     assert!(retrieved_rav.unwrap().unwrap() == signed_rav);
 }
+
+#[rstest]
+#[tokio::test]
+async fn rav_storage_keeps_history(domain_separator: Eip712Domain, context: InMemoryContext) {
+    let wallet = PrivateKeySigner::random();
+    let sender = wallet.address();
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+
+    let mut ravs = Vec::new();
+    let mut previous_rav = None;
+    for value in [10, 20, 30] {
+        let receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_id, value).unwrap(),
+            &wallet,
+        )
+        .unwrap();
+        let signed_rav = Eip712SignedMessage::new(
+            &domain_separator,
+            ReceiptAggregateVoucher::aggregate_receipts(allocation_id, &[receipt], previous_rav)
+                .unwrap(),
+            &wallet,
+        )
+        .unwrap();
+        context
+            .update_last_rav(sender, allocation_id, signed_rav.clone())
+            .await
+            .unwrap();
+        previous_rav = Some(signed_rav.clone());
+        ravs.push(signed_rav);
+    }
+
+    // the latest RAV is the newest, the prior ones are still there
+    assert_eq!(
+        context.get_last_rav(sender, allocation_id).await.unwrap(),
+        ravs.last().cloned()
+    );
+    assert_eq!(
+        context
+            .get_rav_history(sender, allocation_id)
+            .await
+            .unwrap(),
+        ravs
+    );
+    assert!(context
+        .get_rav_history(allocation_id, sender)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn compute_expected_rav_without_manager(domain_separator: Eip712Domain) {
+    let wallet = PrivateKeySigner::random();
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+    let other_allocation_id =
+        Address::from_str("0xdeaddeaddeaddeaddeaddeaddeaddeaddeaddead").unwrap();
+
+    let mut receipts = Vec::new();
+    for value in 50..60 {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_id, value).unwrap(),
+            &wallet,
+        )
+        .unwrap();
+        receipts.push(
+            ReceiptWithState::new(signed_receipt)
+                .finalize_receipt_checks(&Context::new(), &CheckList::empty())
+                .await
+                .unwrap()
+                .unwrap(),
+        );
+    }
+    let previous_rav = Eip712SignedMessage::new(
+        &domain_separator,
+        ReceiptAggregateVoucher {
+            allocationId: allocation_id,
+            timestampNs: 1,
+            valueAggregate: 1000,
+        },
+        &wallet,
+    )
+    .unwrap();
+
+    let rav: ReceiptAggregateVoucher =
+        compute_expected_rav(allocation_id, Some(previous_rav.clone()), &receipts).unwrap();
+    assert_eq!(rav.allocationId, allocation_id);
+    assert_eq!(rav.valueAggregate, 1000 + (50..60).sum::<u128>());
+
+    assert!(matches!(
+        compute_expected_rav::<_, ReceiptAggregateVoucher>(
+            other_allocation_id,
+            None,
+            &receipts
+        ),
+        Err(AggregationError::AllocationIdMismatch { expected, .. })
+            if expected == other_allocation_id
+    ));
+    let mut other_rav = previous_rav;
+    other_rav.message.allocationId = other_allocation_id;
+    assert!(matches!(
+        compute_expected_rav(allocation_id, Some(other_rav), &receipts),
+        Err(AggregationError::AllocationIdMismatch { received, .. })
+            if received == other_allocation_id
+    ));
+}
+
+#[rstest]
+#[tokio::test]
+async fn audit_rav_against_receipts(
+    domain_separator: Eip712Domain,
+    #[values(0, 10)] tampered_value: u128,
+) {
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+    let wallet = PrivateKeySigner::random();
+    let mut receipts = Vec::new();
+    for value in 50..60 {
+        receipts.push(
+            Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(allocation_id, value).unwrap(),
+                &wallet,
+            )
+            .unwrap(),
+        );
+    }
+
+    let mut rav =
+        ReceiptAggregateVoucher::aggregate_receipts(allocation_id, &receipts, None).unwrap();
+    rav.valueAggregate += tampered_value;
+    let signed_rav = Eip712SignedMessage::new(&domain_separator, rav, &wallet).unwrap();
+
+    let report = verify_rav_against_receipts(&domain_separator, receipts, &signed_rav)
+        .await
+        .unwrap();
+
+    assert_eq!(report.rav_signer, wallet.address());
+    assert!(report.invalid_receipts.is_empty());
+    assert_eq!(report.recomputed_value_aggregate, (50..60).sum::<u128>());
+    assert_eq!(
+        report.claimed_value_aggregate,
+        report.recomputed_value_aggregate + tampered_value
+    );
+    assert_eq!(report.is_consistent(), tampered_value == 0);
+}
+
+#[rstest]
+#[tokio::test]
+async fn audit_rav_reports_receipts_from_other_signer(domain_separator: Eip712Domain) {
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+    let wallet = PrivateKeySigner::random();
+    let other_wallet = PrivateKeySigner::random();
+    let mut receipts = Vec::new();
+    for value in 50..60 {
+        receipts.push(
+            Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(allocation_id, value).unwrap(),
+                &wallet,
+            )
+            .unwrap(),
+        );
+    }
+    let signed_rav = Eip712SignedMessage::new(
+        &domain_separator,
+        ReceiptAggregateVoucher::aggregate_receipts(allocation_id, &receipts, None).unwrap(),
+        &wallet,
+    )
+    .unwrap();
+
+    receipts.push(
+        Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_id, 100).unwrap(),
+            &other_wallet,
+        )
+        .unwrap(),
+    );
+
+    let report = verify_rav_against_receipts(&domain_separator, receipts, &signed_rav)
+        .await
+        .unwrap();
+
+    assert_eq!(report.invalid_receipts.len(), 1);
+    assert_eq!(
+        report.recomputed_value_aggregate,
+        report.claimed_value_aggregate
+    );
+    assert!(!report.is_consistent());
+}
+
+#[rstest]
+#[tokio::test]
+async fn audit_rav_flags_receipts_missing_from_sender_set(domain_separator: Eip712Domain) {
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+    let wallet = PrivateKeySigner::random();
+    let mut receipts = Vec::new();
+    for value in 50..60 {
+        receipts.push(
+            Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(allocation_id, value).unwrap(),
+                &wallet,
+            )
+            .unwrap(),
+        );
+    }
+    let sender_signed_set = receipts
+        .iter()
+        .map(|receipt| receipt.unique_hash())
+        .collect::<HashSet<_>>();
+
+    // the RAV counts one receipt the sender has no record of
+    receipts.push(
+        Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_id, 100).unwrap(),
+            &wallet,
+        )
+        .unwrap(),
+    );
+    let signed_rav = Eip712SignedMessage::new(
+        &domain_separator,
+        ReceiptAggregateVoucher::aggregate_receipts(allocation_id, &receipts, None).unwrap(),
+        &wallet,
+    )
+    .unwrap();
+
+    let report = audit_rav_receipts(&domain_separator, &signed_rav, receipts, sender_signed_set)
+        .await
+        .unwrap();
+
+    assert_eq!(report.rav_signer, wallet.address());
+    assert_eq!(report.flagged_receipts.len(), 1);
+    assert_eq!(
+        report.flagged_receipts[0].signed_receipt().message.value,
+        100
+    );
+    assert_eq!(report.legitimate_value_aggregate, (50..60).sum::<u128>());
+    assert_eq!(
+        report.claimed_value_aggregate,
+        report.legitimate_value_aggregate + 100
+    );
+    assert!(report.is_inflated());
+}
+
+#[rstest]
+fn rav_signed_by_two_signers(domain_separator: Eip712Domain) {
+    let signers = [PrivateKeySigner::random(), PrivateKeySigner::random()];
+    let addresses: Vec<_> = signers.iter().map(PrivateKeySigner::address).collect();
+    let rav = ReceiptAggregateVoucher {
+        allocationId: Address::from([0x11u8; 20]),
+        timestampNs: 10,
+        valueAggregate: 100,
+    };
+    let partial =
+        |signer| Eip712SignedMessage::new(&domain_separator, rav.clone(), signer).unwrap();
+
+    let mut multi_signer =
+        MultiSigner::new(&domain_separator, rav.clone(), addresses.clone(), 2).unwrap();
+    assert_eq!(
+        multi_signer.add_partial(partial(&signers[0])).unwrap(),
+        addresses[0]
+    );
+    assert!(!multi_signer.is_complete());
+    assert!(matches!(
+        multi_signer.add_partial(partial(&signers[0])),
+        Err(Eip712Error::DuplicateSigner(signer)) if signer == addresses[0]
+    ));
+    let outsider = PrivateKeySigner::random();
+    assert!(matches!(
+        multi_signer.add_partial(partial(&outsider)),
+        Err(Eip712Error::UnauthorizedSigner(signer)) if signer == outsider.address()
+    ));
+    multi_signer.add_partial(partial(&signers[1])).unwrap();
+    assert!(multi_signer.is_complete());
+
+    let signed_rav = multi_signer.finalize().unwrap();
+    assert_eq!(signed_rav.message, rav);
+    let mut recovered = signed_rav.recover_signers(&domain_separator).unwrap();
+    recovered.sort();
+    let mut expected = addresses.clone();
+    expected.sort();
+    assert_eq!(recovered, expected);
+    assert!(signed_rav
+        .verify_threshold(&domain_separator, &addresses, 2)
+        .unwrap());
+    // a single signature isn't enough
+    let half_signed_rav = Eip712MultiSignedMessage {
+        message: rav.clone(),
+        signatures: signed_rav.signatures[..1].to_vec(),
+    };
+    assert!(!half_signed_rav
+        .verify_threshold(&domain_separator, &addresses, 2)
+        .unwrap());
+    // and a threshold out of reach of the signers is refused
+    for threshold in [0, 3] {
+        assert!(matches!(
+            signed_rav.verify_threshold(&domain_separator, &addresses, threshold),
+            Err(Eip712Error::InvalidThreshold { signers: 2, .. })
+        ));
+        assert!(matches!(
+            MultiSigner::new(&domain_separator, rav.clone(), addresses.clone(), threshold),
+            Err(Eip712Error::InvalidThreshold { signers: 2, .. })
+        ));
+    }
+    // duplicates don't count as signers
+    assert!(matches!(
+        MultiSigner::new(
+            &domain_separator,
+            rav.clone(),
+            vec![addresses[0], addresses[0]],
+            2
+        ),
+        Err(Eip712Error::InvalidThreshold { signers: 1, .. })
+    ));
+
+    let mut multi_signer = MultiSigner::new(&domain_separator, rav.clone(), addresses, 2).unwrap();
+    multi_signer.add_partial(partial(&signers[1])).unwrap();
+    assert!(matches!(
+        multi_signer.finalize(),
+        Err(Eip712Error::ThresholdNotReached {
+            collected: 1,
+            threshold: 2
+        })
+    ));
+}
+
+#[rstest]
+fn rav_chain_verification(domain_separator: Eip712Domain) {
+    let wallet = PrivateKeySigner::random();
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+    let sign = |timestamp_ns, value_aggregate| {
+        Eip712SignedMessage::new(
+            &domain_separator,
+            ReceiptAggregateVoucher {
+                allocationId: allocation_id,
+                timestampNs: timestamp_ns,
+                valueAggregate: value_aggregate,
+            },
+            &wallet,
+        )
+        .unwrap()
+    };
+
+    let mut ravs = vec![sign(100, 10), sign(200, 30), sign(300, 30), sign(400, 60)];
+    verify_rav_chain(&ravs, &domain_separator, wallet.address()).unwrap();
+    verify_rav_chain::<ReceiptAggregateVoucher>(&[], &domain_separator, wallet.address()).unwrap();
+
+    // a RAV worth less than the one before it breaks the chain
+    ravs[2] = sign(300, 20);
+    assert!(matches!(
+        verify_rav_chain(&ravs, &domain_separator, wallet.address()),
+        Err(Error::BrokenRavChain {
+            index: 2,
+            value_aggregate: 20,
+            previous_value_aggregate: 30,
+            ..
+        })
+    ));
+
+    // and so does a RAV that isn't later than the one before it
+    ravs[2] = sign(200, 40);
+    assert!(matches!(
+        verify_rav_chain(&ravs, &domain_separator, wallet.address()),
+        Err(Error::BrokenRavChain {
+            index: 2,
+            timestamp_ns: 200,
+            previous_timestamp_ns: 200,
+            ..
+        })
+    ));
+
+    // a RAV whose value was raised after signing no longer recovers to the signer
+    ravs[2] = sign(300, 40);
+    verify_rav_chain(&ravs, &domain_separator, wallet.address()).unwrap();
+    ravs[2].message.valueAggregate = 50;
+    assert!(matches!(
+        verify_rav_chain(&ravs, &domain_separator, wallet.address()),
+        Err(Error::InvalidRecoveredSigner { .. })
+    ));
+
+    // and neither does a chain signed by someone else
+    ravs[2] = sign(300, 40);
+    assert!(matches!(
+        verify_rav_chain(
+            &ravs,
+            &domain_separator,
+            PrivateKeySigner::random().address()
+        ),
+        Err(Error::InvalidRecoveredSigner { .. })
+    ));
+}
+
+#[rstest]
+fn rav_receipt_commitment(domain_separator: Eip712Domain) {
+    let wallet = PrivateKeySigner::random();
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+    let receipts: Vec<_> = [10, 20, 30]
+        .into_iter()
+        .map(|value| {
+            Eip712SignedMessage::new(
+                &domain_separator,
+                tap_graph::v2::Receipt::new(
+                    allocation_id,
+                    wallet.address(),
+                    Address::ZERO,
+                    Address::ZERO,
+                    value,
+                )
+                .unwrap(),
+                &wallet,
+            )
+            .unwrap()
+        })
+        .collect();
+    let rav = tap_graph::v2::ReceiptAggregateVoucher::aggregate_receipts(
+        allocation_id,
+        wallet.address(),
+        Address::ZERO,
+        Address::ZERO,
+        &receipts,
+        None,
+    )
+    .unwrap();
+    let receipt_hashes: Vec<_> = receipts.iter().map(|r| r.receipt_hash()).collect();
+
+    // a RAV without a commitment can't be checked against its receipts
+    let signed_rav = Eip712SignedMessage::new(&domain_separator, rav.clone(), &wallet).unwrap();
+    assert!(matches!(
+        verify_rav_receipt_commitment(&signed_rav, &receipts),
+        Err(Error::MissingReceiptCommitment)
+    ));
+
+    let signed_rav = Eip712SignedMessage::new(
+        &domain_separator,
+        rav.with_receipt_commitment(merkle_root(&receipt_hashes)),
+        &wallet,
+    )
+    .unwrap();
+    verify_rav_receipt_commitment(&signed_rav, &receipts).unwrap();
+
+    // a missing receipt or a different order gives another root
+    assert!(matches!(
+        verify_rav_receipt_commitment(&signed_rav, &receipts[..2]),
+        Err(Error::ReceiptCommitmentMismatch { .. })
+    ));
+    let mut tampered = receipts.clone();
+    tampered.swap(0, 1);
+    assert!(matches!(
+        verify_rav_receipt_commitment(&signed_rav, &tampered),
+        Err(Error::ReceiptCommitmentMismatch { .. })
+    ));
+}
+
+#[rstest]
+#[tokio::test]
+async fn dual_sign_rav(domain_separator: Eip712Domain) {
+    let local = PrivateKeySigner::random();
+    let rav = ReceiptAggregateVoucher {
+        allocationId: Address::from_str("0xabababababababababababababababababababab").unwrap(),
+        timestampNs: 100,
+        valueAggregate: 10,
+    };
+
+    // the remote signer holds the same key as the local one
+    let remote = PrivateKeySigner::from_bytes(&local.to_bytes()).unwrap();
+    dual_sign_and_verify(&rav, &domain_separator, &local, &remote)
+        .await
+        .unwrap();
+
+    let remote = PrivateKeySigner::random();
+    assert!(matches!(
+        dual_sign_and_verify(&rav, &domain_separator, &local, &remote).await,
+        Err(Error::SignerMismatch { local: local_address, remote: remote_address })
+            if local_address == local.address() && remote_address == remote.address()
+    ));
+}
@@ -2,18 +2,33 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    num::NonZeroUsize,
     str::FromStr,
     sync::{Arc, RwLock},
 };
 
-use alloy::{dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner};
+use alloy::{
+    dyn_abi::Eip712Domain,
+    primitives::{Address, PrimitiveSignature as Signature, U256},
+    signers::local::PrivateKeySigner,
+};
 use rand::{seq::SliceRandom, thread_rng};
 use rstest::*;
 use tap_core::{
-    manager::{adapters::ReceiptStore, context::memory::InMemoryContext},
-    receipt::{checks::StatefulTimestampCheck, state::Checking, ReceiptWithState},
-    signed_message::Eip712SignedMessage,
+    manager::{
+        adapters::{ReceiptRead, ReceiptStore, StoredReceiptRead},
+        context::memory::InMemoryContext,
+        DomainSeparators,
+    },
+    receipt::{
+        checks::{Check, CheckError, StatefulTimestampCheck},
+        receipt_order_key,
+        state::Checking,
+        Context, ReceiptError, ReceiptWithState,
+    },
+    signature_cache::{SignatureCache, SignerDenylistCheck},
+    signed_message::{verify_same_sender, Eip712Error, Eip712SignedMessage},
     tap_eip712_domain,
 };
 use tap_graph::{Receipt, SignedReceipt};
@@ -26,7 +41,7 @@ fn domain_separator() -> Eip712Domain {
 #[fixture]
 fn context() -> InMemoryContext {
     let escrow_storage = Arc::new(RwLock::new(HashMap::new()));
-    let rav_storage = Arc::new(RwLock::new(None));
+    let rav_storage = Arc::new(RwLock::new(HashMap::new()));
     let receipt_storage = Arc::new(RwLock::new(HashMap::new()));
 
     let timestamp_check = Arc::new(StatefulTimestampCheck::new(0));
@@ -196,3 +211,341 @@ fn safe_truncate_receipts_test(
         );
     }
 }
+
+#[rstest]
+#[test]
+fn high_s_signature_is_rejected_when_strict(domain_separator: Eip712Domain) {
+    // secp256k1 curve order
+    let curve_order =
+        U256::from_str("0xfffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141")
+            .unwrap();
+    let wallet = PrivateKeySigner::random();
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+
+    let mut signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_id, 100).unwrap(),
+        &wallet,
+    )
+    .unwrap();
+    assert_eq!(
+        signed_receipt
+            .recover_signer_strict(&domain_separator)
+            .unwrap(),
+        wallet.address()
+    );
+
+    // same signature in its high-S form, which recovers the same signer
+    let signature = signed_receipt.signature;
+    signed_receipt.signature =
+        Signature::new(signature.r(), curve_order - signature.s(), !signature.v());
+    assert!(signed_receipt.has_high_s_signature());
+    assert_eq!(
+        signed_receipt.recover_signer(&domain_separator).unwrap(),
+        wallet.address()
+    );
+    assert!(matches!(
+        signed_receipt.recover_signer_strict(&domain_separator),
+        Err(Eip712Error::MalleableSignature)
+    ));
+}
+
+#[rstest]
+#[test]
+fn signature_cache_is_keyed_on_domain(domain_separator: Eip712Domain) {
+    let wallet = PrivateKeySigner::random();
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_id, 100).unwrap(),
+        &wallet,
+    )
+    .unwrap();
+    let signature_cache = SignatureCache::new(NonZeroUsize::new(16).unwrap());
+
+    for _ in 0..3 {
+        assert_eq!(
+            signature_cache
+                .recover_signer(&signed_receipt, &domain_separator)
+                .unwrap(),
+            wallet.address()
+        );
+    }
+    assert_eq!(signature_cache.recoveries(), 1);
+
+    // the cached signer is not reused for another domain
+    let other_domain = tap_eip712_domain(2, Address::from([0x11u8; 20]));
+    let other_signer = signature_cache
+        .recover_signer(&signed_receipt, &other_domain)
+        .unwrap();
+    assert_ne!(other_signer, wallet.address());
+    assert_eq!(signature_cache.recoveries(), 2);
+}
+
+#[rstest]
+#[tokio::test]
+async fn signer_denylist_check(domain_separator: Eip712Domain) {
+    let wallet = PrivateKeySigner::random();
+    let other_wallet = PrivateKeySigner::random();
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+    let receipt = |wallet: &PrivateKeySigner| {
+        ReceiptWithState::new(
+            Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(allocation_id, 100).unwrap(),
+                wallet,
+            )
+            .unwrap(),
+        )
+    };
+    let revoked_signers = Arc::new(RwLock::new(HashSet::new()));
+    let signature_cache = SignatureCache::new(NonZeroUsize::new(16).unwrap());
+    let check = SignerDenylistCheck::new(domain_separator.clone(), revoked_signers.clone())
+        .with_signature_cache(signature_cache.clone());
+
+    let first_receipt = receipt(&wallet);
+    assert!(check.check(&Context::new(), &first_receipt).await.is_ok());
+
+    revoked_signers.write().unwrap().insert(wallet.address());
+    let err = check
+        .check(&Context::new(), &receipt(&wallet))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        CheckError::Failed(err) if matches!(
+            err.downcast_ref::<ReceiptError>(),
+            Some(ReceiptError::RevokedSigner { signer }) if *signer == wallet.address()
+        )
+    ));
+    assert!(check
+        .check(&Context::new(), &receipt(&other_wallet))
+        .await
+        .is_ok());
+
+    // the signer of a receipt seen before comes from the cache
+    let recoveries = signature_cache.recoveries();
+    assert!(check.check(&Context::new(), &first_receipt).await.is_err());
+    assert_eq!(signature_cache.recoveries(), recoveries);
+
+    // a revoked signer signing with a previous domain is rejected as well
+    let previous_domain = tap_eip712_domain(1, Address::from([0x22u8; 20]));
+    let domain_separators = DomainSeparators::new(domain_separator.clone());
+    domain_separators.set_previous_domains(vec![previous_domain.clone()]);
+    let check = SignerDenylistCheck::new(domain_separators, revoked_signers);
+    let previous_domain_receipt = ReceiptWithState::new(
+        Eip712SignedMessage::new(
+            &previous_domain,
+            Receipt::new(allocation_id, 100).unwrap(),
+            &wallet,
+        )
+        .unwrap(),
+    );
+    assert!(matches!(
+        check.check(&Context::new(), &previous_domain_receipt).await,
+        Err(CheckError::Failed(_))
+    ));
+    assert!(check
+        .check(&Context::new(), &receipt(&other_wallet))
+        .await
+        .is_ok());
+}
+
+#[rstest]
+#[tokio::test]
+async fn stored_receipt_caches_signer(domain_separator: Eip712Domain, context: InMemoryContext) {
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+    let wallets = [PrivateKeySigner::random(), PrivateKeySigner::random()];
+
+    let mut ids = Vec::new();
+    for wallet in &wallets {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_id, 100).unwrap(),
+            wallet,
+        )
+        .unwrap();
+        ids.push(
+            context
+                .store_receipt_in_domain(ReceiptWithState::new(signed_receipt), &domain_separator)
+                .await
+                .unwrap(),
+        );
+    }
+
+    let mut stored_receipts = context
+        .retrieve_stored_receipts_in_timestamp_range(..)
+        .await
+        .unwrap();
+    stored_receipts.sort_by_key(|stored_receipt| stored_receipt.id());
+    assert_eq!(stored_receipts.len(), 2);
+    for ((stored_receipt, id), wallet) in stored_receipts.iter().zip(ids).zip(&wallets) {
+        assert_eq!(stored_receipt.id(), id);
+        assert_eq!(stored_receipt.signer(), wallet.address());
+        assert_eq!(
+            stored_receipt.signer(),
+            stored_receipt
+                .signed_receipt()
+                .recover_signer(&domain_separator)
+                .unwrap()
+        );
+    }
+}
+
+#[rstest]
+fn batch_has_same_sender(domain_separator: Eip712Domain) {
+    let wallet = PrivateKeySigner::random();
+    let other_wallet = PrivateKeySigner::random();
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+    let sign = |wallet: &PrivateKeySigner, value| {
+        Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_id, value).unwrap(),
+            wallet,
+        )
+        .unwrap()
+    };
+
+    let mut receipts: Vec<SignedReceipt> = (1..=3).map(|value| sign(&wallet, value)).collect();
+    assert_eq!(
+        verify_same_sender(&receipts, &domain_separator).unwrap(),
+        wallet.address()
+    );
+
+    receipts.push(sign(&other_wallet, 4));
+    match verify_same_sender(&receipts, &domain_separator) {
+        Err(tap_core::Error::MixedSenders { expected, received }) => {
+            assert_eq!(expected, wallet.address());
+            assert_eq!(received, other_wallet.address());
+        }
+        result => panic!("Unexpected result: {result:?}"),
+    }
+
+    assert!(matches!(
+        verify_same_sender::<Receipt>(&[], &domain_separator),
+        Err(tap_core::Error::EmptyReceiptBatch)
+    ));
+}
+
+#[rstest]
+fn canonical_json_is_stable(domain_separator: Eip712Domain) {
+    let wallet = PrivateKeySigner::random();
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+    let mut receipt = Receipt::new(allocation_id, u128::MAX).unwrap();
+    receipt.nonce = 42;
+    receipt.timestamp_ns = 1_000;
+    let signed_receipt: SignedReceipt =
+        Eip712SignedMessage::new(&domain_separator, receipt, &wallet).unwrap();
+
+    let canonical = signed_receipt.to_canonical_json();
+    assert!(canonical.starts_with(&format!(
+        r#"{{"message":{{"allocation_id":"{allocation_id:#x}","nonce":42,"timestamp_ns":1000,"value":{}}},"signature":{{"#,
+        u128::MAX
+    )));
+
+    // the same message parsed from JSON with another field order and spacing
+    let reordered = format!(
+        "{{ \"signature\": {},\n  \"message\": {{ \"value\": {}, \"timestamp_ns\": 1000, \"nonce\": 42, \"allocation_id\": \"{allocation_id}\" }} }}",
+        serde_json::to_string(&signed_receipt.signature).unwrap(),
+        u128::MAX,
+    );
+    let parsed: SignedReceipt = serde_json::from_str(&reordered).unwrap();
+    assert_eq!(parsed.to_canonical_json(), canonical);
+    assert_eq!(signed_receipt.to_canonical_json(), canonical);
+}
+
+#[rstest]
+#[tokio::test]
+async fn stored_receipts_by_ids(domain_separator: Eip712Domain, context: InMemoryContext) {
+    let wallet = PrivateKeySigner::random();
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+
+    let mut ids = Vec::new();
+    for value in [10, 20, 30, 40, 50] {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_id, value).unwrap(),
+            &wallet,
+        )
+        .unwrap();
+        ids.push(
+            context
+                .store_receipt_in_domain(ReceiptWithState::new(signed_receipt), &domain_separator)
+                .await
+                .unwrap(),
+        );
+    }
+
+    // in the requested order, unknown ids are skipped
+    let stored_receipts = context
+        .retrieve_stored_receipts_by_ids(&[ids[3], 999, ids[1]])
+        .await
+        .unwrap();
+    assert_eq!(
+        stored_receipts
+            .iter()
+            .map(|stored_receipt| (
+                stored_receipt.id(),
+                stored_receipt.signed_receipt().message.value
+            ))
+            .collect::<Vec<_>>(),
+        vec![(ids[3], 40), (ids[1], 20)]
+    );
+    assert!(stored_receipts
+        .iter()
+        .all(|stored_receipt| stored_receipt.signer() == wallet.address()));
+    assert!(context
+        .retrieve_stored_receipts_by_ids(&[])
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn receipts_with_equal_timestamps_are_ordered_by_hash(
+    domain_separator: Eip712Domain,
+    context: InMemoryContext,
+) {
+    let wallet = PrivateKeySigner::random();
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+
+    // two receipts at timestamp 100, one at timestamp 200
+    for (timestamp_ns, nonce) in [(100, 0), (100, 1), (200, 2)] {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt {
+                allocation_id,
+                timestamp_ns,
+                nonce,
+                value: 10,
+            },
+            &wallet,
+        )
+        .unwrap();
+        context
+            .store_receipt(ReceiptWithState::new(signed_receipt))
+            .await
+            .unwrap();
+    }
+
+    // the page of 2 keeps both receipts of timestamp 100, hash ordered
+    let mut pages = Vec::new();
+    for _ in 0..5 {
+        let page: Vec<_> = context
+            .retrieve_receipts_in_timestamp_range(.., Some(2))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|receipt| receipt.signed_receipt().clone())
+            .collect();
+        assert_eq!(page.len(), 2);
+        assert!(page.is_sorted_by_key(receipt_order_key));
+        pages.push(page);
+    }
+    assert!(pages.windows(2).all(|pages| pages[0] == pages[1]));
+    assert_eq!(
+        receipt_order_key(&pages[0][0]).0,
+        receipt_order_key(&pages[0][1]).0
+    );
+}
@@ -22,6 +22,8 @@
 //! ```
 //!
 
+use std::collections::BTreeMap;
+
 use alloy::{
     dyn_abi::Eip712Domain,
     primitives::{Address, PrimitiveSignature as Signature},
@@ -29,6 +31,11 @@ use alloy::{
     sol_types::SolStruct,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+
+mod multisig;
+
+pub use multisig::{Eip712MultiSignedMessage, MultiSigner};
 
 /// Errors returned by creation of messages and verify signature
 #[derive(thiserror::Error, Debug)]
@@ -40,6 +47,31 @@ pub enum Eip712Error {
     /// `alloy` signature error
     #[error(transparent)]
     SignatureError(#[from] alloy::primitives::SignatureError),
+
+    /// Signature S value is in the upper half of the curve order
+    #[error("Malleable signature: S value must be in the lower half of the curve order")]
+    MalleableSignature,
+
+    /// Signature from a signer outside of the set of a [`MultiSigner`]
+    #[error("Signer {0} is not one of the authorized signers")]
+    UnauthorizedSigner(Address),
+
+    /// Second signature from the same signer of a [`MultiSigner`]
+    #[error("Signer {0} already signed")]
+    DuplicateSigner(Address),
+
+    /// Partial signature of another message than the one of a [`MultiSigner`]
+    #[error("Partial signature is for another message")]
+    MessageMismatch,
+
+    /// Not enough signatures to finalize a [`MultiSigner`]
+    #[error("Only {collected} of the {threshold} required signatures were collected")]
+    ThresholdNotReached { collected: usize, threshold: usize },
+
+    /// Threshold of a [`MultiSigner`] that is zero or more than its number of
+    /// distinct signers
+    #[error("Threshold {threshold} must be between 1 and the {signers} signers")]
+    InvalidThreshold { threshold: usize, signers: usize },
 }
 
 /// EIP712 signed message
@@ -80,6 +112,10 @@ pub struct MessageId(pub [u8; 32]);
 impl<M: SolStruct> Eip712SignedMessage<M> {
     /// Creates a signed message with signed EIP712 hash of `message` using `signing_wallet`
     ///
+    /// The ECDSA nonce is derived from the key and the hash as per RFC 6979,
+    /// so signing the same message with the same key and domain always yields
+    /// the same signature.
+    ///
     /// # Errors
     ///
     /// Returns [`crate::Error::WalletError`] if could not sign using the wallet
@@ -97,6 +133,9 @@ impl<M: SolStruct> Eip712SignedMessage<M> {
     }
 
     /// Recovers and returns the signer of the message from the signature.
+    ///
+    /// Both the low-S and high-S forms of a signature are accepted, see
+    /// [`Eip712SignedMessage::recover_signer_strict`] to reject the latter.
     pub fn recover_signer(&self, domain_separator: &Eip712Domain) -> Result<Address, Eip712Error> {
         let recovery_message_hash = self.message.eip712_signing_hash(domain_separator);
         let recovered_address = self
@@ -105,6 +144,32 @@ impl<M: SolStruct> Eip712SignedMessage<M> {
         Ok(recovered_address)
     }
 
+    /// Same as [`Eip712SignedMessage::recover_signer`], but only accepts
+    /// signatures in their low-S form.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Eip712Error::MalleableSignature`] if the signature is not in
+    /// its low-S form. A high-S signature can be derived from any valid
+    /// signature without the signer's key, so accepting both would give each
+    /// message two distinct valid signatures.
+    ///
+    pub fn recover_signer_strict(
+        &self,
+        domain_separator: &Eip712Domain,
+    ) -> Result<Address, Eip712Error> {
+        if self.has_high_s_signature() {
+            return Err(Eip712Error::MalleableSignature);
+        }
+        self.recover_signer(domain_separator)
+    }
+
+    /// Returns whether the signature is in its high-S form, see
+    /// [`Eip712SignedMessage::recover_signer_strict`]
+    pub fn has_high_s_signature(&self) -> bool {
+        self.signature.normalize_s().is_some()
+    }
+
     /// Checks that receipts signature is valid for given verifying key, returns `Ok(true)` if it is valid.
     ///
     /// # Errors
@@ -126,3 +191,58 @@ impl<M: SolStruct> Eip712SignedMessage<M> {
         MessageId(self.message.eip712_hash_struct().into())
     }
 }
+
+impl<M: SolStruct + Serialize> Eip712SignedMessage<M> {
+    /// Returns the JSON of the signed message in a canonical form: object
+    /// keys sorted by their UTF-8 bytes, no whitespace, and strings and
+    /// numbers formatted as by `serde_json`. The same signed message always
+    /// gives the same bytes, e.g. for systems hashing or deduplicating the
+    /// JSON, whatever the field order of the JSON it was parsed from.
+    ///
+    /// This is unrelated to the EIP-712 hash, which is canonical already.
+    pub fn to_canonical_json(&self) -> String {
+        let json = serde_json::to_string(self).expect("signed messages serialize to JSON");
+        let mut canonical = String::with_capacity(json.len());
+        write_canonical_json(&mut canonical, &json).expect("serde_json output is valid JSON");
+        canonical
+    }
+}
+
+/// Appends the canonical form of the compact JSON `json` to `out`, see
+/// [`Eip712SignedMessage::to_canonical_json`]. Numbers are copied verbatim
+/// so that values beyond `u64` keep their precision.
+fn write_canonical_json(out: &mut String, json: &str) -> Result<(), serde_json::Error> {
+    match json.as_bytes().first() {
+        Some(b'{') => {
+            let object: BTreeMap<String, &RawValue> = serde_json::from_str(json)?;
+            out.push('{');
+            for (index, (key, value)) in object.into_iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::to_string(&key)?);
+                out.push(':');
+                write_canonical_json(out, value.get())?;
+            }
+            out.push('}');
+        }
+        Some(b'[') => {
+            let array: Vec<&RawValue> = serde_json::from_str(json)?;
+            out.push('[');
+            for (index, value) in array.into_iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_canonical_json(out, value.get())?;
+            }
+            out.push(']');
+        }
+        Some(b'"') => {
+            let string: String = serde_json::from_str(json)?;
+            out.push_str(&serde_json::to_string(&string)?);
+        }
+        // numbers, booleans and null
+        _ => out.push_str(json),
+    }
+    Ok(())
+}
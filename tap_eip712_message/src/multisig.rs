@@ -0,0 +1,197 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! # m-of-n signed messages
+//!
+//! A [`MultiSigner`] collects the signatures of the same EIP712 message from
+//! several signers, e.g. independent signing services of an aggregator, into
+//! an [`Eip712MultiSignedMessage`] once `threshold` of them signed. Each
+//! signature is a regular ECDSA signature over the EIP712 hash, so the result
+//! can be checked on-chain by a contract verifying several signatures.
+
+use std::collections::{btree_map::Entry, BTreeMap, BTreeSet};
+
+use alloy::{
+    dyn_abi::Eip712Domain,
+    primitives::{Address, PrimitiveSignature as Signature, B256},
+    sol_types::SolStruct,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{Eip712Error, Eip712SignedMessage};
+
+/// EIP712 message signed by several signers
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Eip712MultiSignedMessage<M: SolStruct> {
+    /// Message to be signed
+    pub message: M,
+    /// ECDSA signatures of the eip712 hash of message, ordered by signer address
+    pub signatures: Vec<Signature>,
+}
+
+impl<M: SolStruct> Eip712MultiSignedMessage<M> {
+    /// Recovers the signer of each signature, in the order of `signatures`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Eip712Error::MalleableSignature`] if a signature is not in its
+    /// low-S form
+    ///
+    pub fn recover_signers(
+        &self,
+        domain_separator: &Eip712Domain,
+    ) -> Result<Vec<Address>, Eip712Error> {
+        let message_hash = self.message.eip712_signing_hash(domain_separator);
+        self.signatures
+            .iter()
+            .map(|signature| recover(signature, &message_hash))
+            .collect()
+    }
+
+    /// Returns `true` if at least `threshold` distinct signers of `signers`
+    /// signed the message
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Eip712Error::MalleableSignature`] if a signature is not in its
+    /// low-S form
+    ///
+    /// Returns [`Eip712Error::InvalidThreshold`] if `threshold` is zero or more
+    /// than the number of distinct `signers`
+    ///
+    pub fn verify_threshold(
+        &self,
+        domain_separator: &Eip712Domain,
+        signers: &[Address],
+        threshold: usize,
+    ) -> Result<bool, Eip712Error> {
+        validate_threshold(signers, threshold)?;
+        let mut recovered = self.recover_signers(domain_separator)?;
+        recovered.retain(|signer| signers.contains(signer));
+        recovered.sort_unstable();
+        recovered.dedup();
+        Ok(recovered.len() >= threshold)
+    }
+}
+
+/// Collects the signatures of `message` from a set of authorized signers
+/// until `threshold` of them signed
+#[derive(Debug)]
+pub struct MultiSigner<M: SolStruct> {
+    message: M,
+    message_hash: B256,
+    signers: Vec<Address>,
+    threshold: usize,
+    signatures: BTreeMap<Address, Signature>,
+}
+
+impl<M: SolStruct> MultiSigner<M> {
+    /// Creates a collector of signatures of `message` requiring `threshold`
+    /// of `signers`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Eip712Error::InvalidThreshold`] if `threshold` is zero or more
+    /// than the number of distinct `signers`, as it could never be reached
+    ///
+    pub fn new(
+        domain_separator: &Eip712Domain,
+        message: M,
+        signers: Vec<Address>,
+        threshold: usize,
+    ) -> Result<Self, Eip712Error> {
+        validate_threshold(&signers, threshold)?;
+        let message_hash = message.eip712_signing_hash(domain_separator);
+        Ok(Self {
+            message,
+            message_hash,
+            signers,
+            threshold,
+            signatures: BTreeMap::new(),
+        })
+    }
+
+    /// Adds the signature of one of the signers, returning its address
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Eip712Error::UnauthorizedSigner`] if the signature isn't from
+    /// one of the signers
+    ///
+    /// Returns [`Eip712Error::DuplicateSigner`] if the signer already signed
+    ///
+    /// Returns [`Eip712Error::MalleableSignature`] if the signature is not in
+    /// its low-S form
+    ///
+    pub fn add_signature(&mut self, signature: Signature) -> Result<Address, Eip712Error> {
+        let signer = recover(&signature, &self.message_hash)?;
+        if !self.signers.contains(&signer) {
+            return Err(Eip712Error::UnauthorizedSigner(signer));
+        }
+        match self.signatures.entry(signer) {
+            Entry::Occupied(_) => Err(Eip712Error::DuplicateSigner(signer)),
+            Entry::Vacant(entry) => {
+                entry.insert(signature);
+                Ok(signer)
+            }
+        }
+    }
+
+    /// Adds the signature of a partially signed message, see
+    /// [`MultiSigner::add_signature`]
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Eip712Error::MessageMismatch`] if `partial` signs another
+    /// message
+    ///
+    pub fn add_partial(&mut self, partial: Eip712SignedMessage<M>) -> Result<Address, Eip712Error> {
+        if partial.message.eip712_hash_struct() != self.message.eip712_hash_struct() {
+            return Err(Eip712Error::MessageMismatch);
+        }
+        self.add_signature(partial.signature)
+    }
+
+    /// Returns `true` once `threshold` signers signed
+    pub fn is_complete(&self) -> bool {
+        self.signatures.len() >= self.threshold
+    }
+
+    /// Returns the message signed by all the collected signatures
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Eip712Error::ThresholdNotReached`] if fewer than `threshold`
+    /// signers signed
+    ///
+    pub fn finalize(self) -> Result<Eip712MultiSignedMessage<M>, Eip712Error> {
+        if !self.is_complete() {
+            return Err(Eip712Error::ThresholdNotReached {
+                collected: self.signatures.len(),
+                threshold: self.threshold,
+            });
+        }
+        Ok(Eip712MultiSignedMessage {
+            message: self.message,
+            signatures: self.signatures.into_values().collect(),
+        })
+    }
+}
+
+fn validate_threshold(signers: &[Address], threshold: usize) -> Result<(), Eip712Error> {
+    let distinct_signers = signers.iter().collect::<BTreeSet<_>>().len();
+    if threshold == 0 || threshold > distinct_signers {
+        return Err(Eip712Error::InvalidThreshold {
+            threshold,
+            signers: distinct_signers,
+        });
+    }
+    Ok(())
+}
+
+fn recover(signature: &Signature, message_hash: &B256) -> Result<Address, Eip712Error> {
+    if signature.normalize_s().is_some() {
+        return Err(Eip712Error::MalleableSignature);
+    }
+    Ok(signature.recover_address_from_prehash(message_hash)?)
+}
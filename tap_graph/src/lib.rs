@@ -11,4 +11,7 @@ mod v1;
 #[cfg(any(test, feature = "v2"))]
 pub mod v2;
 
+#[cfg(any(test, feature = "v3"))]
+pub mod v3;
+
 pub use v1::{Receipt, ReceiptAggregateVoucher, SignedRav, SignedReceipt};
@@ -43,9 +43,9 @@ use alloy::{primitives::Address, sol};
 use serde::{Deserialize, Serialize};
 use tap_eip712_message::Eip712SignedMessage;
 use tap_receipt::{
-    rav::{Aggregate, AggregationError},
+    rav::{Aggregate, AggregationError, FromAggregate},
     state::Checked,
-    ReceiptWithState, WithValueAndTimestamp,
+    ReceiptWithState, WithAllocationId, WithValueAndTimestamp,
 };
 
 use super::{Receipt, SignedReceipt};
@@ -108,6 +108,63 @@ impl ReceiptAggregateVoucher {
             valueAggregate: value_aggregate,
         })
     }
+
+    /// Merges two RAVs for the same allocation into one, e.g. RAVs produced by
+    /// two aggregator instances or before and after a key rotation.
+    /// The merged RAV has the latest timestamp of both and the sum of their
+    /// values, so it can be used as the previous RAV of the next aggregation.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AggregationError::AllocationIdMismatch`] if the RAVs are for
+    /// different allocations
+    ///
+    /// Returns [`AggregationError::AggregateOverflow`] if the sum of both
+    /// values overflows
+    pub fn merge(a: &Self, b: &Self) -> Result<Self, AggregationError> {
+        if a.allocationId != b.allocationId {
+            return Err(AggregationError::AllocationIdMismatch {
+                expected: a.allocationId,
+                received: b.allocationId,
+            });
+        }
+        Ok(Self {
+            allocationId: a.allocationId,
+            timestampNs: cmp::max(a.timestampNs, b.timestampNs),
+            valueAggregate: a
+                .valueAggregate
+                .checked_add(b.valueAggregate)
+                .ok_or(AggregationError::AggregateOverflow)?,
+        })
+    }
+
+    /// Splits the RAV in two for partial redemption: a RAV for `amount` and a
+    /// RAV for the remainder, both with the allocation id and timestamp of
+    /// this RAV. Neither is signed, both must be signed again before they can
+    /// be redeemed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AggregationError::SplitExceedsAggregate`] if `amount` is
+    /// more than the value of the RAV
+    pub fn split(&self, amount: u128) -> Result<(Self, Self), AggregationError> {
+        let remainder = self.valueAggregate.checked_sub(amount).ok_or(
+            AggregationError::SplitExceedsAggregate {
+                amount,
+                value_aggregate: self.valueAggregate,
+            },
+        )?;
+        Ok((
+            Self {
+                valueAggregate: amount,
+                ..self.clone()
+            },
+            Self {
+                valueAggregate: remainder,
+                ..self.clone()
+            },
+        ))
+    }
 }
 
 impl Aggregate<SignedReceipt> for ReceiptAggregateVoucher {
@@ -131,6 +188,22 @@ impl Aggregate<SignedReceipt> for ReceiptAggregateVoucher {
     }
 }
 
+impl FromAggregate for ReceiptAggregateVoucher {
+    fn from_aggregate(allocation_id: Address, timestamp_ns: u64, value_aggregate: u128) -> Self {
+        Self {
+            allocationId: allocation_id,
+            timestampNs: timestamp_ns,
+            valueAggregate: value_aggregate,
+        }
+    }
+}
+
+impl WithAllocationId for ReceiptAggregateVoucher {
+    fn allocation_id(&self) -> Address {
+        self.allocationId
+    }
+}
+
 impl WithValueAndTimestamp for ReceiptAggregateVoucher {
     fn value(&self) -> u128 {
         self.valueAggregate
@@ -140,3 +213,138 @@ impl WithValueAndTimestamp for ReceiptAggregateVoucher {
         self.timestampNs
     }
 }
+
+#[cfg(test)]
+mod rav_unit_test {
+    use std::str::FromStr;
+
+    use alloy::{
+        dyn_abi::Eip712Domain, signers::local::PrivateKeySigner, sol_types::eip712_domain,
+    };
+    use rstest::*;
+
+    use super::*;
+
+    #[fixture]
+    fn allocation_ids() -> Vec<Address> {
+        vec![
+            Address::from_str("0xabababababababababababababababababababab").unwrap(),
+            Address::from_str("0xdeaddeaddeaddeaddeaddeaddeaddeaddeaddead").unwrap(),
+        ]
+    }
+
+    #[fixture]
+    fn domain_separator() -> Eip712Domain {
+        eip712_domain! {
+            name: "TAP",
+            version: "1",
+            chain_id: 1,
+            verifying_contract: Address::from([0x11u8; 20]),
+        }
+    }
+
+    #[rstest]
+    fn merge_same_allocation(allocation_ids: Vec<Address>, domain_separator: Eip712Domain) {
+        let wallet = PrivateKeySigner::random();
+        let receipts = (1..=4u128)
+            .map(|value| {
+                let mut receipt = Receipt::new(allocation_ids[0], value).unwrap();
+                receipt.timestamp_ns = value as u64;
+                Eip712SignedMessage::new(&domain_separator, receipt, &wallet).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let a =
+            ReceiptAggregateVoucher::aggregate_receipts(allocation_ids[0], &receipts[..2], None)
+                .unwrap();
+        let b =
+            ReceiptAggregateVoucher::aggregate_receipts(allocation_ids[0], &receipts[2..3], None)
+                .unwrap();
+        let merged = ReceiptAggregateVoucher::merge(&a, &b).unwrap();
+
+        assert_eq!(merged.allocationId, allocation_ids[0]);
+        assert_eq!(merged.timestampNs, 3);
+        assert_eq!(merged.valueAggregate, 1 + 2 + 3);
+
+        // the merged RAV chains like any previous RAV
+        let signed_merged = Eip712SignedMessage::new(&domain_separator, merged, &wallet).unwrap();
+        let next = ReceiptAggregateVoucher::aggregate_receipts(
+            allocation_ids[0],
+            &receipts[3..],
+            Some(signed_merged),
+        )
+        .unwrap();
+        assert_eq!(
+            next,
+            ReceiptAggregateVoucher::aggregate_receipts(allocation_ids[0], &receipts, None)
+                .unwrap()
+        );
+    }
+
+    #[rstest]
+    fn merge_rejects_different_allocations(allocation_ids: Vec<Address>) {
+        let a = ReceiptAggregateVoucher {
+            allocationId: allocation_ids[0],
+            timestampNs: 10,
+            valueAggregate: 100,
+        };
+        let b = ReceiptAggregateVoucher {
+            allocationId: allocation_ids[1],
+            timestampNs: 20,
+            valueAggregate: 200,
+        };
+
+        assert!(matches!(
+            ReceiptAggregateVoucher::merge(&a, &b),
+            Err(AggregationError::AllocationIdMismatch { expected, received })
+                if expected == allocation_ids[0] && received == allocation_ids[1]
+        ));
+    }
+
+    #[rstest]
+    fn split_rav(allocation_ids: Vec<Address>, domain_separator: Eip712Domain) {
+        let rav = ReceiptAggregateVoucher {
+            allocationId: allocation_ids[0],
+            timestampNs: 10,
+            valueAggregate: 100,
+        };
+
+        let (redeemed, remainder) = rav.split(30).unwrap();
+        assert_eq!(redeemed.valueAggregate, 30);
+        assert_eq!(remainder.valueAggregate, 70);
+        for part in [&redeemed, &remainder] {
+            assert_eq!(part.allocationId, rav.allocationId);
+            assert_eq!(part.timestampNs, rav.timestampNs);
+        }
+        assert_eq!(
+            ReceiptAggregateVoucher::merge(&redeemed, &remainder).unwrap(),
+            rav
+        );
+
+        // each part needs a signature of its own
+        let wallet = PrivateKeySigner::random();
+        let signed_rav = Eip712SignedMessage::new(&domain_separator, rav.clone(), &wallet).unwrap();
+        let signed_part = Eip712SignedMessage::new(&domain_separator, redeemed, &wallet).unwrap();
+        assert_ne!(signed_part.signature, signed_rav.signature);
+
+        let (all, nothing) = rav.split(100).unwrap();
+        assert_eq!((all.valueAggregate, nothing.valueAggregate), (100, 0));
+    }
+
+    #[rstest]
+    fn split_rejects_amount_above_aggregate(allocation_ids: Vec<Address>) {
+        let rav = ReceiptAggregateVoucher {
+            allocationId: allocation_ids[0],
+            timestampNs: 10,
+            valueAggregate: 100,
+        };
+
+        assert!(matches!(
+            rav.split(101),
+            Err(AggregationError::SplitExceedsAggregate {
+                amount: 101,
+                value_aggregate: 100
+            })
+        ));
+    }
+}
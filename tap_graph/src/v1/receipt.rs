@@ -14,7 +14,7 @@ use alloy::{primitives::Address, sol};
 use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 use tap_eip712_message::Eip712SignedMessage;
-use tap_receipt::WithValueAndTimestamp;
+use tap_receipt::{WithAllocationId, WithNonce, WithValueAndTimestamp};
 
 /// A Receipt wrapped in an Eip712SignedMessage
 pub type SignedReceipt = Eip712SignedMessage<Receipt>;
@@ -39,7 +39,15 @@ fn get_current_timestamp_u64_ns() -> Result<u64, SystemTimeError> {
 }
 
 impl Receipt {
-    /// Returns a receipt with provided values
+    /// Schema version of this receipt type, to send along with it in a
+    /// [`VersionedReceipt`](tap_receipt::VersionedReceipt)
+    pub const VERSION: u8 = 1;
+
+    /// Returns a receipt with provided values.
+    ///
+    /// A `value` of zero is allowed, e.g. for free queries. Receivers that
+    /// don't accept them can reject them with
+    /// [`NonZeroValueCheck`](tap_receipt::checks::NonZeroValueCheck).
     pub fn new(allocation_id: Address, value: u128) -> Result<Self, SystemTimeError> {
         let timestamp_ns = get_current_timestamp_u64_ns()?;
         let nonce = thread_rng().gen::<u64>();
@@ -52,6 +60,18 @@ impl Receipt {
     }
 }
 
+impl WithAllocationId for Receipt {
+    fn allocation_id(&self) -> Address {
+        self.allocation_id
+    }
+}
+
+impl WithNonce for Receipt {
+    fn nonce(&self) -> u64 {
+        self.nonce
+    }
+}
+
 impl WithValueAndTimestamp for Receipt {
     fn value(&self) -> u128 {
         self.value
@@ -101,6 +121,13 @@ mod receipt_unit_test {
         assert!(receipt.timestamp_ns >= now - 5000000); // 5 second tolerance
     }
 
+    #[rstest]
+    fn test_new_receipt_with_zero_value(allocation_ids: Vec<Address>) {
+        // zero-value receipts are allowed, NonZeroValueCheck rejects them
+        let receipt = Receipt::new(allocation_ids[0], 0).unwrap();
+        assert_eq!(receipt.value, 0);
+    }
+
     #[rstest]
     fn test_unique_nonce_and_timestamp(allocation_ids: Vec<Address>) {
         let value = 1234;
@@ -5,4 +5,6 @@ mod rav;
 mod receipt;
 
 pub use rav::{ReceiptAggregateVoucher, SignedRav};
-pub use receipt::{Receipt, SignedReceipt};
+pub use receipt::{
+    upgrade_receipt, upgrade_signed_receipt, Receipt, SignedReceipt, UpgradedReceipt,
+};
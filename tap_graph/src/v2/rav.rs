@@ -14,7 +14,7 @@ use tap_eip712_message::Eip712SignedMessage;
 use tap_receipt::{
     rav::{Aggregate, AggregationError},
     state::Checked,
-    ReceiptWithState, WithValueAndTimestamp,
+    ReceiptWithState, WithAllocationId, WithReceiptCommitment, WithValueAndTimestamp,
 };
 
 use super::{Receipt, SignedReceipt};
@@ -64,6 +64,33 @@ impl ReceiptAggregateVoucher {
     ) -> Result<Self, AggregationError> {
         //TODO(#29): When receipts in flight struct in created check that the state
         // of every receipt is OK with all checks complete (relies on #28)
+        Self::aggregate_messages(
+            allocation_id,
+            payer,
+            data_service,
+            service_provider,
+            receipts.iter().map(|receipt| &receipt.message),
+            previous_rav,
+        )
+    }
+
+    /// Same as [`ReceiptAggregateVoucher::aggregate_receipts`], for receipt
+    /// messages without their signature, e.g. the messages of
+    /// [`UpgradedReceipt`](super::UpgradedReceipt)s whose v1 signature was
+    /// verified already.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AggregateOverflow`] if any receipt value causes aggregate
+    /// value to overflow
+    pub fn aggregate_messages<'a>(
+        allocation_id: Address,
+        payer: Address,
+        data_service: Address,
+        service_provider: Address,
+        receipts: impl IntoIterator<Item = &'a Receipt>,
+        previous_rav: Option<Eip712SignedMessage<Self>>,
+    ) -> Result<Self, AggregationError> {
         // If there is a previous RAV get initialize values from it, otherwise get default values
         let mut timestamp_max = 0u64;
         let mut value_aggregate = 0u128;
@@ -75,10 +102,10 @@ impl ReceiptAggregateVoucher {
 
         for receipt in receipts {
             value_aggregate = value_aggregate
-                .checked_add(receipt.message.value)
+                .checked_add(receipt.value)
                 .ok_or(AggregationError::AggregateOverflow)?;
 
-            timestamp_max = cmp::max(timestamp_max, receipt.message.timestamp_ns)
+            timestamp_max = cmp::max(timestamp_max, receipt.timestamp_ns)
         }
 
         Ok(Self {
@@ -91,6 +118,13 @@ impl ReceiptAggregateVoucher {
             metadata: Bytes::new(),
         })
     }
+
+    /// Embeds `receipts_root`, the Merkle root of the receipts aggregated
+    /// into the RAV, as its metadata, see [`WithReceiptCommitment`]
+    pub fn with_receipt_commitment(mut self, receipts_root: [u8; 32]) -> Self {
+        self.metadata = Bytes::copy_from_slice(&receipts_root);
+        self
+    }
 }
 
 impl Aggregate<SignedReceipt> for ReceiptAggregateVoucher {
@@ -120,6 +154,12 @@ impl Aggregate<SignedReceipt> for ReceiptAggregateVoucher {
     }
 }
 
+impl WithAllocationId for ReceiptAggregateVoucher {
+    fn allocation_id(&self) -> Address {
+        self.allocationId
+    }
+}
+
 impl WithValueAndTimestamp for ReceiptAggregateVoucher {
     fn value(&self) -> u128 {
         self.valueAggregate
@@ -129,3 +169,10 @@ impl WithValueAndTimestamp for ReceiptAggregateVoucher {
         self.timestampNs
     }
 }
+
+/// The commitment is the metadata of the RAV when it is exactly 32 bytes long
+impl WithReceiptCommitment for ReceiptAggregateVoucher {
+    fn receipt_commitment(&self) -> Option<[u8; 32]> {
+        self.metadata.as_ref().try_into().ok()
+    }
+}
@@ -5,11 +5,11 @@
 
 use std::time::{SystemTime, SystemTimeError, UNIX_EPOCH};
 
-use alloy::{primitives::Address, sol};
+use alloy::{dyn_abi::Eip712Domain, primitives::Address, sol};
 use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
-use tap_eip712_message::Eip712SignedMessage;
-use tap_receipt::WithValueAndTimestamp;
+use tap_eip712_message::{Eip712Error, Eip712SignedMessage};
+use tap_receipt::{WithAllocationId, WithNonce, WithValueAndTimestamp};
 
 /// A signed receipt message
 pub type SignedReceipt = Eip712SignedMessage<Receipt>;
@@ -41,6 +41,10 @@ fn get_current_timestamp_u64_ns() -> Result<u64, SystemTimeError> {
     Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64)
 }
 impl Receipt {
+    /// Schema version of this receipt type, to send along with it in a
+    /// [`VersionedReceipt`](tap_receipt::VersionedReceipt)
+    pub const VERSION: u8 = 2;
+
     /// Returns a receipt with provided values
     pub fn new(
         allocation_id: Address,
@@ -63,6 +67,75 @@ impl Receipt {
     }
 }
 
+/// Upgrades a v1 receipt to v2 so that stored v1 receipts can still be
+/// aggregated along with v2 ones. The fields added in v2, `payer`,
+/// `data_service` and `service_provider`, are set to the zero address.
+pub fn upgrade_receipt(v1: crate::v1::Receipt) -> Receipt {
+    Receipt {
+        allocation_id: v1.allocation_id,
+        payer: Address::ZERO,
+        data_service: Address::ZERO,
+        service_provider: Address::ZERO,
+        timestamp_ns: v1.timestamp_ns,
+        nonce: v1.nonce,
+        value: v1.value,
+    }
+}
+
+/// v1 receipt upgraded to v2 with [`upgrade_signed_receipt`], along with the
+/// signer recovered from its v1 signature. The v1 signature doesn't verify
+/// against the v2 type hash, so it isn't kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpgradedReceipt {
+    pub message: Receipt,
+    pub signer: Address,
+}
+
+/// Same as [`upgrade_receipt`], recovering the signer of the v1 receipt
+/// against `domain_separator` first.
+///
+/// # Errors
+///
+/// Returns an error if the signer can't be recovered from the v1 signature
+///
+pub fn upgrade_signed_receipt(
+    v1: &crate::v1::SignedReceipt,
+    domain_separator: &Eip712Domain,
+) -> Result<UpgradedReceipt, Eip712Error> {
+    Ok(UpgradedReceipt {
+        signer: v1.recover_signer(domain_separator)?,
+        message: upgrade_receipt(v1.message.clone()),
+    })
+}
+
+impl WithAllocationId for UpgradedReceipt {
+    fn allocation_id(&self) -> Address {
+        self.message.allocation_id
+    }
+}
+
+impl WithValueAndTimestamp for UpgradedReceipt {
+    fn value(&self) -> u128 {
+        self.message.value
+    }
+
+    fn timestamp_ns(&self) -> u64 {
+        self.message.timestamp_ns
+    }
+}
+
+impl WithAllocationId for Receipt {
+    fn allocation_id(&self) -> Address {
+        self.allocation_id
+    }
+}
+
+impl WithNonce for Receipt {
+    fn nonce(&self) -> u64 {
+        self.nonce
+    }
+}
+
 impl WithValueAndTimestamp for Receipt {
     fn value(&self) -> u128 {
         self.value
@@ -77,10 +150,14 @@ impl WithValueAndTimestamp for Receipt {
 mod receipt_unit_test {
     use std::time::{SystemTime, UNIX_EPOCH};
 
-    use alloy::primitives::address;
+    use alloy::{
+        dyn_abi::Eip712Domain, primitives::address, signers::local::PrivateKeySigner,
+        sol_types::eip712_domain,
+    };
     use rstest::*;
 
     use super::*;
+    use crate::v2::ReceiptAggregateVoucher;
 
     #[fixture]
     fn allocation_id() -> Address {
@@ -118,6 +195,85 @@ mod receipt_unit_test {
         Receipt::new(allocation_id, payer, data_service, service_provider, value).unwrap()
     }
 
+    #[fixture]
+    fn domain_separator() -> Eip712Domain {
+        eip712_domain! {
+            name: "TAP",
+            version: "1",
+            chain_id: 1,
+            verifying_contract: Address::from([0x11u8; 20]),
+        }
+    }
+
+    #[rstest]
+    fn test_upgrade_receipt(allocation_id: Address, value: u128) {
+        let v1 = crate::v1::Receipt::new(allocation_id, value).unwrap();
+        let v2 = upgrade_receipt(v1.clone());
+
+        assert_eq!(v2.allocation_id, v1.allocation_id);
+        assert_eq!(v2.payer, Address::ZERO);
+        assert_eq!(v2.data_service, Address::ZERO);
+        assert_eq!(v2.service_provider, Address::ZERO);
+        assert_eq!(v2.timestamp_ns, v1.timestamp_ns);
+        assert_eq!(v2.nonce, v1.nonce);
+        assert_eq!(v2.value, v1.value);
+    }
+
+    #[rstest]
+    fn test_aggregate_upgraded_receipts(
+        allocation_id: Address,
+        payer: Address,
+        data_service: Address,
+        service_provider: Address,
+        domain_separator: Eip712Domain,
+    ) {
+        let wallet = PrivateKeySigner::random();
+        // a backlog of v1 receipts followed by v2 receipts
+        let upgraded = (1..=3u128)
+            .map(|value| {
+                let receipt = crate::v1::Receipt::new(allocation_id, value).unwrap();
+                let signed = Eip712SignedMessage::new(&domain_separator, receipt, &wallet).unwrap();
+                upgrade_signed_receipt(&signed, &domain_separator).unwrap()
+            })
+            .collect::<Vec<_>>();
+        // the signer comes from the v1 signature
+        assert!(upgraded
+            .iter()
+            .all(|receipt| receipt.signer == wallet.address()));
+        let receipts = (4..=5u128)
+            .map(|value| {
+                let receipt =
+                    Receipt::new(allocation_id, payer, data_service, service_provider, value)
+                        .unwrap();
+                Eip712SignedMessage::new(&domain_separator, receipt, &wallet).unwrap()
+            })
+            .collect::<Vec<_>>();
+        let messages = upgraded
+            .iter()
+            .map(|receipt| &receipt.message)
+            .chain(receipts.iter().map(|receipt| &receipt.message))
+            .collect::<Vec<_>>();
+
+        let rav = ReceiptAggregateVoucher::aggregate_messages(
+            allocation_id,
+            payer,
+            data_service,
+            service_provider,
+            messages.iter().copied(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(rav.valueAggregate, 1 + 2 + 3 + 4 + 5);
+        assert_eq!(
+            rav.timestampNs,
+            messages
+                .iter()
+                .map(|receipt| receipt.timestamp_ns)
+                .max()
+                .unwrap()
+        );
+    }
+
     #[rstest]
     fn test_new_receipt(allocation_id: Address, value: u128, receipt: Receipt) {
         assert_eq!(receipt.allocation_id, allocation_id);
@@ -0,0 +1,23 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! # Receipts and RAVs v3
+//!
+//! Same as v2, plus the `token` the value is denominated in, so
+//! that payments in different tokens are never mixed. A RAV aggregates the
+//! receipts of a single allocation and a single token.
+//!
+//! ## Migrating from v2
+//!
+//! The `token` field changes the EIP-712 type hash, so v2 and v3 messages
+//! can't be converted into one another: a v3 receipt must be signed as such
+//! by the sender, and a v2 RAV can't be the previous RAV of a v3 aggregation.
+//! Redeem the last v2 RAV of an allocation, then start aggregating v3
+//! receipts without a previous RAV. Escrow must be looked up per
+//! `(sender, token)`, see `tap_core::manager::checks::TokenEscrowCheck`.
+
+mod rav;
+mod receipt;
+
+pub use rav::{ReceiptAggregateVoucher, SignedRav};
+pub use receipt::{Receipt, SignedReceipt};
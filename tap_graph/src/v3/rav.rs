@@ -0,0 +1,246 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! # Receipt Aggregate Voucher v3
+
+use std::cmp;
+
+use alloy::{
+    primitives::{Address, Bytes},
+    sol,
+};
+use serde::{Deserialize, Serialize};
+use tap_eip712_message::Eip712SignedMessage;
+use tap_receipt::{
+    rav::{Aggregate, AggregationError},
+    state::Checked,
+    ReceiptWithState, WithAllocationId, WithReceiptCommitment, WithToken, WithValueAndTimestamp,
+};
+
+use super::{Receipt, SignedReceipt};
+
+/// EIP712 signed message for ReceiptAggregateVoucher
+pub type SignedRav = Eip712SignedMessage<ReceiptAggregateVoucher>;
+
+sol! {
+    /// Holds information needed for promise of payment signed with ECDSA
+    ///
+    /// We use camelCase for field names to match the Ethereum ABI encoding
+    #[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+    struct ReceiptAggregateVoucher {
+        /// Unique allocation id this RAV belongs to
+        address allocationId;
+        // The address of the payer the RAV was issued by
+        address payer;
+        // The address of the data service the RAV was issued to
+        address dataService;
+        // The address of the service provider the RAV was issued to
+        address serviceProvider;
+        // The RAV timestamp, indicating the latest TAP Receipt in the RAV
+        uint64 timestampNs;
+        // The payment token all aggregated receipts are denominated in
+        address token;
+        // Total amount owed to the service provider since the beginning of the
+        // payer-service provider relationship, including all debt that is already paid for.
+        uint128 valueAggregate;
+        // Arbitrary metadata to extend functionality if a data service requires it
+        bytes metadata;
+    }
+}
+
+impl ReceiptAggregateVoucher {
+    /// Aggregates a batch of validated receipts with optional validated previous RAV,
+    /// returning a new RAV if all provided items are valid or an error if not.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AggregationError::AggregateOverflow`] if any receipt value causes aggregate
+    /// value to overflow
+    ///
+    /// Returns [`AggregationError::TokenMismatch`] if a receipt or the previous
+    /// RAV isn't denominated in `token`
+    #[allow(clippy::too_many_arguments)]
+    pub fn aggregate_receipts(
+        allocation_id: Address,
+        payer: Address,
+        data_service: Address,
+        service_provider: Address,
+        token: Address,
+        receipts: &[Eip712SignedMessage<Receipt>],
+        previous_rav: Option<Eip712SignedMessage<Self>>,
+    ) -> Result<Self, AggregationError> {
+        // If there is a previous RAV get initialize values from it, otherwise get default values
+        let mut timestamp_max = 0u64;
+        let mut value_aggregate = 0u128;
+
+        if let Some(prev_rav) = previous_rav {
+            check_token(token, prev_rav.message.token)?;
+            timestamp_max = prev_rav.message.timestampNs;
+            value_aggregate = prev_rav.message.valueAggregate;
+        }
+
+        for receipt in receipts {
+            check_token(token, receipt.message.token)?;
+            value_aggregate = value_aggregate
+                .checked_add(receipt.message.value)
+                .ok_or(AggregationError::AggregateOverflow)?;
+
+            timestamp_max = cmp::max(timestamp_max, receipt.message.timestamp_ns)
+        }
+
+        Ok(Self {
+            allocationId: allocation_id,
+            timestampNs: timestamp_max,
+            valueAggregate: value_aggregate,
+            payer,
+            dataService: data_service,
+            serviceProvider: service_provider,
+            token,
+            metadata: Bytes::new(),
+        })
+    }
+
+    /// Embeds `receipts_root`, the Merkle root of the receipts aggregated
+    /// into the RAV, as its metadata, see [`WithReceiptCommitment`]
+    pub fn with_receipt_commitment(mut self, receipts_root: [u8; 32]) -> Self {
+        self.metadata = Bytes::copy_from_slice(&receipts_root);
+        self
+    }
+}
+
+fn check_token(expected: Address, received: Address) -> Result<(), AggregationError> {
+    if expected != received {
+        return Err(AggregationError::TokenMismatch { expected, received });
+    }
+    Ok(())
+}
+
+impl Aggregate<SignedReceipt> for ReceiptAggregateVoucher {
+    fn aggregate_receipts(
+        receipts: &[ReceiptWithState<Checked, SignedReceipt>],
+        previous_rav: Option<Eip712SignedMessage<Self>>,
+    ) -> Result<Self, AggregationError> {
+        if receipts.is_empty() {
+            return Err(AggregationError::NoValidReceiptsForRavRequest);
+        }
+        let first_receipt = &receipts[0].signed_receipt().message;
+        let receipts = receipts
+            .iter()
+            .map(|rx_receipt| rx_receipt.signed_receipt().clone())
+            .collect::<Vec<_>>();
+        ReceiptAggregateVoucher::aggregate_receipts(
+            first_receipt.allocation_id,
+            first_receipt.payer,
+            first_receipt.data_service,
+            first_receipt.service_provider,
+            first_receipt.token,
+            receipts.as_slice(),
+            previous_rav,
+        )
+    }
+}
+
+impl WithAllocationId for ReceiptAggregateVoucher {
+    fn allocation_id(&self) -> Address {
+        self.allocationId
+    }
+}
+
+impl WithValueAndTimestamp for ReceiptAggregateVoucher {
+    fn value(&self) -> u128 {
+        self.valueAggregate
+    }
+
+    fn timestamp_ns(&self) -> u64 {
+        self.timestampNs
+    }
+}
+
+/// The commitment is the metadata of the RAV when it is exactly 32 bytes long
+impl WithReceiptCommitment for ReceiptAggregateVoucher {
+    fn receipt_commitment(&self) -> Option<[u8; 32]> {
+        self.metadata.as_ref().try_into().ok()
+    }
+}
+
+impl WithToken for ReceiptAggregateVoucher {
+    fn token(&self) -> Address {
+        self.token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{
+        dyn_abi::Eip712Domain, primitives::address, signers::local::PrivateKeySigner,
+        sol_types::eip712_domain,
+    };
+    use rstest::*;
+
+    use super::*;
+
+    #[fixture]
+    fn domain_separator() -> Eip712Domain {
+        eip712_domain! {
+            name: "TAP",
+            version: "1",
+            chain_id: 1,
+            verifying_contract: Address::from([0x11u8; 20]),
+        }
+    }
+
+    #[rstest]
+    fn rav_per_token_with_shared_sender(domain_separator: Eip712Domain) {
+        // one sender paying in two tokens for the same allocation
+        let sender = PrivateKeySigner::random();
+        let allocation_id = address!("1234567890abcdef1234567890abcdef12345678");
+        let token_a = address!("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+        let token_b = address!("bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb");
+        let receipt = |token, value| {
+            Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(
+                    allocation_id,
+                    sender.address(),
+                    Address::ZERO,
+                    Address::ZERO,
+                    token,
+                    value,
+                )
+                .unwrap(),
+                &sender,
+            )
+            .unwrap()
+        };
+        let aggregate = |token, receipts: &[SignedReceipt], previous_rav| {
+            ReceiptAggregateVoucher::aggregate_receipts(
+                allocation_id,
+                sender.address(),
+                Address::ZERO,
+                Address::ZERO,
+                token,
+                receipts,
+                previous_rav,
+            )
+        };
+
+        let rav_a =
+            aggregate(token_a, &[receipt(token_a, 10), receipt(token_a, 20)], None).unwrap();
+        let rav_b = aggregate(token_b, &[receipt(token_b, 5)], None).unwrap();
+        assert_eq!((rav_a.token, rav_a.valueAggregate), (token_a, 30));
+        assert_eq!((rav_b.token, rav_b.valueAggregate), (token_b, 5));
+
+        // receipts of both tokens can't be mixed
+        assert!(matches!(
+            aggregate(token_a, &[receipt(token_a, 10), receipt(token_b, 5)], None),
+            Err(AggregationError::TokenMismatch { expected, received })
+                if expected == token_a && received == token_b
+        ));
+        // nor can the RAV of one token be the previous RAV of the other
+        let signed_rav_b = Eip712SignedMessage::new(&domain_separator, rav_b, &sender).unwrap();
+        assert!(matches!(
+            aggregate(token_a, &[receipt(token_a, 10)], Some(signed_rav_b)),
+            Err(AggregationError::TokenMismatch { .. })
+        ));
+    }
+}
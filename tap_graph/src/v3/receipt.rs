@@ -0,0 +1,100 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Receipt v3
+
+use std::time::{SystemTime, SystemTimeError, UNIX_EPOCH};
+
+use alloy::{primitives::Address, sol};
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use tap_eip712_message::Eip712SignedMessage;
+use tap_receipt::{WithAllocationId, WithNonce, WithToken, WithValueAndTimestamp};
+
+/// A signed receipt message
+pub type SignedReceipt = Eip712SignedMessage<Receipt>;
+
+sol! {
+    /// Holds information needed for promise of payment signed with ECDSA
+    #[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+    struct Receipt {
+        /// Unique allocation id this receipt belongs to
+        address allocation_id;
+
+        // The address of the payer the RAV was issued by
+        address payer;
+        // The address of the data service the RAV was issued to
+        address data_service;
+        // The address of the service provider the RAV was issued to
+        address service_provider;
+
+        /// Unix Epoch timestamp in nanoseconds (Truncated to 64-bits)
+        uint64 timestamp_ns;
+        /// Random value used to avoid collisions from multiple receipts with one timestamp
+        uint64 nonce;
+        /// Address of the payment token `value` is denominated in
+        address token;
+        /// Value for transaction, in units of `token` (truncate to lower bits)
+        uint128 value;
+    }
+}
+
+fn get_current_timestamp_u64_ns() -> Result<u64, SystemTimeError> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64)
+}
+impl Receipt {
+    /// Schema version of this receipt type, to send along with it in a
+    /// [`VersionedReceipt`](tap_receipt::VersionedReceipt)
+    pub const VERSION: u8 = 3;
+
+    /// Returns a receipt with provided values
+    pub fn new(
+        allocation_id: Address,
+        payer: Address,
+        data_service: Address,
+        service_provider: Address,
+        token: Address,
+        value: u128,
+    ) -> Result<Self, SystemTimeError> {
+        let timestamp_ns = get_current_timestamp_u64_ns()?;
+        let nonce = thread_rng().gen::<u64>();
+        Ok(Self {
+            allocation_id,
+            payer,
+            data_service,
+            service_provider,
+            timestamp_ns,
+            nonce,
+            token,
+            value,
+        })
+    }
+}
+
+impl WithAllocationId for Receipt {
+    fn allocation_id(&self) -> Address {
+        self.allocation_id
+    }
+}
+
+impl WithNonce for Receipt {
+    fn nonce(&self) -> u64 {
+        self.nonce
+    }
+}
+
+impl WithValueAndTimestamp for Receipt {
+    fn value(&self) -> u128 {
+        self.value
+    }
+
+    fn timestamp_ns(&self) -> u64 {
+        self.timestamp_ns
+    }
+}
+
+impl WithToken for Receipt {
+    fn token(&self) -> Address {
+        self.token
+    }
+}
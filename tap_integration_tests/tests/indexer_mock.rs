@@ -19,7 +19,7 @@ use tap_aggregator::jsonrpsee_helpers;
 use tap_core::{
     manager::{
         adapters::{RavRead, RavStore, ReceiptRead, ReceiptStore, SignatureChecker},
-        Manager,
+        Manager, RavKey,
     },
     receipt::{checks::CheckList, Context},
 };
@@ -43,8 +43,10 @@ pub trait Rpc {
 /// receipt_count is a thread-safe counter that increments with each receipt verified and stored.
 /// threshold is a limit to which receipt_count can increment, after reaching which RAV request is triggered.
 /// aggregator_client is an HTTP client used for making JSON-RPC requests to another server.
+/// domain_separator is used to recover the sender of the receipt triggering a RAV request.
 pub struct RpcManager<E> {
     manager: Arc<Manager<E, SignedReceipt>>, // Manager object reference counted with an Arc
+    domain_separator: Eip712Domain,          // EIP712 domain separator of the receipts
     receipt_count: Arc<AtomicU64>,           // Thread-safe atomic counter for receipts
     threshold: u64,                          // The count at which a RAV request will be triggered
     aggregator_client: (HttpClient, String), // HTTP client for sending requests to the aggregator server
@@ -67,10 +69,11 @@ where
     ) -> Result<Self> {
         Ok(Self {
             manager: Arc::new(Manager::<E, SignedReceipt>::new(
-                domain_separator,
+                domain_separator.clone(),
                 context,
                 required_checks,
             )),
+            domain_separator,
             receipt_count: Arc::new(AtomicU64::new(0)),
             threshold,
             aggregator_client: (
@@ -97,6 +100,13 @@ where
         &self,
         receipt: SignedReceipt,
     ) -> Result<(), jsonrpsee::types::ErrorObjectOwned> {
+        // RAVs are chained per sender and allocation, taken from the latest receipt
+        let rav_key = receipt
+            .recover_signer(&self.domain_separator)
+            .map(|sender| RavKey {
+                sender,
+                allocation_id: receipt.message.allocation_id,
+            });
         let verify_result = match self
             .manager
             .verify_and_store_receipt(&Context::new(), receipt)
@@ -119,6 +129,7 @@ where
             let time_stamp_buffer = 0;
             match request_rav(
                 &self.manager,
+                rav_key.map_err(|e| to_rpc_error(Box::new(e), "Failed to recover sender"))?,
                 time_stamp_buffer,
                 &self.aggregator_client,
                 self.threshold as usize,
@@ -185,6 +196,7 @@ where
 // request_rav function creates a request for aggregate receipts (RAV), sends it to another server and verifies the result.
 async fn request_rav<E>(
     manager: &Arc<Manager<E, SignedReceipt>>,
+    rav_key: RavKey,        // Sender and allocation of the requested RAV
     time_stamp_buffer: u64, // Buffer for timestamping, see tap_core for details
     aggregator_client: &(HttpClient, String), // HttpClient for making requests to the tap_aggregator server
     threshold: usize,
@@ -195,9 +207,12 @@ where
         + RavStore<ReceiptAggregateVoucher>
         + SignatureChecker,
 {
+    let mut ctx = Context::new();
+    ctx.insert(rav_key);
+
     // Create the aggregate_receipts request params
     let rav_request = manager
-        .create_rav_request(&Context::new(), time_stamp_buffer, None)
+        .create_rav_request(&ctx, time_stamp_buffer, None)
         .await?;
 
     // To-do: Need to add previous RAV, when tap_manager supports replacing receipts
@@ -217,7 +232,7 @@ where
         .request("aggregate_receipts", params)
         .await?;
     manager
-        .verify_and_store_rav(rav_request.expected_rav?, remote_rav_result.data)
+        .verify_and_store_rav_with_context(&ctx, rav_request.expected_rav?, remote_rav_result.data)
         .await?;
 
     // For these tests, we expect every receipt to be valid, i.e. there should be no invalid receipts, nor any missing receipts (less than the expected threshold).
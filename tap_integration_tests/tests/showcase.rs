@@ -170,7 +170,7 @@ fn context(
 ) -> ContextFixture {
     let receipt_storage = Arc::new(RwLock::new(HashMap::new()));
     let escrow_storage = Arc::new(RwLock::new(HashMap::new()));
-    let rav_storage = Arc::new(RwLock::new(None));
+    let rav_storage = Arc::new(RwLock::new(HashMap::new()));
     let timestamp_check = Arc::new(StatefulTimestampCheck::new(0));
     let context = InMemoryContext::new(
         rav_storage,
@@ -567,8 +567,7 @@ async fn test_manager_wrong_aggregator_keys(
     let indexer_1_address = "http://".to_string() + &socket_addr.to_string();
     let client_1 = HttpClientBuilder::default().build(indexer_1_address)?;
 
-    let mut counter = 1;
-    for receipt_1 in requests_1 {
+    for (counter, receipt_1) in (1..).zip(requests_1) {
         let result: Result<(), jsonrpsee::core::ClientError> =
             client_1.request("request", (receipt_1,)).await;
         // The rav request is being made with messages that have been signed with a key that differs from the sender aggregator's.
@@ -588,7 +587,6 @@ async fn test_manager_wrong_aggregator_keys(
                 result.unwrap_err()
             );
         }
-        counter += 1;
     }
 
     Ok(())
@@ -652,8 +650,7 @@ async fn test_tap_manager_rav_timestamp_cuttoff(
     let client_1 = HttpClientBuilder::default().build(indexer_1_address)?;
     let client_2 = HttpClientBuilder::default().build(indexer_2_address)?;
 
-    let mut counter = 1;
-    for receipt_1 in repeated_timestamp_request {
+    for (counter, receipt_1) in (1..).zip(repeated_timestamp_request) {
         let result: Result<(), jsonrpsee::core::ClientError> =
             client_1.request("request", (receipt_1,)).await;
 
@@ -670,7 +667,6 @@ async fn test_tap_manager_rav_timestamp_cuttoff(
                 result.unwrap_err()
             );
         }
-        counter += 1;
     }
 
     server_handle_1.stop()?;
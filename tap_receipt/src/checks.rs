@@ -29,16 +29,29 @@
 //!
 //! let my_check: ReceiptCheck<SignedReceipt> = Arc::new(MyCheck);
 //! ```
+//!
+//! Built-in and custom checks are assembled into a [`CheckList`] with a
+//! [`CheckListBuilder`], the built-in checks offer a `boxed` constructor
+//! returning a [`ReceiptCheck`].
 
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
+    future::Future,
+    hash::Hash,
     ops::Deref,
-    sync::{Arc, RwLock},
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use alloy::{primitives::Address, sol_types::SolStruct};
+use serde::{Deserialize, Serialize};
+use tap_eip712_message::{Eip712Error, Eip712SignedMessage};
+
 use super::{
     state::{Checking, Failed},
-    Context, ReceiptError, ReceiptWithState, WithUniqueId, WithValueAndTimestamp,
+    Context, ReceiptError, ReceiptResult, ReceiptWithState, WithAllocationId, WithUniqueId,
+    WithValueAndTimestamp,
 };
 
 /// ReceiptCheck is a type alias for an Arc of a struct that implements the `Check` trait.
@@ -55,6 +68,20 @@ pub enum CheckError {
     Failed(anyhow::Error),
 }
 
+/// A check failing with a [`ReceiptError`], e.g.
+/// [`ReceiptError::NotEnoughEscrow`], keeps it, other failures become
+/// [`ReceiptError::CheckFailure`]
+impl From<CheckError> for ReceiptError {
+    fn from(error: CheckError) -> Self {
+        match error {
+            CheckError::Retryable(e) => ReceiptError::RetryableCheck(e.to_string()),
+            CheckError::Failed(e) => e
+                .downcast::<ReceiptError>()
+                .unwrap_or_else(|e| ReceiptError::CheckFailure(e.to_string())),
+        }
+    }
+}
+
 /// CheckList is a NewType pattern to store a list of checks.
 /// It is a wrapper around an Arc of ReceiptCheck[].
 pub struct CheckList<Rcpt>(Arc<[ReceiptCheck<Rcpt>]>);
@@ -69,6 +96,59 @@ impl<Rcpt> CheckList<Rcpt> {
     }
 }
 
+/// Assembles a [`CheckList`] out of built-in and custom checks
+///
+/// ```rust
+/// # use tap_receipt::{checks::{CheckListBuilder, StatefulTimestampCheck}, WithValueAndTimestamp};
+/// # fn example<Rcpt: WithValueAndTimestamp + Sync>() {
+/// let checks = CheckListBuilder::<Rcpt>::new()
+///     .with_check(StatefulTimestampCheck::boxed(0))
+///     .build();
+/// # }
+/// ```
+pub struct CheckListBuilder<Rcpt> {
+    checks: Vec<ReceiptCheck<Rcpt>>,
+}
+
+impl<Rcpt> CheckListBuilder<Rcpt> {
+    pub fn new() -> Self {
+        Self { checks: Vec::new() }
+    }
+
+    /// Appends a check, e.g. built with the `boxed` constructor of a
+    /// built-in check
+    pub fn with_check(mut self, check: ReceiptCheck<Rcpt>) -> Self {
+        self.checks.push(check);
+        self
+    }
+
+    /// Appends a check, boxing it
+    pub fn with_custom_check<C>(self, check: C) -> Self
+    where
+        C: Check<Rcpt> + Send + Sync + 'static,
+    {
+        self.with_check(Arc::new(check))
+    }
+
+    /// Appends a list of checks, e.g. a full list of built-in checks to
+    /// extend with custom ones
+    pub fn with_checks(mut self, checks: impl IntoIterator<Item = ReceiptCheck<Rcpt>>) -> Self {
+        self.checks.extend(checks);
+        self
+    }
+
+    /// Returns the list of checks, run in the order they were appended
+    pub fn build(self) -> CheckList<Rcpt> {
+        CheckList::new(self.checks)
+    }
+}
+
+impl<Rcpt> Default for CheckListBuilder<Rcpt> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<Rcpt> Deref for CheckList<Rcpt> {
     type Target = [ReceiptCheck<Rcpt>];
 
@@ -77,11 +157,163 @@ impl<Rcpt> Deref for CheckList<Rcpt> {
     }
 }
 
+/// How a [`CheckPipeline`] handles a failing check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PipelineMode {
+    /// Stops at the first failing check, the following checks don't run
+    #[default]
+    ShortCircuit,
+    /// Runs every check, to report all the reasons a receipt is invalid
+    CollectAll,
+}
+
+/// Checks run on each receipt, in the order they were given, along with the
+/// [`PipelineMode`] deciding whether a failure stops the pipeline.
+pub struct CheckPipeline<Rcpt> {
+    checks: CheckList<Rcpt>,
+    mode: PipelineMode,
+}
+
+impl<Rcpt> CheckPipeline<Rcpt> {
+    /// Creates a [`PipelineMode::ShortCircuit`] pipeline running `checks` in order
+    pub fn new(checks: CheckList<Rcpt>) -> Self {
+        Self {
+            checks,
+            mode: PipelineMode::default(),
+        }
+    }
+
+    pub fn with_mode(mut self, mode: PipelineMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn mode(&self) -> PipelineMode {
+        self.mode
+    }
+
+    /// Runs the checks on `receipt`
+    ///
+    /// # Errors
+    ///
+    /// See [`CheckPipeline::run`]
+    ///
+    pub async fn check(
+        &self,
+        ctx: &Context,
+        receipt: &ReceiptWithState<Checking, Rcpt>,
+    ) -> ReceiptResult<()> {
+        self.run(|_, check| async move { check.check(ctx, receipt).await.map_err(Into::into) })
+            .await
+    }
+
+    /// Runs each check, in order, through `perform_check`, called with the
+    /// position of the check in the pipeline. This lets the caller wrap the
+    /// checks, e.g. with timeouts, while keeping the pipeline semantics.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the first failing check in
+    /// [`PipelineMode::ShortCircuit`] mode.
+    ///
+    /// In [`PipelineMode::CollectAll`] mode, returns the first retryable
+    /// error if any, since the receipt will be checked again anyway.
+    /// Otherwise returns the error of the failing check if only one failed,
+    /// or [`ReceiptError::CheckFailures`] with all of them.
+    ///
+    pub async fn run<'a, F, Fut>(&'a self, mut perform_check: F) -> ReceiptResult<()>
+    where
+        F: FnMut(usize, &'a ReceiptCheck<Rcpt>) -> Fut,
+        Fut: Future<Output = ReceiptResult<()>>,
+    {
+        let mut errors = Vec::new();
+        for (index, check) in self.checks.iter().enumerate() {
+            if let Err(error) = perform_check(index, check).await {
+                if self.mode == PipelineMode::ShortCircuit {
+                    return Err(error);
+                }
+                errors.push(error);
+            }
+        }
+        if let Some(position) = errors.iter().position(ReceiptError::is_retryable) {
+            return Err(errors.swap_remove(position));
+        }
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(ReceiptError::CheckFailures(errors)),
+        }
+    }
+}
+
+impl<Rcpt> From<CheckList<Rcpt>> for CheckPipeline<Rcpt> {
+    fn from(checks: CheckList<Rcpt>) -> Self {
+        Self::new(checks)
+    }
+}
+
+impl<Rcpt> Deref for CheckPipeline<Rcpt> {
+    type Target = [ReceiptCheck<Rcpt>];
+
+    fn deref(&self) -> &Self::Target {
+        &self.checks
+    }
+}
+
+/// What a failure of a check does to the receipt, see [`Check::severity`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CheckSeverity {
+    /// The receipt is rejected
+    #[default]
+    Reject,
+    /// The receipt is accepted anyway, with a warning attached, e.g. for
+    /// advisory checks like a low but still sufficient escrow
+    Warn,
+}
+
 /// Check trait is implemented by the lib user to validate receipts before they are stored.
 #[async_trait::async_trait]
 pub trait Check<Rcpt> {
     async fn check(&self, ctx: &Context, receipt: &ReceiptWithState<Checking, Rcpt>)
         -> CheckResult;
+
+    /// Name of the check used in logs and timings, defaults to its type name
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Severity of a failure of the check, defaults to
+    /// [`CheckSeverity::Reject`]
+    fn severity(&self) -> CheckSeverity {
+        CheckSeverity::Reject
+    }
+
+    /// Whether the check records the receipts it sees, e.g. to reject them
+    /// the next time. Stateful checks are skipped by dry runs such as
+    /// `Manager::check_receipt`, so that probing a receipt doesn't change
+    /// whether it is accepted later. Defaults to `false`, i.e. pure.
+    fn is_stateful(&self) -> bool {
+        false
+    }
+
+    /// Called once `receipt` passed every check of the manager and was
+    /// stored, for stateful checks to record it, e.g. raise a watermark.
+    /// Recording it in [`Check::check`] instead would let a receipt rejected
+    /// by a later check, or that couldn't be stored, still count. Defaults to
+    /// doing nothing.
+    async fn commit(&self, _ctx: &Context, _receipt: &ReceiptWithState<Checking, Rcpt>) {}
+
+    /// Called when `receipt` passed the check but is rejected anyway, by a
+    /// later check or because it couldn't be stored, for stateful checks to
+    /// undo what [`Check::check`] did, e.g. release the escrow it reserved.
+    /// Defaults to doing nothing.
+    async fn rollback(&self, _ctx: &Context, _receipt: &ReceiptWithState<Checking, Rcpt>) {}
+
+    /// Called once a RAV of `sender` for `allocation_id`, aggregating the
+    /// receipts up to `timestamp_ns`, is stored, for stateful checks to
+    /// forget the receipts it covers, which are left out of the later RAVs.
+    /// Defaults to doing nothing.
+    async fn rav_stored(&self, _sender: Address, _allocation_id: Address, _timestamp_ns: u64) {}
 }
 
 type CheckBatchResponse<Rcpt> = (
@@ -99,7 +331,10 @@ pub trait CheckBatch<Rcpt> {
 }
 
 /// Provides a built-in check to verify that the timestamp of a receipt
-/// is greater than a given value.
+/// is greater or equal than a given value. Like every timestamp minimum of
+/// the library, the minimum is inclusive: a receipt at exactly the minimum
+/// is accepted. A minimum taken from the timestamp of the last RAV, which
+/// covers receipts at its own timestamp, is the RAV timestamp plus one.
 ///
 /// This check is stateful, meaning that it can be updated with a new minimum
 /// timestamp.
@@ -114,7 +349,16 @@ impl StatefulTimestampCheck {
             min_timestamp_ns: RwLock::new(min_timestamp_ns),
         }
     }
-    /// Updates the minimum timestamp that will be accepted for a receipt (exclusive).
+
+    /// Same as [`StatefulTimestampCheck::new`], as a [`ReceiptCheck`]. The
+    /// minimum timestamp can't be updated anymore then.
+    pub fn boxed<Rcpt>(min_timestamp_ns: u64) -> ReceiptCheck<Rcpt>
+    where
+        Rcpt: WithValueAndTimestamp + Sync,
+    {
+        Arc::new(Self::new(min_timestamp_ns))
+    }
+    /// Updates the minimum timestamp that will be accepted for a receipt (inclusive).
     pub fn update_min_timestamp_ns(&self, min_timestamp_ns: u64) {
         *self.min_timestamp_ns.write().unwrap() = min_timestamp_ns;
     }
@@ -128,7 +372,7 @@ where
     async fn check(&self, _: &Context, receipt: &ReceiptWithState<Checking, Rcpt>) -> CheckResult {
         let min_timestamp_ns = *self.min_timestamp_ns.read().unwrap();
         let signed_receipt = receipt.signed_receipt();
-        if signed_receipt.timestamp_ns() <= min_timestamp_ns {
+        if signed_receipt.timestamp_ns() < min_timestamp_ns {
             return Err(CheckError::Failed(
                 ReceiptError::InvalidTimestamp {
                     received_timestamp: signed_receipt.timestamp_ns(),
@@ -141,6 +385,311 @@ where
     }
 }
 
+/// Provides a built-in check rejecting receipts with a value of zero.
+///
+/// Zero-value receipts are valid receipts, they just don't pay for anything,
+/// so this check is opt-in for receivers that don't serve free queries.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NonZeroValueCheck;
+
+impl NonZeroValueCheck {
+    /// Same as [`NonZeroValueCheck`], as a [`ReceiptCheck`]
+    pub fn boxed<Rcpt>() -> ReceiptCheck<Rcpt>
+    where
+        Rcpt: WithValueAndTimestamp + Sync,
+    {
+        Arc::new(Self)
+    }
+}
+
+#[async_trait::async_trait]
+impl<Rcpt> Check<Rcpt> for NonZeroValueCheck
+where
+    Rcpt: WithValueAndTimestamp + Sync,
+{
+    async fn check(&self, _: &Context, receipt: &ReceiptWithState<Checking, Rcpt>) -> CheckResult {
+        if receipt.signed_receipt().value() == 0 {
+            return Err(CheckError::Failed(ReceiptError::ZeroValueReceipt.into()));
+        }
+        Ok(())
+    }
+}
+
+/// Provides a built-in check rejecting receipts whose signature is in its
+/// high-S form.
+///
+/// A high-S signature can be derived from any valid signature without the
+/// signer's key, so a receipt accepted in both forms has two distinct valid
+/// signatures, e.g. getting past checks keyed on the signature such as
+/// [`UniqueCheck`]. Both forms are accepted unless this check is added.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LowSSignatureCheck;
+
+impl LowSSignatureCheck {
+    /// Same as [`LowSSignatureCheck`], as a [`ReceiptCheck`]
+    pub fn boxed<T>() -> ReceiptCheck<Eip712SignedMessage<T>>
+    where
+        T: SolStruct + Sync,
+    {
+        Arc::new(Self)
+    }
+}
+
+#[async_trait::async_trait]
+impl<T> Check<Eip712SignedMessage<T>> for LowSSignatureCheck
+where
+    T: SolStruct + Sync,
+{
+    async fn check(
+        &self,
+        _: &Context,
+        receipt: &ReceiptWithState<Checking, Eip712SignedMessage<T>>,
+    ) -> CheckResult {
+        if receipt.signed_receipt().has_high_s_signature() {
+            return Err(CheckError::Failed(
+                ReceiptError::InvalidSignature {
+                    source_error_message: Eip712Error::MalleableSignature.to_string(),
+                }
+                .into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Declarative receipt validation rules, e.g. deserialized from the config
+/// of an operator, checked as a single [`Check`]. Rules left unset aren't
+/// checked.
+///
+/// ```rust
+/// # use tap_receipt::{checks::{CheckListBuilder, ReceiptRules}, WithAllocationId, WithValueAndTimestamp};
+/// # fn example<Rcpt: WithAllocationId + WithValueAndTimestamp + Sync>() -> anyhow::Result<()> {
+/// let rules: ReceiptRules = serde_json::from_str(r#"{ "min_value": 10, "max_age_ns": 3600000000000 }"#)?;
+/// let checks = CheckListBuilder::<Rcpt>::new()
+///     .with_check(rules.boxed())
+///     .build();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReceiptRules {
+    /// Allocations receipts must be for
+    pub allocation_ids: Option<HashSet<Address>>,
+    /// Minimum value of a receipt, inclusive
+    pub min_value: Option<u128>,
+    /// Maximum value of a receipt, inclusive
+    pub max_value: Option<u128>,
+    /// Maximum age of a receipt at the time it is checked, in nanoseconds
+    pub max_age_ns: Option<u64>,
+    /// Maximum time a receipt can be timestamped ahead of the time it is
+    /// checked, in nanoseconds
+    pub max_future_ns: Option<u64>,
+}
+
+impl ReceiptRules {
+    /// Reads the rules from a JSON file
+    pub fn from_json_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let rules = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&rules)?)
+    }
+
+    /// Same as the rules, as a [`ReceiptCheck`]
+    pub fn boxed<Rcpt>(self) -> ReceiptCheck<Rcpt>
+    where
+        Rcpt: WithAllocationId + WithValueAndTimestamp + Sync,
+    {
+        Arc::new(self)
+    }
+}
+
+#[async_trait::async_trait]
+impl<Rcpt> Check<Rcpt> for ReceiptRules
+where
+    Rcpt: WithAllocationId + WithValueAndTimestamp + Sync,
+{
+    async fn check(&self, _: &Context, receipt: &ReceiptWithState<Checking, Rcpt>) -> CheckResult {
+        let signed_receipt = receipt.signed_receipt();
+        let allocation_id = signed_receipt.allocation_id();
+        if self
+            .allocation_ids
+            .as_ref()
+            .is_some_and(|allocation_ids| !allocation_ids.contains(&allocation_id))
+        {
+            return Err(CheckError::Failed(
+                ReceiptError::InvalidAllocationID {
+                    received_allocation_id: allocation_id,
+                }
+                .into(),
+            ));
+        }
+
+        let value = signed_receipt.value();
+        if self.min_value.is_some_and(|min_value| value < min_value)
+            || self.max_value.is_some_and(|max_value| value > max_value)
+        {
+            return Err(CheckError::Failed(
+                ReceiptError::InvalidValue {
+                    received_value: value,
+                }
+                .into(),
+            ));
+        }
+
+        if self.max_age_ns.is_none() && self.max_future_ns.is_none() {
+            return Ok(());
+        }
+        let now_ns = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|err| CheckError::Retryable(err.into()))?
+            .as_nanos() as u64;
+        let timestamp_ns = signed_receipt.timestamp_ns();
+        if let Some(max_age_ns) = self.max_age_ns {
+            let timestamp_min = now_ns.saturating_sub(max_age_ns);
+            if timestamp_ns < timestamp_min {
+                return Err(CheckError::Failed(
+                    ReceiptError::InvalidTimestamp {
+                        received_timestamp: timestamp_ns,
+                        timestamp_min,
+                    }
+                    .into(),
+                ));
+            }
+        }
+        if let Some(max_future_ns) = self.max_future_ns {
+            let timestamp_max = now_ns.saturating_add(max_future_ns);
+            if timestamp_ns > timestamp_max {
+                return Err(CheckError::Failed(
+                    ReceiptError::CheckFailure(format!(
+                        "timestamp {timestamp_ns} is too far in the future (expected max {timestamp_max})"
+                    ))
+                    .into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Number of cached results above which the expired ones are dropped, to
+/// bound the memory used by a [`CachedCheck`]
+const CACHE_PRUNE_THRESHOLD: usize = 10_000;
+
+/// Result cached by a [`CachedCheck`], along with when it was cached
+type CachedResult = (Instant, Result<(), ReceiptError>);
+
+/// Memoizes the results of a slow changing check, e.g. an allocation status
+/// lookup, for `ttl`. Results are keyed by the value returned by the key
+/// function for each receipt, e.g. its allocation id, so receipts sharing a
+/// key share the result.
+///
+/// The wrapped check must be pure, its result depending only on the key: a
+/// cached pass is reused for every receipt of the key until it expires,
+/// whatever their value. Never cache checks depending on the receipt itself
+/// or on what was accepted before, such as escrow checks, as a cached pass
+/// would let a sender overspend its escrow for the whole `ttl`. Stateful
+/// checks, see [`Check::is_stateful`], are never cached and run on every
+/// receipt.
+///
+/// Only passes and [`CheckError::Failed`] results are cached, retryable
+/// failures are transient and the check runs again on the next receipt.
+pub struct CachedCheck<C, K, F> {
+    check: C,
+    key: F,
+    ttl: Duration,
+    results: Mutex<HashMap<K, CachedResult>>,
+}
+
+impl<C, K, F> CachedCheck<C, K, F> {
+    pub fn new(check: C, ttl: Duration, key: F) -> Self {
+        Self {
+            check,
+            key,
+            ttl,
+            results: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Same as [`CachedCheck::new`], as a [`ReceiptCheck`]
+    pub fn boxed<Rcpt>(check: C, ttl: Duration, key: F) -> ReceiptCheck<Rcpt>
+    where
+        Rcpt: Sync,
+        C: Check<Rcpt> + Send + Sync + 'static,
+        K: Eq + Hash + Send + Sync + 'static,
+        F: Fn(&Rcpt) -> K + Send + Sync + 'static,
+    {
+        Arc::new(Self::new(check, ttl, key))
+    }
+}
+
+#[async_trait::async_trait]
+impl<Rcpt, C, K, F> Check<Rcpt> for CachedCheck<C, K, F>
+where
+    Rcpt: Sync,
+    C: Check<Rcpt> + Send + Sync,
+    K: Eq + Hash + Send + Sync,
+    F: Fn(&Rcpt) -> K + Send + Sync,
+{
+    async fn check(
+        &self,
+        ctx: &Context,
+        receipt: &ReceiptWithState<Checking, Rcpt>,
+    ) -> CheckResult {
+        if self.check.is_stateful() {
+            return self.check.check(ctx, receipt).await;
+        }
+        let key = (self.key)(receipt.signed_receipt());
+        if let Some((cached_at, result)) = self.results.lock().unwrap().get(&key) {
+            if cached_at.elapsed() < self.ttl {
+                return result.clone().map_err(|err| CheckError::Failed(err.into()));
+            }
+        }
+
+        let result = self.check.check(ctx, receipt).await;
+        let cached = match &result {
+            Ok(()) => Ok(()),
+            Err(CheckError::Failed(err)) => Err(err
+                .downcast_ref::<ReceiptError>()
+                .cloned()
+                .unwrap_or_else(|| ReceiptError::CheckFailure(err.to_string()))),
+            Err(CheckError::Retryable(_)) => return result,
+        };
+        let now = Instant::now();
+        let mut results = self.results.lock().unwrap();
+        if results.len() >= CACHE_PRUNE_THRESHOLD {
+            results.retain(|_, (cached_at, _)| now.duration_since(*cached_at) < self.ttl);
+        }
+        results.insert(key, (now, cached));
+        result
+    }
+
+    fn name(&self) -> &'static str {
+        self.check.name()
+    }
+
+    fn severity(&self) -> CheckSeverity {
+        self.check.severity()
+    }
+
+    fn is_stateful(&self) -> bool {
+        self.check.is_stateful()
+    }
+
+    async fn commit(&self, ctx: &Context, receipt: &ReceiptWithState<Checking, Rcpt>) {
+        self.check.commit(ctx, receipt).await;
+    }
+
+    async fn rollback(&self, ctx: &Context, receipt: &ReceiptWithState<Checking, Rcpt>) {
+        self.check.rollback(ctx, receipt).await;
+    }
+
+    async fn rav_stored(&self, sender: Address, allocation_id: Address, timestamp_ns: u64) {
+        self.check
+            .rav_stored(sender, allocation_id, timestamp_ns)
+            .await;
+    }
+}
+
 /// Timestamp Check verifies if the receipt is **greater or equal** than the
 /// minimum timestamp provided.
 ///
@@ -175,6 +724,41 @@ where
     }
 }
 
+/// Allocation Check verifies that the receipt belongs to the allocation
+/// provided, regardless of how storage selected it.
+///
+/// Used by the [`crate::manager::Manager`].
+pub struct AllocationCheck(pub Address);
+
+impl<Rcpt> CheckBatch<Rcpt> for AllocationCheck
+where
+    Rcpt: WithAllocationId,
+{
+    fn check_batch(
+        &self,
+        receipts: Vec<ReceiptWithState<Checking, Rcpt>>,
+    ) -> (
+        Vec<ReceiptWithState<Checking, Rcpt>>,
+        Vec<ReceiptWithState<Failed, Rcpt>>,
+    ) {
+        let (mut checking, mut failed) = (vec![], vec![]);
+        for receipt in receipts.into_iter() {
+            let received = receipt.signed_receipt().allocation_id();
+            if received == self.0 {
+                checking.push(receipt);
+            } else {
+                failed.push(
+                    receipt.perform_state_error(ReceiptError::AllocationMismatch {
+                        expected: self.0,
+                        received,
+                    }),
+                );
+            }
+        }
+        (checking, failed)
+    }
+}
+
 /// UniqueCheck is a batch check that verifies if any given list of receipts
 /// has unique signatures.
 ///
@@ -212,10 +796,8 @@ mod tests {
     use std::time::{Duration, SystemTime};
 
     use alloy::{
-        dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner, sol,
-        sol_types::eip712_domain,
+        dyn_abi::Eip712Domain, signers::local::PrivateKeySigner, sol, sol_types::eip712_domain,
     };
-    use tap_eip712_message::Eip712SignedMessage;
 
     use super::*;
 
@@ -289,4 +871,341 @@ mod tests {
         assert_eq!(valid_receipts.len(), 1);
         assert_eq!(invalid_receipts.len(), 1);
     }
+
+    #[tokio::test]
+    async fn test_receipt_timestamp_boundaries() {
+        let signed_receipt = create_signed_receipt_with_custom_value(10);
+        let floor = signed_receipt.receipt.message.timestamp_ns;
+
+        // both checks include their minimum
+        let check = StatefulTimestampCheck::new(0);
+        let mut accepted = Vec::new();
+        for min_timestamp_ns in [floor - 1, floor, floor + 1] {
+            check.update_min_timestamp_ns(min_timestamp_ns);
+            accepted.push(check.check(&Context::new(), &signed_receipt).await.is_ok());
+        }
+        assert_eq!(accepted, [true, true, false]);
+
+        let accepted = [floor - 1, floor, floor + 1].map(|min_timestamp_ns| {
+            let (valid_receipts, _) =
+                TimestampCheck(min_timestamp_ns).check_batch(vec![signed_receipt.clone()]);
+            valid_receipts.len() == 1
+        });
+        assert_eq!(accepted, [true, true, false]);
+    }
+
+    #[tokio::test]
+    async fn test_receipt_non_zero_value_check() {
+        let check = NonZeroValueCheck;
+        let ctx = Context::new();
+        assert!(check
+            .check(&ctx, &create_signed_receipt_with_custom_value(1))
+            .await
+            .is_ok());
+        match check
+            .check(&ctx, &create_signed_receipt_with_custom_value(0))
+            .await
+        {
+            Err(CheckError::Failed(err)) => assert!(matches!(
+                err.downcast_ref::<ReceiptError>(),
+                Some(ReceiptError::ZeroValueReceipt)
+            )),
+            result => panic!("Unexpected result: {result:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_receipt_low_s_signature_check() {
+        // secp256k1 curve order
+        let curve_order = alloy::primitives::U256::from_str_radix(
+            "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141",
+            16,
+        )
+        .unwrap();
+        let check = LowSSignatureCheck;
+        let ctx = Context::new();
+        let receipt = create_signed_receipt_with_custom_value(1);
+        assert!(check.check(&ctx, &receipt).await.is_ok());
+
+        // same signature in its high-S form
+        let mut signed_receipt = receipt.into_signed_receipt();
+        let signature = signed_receipt.signature;
+        signed_receipt.signature = alloy::primitives::PrimitiveSignature::new(
+            signature.r(),
+            curve_order - signature.s(),
+            !signature.v(),
+        );
+        match check
+            .check(&ctx, &ReceiptWithState::new(signed_receipt))
+            .await
+        {
+            Err(CheckError::Failed(err)) => assert!(matches!(
+                err.downcast_ref::<ReceiptError>(),
+                Some(ReceiptError::InvalidSignature { .. })
+            )),
+            result => panic!("Unexpected result: {result:?}"),
+        }
+    }
+
+    sol! {
+        struct MyAllocationReceipt {
+            address allocation_id;
+            uint64 timestamp_ns;
+            uint128 value;
+        }
+    }
+
+    impl WithAllocationId for MyAllocationReceipt {
+        fn allocation_id(&self) -> Address {
+            self.allocation_id
+        }
+    }
+
+    impl WithValueAndTimestamp for MyAllocationReceipt {
+        fn value(&self) -> u128 {
+            self.value
+        }
+
+        fn timestamp_ns(&self) -> u64 {
+            self.timestamp_ns
+        }
+    }
+
+    #[tokio::test]
+    async fn test_receipt_rules() {
+        let domain_separator = eip712_domain! {
+            name: "TAP",
+            version: "1",
+            chain_id: 1,
+            verifying_contract: Address::from([0x11u8; 20]),
+        };
+        let allocation_id = Address::from([0xaau8; 20]);
+        let rules_path = std::env::temp_dir().join(format!(
+            "receipt-rules-{}.json",
+            PrivateKeySigner::random().address()
+        ));
+        std::fs::write(
+            &rules_path,
+            format!(
+                r#"{{
+                    "allocation_ids": ["{allocation_id}"],
+                    "min_value": 10,
+                    "max_value": 100,
+                    "max_age_ns": 60000000000,
+                    "max_future_ns": 1000000000
+                }}"#
+            ),
+        )
+        .unwrap();
+        let check = ReceiptRules::from_json_file(&rules_path).unwrap();
+        std::fs::remove_file(rules_path).unwrap();
+
+        let now_ns = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        let wallet = PrivateKeySigner::random();
+        let receipt = |allocation_id, timestamp_ns, value| {
+            ReceiptWithState::new(
+                Eip712SignedMessage::new(
+                    &domain_separator,
+                    MyAllocationReceipt {
+                        allocation_id,
+                        timestamp_ns,
+                        value,
+                    },
+                    &wallet,
+                )
+                .unwrap(),
+            )
+        };
+        let ctx = Context::new();
+
+        assert!(check
+            .check(&ctx, &receipt(allocation_id, now_ns, 10))
+            .await
+            .is_ok());
+        assert!(check
+            .check(&ctx, &receipt(allocation_id, now_ns, 100))
+            .await
+            .is_ok());
+        for rejected in [
+            receipt(Address::ZERO, now_ns, 50),
+            receipt(allocation_id, now_ns, 9),
+            receipt(allocation_id, now_ns, 101),
+            receipt(allocation_id, now_ns - 120_000_000_000, 50),
+            receipt(allocation_id, now_ns + 120_000_000_000, 50),
+        ] {
+            assert!(matches!(
+                check.check(&ctx, &rejected).await,
+                Err(CheckError::Failed(_))
+            ));
+        }
+
+        // unset rules aren't checked
+        let check = ReceiptRules::default();
+        assert!(check
+            .check(&ctx, &receipt(Address::ZERO, 0, 0))
+            .await
+            .is_ok());
+        assert!(serde_json::from_str::<ReceiptRules>(r#"{ "max_valeu": 1 }"#).is_err());
+    }
+
+    struct FailingCheck(&'static str);
+
+    #[async_trait::async_trait]
+    impl<T: Sync> Check<T> for FailingCheck {
+        async fn check(&self, _: &Context, _: &ReceiptWithState<Checking, T>) -> CheckResult {
+            Err(CheckError::Failed(anyhow::anyhow!(self.0)))
+        }
+    }
+
+    struct CountingCheck(Arc<std::sync::atomic::AtomicUsize>);
+
+    #[async_trait::async_trait]
+    impl<T: Sync> Check<T> for CountingCheck {
+        async fn check(&self, _: &Context, _: &ReceiptWithState<Checking, T>) -> CheckResult {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    struct StatefulCountingCheck(Arc<std::sync::atomic::AtomicUsize>);
+
+    #[async_trait::async_trait]
+    impl<T: Sync> Check<T> for StatefulCountingCheck {
+        async fn check(&self, _: &Context, _: &ReceiptWithState<Checking, T>) -> CheckResult {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn is_stateful(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cached_check() {
+        let ctx = Context::new();
+        let runs = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let ttl = Duration::from_millis(100);
+        let check = CachedCheck::new(
+            CountingCheck(runs.clone()),
+            ttl,
+            |receipt: &Eip712SignedMessage<MyReceipt>| receipt.message.value,
+        );
+        let runs_count = || runs.load(std::sync::atomic::Ordering::SeqCst);
+
+        let receipt = create_signed_receipt_with_custom_value(10);
+        check.check(&ctx, &receipt).await.unwrap();
+        check.check(&ctx, &receipt).await.unwrap();
+        // another receipt with the same key shares the result
+        check
+            .check(&ctx, &create_signed_receipt_with_custom_value(10))
+            .await
+            .unwrap();
+        assert_eq!(runs_count(), 1);
+
+        // another key isn't cached yet
+        check
+            .check(&ctx, &create_signed_receipt_with_custom_value(15))
+            .await
+            .unwrap();
+        assert_eq!(runs_count(), 2);
+
+        tokio::time::sleep(ttl).await;
+        check.check(&ctx, &receipt).await.unwrap();
+        assert_eq!(runs_count(), 3);
+
+        // failures are cached too
+        let check = CachedCheck::new(
+            FailingCheck("unauthorized"),
+            ttl,
+            |receipt: &Eip712SignedMessage<MyReceipt>| receipt.message.value,
+        );
+        for _ in 0..2 {
+            assert!(matches!(
+                check.check(&ctx, &receipt).await,
+                Err(CheckError::Failed(err)) if err.to_string().contains("unauthorized")
+            ));
+        }
+
+        // stateful checks are never cached
+        let check = CachedCheck::new(
+            StatefulCountingCheck(runs.clone()),
+            ttl,
+            |receipt: &Eip712SignedMessage<MyReceipt>| receipt.message.value,
+        );
+        check.check(&ctx, &receipt).await.unwrap();
+        check.check(&ctx, &receipt).await.unwrap();
+        assert_eq!(runs_count(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_check_list_builder() {
+        let receipt = create_signed_receipt_with_custom_value(10);
+        let ctx = Context::new();
+        let runs = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let checks = CheckListBuilder::new()
+            .with_check(StatefulTimestampCheck::boxed(0))
+            .with_custom_check(CountingCheck(runs.clone()))
+            .build();
+        assert_eq!(checks.len(), 2);
+
+        CheckPipeline::new(checks)
+            .check(&ctx, &receipt)
+            .await
+            .unwrap();
+        assert_eq!(runs.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // the built-in check runs first
+        let runs = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let checks = CheckListBuilder::new()
+            .with_check(StatefulTimestampCheck::boxed(u64::MAX))
+            .with_custom_check(CountingCheck(runs.clone()))
+            .build();
+        assert!(matches!(
+            CheckPipeline::new(checks).check(&ctx, &receipt).await,
+            Err(ReceiptError::InvalidTimestamp { .. })
+        ));
+        assert_eq!(runs.load(std::sync::atomic::Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_check_pipeline_modes() {
+        let receipt = create_signed_receipt_with_custom_value(10);
+        let ctx = Context::new();
+        let runs = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let pipeline = |mode| {
+            CheckPipeline::new(CheckList::new(vec![
+                Arc::new(FailingCheck("early")),
+                Arc::new(CountingCheck(runs.clone())),
+                Arc::new(FailingCheck("late")),
+            ]))
+            .with_mode(mode)
+        };
+
+        // the early failure stops the pipeline
+        let result = pipeline(PipelineMode::ShortCircuit)
+            .check(&ctx, &receipt)
+            .await;
+        assert!(matches!(result, Err(ReceiptError::CheckFailure(message)) if message == "early"));
+        assert_eq!(runs.load(std::sync::atomic::Ordering::SeqCst), 0);
+
+        // every check runs and both failures are reported, in order
+        let result = pipeline(PipelineMode::CollectAll)
+            .check(&ctx, &receipt)
+            .await;
+        let Err(ReceiptError::CheckFailures(errors)) = result else {
+            panic!("expected both failures, got {result:?}");
+        };
+        assert_eq!(
+            errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                "Issue encountered while performing check: early",
+                "Issue encountered while performing check: late"
+            ]
+        );
+        assert_eq!(runs.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
 }
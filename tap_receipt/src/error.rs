@@ -9,6 +9,11 @@ use serde::{Deserialize, Serialize};
 pub enum ReceiptError {
     #[error("invalid allocation ID: {received_allocation_id}")]
     InvalidAllocationID { received_allocation_id: Address },
+    #[error("Allocation mismatch: expected {expected}, got {received}")]
+    AllocationMismatch {
+        expected: Address,
+        received: Address,
+    },
     #[error("Signature check failed:\n{source_error_message}")]
     InvalidSignature { source_error_message: String },
     #[error("invalid timestamp: {received_timestamp} (expected min {timestamp_min})")]
@@ -16,14 +21,51 @@ pub enum ReceiptError {
         received_timestamp: u64,
         timestamp_min: u64,
     },
+    #[error("Receipt timestamp {receipt_ts} is below the minimum timestamp {floor}")]
+    BelowMinTimestamp { receipt_ts: u64, floor: u64 },
     #[error("Invalid Value: {received_value} ")]
     InvalidValue { received_value: u128 },
+    #[error("Receipt value is zero")]
+    ZeroValueReceipt,
     #[error("Receipt is not unique")]
     NonUniqueReceipt,
     #[error("Attempt to collect escrow failed")]
     SubtractEscrowFailed,
+    #[error("Not enough escrow from sender {sender}: {available_escrow} available to cover {received_value}")]
+    NotEnoughEscrow {
+        sender: Address,
+        available_escrow: u128,
+        received_value: u128,
+    },
+    #[error("Sender {sender} is not authorized for allocation {allocation_id}")]
+    UnauthorizedSender {
+        sender: Address,
+        allocation_id: Address,
+    },
+    #[error("Receipt signed by revoked signer {signer}")]
+    RevokedSigner { signer: Address },
     #[error("Issue encountered while performing check: {0}")]
     CheckFailure(String),
     #[error("Retryable check error encountered: {0}")]
     RetryableCheck(String),
+    #[error(
+        "{} checks failed: {}",
+        .0.len(),
+        .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+    )]
+    CheckFailures(Vec<ReceiptError>),
+    #[error("Check {check_name} timed out")]
+    CheckTimeout { check_name: String },
+}
+
+impl ReceiptError {
+    /// Whether the receipt may be accepted if sent again later, e.g. when a
+    /// check couldn't reach its backend. Other errors are permanent.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ReceiptError::RetryableCheck(_) | ReceiptError::CheckTimeout { .. } => true,
+            ReceiptError::CheckFailures(errors) => errors.iter().any(Self::is_retryable),
+            _ => false,
+        }
+    }
 }
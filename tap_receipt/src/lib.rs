@@ -25,9 +25,10 @@ pub mod rav;
 mod received_receipt;
 pub mod state;
 
-use alloy::sol_types::SolStruct;
+use alloy::{primitives::Address, sol_types::SolStruct};
 pub use error::ReceiptError;
 pub use received_receipt::ReceiptWithState;
+use serde::{Deserialize, Serialize};
 use tap_eip712_message::{Eip712SignedMessage, SignatureBytes, SignatureBytesExt};
 
 /// Result type for receipt
@@ -42,6 +43,81 @@ pub trait WithValueAndTimestamp {
     fn timestamp_ns(&self) -> u64;
 }
 
+/// Extension for receipts and RAVs denominated in a given payment token,
+/// allowing escrow to be accounted per token
+pub trait WithToken {
+    fn token(&self) -> Address;
+}
+
+/// Extension for receipts bound to an allocation, allowing RAV requests to
+/// only aggregate receipts of their own allocation
+pub trait WithAllocationId {
+    fn allocation_id(&self) -> Address;
+}
+
+/// Extension exposing the nonce a sender picks for each query, identifying
+/// the query among the receipts of an allocation
+pub trait WithNonce {
+    fn nonce(&self) -> u64;
+}
+
+/// Extension giving a canonical hash of the receipt content, used to order
+/// and commit to the receipts of a RAV request
+pub trait WithReceiptHash {
+    fn receipt_hash(&self) -> [u8; 32];
+}
+
+/// Returns the key receipts are ordered by: timestamp first, then receipt
+/// hash for receipts sharing a timestamp.
+///
+/// Nanosecond timestamps rarely collide, but ordering-dependent results such
+/// as the Merkle root of a RAV request or the pages of a truncated query must
+/// not depend on the order storage returns receipts in. Breaking ties by
+/// [`WithReceiptHash::receipt_hash`] makes the order total and the same for
+/// every holder of the receipts.
+pub fn receipt_order_key<R>(receipt: &R) -> (u64, [u8; 32])
+where
+    R: WithValueAndTimestamp + WithReceiptHash,
+{
+    (receipt.timestamp_ns(), receipt.receipt_hash())
+}
+
+/// Extension for RAVs embedding a commitment to the receipts they aggregate,
+/// i.e. the Merkle root of the hashes of the receipts, see
+/// [`WithReceiptHash`]
+pub trait WithReceiptCommitment {
+    /// Returns the embedded commitment, or `None` if the RAV carries none
+    fn receipt_commitment(&self) -> Option<[u8; 32]>;
+}
+
+/// Extension exposing the schema version a receipt was sent as, see
+/// [`VersionedReceipt`]
+pub trait WithVersion {
+    fn version(&self) -> u8;
+}
+
+/// Receipt sent along with an explicit schema version discriminant, e.g. the
+/// `VERSION` of the receipt type it was built with. The version isn't a signed
+/// field, so it doesn't change the EIP-712 type hash, but it lets a receiver
+/// reject receipts of a version it doesn't support.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionedReceipt<Rcpt> {
+    pub version: u8,
+    pub receipt: Rcpt,
+}
+
+impl<Rcpt> VersionedReceipt<Rcpt> {
+    pub fn new(version: u8, receipt: Rcpt) -> Self {
+        Self { version, receipt }
+    }
+}
+
+impl<Rcpt> WithVersion for VersionedReceipt<Rcpt> {
+    fn version(&self) -> u8 {
+        self.version
+    }
+}
+
 /// Extension that allows UniqueCheck for any SolStruct receipt
 pub trait WithUniqueId {
     type Output: Eq + std::hash::Hash;
@@ -61,6 +137,43 @@ where
     }
 }
 
+impl<T> WithAllocationId for Eip712SignedMessage<T>
+where
+    T: SolStruct + WithAllocationId,
+{
+    fn allocation_id(&self) -> Address {
+        self.message.allocation_id()
+    }
+}
+
+impl<T> WithNonce for Eip712SignedMessage<T>
+where
+    T: SolStruct + WithNonce,
+{
+    fn nonce(&self) -> u64 {
+        self.message.nonce()
+    }
+}
+
+impl<T> WithReceiptHash for Eip712SignedMessage<T>
+where
+    T: SolStruct,
+{
+    /// The EIP-712 struct hash of the message
+    fn receipt_hash(&self) -> [u8; 32] {
+        self.unique_hash().0
+    }
+}
+
+impl<T> WithToken for Eip712SignedMessage<T>
+where
+    T: SolStruct + WithToken,
+{
+    fn token(&self) -> Address {
+        self.message.token()
+    }
+}
+
 impl<T> WithUniqueId for Eip712SignedMessage<T>
 where
     T: SolStruct,
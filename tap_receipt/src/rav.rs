@@ -3,7 +3,7 @@
 
 //! Aggregation of Receipts
 
-use alloy::sol_types::SolStruct;
+use alloy::{primitives::Address, sol_types::SolStruct};
 use tap_eip712_message::Eip712SignedMessage;
 
 use crate::{state::Checked, ReceiptWithState};
@@ -17,6 +17,14 @@ pub trait Aggregate<T>: SolStruct {
     ) -> Result<Self, AggregationError>;
 }
 
+/// Extension for RAVs that can be built from the aggregate of their receipts
+/// alone, without the receipts themselves
+pub trait FromAggregate {
+    /// Returns the RAV of `allocation_id` aggregating `value_aggregate` up to
+    /// `timestamp_ns`
+    fn from_aggregate(allocation_id: Address, timestamp_ns: u64, value_aggregate: u128) -> Self;
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum AggregationError {
     /// Error when trying to aggregate receipts and the result overflows
@@ -27,6 +35,24 @@ pub enum AggregationError {
     #[error("Failed to produce rav request, no valid receipts")]
     NoValidReceiptsForRavRequest,
 
+    /// Error when RAVs or receipts for different allocations are combined
+    #[error("Allocation id mismatch: expected {expected}, got {received}")]
+    AllocationIdMismatch {
+        expected: Address,
+        received: Address,
+    },
+
+    /// Error when receipts or RAVs denominated in different tokens are combined
+    #[error("Token mismatch: expected {expected}, got {received}")]
+    TokenMismatch {
+        expected: Address,
+        received: Address,
+    },
+
+    /// Error when splitting off more value than a RAV aggregates
+    #[error("Cannot split {amount} off a RAV aggregating {value_aggregate}")]
+    SplitExceedsAggregate { amount: u128, value_aggregate: u128 },
+
     /// Other user-defined error
     #[error(transparent)]
     Other(anyhow::Error),
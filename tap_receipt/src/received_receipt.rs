@@ -13,7 +13,7 @@
 //! This module is useful for managing and tracking the state of received receipts, as well as
 //! their progress through various checks and stages of inclusion in RAV requests and received RAVs.
 
-use super::{checks::CheckError, Context, ReceiptError, ReceiptResult};
+use super::{Context, ReceiptError, ReceiptResult};
 use crate::{
     checks::ReceiptCheck,
     state::{Checked, Checking, Failed, ReceiptState},
@@ -69,14 +69,20 @@ impl<Rcpt> ReceiptWithState<Checking, Rcpt> {
     ) -> ReceiptResult<()> {
         for check in checks {
             // return early on an error
-            check.check(ctx, self).await.map_err(|e| match e {
-                CheckError::Retryable(e) => ReceiptError::RetryableCheck(e.to_string()),
-                CheckError::Failed(e) => ReceiptError::CheckFailure(e.to_string()),
-            })?;
+            self.perform_check(ctx, check).await?;
         }
         Ok(())
     }
 
+    /// Performs a single check on the receipt
+    pub async fn perform_check(
+        &self,
+        ctx: &Context,
+        check: &ReceiptCheck<Rcpt>,
+    ) -> ReceiptResult<()> {
+        check.check(ctx, self).await.map_err(ReceiptError::from)
+    }
+
     /// Completes all checks and transitions the receipt to the next state
     ///
     /// Returns `Err` with a [`ReceiptWithState<Failed>`] in case of error,
@@ -88,13 +94,27 @@ impl<Rcpt> ReceiptWithState<Checking, Rcpt> {
         checks: &[ReceiptCheck<Rcpt>],
     ) -> Result<ResultReceipt<Checked, Rcpt>, String> {
         let all_checks_passed = self.perform_checks(ctx, checks).await;
-        if let Err(ReceiptError::RetryableCheck(e)) = all_checks_passed {
-            Err(e.to_string())
-        } else if let Err(e) = all_checks_passed {
-            Ok(Err(self.perform_state_error(e)))
-        } else {
-            let checked = self.perform_state_changes(Checked);
-            Ok(Ok(checked))
+        self.complete_checks(all_checks_passed)
+    }
+
+    /// Transitions the receipt to the next state given the outcome of its
+    /// checks, for callers running the checks themselves with
+    /// [`ReceiptWithState::perform_check`]
+    ///
+    /// Returns `Err` with the message of a retryable failure or of a check
+    /// timeout, since the receipt should then be checked again rather than
+    /// declined.
+    ///
+    pub fn complete_checks(
+        self,
+        all_checks_passed: ReceiptResult<()>,
+    ) -> Result<ResultReceipt<Checked, Rcpt>, String> {
+        match all_checks_passed {
+            Err(ReceiptError::RetryableCheck(e)) => Err(e),
+            // a check that timed out may well pass on a later attempt
+            Err(e @ ReceiptError::CheckTimeout { .. }) => Err(e.to_string()),
+            Err(e) => Ok(Err(self.perform_state_error(e))),
+            Ok(()) => Ok(Ok(self.perform_state_changes(Checked))),
         }
     }
 }
@@ -130,4 +150,9 @@ where
     pub fn signed_receipt(&self) -> &Rcpt {
         &self.receipt
     }
+
+    /// Consumes the wrapper, returning the signed receipt
+    pub fn into_signed_receipt(self) -> Rcpt {
+        self.receipt
+    }
 }